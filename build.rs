@@ -0,0 +1,24 @@
+use std::fs;
+
+/// Dependencies whose resolved versions are reported by `--version-info`
+const REPORTED_DEPENDENCIES: [&str; 4] = ["svg2gcode", "g-code", "roxmltree", "svgtypes"];
+
+fn main() {
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    let lock = fs::read_to_string("Cargo.lock").unwrap_or_default();
+    for dependency in REPORTED_DEPENDENCIES {
+        let version = locked_version(&lock, dependency).unwrap_or("unknown");
+        let key = dependency.to_uppercase().replace('-', "_");
+        println!("cargo:rustc-env=DEP_VERSION_{}={}", key, version);
+    }
+}
+
+/// Version of the first package with this name in the lock file
+fn locked_version<'a>(lock: &'a str, name: &str) -> Option<&'a str> {
+    let mut lines = lock.lines();
+    lines.find(|line| *line == format!("name = \"{}\"", name))?;
+    lines
+        .next()?
+        .strip_prefix("version = \"")?
+        .strip_suffix('"')
+}
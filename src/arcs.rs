@@ -0,0 +1,495 @@
+use g_code::emit::Field;
+
+use crate::gcode::Line;
+
+/// Fewest straight cuts replaced by an arc
+const MIN_CUTS: usize = 3;
+
+/// How arcs give their center
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ArcMode {
+    /// `I` and `J` offsets from the start to the center
+    Ij,
+    /// `R` radius, for controllers that prefer it. Arcs over 120 degrees are split, as a radius
+    /// alone can't tell an arc from the shorter one between the same points, and pins the
+    /// center down poorly near a half circle
+    R,
+}
+
+/// Replaces runs of straight cuts that lie on a circle, within `tolerance` millimeters, with
+/// `G2`/`G3` arcs.
+///
+/// A run only joins cuts whose other words, like the feedrate and power, are the same, and an
+/// arc has to turn one way through less than a full circle.
+pub fn fit_arcs(lines: Vec<Line>, tolerance: f64) -> Vec<Line> {
+    let mut output = Vec::with_capacity(lines.len());
+    let mut position = [None, None];
+    let mut i = 0;
+    while i < lines.len() {
+        let [Some(x), Some(y)] = position else {
+            update_position(&mut position, &lines[i]);
+            output.push(lines[i].clone());
+            i += 1;
+            continue;
+        };
+        if point(&lines[i]).is_none() {
+            update_position(&mut position, &lines[i]);
+            output.push(lines[i].clone());
+            i += 1;
+            continue;
+        }
+
+        // A run of straight cuts sharing their other words, and the points they pass through
+        let mut points = vec![[x, y]];
+        let mut end = i;
+        while end < lines.len() && same_words(&lines[i], &lines[end]) {
+            let Some(next) = point(&lines[end]) else {
+                break;
+            };
+            points.push(next);
+            end += 1;
+        }
+
+        let mut k = 0;
+        while k < end - i {
+            let mut fitted = None;
+            let mut last = k + MIN_CUTS;
+            while last <= end - i {
+                match fit(&points[k..=last], tolerance) {
+                    Some(arc) => fitted = Some((last, arc)),
+                    None => break,
+                }
+                last += 1;
+            }
+            match fitted {
+                Some((last, (center, clockwise))) => {
+                    output.push(arc_line(
+                        &lines[i + last - 1],
+                        points[k],
+                        points[last],
+                        center,
+                        clockwise,
+                    ));
+                    k = last;
+                }
+                None => {
+                    output.push(lines[i + k].clone());
+                    k += 1;
+                }
+            }
+        }
+        position = [Some(points[end - i][0]), Some(points[end - i][1])];
+        i = end;
+    }
+    output
+}
+
+/// End point of a straight cut in the XY plane
+fn point(line: &Line) -> Option<[f64; 2]> {
+    match line.is("G", 1) {
+        true => Some([line.value("X")?, line.value("Y")?]),
+        false => None,
+    }
+}
+
+fn update_position(position: &mut [Option<f64>; 2], line: &Line) {
+    for (axis, letter) in ["X", "Y"].into_iter().enumerate() {
+        if let Some(value) = line.value(letter) {
+            position[axis] = Some(value);
+        }
+    }
+}
+
+/// Words of the line other than its X and Y
+fn other_words(line: &Line) -> Vec<String> {
+    match line {
+        Line::Command(fields) => fields
+            .iter()
+            .skip(1)
+            .filter(|field| !is_axis(field))
+            .map(|field| field.to_string())
+            .collect(),
+        Line::Comment(_) => Vec::new(),
+    }
+}
+
+fn is_axis(field: &Field) -> bool {
+    field.letters.eq_ignore_ascii_case("X") || field.letters.eq_ignore_ascii_case("Y")
+}
+
+fn same_words(a: &Line, b: &Line) -> bool {
+    other_words(a) == other_words(b)
+}
+
+/// Center and direction of an arc through all the points and their chords, if there is one
+fn fit(points: &[[f64; 2]], tolerance: f64) -> Option<([f64; 2], bool)> {
+    let center = circumcenter(
+        points[0],
+        points[points.len() / 2],
+        points[points.len() - 1],
+    )?;
+    let radius = distance(center, points[0]);
+    let mut sweep = 0.0;
+    let mut turn = 0.0;
+    for pair in points.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        if (distance(center, b) - radius).abs() > tolerance {
+            return None;
+        }
+        // How far the chord's middle falls inside the arc
+        let half_chord = distance(a, b) / 2.0;
+        if half_chord > radius
+            || radius - (radius * radius - half_chord * half_chord).sqrt() > tolerance
+        {
+            return None;
+        }
+        let (u, v) = (
+            [a[0] - center[0], a[1] - center[1]],
+            [b[0] - center[0], b[1] - center[1]],
+        );
+        let cross = u[0] * v[1] - u[1] * v[0];
+        let angle = cross.atan2(u[0] * v[0] + u[1] * v[1]);
+        if angle == 0.0 || (turn != 0.0 && angle.signum() != turn) {
+            return None;
+        }
+        turn = angle.signum();
+        sweep += angle.abs();
+    }
+    (sweep < std::f64::consts::TAU - 1e-6).then_some((center, turn < 0.0))
+}
+
+fn circumcenter(a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> Option<[f64; 2]> {
+    let d = 2.0 * (a[0] * (b[1] - c[1]) + b[0] * (c[1] - a[1]) + c[0] * (a[1] - b[1]));
+    if d.abs() < 1e-12 {
+        return None;
+    }
+    let (a2, b2, c2) = (
+        a[0] * a[0] + a[1] * a[1],
+        b[0] * b[0] + b[1] * b[1],
+        c[0] * c[0] + c[1] * c[1],
+    );
+    Some([
+        (a2 * (b[1] - c[1]) + b2 * (c[1] - a[1]) + c2 * (a[1] - b[1])) / d,
+        (a2 * (c[0] - b[0]) + b2 * (a[0] - c[0]) + c2 * (b[0] - a[0])) / d,
+    ])
+}
+
+fn distance(a: [f64; 2], b: [f64; 2]) -> f64 {
+    (b[0] - a[0]).hypot(b[1] - a[1])
+}
+
+/// An arc from `from` to `to` around `center`, carrying the other words of the cut it ends in
+fn arc_line(last: &Line, from: [f64; 2], to: [f64; 2], center: [f64; 2], clockwise: bool) -> Line {
+    let mut arc = Line::command(
+        "G",
+        if clockwise { 2 } else { 3 },
+        &[
+            ("X", to[0]),
+            ("Y", to[1]),
+            ("I", center[0] - from[0]),
+            ("J", center[1] - from[1]),
+        ],
+    );
+    if let (Line::Command(fields), Line::Command(words)) = (&mut arc, last) {
+        fields.extend(
+            words
+                .iter()
+                .skip(1)
+                .filter(|field| !is_axis(field))
+                .cloned(),
+        );
+    }
+    arc
+}
+
+/// Splits every `G2`/`G3` arc sweeping more than `max_degrees` into equal shorter arcs around
+/// the same center, ending where it did.
+///
+/// An arc ending where it starts is a full circle, and is split like any other.
+pub fn split_arcs(lines: Vec<Line>, max_degrees: f64) -> Vec<Line> {
+    let mut output = Vec::with_capacity(lines.len());
+    let mut position = [None, None];
+    for line in lines {
+        let from = position;
+        update_position(&mut position, &line);
+        let (Some(arc), [Some(x), Some(y)]) = (arc(&line), from) else {
+            output.push(line);
+            continue;
+        };
+        output.extend(arc.split(&line, [x, y], arc.parts([x, y], max_degrees)));
+    }
+    output
+}
+
+/// Most an `R` arc turns through, well short of the half circle where its center is ambiguous
+const MAX_RADIUS_ARC: f64 = 120.0;
+
+/// Gives every arc's center as an `R` radius in place of `I` and `J`, splitting arcs of over
+/// [`MAX_RADIUS_ARC`] degrees so each is the shorter of the two arcs its radius could mean
+pub fn to_radius(lines: Vec<Line>) -> Vec<Line> {
+    let mut output = Vec::with_capacity(lines.len());
+    let mut position = [None, None];
+    for line in lines {
+        let from = position;
+        update_position(&mut position, &line);
+        let (Some(arc), [Some(x), Some(y)]) = (arc(&line), from) else {
+            output.push(line);
+            continue;
+        };
+        for mut part in arc.split(&line, [x, y], arc.parts([x, y], MAX_RADIUS_ARC)) {
+            let radius = part.value("I").unwrap().hypot(part.value("J").unwrap());
+            if let Line::Command(fields) = &mut part {
+                fields.retain(|field| {
+                    !field.letters.eq_ignore_ascii_case("I")
+                        && !field.letters.eq_ignore_ascii_case("J")
+                });
+            }
+            part.set_value("R", radius);
+            output.push(part);
+        }
+    }
+    output
+}
+
+/// End, center offset and direction of an arc move
+struct Arc {
+    to: [f64; 2],
+    offset: [f64; 2],
+    clockwise: bool,
+}
+
+fn arc(line: &Line) -> Option<Arc> {
+    let clockwise = match (line.is("G", 2), line.is("G", 3)) {
+        (true, _) => true,
+        (_, true) => false,
+        _ => return None,
+    };
+    Some(Arc {
+        to: [line.value("X")?, line.value("Y")?],
+        offset: [
+            line.value("I").unwrap_or(0.0),
+            line.value("J").unwrap_or(0.0),
+        ],
+        clockwise,
+    })
+}
+
+impl Arc {
+    /// Angle in radians the arc turns through from `from`, a whole turn when it comes back there
+    fn sweep(&self, from: [f64; 2]) -> f64 {
+        let center = [from[0] + self.offset[0], from[1] + self.offset[1]];
+        if distance(from, self.to) < 1e-9 {
+            return std::f64::consts::TAU;
+        }
+        let angle = |point: [f64; 2]| (point[1] - center[1]).atan2(point[0] - center[0]);
+        let turn = match self.clockwise {
+            true => angle(from) - angle(self.to),
+            false => angle(self.to) - angle(from),
+        };
+        turn.rem_euclid(std::f64::consts::TAU)
+    }
+
+    /// Fewest equal arcs that each turn through no more than `max_degrees`
+    fn parts(&self, from: [f64; 2], max_degrees: f64) -> usize {
+        (self.sweep(from) / max_degrees.to_radians() - 1e-9)
+            .ceil()
+            .max(1.0) as usize
+    }
+
+    /// The arc as `parts` equal arcs, each a copy of `line` with its own end and center offset
+    fn split(&self, line: &Line, from: [f64; 2], parts: usize) -> Vec<Line> {
+        let center = [from[0] + self.offset[0], from[1] + self.offset[1]];
+        let radius = distance(center, from);
+        let start = (from[1] - center[1]).atan2(from[0] - center[0]);
+        let step = match self.clockwise {
+            true => -self.sweep(from) / parts as f64,
+            false => self.sweep(from) / parts as f64,
+        };
+        let mut lines = Vec::with_capacity(parts);
+        let mut part_from = from;
+        for k in 1..=parts {
+            let to = match k == parts {
+                true => self.to,
+                false => {
+                    let angle = start + step * k as f64;
+                    [
+                        center[0] + radius * angle.cos(),
+                        center[1] + radius * angle.sin(),
+                    ]
+                }
+            };
+            let mut part = line.clone();
+            part.set_value("X", to[0]);
+            part.set_value("Y", to[1]);
+            part.set_value("I", center[0] - part_from[0]);
+            part.set_value("J", center[1] - part_from[1]);
+            lines.push(part);
+            part_from = to;
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RADIUS: f64 = 10.0;
+
+    fn rapid(point: [f64; 2]) -> Line {
+        Line::command("G", 0, &[("X", point[0]), ("Y", point[1])])
+    }
+
+    fn cut(point: [f64; 2]) -> Line {
+        Line::command("G", 1, &[("X", point[0]), ("Y", point[1]), ("F", 1000.0)])
+    }
+
+    /// The point at `degrees` on a circle around the origin
+    fn on_circle(degrees: f64) -> [f64; 2] {
+        let angle = degrees.to_radians();
+        [RADIUS * angle.cos(), RADIUS * angle.sin()]
+    }
+
+    /// A move to the first angle, then straight cuts through the rest
+    fn cuts(degrees: &[f64]) -> Vec<Line> {
+        let mut lines = vec![rapid(on_circle(degrees[0]))];
+        lines.extend(degrees[1..].iter().map(|&degrees| cut(on_circle(degrees))));
+        lines
+    }
+
+    fn assert_near(value: Option<f64>, expected: f64) {
+        let value = value.unwrap();
+        assert!(
+            (value - expected).abs() < 1e-6,
+            "{} is not {}",
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn fit_arcs_needs_at_least_min_cuts() {
+        // Each cut's chord falls 0.038 inside the circle
+        let fewer = cuts(&[0.0, 10.0, 20.0]);
+        assert_eq!(fit_arcs(fewer.clone(), 0.05), fewer);
+
+        let arcs = fit_arcs(cuts(&[0.0, 10.0, 20.0, 30.0]), 0.05);
+        assert_eq!(arcs.len(), 2);
+        assert!(arcs[1].is("G", 3));
+        assert_near(arcs[1].value("X"), on_circle(30.0)[0]);
+        assert_near(arcs[1].value("Y"), on_circle(30.0)[1]);
+        assert_near(arcs[1].value("I"), -RADIUS);
+        assert_near(arcs[1].value("J"), 0.0);
+        assert_eq!(arcs[1].value("F"), Some(1000.0));
+    }
+
+    #[test]
+    fn fit_arcs_follows_the_direction_of_the_cuts() {
+        let arcs = fit_arcs(cuts(&[30.0, 20.0, 10.0, 0.0]), 0.05);
+        assert!(arcs[1].is("G", 2));
+    }
+
+    #[test]
+    fn fit_arcs_refuses_cuts_turning_both_ways() {
+        // On the circle throughout, but the last cut turns back
+        let back = cuts(&[0.0, 10.0, 20.0, 10.0]);
+        assert_eq!(fit_arcs(back.clone(), 0.05), back);
+    }
+
+    #[test]
+    fn fit_arcs_keeps_cuts_that_differ_in_other_words() {
+        let mut lines = cuts(&[0.0, 10.0, 20.0, 30.0]);
+        lines[2].set_value("F", 500.0);
+        assert_eq!(fit_arcs(lines.clone(), 0.05), lines);
+    }
+
+    #[test]
+    fn fit_arcs_replaces_a_flattened_circle_with_a_few_arcs() {
+        let degrees: Vec<f64> = (0..=36).map(|step| step as f64 * 10.0).collect();
+        let arcs = fit_arcs(cuts(&degrees), 0.05);
+        // An arc has to turn through less than a full circle, so a cut is left over at the end
+        assert_eq!(arcs.len(), 3, "{:?}", arcs);
+        assert!(arcs[1].is("G", 3));
+        assert!(arcs[2].is("G", 1));
+        let last = arcs.last().unwrap();
+        assert_near(last.value("X"), RADIUS);
+        assert_near(last.value("Y"), 0.0);
+    }
+
+    #[test]
+    fn split_arcs_splits_a_full_circle() {
+        let start = on_circle(0.0);
+        let circle = Line::command(
+            "G",
+            2,
+            &[("X", start[0]), ("Y", start[1]), ("I", -RADIUS), ("J", 0.0)],
+        );
+        let parts = split_arcs(vec![rapid(start), circle], 90.0);
+        assert_eq!(parts.len(), 5);
+        // Clockwise from the right of the circle goes down first
+        for (part, degrees) in parts[1..].iter().zip([-90.0, -180.0, -270.0, 0.0]) {
+            assert!(part.is("G", 2));
+            assert_near(part.value("X"), on_circle(degrees)[0]);
+            assert_near(part.value("Y"), on_circle(degrees)[1]);
+        }
+        assert_near(parts[2].value("I"), 0.0);
+        assert_near(parts[2].value("J"), RADIUS);
+    }
+
+    #[test]
+    fn to_radius_splits_arcs_over_120_degrees() {
+        let arc_to = |degrees: f64| {
+            let to = on_circle(degrees);
+            let arc = Line::command(
+                "G",
+                3,
+                &[("X", to[0]), ("Y", to[1]), ("I", -RADIUS), ("J", 0.0)],
+            );
+            to_radius(vec![rapid(on_circle(0.0)), arc])
+        };
+
+        let whole = arc_to(120.0);
+        assert_eq!(whole.len(), 2);
+        assert_near(whole[1].value("R"), RADIUS);
+        assert_eq!(whole[1].value("I"), None);
+        assert_eq!(whole[1].value("J"), None);
+
+        let halves = arc_to(180.0);
+        assert_eq!(halves.len(), 3);
+        assert_near(halves[1].value("X"), on_circle(90.0)[0]);
+        assert_near(halves[1].value("Y"), on_circle(90.0)[1]);
+        for half in &halves[1..] {
+            assert!(half.is("G", 3));
+            assert_near(half.value("R"), RADIUS);
+        }
+    }
+
+    #[test]
+    fn split_arcs_splits_longer_arcs_into_equal_parts() {
+        let to = on_circle(150.0);
+        let arc = Line::command(
+            "G",
+            3,
+            &[
+                ("X", to[0]),
+                ("Y", to[1]),
+                ("I", -RADIUS),
+                ("J", 0.0),
+                ("F", 500.0),
+            ],
+        );
+        let parts = split_arcs(vec![rapid(on_circle(0.0)), arc.clone()], 90.0);
+        assert_eq!(parts.len(), 3);
+        assert_near(parts[1].value("X"), on_circle(75.0)[0]);
+        assert_near(parts[1].value("Y"), on_circle(75.0)[1]);
+        // The last part ends exactly where the arc did, around the same center
+        assert_eq!(parts[2].value("X"), Some(to[0]));
+        assert_eq!(parts[2].value("Y"), Some(to[1]));
+        assert_near(parts[2].value("I"), -on_circle(75.0)[0]);
+        assert_near(parts[2].value("J"), -on_circle(75.0)[1]);
+        assert!(parts[1..].iter().all(|part| part.value("F") == Some(500.0)));
+
+        let short = vec![rapid(on_circle(0.0)), arc];
+        assert_eq!(split_arcs(short.clone(), 180.0), short);
+    }
+}
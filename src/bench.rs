@@ -0,0 +1,190 @@
+use serde::Serialize;
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+use svg2gcode::{svg2program, ConversionOptions};
+
+use crate::{
+    arcs,
+    gcode::{self, Line},
+    postprocess,
+    settings::Settings,
+    Args,
+};
+
+const PHASES: [&str; 4] = ["parse", "convert", "post-process", "write"];
+
+pub struct BenchOptions {
+    pub iterations: u32,
+    /// Tolerance to fit arcs with, when they should be
+    pub arc_tolerance: Option<f64>,
+    pub allow_dtd: bool,
+}
+
+/// Timings of every run with one set of settings
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub label: String,
+    pub iterations: u32,
+    pub phases: Vec<Phase>,
+    pub total: Phase,
+    pub lines: usize,
+    pub output_bytes: u64,
+    pub tokens_per_second: f64,
+}
+
+/// Fastest, median and slowest time of a phase, in milliseconds
+#[derive(Debug, Serialize)]
+pub struct Phase {
+    pub name: &'static str,
+    pub min_ms: f64,
+    pub median_ms: f64,
+    pub max_ms: f64,
+}
+
+impl Phase {
+    fn new(name: &'static str, mut times: Vec<Duration>) -> Self {
+        times.sort();
+        let ms = |time: Duration| time.as_secs_f64() * 1000.0;
+        Phase {
+            name,
+            min_ms: ms(times[0]),
+            median_ms: ms(times[times.len() / 2]),
+            max_ms: ms(times[times.len() - 1]),
+        }
+    }
+}
+
+/// Converts the SVG `iterations` times, timing each phase separately.
+///
+/// The core of a conversion is run: reading and parsing the SVG, converting it with the
+/// settings, the final safety check and arc fitting, and writing it to a temporary file with
+/// the default output options.
+pub fn run(
+    label: String,
+    input_path: &Path,
+    settings: &Settings,
+    options: &BenchOptions,
+    args: &Args,
+) -> Report {
+    let output_path =
+        std::env::temp_dir().join(format!("usgcode-bench-{}.gcode", std::process::id()));
+    let safe_height = postprocess::lift_height(&gcode::snippet_lines(&settings.machine.tool_off));
+    let mut times = vec![Vec::new(); PHASES.len()];
+    let mut totals = Vec::new();
+    let (mut lines, mut tokens, mut output_bytes) = (0, 0, 0);
+    for _ in 0..options.iterations {
+        let start = Instant::now();
+        let svg_xml = crate::read_svg(input_path, crate::DEFAULT_MAX_INPUT_SIZE, args.input_format);
+        let doc = crate::parse_document(&svg_xml, input_path, options.allow_dtd);
+        let parsed = Instant::now();
+
+        let conversion_options = ConversionOptions {
+            dimensions: crate::dimensions(&doc, 1.0),
+        };
+        let gcode = svg2program(
+            &doc,
+            &crate::conversion_config(settings),
+            conversion_options,
+            crate::machine(settings),
+        );
+        let program: Vec<Line> = gcode::assemble(gcode).collect();
+        let converted = Instant::now();
+
+        let mut program: Vec<Line> =
+            postprocess::finish_safely(program.into_iter(), safe_height, &[]).collect();
+        if let Some(tolerance) = options.arc_tolerance {
+            program = arcs::fit_arcs(program, tolerance);
+        }
+        let processed = Instant::now();
+
+        lines = program.len();
+        tokens = program
+            .iter()
+            .map(|line| match line {
+                Line::Command(fields) => fields.len(),
+                Line::Comment(_) => 0,
+            })
+            .sum();
+        crate::write_program(&output_path, program, None, args, || true);
+        let written = Instant::now();
+        output_bytes = fs::metadata(&output_path).map_or(0, |metadata| metadata.len());
+
+        let phases = [
+            parsed - start,
+            converted - parsed,
+            processed - converted,
+            written - processed,
+        ];
+        for (phase, time) in phases.into_iter().enumerate() {
+            times[phase].push(time);
+        }
+        totals.push(written - start);
+    }
+    let _ = fs::remove_file(&output_path);
+
+    let total = Phase::new("total", totals);
+    Report {
+        label,
+        iterations: options.iterations,
+        phases: PHASES
+            .into_iter()
+            .zip(times)
+            .map(|(name, times)| Phase::new(name, times))
+            .collect(),
+        tokens_per_second: tokens as f64 / (total.median_ms / 1000.0),
+        total,
+        lines,
+        output_bytes,
+    }
+}
+
+/// Prints a table of each report, and how the second differs from the first when there are two
+pub fn print(reports: &[Report]) {
+    for report in reports {
+        println!("{}, {} iterations", report.label, report.iterations);
+        println!(
+            "  {:<14}{:>12}{:>12}{:>12}",
+            "phase", "min", "median", "max"
+        );
+        for phase in report.phases.iter().chain([&report.total]) {
+            println!(
+                "  {:<14}{:>10.3}ms{:>10.3}ms{:>10.3}ms",
+                phase.name, phase.min_ms, phase.median_ms, phase.max_ms
+            );
+        }
+        println!(
+            "  {} lines, {} bytes, {:.0} tokens/s",
+            report.lines, report.output_bytes, report.tokens_per_second
+        );
+        println!();
+    }
+
+    if let [a, b] = reports {
+        println!("Change in median from {} to {}", a.label, b.label);
+        let phases = a.phases.iter().zip(&b.phases);
+        for (a, b) in phases.chain([(&a.total, &b.total)]) {
+            println!(
+                "  {:<14}{:>+10.3}ms{:>+9.1}%",
+                a.name,
+                b.median_ms - a.median_ms,
+                (b.median_ms / a.median_ms - 1.0) * 100.0
+            );
+        }
+        println!(
+            "  {:<14}{:>+12}",
+            "output bytes",
+            b.output_bytes as i64 - a.output_bytes as i64
+        );
+    }
+}
+
+pub fn to_json(reports: &[Report]) -> String {
+    match reports {
+        [report] => serde_json::to_string_pretty(report),
+        reports => serde_json::to_string_pretty(reports),
+    }
+    .expect("reports serialize to JSON")
+}
@@ -0,0 +1,446 @@
+use crate::gcode::{self, Line};
+
+/// Rectangle of the machine's XY plane that output is restricted to
+#[derive(Debug, Clone, Copy)]
+pub struct Window {
+    pub min: [f64; 2],
+    pub max: [f64; 2],
+}
+
+impl Window {
+    /// Portion of the segment inside the window, as the range of its parameter from 0 to 1
+    fn clip(&self, from: [f64; 2], to: [f64; 2]) -> Option<(f64, f64)> {
+        // Liang-Barsky: narrow the parameter range against each edge in turn
+        let (mut t0, mut t1) = (0.0_f64, 1.0_f64);
+        for axis in 0..2 {
+            let delta = to[axis] - from[axis];
+            for (p, q) in [
+                (-delta, from[axis] - self.min[axis]),
+                (delta, self.max[axis] - from[axis]),
+            ] {
+                if p == 0.0 {
+                    if q < 0.0 {
+                        return None;
+                    }
+                } else if p < 0.0 {
+                    t0 = t0.max(q / p);
+                } else {
+                    t1 = t1.min(q / p);
+                }
+            }
+        }
+        (t0 <= t1).then_some((t0, t1))
+    }
+}
+
+/// Closed outline in the XY plane that output is restricted to, by the even-odd rule
+#[derive(Debug, Clone)]
+pub struct Polygon(pub Vec<[f64; 2]>);
+
+impl Polygon {
+    /// Portions of the segment inside the polygon, as ranges of its parameter from 0 to 1
+    fn clip(&self, from: [f64; 2], to: [f64; 2]) -> Vec<(f64, f64)> {
+        let points = &self.0;
+        let delta = [to[0] - from[0], to[1] - from[1]];
+        // Where the segment crosses each edge, splitting it into pieces wholly in or out
+        let mut cuts = vec![0.0, 1.0];
+        for i in 0..points.len() {
+            let (a, b) = (points[i], points[(i + 1) % points.len()]);
+            let edge = [b[0] - a[0], b[1] - a[1]];
+            let denominator = delta[0] * edge[1] - delta[1] * edge[0];
+            if denominator.abs() < 1e-12 {
+                continue;
+            }
+            let offset = [a[0] - from[0], a[1] - from[1]];
+            let t = (offset[0] * edge[1] - offset[1] * edge[0]) / denominator;
+            let u = (offset[0] * delta[1] - offset[1] * delta[0]) / denominator;
+            if t > 0.0 && t < 1.0 && (0.0..=1.0).contains(&u) {
+                cuts.push(t);
+            }
+        }
+        cuts.sort_by(f64::total_cmp);
+        cuts.dedup();
+
+        let mut portions: Vec<(f64, f64)> = Vec::new();
+        for pair in cuts.windows(2) {
+            let t = (pair[0] + pair[1]) / 2.0;
+            if !self.contains([from[0] + delta[0] * t, from[1] + delta[1] * t]) {
+                continue;
+            }
+            match portions.last_mut() {
+                Some(last) if last.1 == pair[0] => last.1 = pair[1],
+                _ => portions.push((pair[0], pair[1])),
+            }
+        }
+        portions
+    }
+
+    /// Whether the point is inside, by counting edge crossings
+    fn contains(&self, point: [f64; 2]) -> bool {
+        let points = &self.0;
+        let mut inside = false;
+        for i in 0..points.len() {
+            let (a, b) = (points[i], points[(i + 1) % points.len()]);
+            if (a[1] > point[1]) != (b[1] > point[1])
+                && point[0] < a[0] + (point[1] - a[1]) / (b[1] - a[1]) * (b[0] - a[0])
+            {
+                inside = !inside;
+            }
+        }
+        inside
+    }
+}
+
+/// Indices of the paths with a cut going outside the window
+pub fn outside(lines: &[Line], window: &Window, tool_on: &[Line]) -> Vec<usize> {
+    let inside = |i: &usize| {
+        let point = [lines[*i].value("X").unwrap(), lines[*i].value("Y").unwrap()];
+        (0..2).all(|axis| (window.min[axis]..=window.max[axis]).contains(&point[axis]))
+    };
+    gcode::subpaths(lines, tool_on)
+        .iter()
+        .enumerate()
+        .filter(|(_, subpath)| !subpath.iter().all(inside))
+        .map(|(path, _)| path)
+        .collect()
+}
+
+/// Cuts only the parts of the toolpath inside the window.
+///
+/// Cuts crossing an edge are split there, with the tool lifted for the portion outside and
+/// brought back down after a travel to where the path re-enters.
+pub fn clip(lines: Vec<Line>, window: &Window, tool_on: &[Line], tool_off: &[Line]) -> Vec<Line> {
+    clip_to(
+        lines,
+        |from, to| window.clip(from, to).into_iter().collect(),
+        tool_on,
+        tool_off,
+    )
+}
+
+/// Cuts only the parts of the toolpath inside the polygon, as [`clip`] does for a window
+pub fn clip_polygon(
+    lines: Vec<Line>,
+    polygon: &Polygon,
+    tool_on: &[Line],
+    tool_off: &[Line],
+) -> Vec<Line> {
+    clip_to(lines, |from, to| polygon.clip(from, to), tool_on, tool_off)
+}
+
+/// Cuts the portions of each cut that `inside` gives, as ranges of its parameter from 0 to 1
+fn clip_to(
+    lines: Vec<Line>,
+    inside: impl Fn([f64; 2], [f64; 2]) -> Vec<(f64, f64)>,
+    tool_on: &[Line],
+    tool_off: &[Line],
+) -> Vec<Line> {
+    let mut output = Vec::with_capacity(lines.len());
+    let mut position = [0.0, 0.0];
+    // Where the machine actually is, which differs from `position` while skipping outside cuts
+    let mut emitted = [0.0, 0.0];
+    // Whether the program has the tool down, and whether it is down in the clipped output
+    let mut wants_down = false;
+    let mut down = false;
+    let mut i = 0;
+
+    while i < lines.len() {
+        if lines[i..].starts_with(tool_on) {
+            wants_down = true;
+            i += tool_on.len();
+            continue;
+        }
+        if lines[i..].starts_with(tool_off) {
+            // A path that never entered the window has nothing to lift from
+            if down || !wants_down {
+                output.extend_from_slice(tool_off);
+            }
+            wants_down = false;
+            down = false;
+            i += tool_off.len();
+            continue;
+        }
+
+        let line = &lines[i];
+        i += 1;
+        let target = [
+            line.value("X").unwrap_or(position[0]),
+            line.value("Y").unwrap_or(position[1]),
+        ];
+        if !line.is("G", 1) || !wants_down {
+            if line.value("X").is_some() || line.value("Y").is_some() {
+                emitted = target;
+            }
+            position = target;
+            output.push(line.clone());
+            continue;
+        }
+        if target == position {
+            continue;
+        }
+
+        let from = position;
+        position = target;
+        let portions = inside(from, target);
+        if down && portions.first().is_none_or(|&(t0, _)| t0 > 0.0) {
+            output.extend_from_slice(tool_off);
+            down = false;
+        }
+        let at = |t: f64| {
+            [
+                from[0] + (target[0] - from[0]) * t,
+                from[1] + (target[1] - from[1]) * t,
+            ]
+        };
+        for (t0, t1) in portions {
+            let (entry, exit) = (at(t0), at(t1));
+            if !down {
+                if emitted != entry {
+                    output.push(Line::command("G", 0, &[("X", entry[0]), ("Y", entry[1])]));
+                }
+                output.extend_from_slice(tool_on);
+                down = true;
+            }
+            let mut cut = line.clone();
+            cut.set_value("X", exit[0]);
+            cut.set_value("Y", exit[1]);
+            output.push(cut);
+            emitted = exit;
+            if t1 < 1.0 {
+                output.extend_from_slice(tool_off);
+                down = false;
+            }
+        }
+    }
+    output
+}
+
+/// Brings travels that end outside the window to its nearest edge
+pub fn clamp_travels(mut lines: Vec<Line>, window: &Window) -> Vec<Line> {
+    for line in lines.iter_mut().filter(|line| line.is("G", 0)) {
+        for (axis, letter) in ["X", "Y"].into_iter().enumerate() {
+            if let Some(value) = line.value(letter) {
+                line.set_value(letter, value.clamp(window.min[axis], window.max[axis]));
+            }
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    const WINDOW: Window = Window {
+        min: [0.0, 0.0],
+        max: [10.0, 10.0],
+    };
+
+    fn square() -> Polygon {
+        Polygon(vec![[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [0.0, 10.0]])
+    }
+
+    fn assert_portions(portions: Vec<(f64, f64)>, expected: &[(f64, f64)]) {
+        assert_eq!(portions.len(), expected.len(), "{:?}", portions);
+        for (portion, expected) in portions.iter().zip(expected) {
+            assert!(
+                (portion.0 - expected.0).abs() < 1e-9 && (portion.1 - expected.1).abs() < 1e-9,
+                "{:?} is not {:?}",
+                portion,
+                expected
+            );
+        }
+    }
+
+    fn rapid(x: f64, y: f64) -> Line {
+        Line::command("G", 0, &[("X", x), ("Y", y)])
+    }
+
+    fn cut(x: f64, y: f64) -> Line {
+        Line::command("G", 1, &[("X", x), ("Y", y)])
+    }
+
+    /// A travel to the first point, then a path cutting through the rest
+    fn path(points: &[[f64; 2]]) -> Vec<Line> {
+        let (tool_on, tool_off) = testing::tool();
+        let mut lines = vec![rapid(points[0][0], points[0][1])];
+        lines.extend(tool_on);
+        lines.extend(points[1..].iter().map(|point| cut(point[0], point[1])));
+        lines.extend(tool_off);
+        lines
+    }
+
+    #[test]
+    fn window_keeps_segments_inside_it() {
+        assert_eq!(WINDOW.clip([2.0, 2.0], [8.0, 5.0]), Some((0.0, 1.0)));
+    }
+
+    #[test]
+    fn window_drops_segments_outside_it() {
+        assert_eq!(WINDOW.clip([12.0, 2.0], [15.0, 8.0]), None);
+        // Across a corner without entering
+        assert_eq!(WINDOW.clip([-5.0, 8.0], [8.0, 21.0]), None);
+    }
+
+    #[test]
+    fn window_cuts_segments_crossing_its_edges() {
+        assert_eq!(WINDOW.clip([5.0, 5.0], [15.0, 5.0]), Some((0.0, 0.5)));
+        assert_eq!(
+            WINDOW.clip([-10.0, 5.0], [20.0, 5.0]),
+            Some((1.0 / 3.0, 2.0 / 3.0))
+        );
+    }
+
+    #[test]
+    fn window_keeps_segments_on_its_edges() {
+        assert_eq!(WINDOW.clip([0.0, 2.0], [0.0, 8.0]), Some((0.0, 1.0)));
+        assert_eq!(WINDOW.clip([5.0, 5.0], [10.0, 5.0]), Some((0.0, 1.0)));
+        // Touching a corner from outside leaves a single point
+        assert_eq!(WINDOW.clip([10.0, 10.0], [15.0, 15.0]), Some((0.0, 0.0)));
+    }
+
+    #[test]
+    fn clip_truncates_a_cut_crossing_the_edge() {
+        let (tool_on, tool_off) = testing::tool();
+        let lines = path(&[[5.0, 5.0], [15.0, 5.0]]);
+        let mut expected = vec![rapid(5.0, 5.0)];
+        expected.extend(tool_on.clone());
+        expected.push(cut(10.0, 5.0));
+        expected.extend(tool_off.clone());
+        assert_eq!(clip(lines, &WINDOW, &tool_on, &tool_off), expected);
+    }
+
+    #[test]
+    fn clip_lifts_the_tool_outside_the_window() {
+        let (tool_on, tool_off) = testing::tool();
+        let lines = path(&[[5.0, 5.0], [15.0, 5.0], [15.0, 8.0], [5.0, 8.0]]);
+        let mut expected = vec![rapid(5.0, 5.0)];
+        expected.extend(tool_on.clone());
+        expected.push(cut(10.0, 5.0));
+        expected.extend(tool_off.clone());
+        expected.push(rapid(10.0, 8.0));
+        expected.extend(tool_on.clone());
+        expected.push(cut(5.0, 8.0));
+        expected.extend(tool_off.clone());
+        assert_eq!(clip(lines, &WINDOW, &tool_on, &tool_off), expected);
+    }
+
+    #[test]
+    fn clip_keeps_paths_inside_the_window() {
+        let (tool_on, tool_off) = testing::tool();
+        let lines = path(&[[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [5.0, 5.0]]);
+        assert_eq!(clip(lines.clone(), &WINDOW, &tool_on, &tool_off), lines);
+    }
+
+    #[test]
+    fn clip_drops_paths_outside_the_window() {
+        let (tool_on, tool_off) = testing::tool();
+        let lines = path(&[[12.0, 2.0], [15.0, 8.0]]);
+        // Nothing is left of it but the travel
+        assert_eq!(
+            clip(lines, &WINDOW, &tool_on, &tool_off),
+            [rapid(12.0, 2.0)]
+        );
+    }
+
+    #[test]
+    fn outside_finds_the_paths_leaving_the_window() {
+        let (tool_on, _) = testing::tool();
+        let mut lines = path(&[[0.0, 0.0], [10.0, 0.0], [10.0, 10.0]]);
+        lines.extend(path(&[[5.0, 5.0], [15.0, 5.0]]));
+        lines.extend(path(&[[2.0, 2.0], [8.0, 8.0]]));
+        lines.extend(path(&[[12.0, 2.0], [15.0, 8.0]]));
+        assert_eq!(outside(&lines, &WINDOW, &tool_on), [1, 3]);
+    }
+
+    #[test]
+    fn clamp_travels_brings_travels_to_the_edge() {
+        let lines = vec![
+            rapid(-5.0, 20.0),
+            cut(15.0, 5.0),
+            Line::command("G", 0, &[("X", 12.0)]),
+            rapid(5.0, 10.0),
+        ];
+        let expected = vec![
+            rapid(0.0, 10.0),
+            cut(15.0, 5.0),
+            Line::command("G", 0, &[("X", 10.0)]),
+            rapid(5.0, 10.0),
+        ];
+        assert_eq!(clamp_travels(lines, &WINDOW), expected);
+    }
+
+    #[test]
+    fn clipping_to_the_bed_keeps_every_move_on_it() {
+        let (tool_on, tool_off) = testing::tool();
+        let mut lines = path(&[[5.0, 5.0], [15.0, 5.0], [15.0, 8.0], [5.0, 8.0]]);
+        lines.extend(path(&[[-4.0, 12.0], [-2.0, 14.0]]));
+        let clipped = clamp_travels(clip(lines, &WINDOW, &tool_on, &tool_off), &WINDOW);
+        for line in clipped
+            .iter()
+            .filter(|line| line.is("G", 0) || line.is("G", 1))
+        {
+            for (axis, letter) in ["X", "Y"].into_iter().enumerate() {
+                if let Some(value) = line.value(letter) {
+                    assert!(
+                        (WINDOW.min[axis]..=WINDOW.max[axis]).contains(&value),
+                        "{:?}",
+                        line
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn polygon_keeps_the_portions_inside_it() {
+        let square = square();
+        assert_portions(square.clip([2.0, 2.0], [8.0, 5.0]), &[(0.0, 1.0)]);
+        assert_portions(square.clip([12.0, 2.0], [15.0, 8.0]), &[]);
+        assert_portions(square.clip([5.0, 5.0], [15.0, 5.0]), &[(0.0, 0.5)]);
+        assert_portions(
+            square.clip([-10.0, 5.0], [20.0, 5.0]),
+            &[(1.0 / 3.0, 2.0 / 3.0)],
+        );
+    }
+
+    #[test]
+    fn polygon_cuts_a_segment_leaving_and_reentering() {
+        // A U shape, which the segment across its arms crosses four times
+        let u = Polygon(vec![
+            [0.0, 0.0],
+            [10.0, 0.0],
+            [10.0, 10.0],
+            [7.0, 10.0],
+            [7.0, 3.0],
+            [3.0, 3.0],
+            [3.0, 10.0],
+            [0.0, 10.0],
+        ]);
+        assert_portions(
+            u.clip([-10.0, 5.0], [20.0, 5.0]),
+            &[(10.0 / 30.0, 13.0 / 30.0), (17.0 / 30.0, 20.0 / 30.0)],
+        );
+    }
+
+    #[test]
+    fn polygon_keeps_segments_ending_on_its_edges() {
+        let square = square();
+        assert_portions(square.clip([5.0, 5.0], [10.0, 5.0]), &[(0.0, 1.0)]);
+        assert_portions(square.clip([10.0, 5.0], [15.0, 5.0]), &[]);
+    }
+
+    #[test]
+    fn clip_polygon_lifts_the_tool_outside_it() {
+        let (tool_on, tool_off) = testing::tool();
+        // A diamond, which the cut along its middle leaves at its right corner
+        let diamond = Polygon(vec![[5.0, 0.0], [10.0, 5.0], [5.0, 10.0], [0.0, 5.0]]);
+        let lines = path(&[[5.0, 5.0], [15.0, 5.0]]);
+        let mut expected = vec![rapid(5.0, 5.0)];
+        expected.extend(tool_on.clone());
+        expected.push(cut(10.0, 5.0));
+        expected.extend(tool_off.clone());
+        assert_eq!(clip_polygon(lines, &diamond, &tool_on, &tool_off), expected);
+    }
+}
@@ -0,0 +1,205 @@
+use roxmltree::{Document, Node, NodeId};
+use std::ops::Range;
+
+use crate::{
+    clip::{self, Polygon},
+    elements,
+    gcode::{self, Line},
+};
+
+/// Prefix of the ids given to the copies of clip paths converted along with the document
+const MARKER_ID: &str = "usgcode-clip-";
+
+/// Shapes a clip path can be made of, each a single closed outline
+const CLOSED_SHAPES: [&str; 5] = ["rect", "circle", "ellipse", "polygon", "path"];
+
+/// Insertions putting a copy of the clip path after every element with a `clip-path`, in the
+/// element's own coordinates, so the converter flattens it into the program's coordinates for
+/// [`apply`] to clip with.
+///
+/// Clip paths that aren't a single closed shape in user space are warned about and not applied.
+pub fn markers(xml: &str, doc: &Document) -> Vec<(Range<usize>, String)> {
+    let mut insertions = Vec::new();
+    for node in doc.descendants().filter(|node| node.is_element()) {
+        let Some(reference) =
+            elements::own_property(&node, "clip-path").filter(|value| *value != "none")
+        else {
+            continue;
+        };
+        let name = elements::node_name(&node);
+        let Some(id) = url_id(reference) else {
+            warning!(
+                "not clipping {}, its clip-path \"{}\" isn't a url(#id)",
+                name,
+                reference
+            );
+            continue;
+        };
+        let Some(clip_path) = doc
+            .descendants()
+            .find(|other| other.has_tag_name("clipPath") && other.attribute("id") == Some(id))
+        else {
+            warning!(
+                "not clipping {}, there is no clipPath with the id \"{}\"",
+                name,
+                id
+            );
+            continue;
+        };
+        let children: Vec<Node> = clip_path.children().filter(Node::is_element).collect();
+        let unsupported = match children.as_slice() {
+            _ if node.parent().is_some_and(|parent| parent.is_root()) => {
+                Some("the root element can't be clipped")
+            }
+            _ if clip_path.attribute("clipPathUnits") == Some("objectBoundingBox") => {
+                Some("clipPathUnits=\"objectBoundingBox\" isn't supported")
+            }
+            [shape] if CLOSED_SHAPES.contains(&shape.tag_name().name()) => None,
+            [_] => Some("only rect, circle, ellipse, polygon and path clip shapes are supported"),
+            _ => Some("only clip paths of a single shape are supported"),
+        };
+        if let Some(reason) = unsupported {
+            warning!("not clipping {} to #{}, {}", name, id, reason);
+            continue;
+        }
+
+        let transform = [
+            node.attribute("transform"),
+            clip_path.attribute("transform"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" ");
+        let marker = format!(
+            "<g id=\"{}{}\" transform=\"{}\">{}</g>",
+            MARKER_ID,
+            insertions.len(),
+            transform,
+            &xml[children[0].range()]
+        );
+        let end = node.range().end;
+        insertions.push((end..end, marker));
+    }
+    insertions.sort_by_key(|(range, _)| range.start);
+    insertions
+}
+
+/// Whether the document has any clip path copies from [`markers`]
+pub fn has_markers(doc: &Document) -> bool {
+    doc.descendants().any(|node| is_marker(&node))
+}
+
+/// Cuts only the portion of each element inside the clip paths of it and its ancestors,
+/// dropping the cuts of the clip paths' copies themselves
+pub fn apply(lines: Vec<Line>, doc: &Document, tool_on: &[Line], tool_off: &[Line]) -> Vec<Line> {
+    let program = elements::split_program(doc, &lines);
+    // Outline each copy was flattened into
+    let mut outlines: Vec<(NodeId, Polygon)> = Vec::new();
+    for (range, node) in program.chunks.iter() {
+        let Some(marker) = node.ancestors().find(is_marker) else {
+            continue;
+        };
+        let chunk = &lines[range.clone()];
+        let Some(subpath) = gcode::subpaths(chunk, tool_on).into_iter().next() else {
+            continue;
+        };
+        let points = subpath
+            .iter()
+            .filter_map(|&i| Some([chunk[i].value("X")?, chunk[i].value("Y")?]))
+            .collect();
+        outlines.push((marker.id(), Polygon(points)));
+    }
+
+    let mut output = lines[program.prefix.clone()].to_vec();
+    for (range, node) in program.chunks.iter() {
+        if node.ancestors().any(|ancestor| is_marker(&ancestor)) {
+            continue;
+        }
+        let mut chunk = lines[range.clone()].to_vec();
+        let clipped = node
+            .ancestors()
+            .filter(|ancestor| elements::own_property(ancestor, "clip-path").is_some())
+            .filter_map(|ancestor| ancestor.next_sibling_element())
+            .filter(is_marker);
+        for marker in clipped {
+            if let Some((_, polygon)) = outlines.iter().find(|(id, _)| *id == marker.id()) {
+                chunk = clip::clip_polygon(chunk, polygon, tool_on, tool_off);
+            }
+        }
+        output.extend(chunk);
+    }
+    output.extend_from_slice(&lines[program.suffix]);
+    output
+}
+
+/// Whether the element is a clip path copy from [`markers`]
+pub fn is_marker(node: &Node) -> bool {
+    node.attribute("id")
+        .is_some_and(|id| id.starts_with(MARKER_ID))
+}
+
+/// The id in a `url(#id)` reference
+fn url_id(reference: &str) -> Option<&str> {
+    let inner = reference.trim().strip_prefix("url(")?.strip_suffix(')')?;
+    inner
+        .trim()
+        .trim_matches(|c| c == '"' || c == '\'')
+        .strip_prefix('#')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preprocess, testing};
+
+    /// Where the program's cuts go, smallest and largest
+    fn cut_bounds(lines: &[Line]) -> [[f64; 2]; 2] {
+        let cuts: Vec<Line> = lines
+            .iter()
+            .filter(|line| line.is("G", 1))
+            .cloned()
+            .collect();
+        crate::postprocess::bounds(&cuts).unwrap()
+    }
+
+    #[test]
+    fn a_circle_clipped_to_a_square_keeps_only_its_inside() {
+        let square = r#"<rect x="40" y="40" width="20" height="20"/>"#;
+        let svg = |body: &str| {
+            format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">{}</svg>"#,
+                body
+            )
+        };
+        let square_bounds = cut_bounds(&testing::program(&Document::parse(&svg(square)).unwrap()));
+
+        let xml = svg(&format!(
+            r#"<clipPath id="square">{}</clipPath><circle cx="50" cy="50" r="12" clip-path="url(#square)"/>"#,
+            square
+        ));
+        let doc = Document::parse(&xml).unwrap();
+        let xml = preprocess::replace_ranges(&xml, &markers(&xml, &doc));
+        let doc = Document::parse(&xml).unwrap();
+        assert!(has_markers(&doc));
+        let (tool_on, tool_off) = testing::tool();
+        let lines = testing::program(&doc);
+        let circle_bounds = cut_bounds(&lines);
+        let clipped = apply(lines, &doc, &tool_on, &tool_off);
+
+        // The circle reaches past the square's sides, but its corners are outside the circle
+        let clipped_bounds = cut_bounds(&clipped);
+        for axis in 0..2 {
+            assert!(circle_bounds[0][axis] < square_bounds[0][axis] - 1.0);
+            assert!(circle_bounds[1][axis] > square_bounds[1][axis] + 1.0);
+            assert!(clipped_bounds[0][axis] >= square_bounds[0][axis] - 1e-6);
+            assert!(clipped_bounds[1][axis] <= square_bounds[1][axis] + 1e-6);
+        }
+        // Only the four arcs of the circle across the square's corners are cut, one of them in
+        // two when the circle starts partway along it
+        let plunges = (0..clipped.len())
+            .filter(|&i| clipped[i..].starts_with(&tool_on))
+            .count();
+        assert!((4..=5).contains(&plunges), "{} plunges", plunges);
+    }
+}
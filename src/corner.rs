@@ -0,0 +1,278 @@
+use std::str::FromStr;
+
+use crate::gcode::Line;
+
+/// A `--corner-slowdown` argument, e.g. `90:0.5` to halve the feedrate around corners sharper
+/// than 90 degrees
+#[derive(Debug, Clone, Copy)]
+pub struct Slowdown {
+    /// Included angle in degrees below which a corner is slowed for, 180 being a straight line
+    pub angle: f64,
+    /// Fraction of the feedrate used near the corner
+    pub factor: f64,
+}
+
+impl FromStr for Slowdown {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (angle, factor) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected <angle>:<factor> such as 90:0.5, got \"{}\"", s))?;
+        let angle = match angle.trim().parse::<f64>() {
+            Ok(angle) if angle > 0.0 && angle <= 180.0 => angle,
+            _ => {
+                return Err(format!(
+                    "angle must be a number of degrees from 0 to 180, got \"{}\"",
+                    angle
+                ))
+            }
+        };
+        let factor = match factor.trim().parse::<f64>() {
+            Ok(factor) if factor > 0.0 && factor <= 1.0 => factor,
+            _ => {
+                return Err(format!(
+                    "factor must be greater than 0 and at most 1, got \"{}\"",
+                    factor
+                ))
+            }
+        };
+        Ok(Slowdown { angle, factor })
+    }
+}
+
+/// Slows the feedrate for `distance` millimeters either side of sharp corners between cuts.
+///
+/// Cuts running into or out of a corner are split where the slowdown starts or ends, so the
+/// path itself is unchanged, and the full feedrate is set again on the move after the corner.
+pub fn slow_corners(lines: Vec<Line>, slowdown: &Slowdown, distance: f64) -> Vec<Line> {
+    let segments = segments(&lines);
+    let sharp: Vec<bool> = (0..segments.len())
+        .map(
+            |i| match (segments[i], segments.get(i + 1).copied().flatten()) {
+                (Some((from, corner)), Some((_, to))) => {
+                    included_angle(from, corner, to).is_some_and(|angle| angle < slowdown.angle)
+                }
+                _ => false,
+            },
+        )
+        .collect();
+
+    let mut output = Vec::with_capacity(lines.len());
+    // Feedrate of the program as written, and whether the output was left slower than it
+    let mut feed = None;
+    let mut reduced = false;
+    for (i, mut line) in lines.into_iter().enumerate() {
+        feed = line.value("F").or(feed);
+        let slow_start = i > 0 && sharp[i - 1];
+        let slow_end = sharp[i];
+        let (Some((from, to)), Some(full), true) = (segments[i], feed, slow_start || slow_end)
+        else {
+            let cutting = line.is("G", 1) || line.is("G", 2) || line.is("G", 3);
+            if let Some(full) = feed.filter(|_| reduced && cutting && line.value("F").is_none()) {
+                line.set_value("F", full);
+            }
+            reduced &= !cutting;
+            output.push(line);
+            continue;
+        };
+
+        let length = (to[0] - from[0]).hypot(to[1] - from[1]);
+        let split = distance.min(length / 2.0) / length;
+        let slow = full * slowdown.factor;
+        // End of each piece along the cut, as a fraction of its length, and its feedrate
+        let mut pieces = Vec::with_capacity(3);
+        if slow_start {
+            pieces.push((split, slow));
+        }
+        match slow_end {
+            true => pieces.extend([(1.0 - split, full), (1.0, slow)]),
+            false => pieces.push((1.0, full)),
+        }
+        let mut previous = 0.0;
+        for (t, piece_feed) in pieces {
+            if t - previous <= 1e-9 {
+                continue;
+            }
+            previous = t;
+            let mut piece = line.clone();
+            piece.set_value("X", from[0] + (to[0] - from[0]) * t);
+            piece.set_value("Y", from[1] + (to[1] - from[1]) * t);
+            piece.set_value("F", piece_feed);
+            reduced = piece_feed < full;
+            output.push(piece);
+        }
+    }
+    output
+}
+
+/// Start and end of every line that is a straight cut in the XY plane
+fn segments(lines: &[Line]) -> Vec<Option<([f64; 2], [f64; 2])>> {
+    let mut position = [None, None];
+    lines
+        .iter()
+        .map(|line| {
+            let from = position;
+            for (axis, letter) in ["X", "Y"].into_iter().enumerate() {
+                if let Some(value) = line.value(letter) {
+                    position[axis] = Some(value);
+                }
+            }
+            match (line.is("G", 1), from, line.value("X"), line.value("Y")) {
+                (true, [Some(x), Some(y)], Some(to_x), Some(to_y)) => Some(([x, y], [to_x, to_y])),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Angle in degrees between the cut arriving at `corner` and the one leaving it, if both have a length
+fn included_angle(from: [f64; 2], corner: [f64; 2], to: [f64; 2]) -> Option<f64> {
+    let back = [from[0] - corner[0], from[1] - corner[1]];
+    let forward = [to[0] - corner[0], to[1] - corner[1]];
+    let lengths = back[0].hypot(back[1]) * forward[0].hypot(forward[1]);
+    if lengths <= 1e-12 {
+        return None;
+    }
+    let cos = (back[0] * forward[0] + back[1] * forward[1]) / lengths;
+    Some(cos.clamp(-1.0, 1.0).acos().to_degrees())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HALF_UNDER_120: Slowdown = Slowdown {
+        angle: 120.0,
+        factor: 0.5,
+    };
+
+    fn cut(x: f64, y: f64, feed: Option<f64>) -> Line {
+        let mut words = vec![("X", x), ("Y", y)];
+        words.extend(feed.map(|feed| ("F", feed)));
+        Line::command("G", 1, &words)
+    }
+
+    /// A travel to the first point and cuts at 1000 mm/min through the rest
+    fn path(points: &[[f64; 2]]) -> Vec<Line> {
+        let mut lines = vec![Line::command(
+            "G",
+            0,
+            &[("X", points[0][0]), ("Y", points[0][1])],
+        )];
+        for (i, point) in points[1..].iter().enumerate() {
+            lines.push(cut(point[0], point[1], (i == 0).then_some(1000.0)));
+        }
+        lines
+    }
+
+    #[test]
+    fn slowdown_parses_angle_and_factor() {
+        let slowdown = Slowdown::from_str("90:0.5").unwrap();
+        assert_eq!((slowdown.angle, slowdown.factor), (90.0, 0.5));
+        for bad in ["90", "0:0.5", "181:0.5", "90:0", "90:1.5", "sharp:0.5"] {
+            assert!(Slowdown::from_str(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn included_angle_between_cuts() {
+        assert_eq!(
+            included_angle([0.0, 0.0], [10.0, 0.0], [10.0, 10.0]),
+            Some(90.0)
+        );
+        assert_eq!(
+            included_angle([0.0, 0.0], [10.0, 0.0], [20.0, 0.0]),
+            Some(180.0)
+        );
+        assert_eq!(
+            included_angle([0.0, 0.0], [10.0, 0.0], [0.0, 0.0]),
+            Some(0.0)
+        );
+        assert_eq!(included_angle([10.0, 0.0], [10.0, 0.0], [20.0, 0.0]), None);
+    }
+
+    #[test]
+    fn slow_corners_slows_either_side_of_a_corner() {
+        let lines = path(&[[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [20.0, 10.0]]);
+        let expected = vec![
+            lines[0].clone(),
+            cut(8.0, 0.0, Some(1000.0)),
+            cut(10.0, 0.0, Some(500.0)),
+            cut(10.0, 2.0, Some(500.0)),
+            cut(10.0, 8.0, Some(1000.0)),
+            cut(10.0, 10.0, Some(500.0)),
+            cut(12.0, 10.0, Some(500.0)),
+            cut(20.0, 10.0, Some(1000.0)),
+        ];
+        assert_eq!(slow_corners(lines, &HALF_UNDER_120, 2.0), expected);
+    }
+
+    #[test]
+    fn slow_corners_leaves_corners_at_the_angle() {
+        let lines = path(&[[0.0, 0.0], [10.0, 0.0], [10.0, 10.0]]);
+        let slowdown = Slowdown {
+            angle: 90.0,
+            factor: 0.5,
+        };
+        assert_eq!(slow_corners(lines.clone(), &slowdown, 2.0), lines);
+    }
+
+    #[test]
+    fn slow_corners_leaves_straight_cuts_and_travels() {
+        let straight = path(&[[0.0, 0.0], [10.0, 0.0], [20.0, 0.0]]);
+        assert_eq!(
+            slow_corners(straight.clone(), &HALF_UNDER_120, 2.0),
+            straight
+        );
+
+        let travels = vec![
+            Line::command("G", 0, &[("X", 0.0), ("Y", 0.0)]),
+            Line::command("G", 0, &[("X", 10.0), ("Y", 0.0)]),
+            Line::command("G", 0, &[("X", 10.0), ("Y", 10.0)]),
+        ];
+        assert_eq!(slow_corners(travels.clone(), &HALF_UNDER_120, 2.0), travels);
+    }
+
+    #[test]
+    fn slow_corners_slows_all_of_a_short_cut_between_corners() {
+        // The 2 mm cut is within the slowdown of both its corners
+        let lines = path(&[[0.0, 0.0], [10.0, 0.0], [10.0, 2.0], [20.0, 2.0]]);
+        let expected = vec![
+            lines[0].clone(),
+            cut(8.0, 0.0, Some(1000.0)),
+            cut(10.0, 0.0, Some(500.0)),
+            cut(10.0, 1.0, Some(500.0)),
+            cut(10.0, 2.0, Some(500.0)),
+            cut(12.0, 2.0, Some(500.0)),
+            cut(20.0, 2.0, Some(1000.0)),
+        ];
+        assert_eq!(slow_corners(lines, &HALF_UNDER_120, 2.0), expected);
+    }
+
+    #[test]
+    fn slow_corners_keeps_the_path_geometry() {
+        let corners = [
+            [0.0, 0.0],
+            [10.0, 0.0],
+            [10.0, 10.0],
+            [0.0, 10.0],
+            [0.0, 0.0],
+        ];
+        let slowed = slow_corners(path(&corners), &HALF_UNDER_120, 2.0);
+
+        // Every cut ends along the path, passing through each corner in turn
+        let mut corner = 1;
+        for line in &slowed[1..] {
+            let point = [line.value("X").unwrap(), line.value("Y").unwrap()];
+            let [from, to] = [corners[corner - 1], corners[corner]];
+            let cross =
+                (to[0] - from[0]) * (point[1] - from[1]) - (to[1] - from[1]) * (point[0] - from[0]);
+            assert!(cross.abs() < 1e-9, "{:?} is off the path", point);
+            if point == to {
+                corner += 1;
+            }
+        }
+        assert_eq!(corner, corners.len());
+    }
+}
@@ -0,0 +1,421 @@
+use roxmltree::Document;
+use std::str::FromStr;
+use svgtypes::Length;
+
+use crate::{
+    elements::{self, length_to_user_units},
+    gcode::Line,
+};
+
+/// Settings for turning `stroke-dasharray` into dashed cuts
+pub struct DashOptions<'a> {
+    pub dimensions: &'a [Option<Length>; 2],
+    pub dpi: f64,
+    /// Dashes and gaps shorter than this (in mm) are merged into their neighbours
+    pub min_dash: f64,
+    pub tool_on: &'a [Line],
+    pub tool_off: &'a [Line],
+}
+
+/// Splits the cuts of elements with a `stroke-dasharray` into dashes, lifting the tool for gaps
+pub fn apply_dasharray(lines: Vec<Line>, doc: &Document, options: &DashOptions) -> Vec<Line> {
+    let mut dashed = Vec::new();
+    for (range, node) in elements::chunks(doc, &lines) {
+        let Some(dasharray) = elements::inherited_property(&node, "stroke-dasharray") else {
+            continue;
+        };
+        let Some(pattern) = parse_dasharray(dasharray) else {
+            if dasharray != "none" {
+                warning!(
+                    "ignoring invalid stroke-dasharray \"{}\" on <{}>",
+                    dasharray,
+                    elements::node_name(&node)
+                );
+            }
+            continue;
+        };
+        let scale = elements::user_unit_scale(&node, options.dimensions, options.dpi);
+        let offset = elements::inherited_property(&node, "stroke-dashoffset")
+            .and_then(|offset| Length::from_str(offset).ok())
+            .map_or(0.0, length_to_user_units);
+        let pattern: Vec<f64> = pattern.iter().map(|length| length * scale).collect();
+        if let Some(pattern) = merge_short_spans(pattern, options.min_dash) {
+            dashed.push((range, pattern, offset * scale));
+        }
+    }
+    if dashed.is_empty() {
+        return lines;
+    }
+
+    let mut output = Vec::with_capacity(lines.len());
+    let mut cursor = 0;
+    for (range, pattern, offset) in dashed {
+        output.extend_from_slice(&lines[cursor..range.start]);
+        output.extend(dash_chunk(&lines[range.clone()], &pattern, offset, options));
+        cursor = range.end;
+    }
+    output.extend_from_slice(&lines[cursor..]);
+    output
+}
+
+/// Parses a dash array into user units, repeating odd-length lists as the spec requires
+fn parse_dasharray(dasharray: &str) -> Option<Vec<f64>> {
+    let mut pattern = dasharray
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|length| !length.is_empty())
+        .map(|length| Length::from_str(length).ok().map(length_to_user_units))
+        .collect::<Option<Vec<f64>>>()?;
+    if pattern.is_empty()
+        || pattern
+            .iter()
+            .any(|length| *length < 0.0 || !length.is_finite())
+        || pattern.iter().sum::<f64>() <= 0.0
+    {
+        return None;
+    }
+    if pattern.len() % 2 == 1 {
+        pattern.extend(pattern.clone());
+    }
+    Some(pattern)
+}
+
+/// Folds spans shorter than `min` into the surrounding spans of the other kind.
+///
+/// Returns `None` if everything merges into one solid cut.
+fn merge_short_spans(pattern: Vec<f64>, min: f64) -> Option<Vec<f64>> {
+    let mut merged: Vec<f64> = Vec::with_capacity(pattern.len());
+    let mut absorb_next = false;
+    for span in pattern {
+        match merged.last_mut() {
+            Some(last) if span < min || absorb_next => {
+                *last += span;
+                absorb_next = span < min && !absorb_next;
+            }
+            _ => merged.push(span),
+        }
+    }
+    if merged.len() % 2 == 1 {
+        // An unpaired trailing dash runs straight into the first dash of the next repetition
+        merged.push(0.0);
+    }
+    match merged.iter().skip(1).step_by(2).all(|gap| *gap == 0.0) {
+        true => None,
+        false => Some(merged),
+    }
+}
+
+/// Position within a dash pattern
+struct Phase<'a> {
+    pattern: &'a [f64],
+    index: usize,
+    remaining: f64,
+}
+
+impl<'a> Phase<'a> {
+    fn new(pattern: &'a [f64], offset: f64) -> Self {
+        let total: f64 = pattern.iter().sum();
+        let mut distance = offset.rem_euclid(total);
+        let mut index = 0;
+        while distance >= pattern[index] {
+            distance -= pattern[index];
+            index = (index + 1) % pattern.len();
+        }
+        Phase {
+            pattern,
+            index,
+            remaining: pattern[index] - distance,
+        }
+    }
+
+    fn is_dash(&self) -> bool {
+        self.index.is_multiple_of(2)
+    }
+
+    fn advance(&mut self) {
+        self.index = (self.index + 1) % self.pattern.len();
+        self.remaining = self.pattern[self.index];
+    }
+}
+
+fn dash_chunk(lines: &[Line], pattern: &[f64], offset: f64, options: &DashOptions) -> Vec<Line> {
+    let mut output = Vec::with_capacity(lines.len());
+    let mut position = [0.0, 0.0];
+    let mut phase = Phase::new(pattern, offset);
+    // Set while the tool has been lifted for a gap, until the next dash starts
+    let mut lifted = false;
+    let mut skip = 0;
+
+    for (i, line) in lines.iter().enumerate() {
+        if skip > 0 {
+            skip -= 1;
+            continue;
+        }
+        if lines[i..].starts_with(options.tool_on) {
+            // Each subpath starts the pattern afresh
+            phase = Phase::new(pattern, offset);
+            lifted = false;
+        } else if lines[i..].starts_with(options.tool_off) && lifted {
+            // Already lifted for a gap
+            skip = options.tool_off.len() - 1;
+            continue;
+        }
+
+        let target = [line.value("X"), line.value("Y")];
+        if !line.is("G", 1) || target == [None, None] {
+            position = [
+                target[0].unwrap_or(position[0]),
+                target[1].unwrap_or(position[1]),
+            ];
+            output.push(line.clone());
+            continue;
+        }
+        let target = [
+            target[0].unwrap_or(position[0]),
+            target[1].unwrap_or(position[1]),
+        ];
+        let feedrate = line.value("F");
+
+        let length = (target[0] - position[0]).hypot(target[1] - position[1]);
+        let mut travelled = 0.0;
+        while travelled < length {
+            let step = phase.remaining.min(length - travelled);
+            travelled += step;
+            phase.remaining -= step;
+            let t = travelled / length;
+            let point = [
+                position[0] + (target[0] - position[0]) * t,
+                position[1] + (target[1] - position[1]) * t,
+            ];
+            if step < 1e-9 {
+                // Zero-length span or floating point leftovers, nothing to emit
+            } else if phase.is_dash() {
+                if lifted {
+                    output.extend_from_slice(options.tool_on);
+                    lifted = false;
+                }
+                let mut words = vec![("X", point[0]), ("Y", point[1])];
+                words.extend(feedrate.map(|feedrate| ("F", feedrate)));
+                output.push(Line::command("G", 1, &words));
+            } else {
+                if !lifted {
+                    output.extend_from_slice(options.tool_off);
+                    lifted = true;
+                } else if output
+                    .last()
+                    .is_some_and(|last| last.is("G", 0) && last.value("X").is_some())
+                {
+                    // Gaps spanning several segments need only one travel
+                    output.pop();
+                }
+                output.push(Line::command("G", 0, &[("X", point[0]), ("Y", point[1])]));
+            }
+            if phase.remaining <= 0.0 {
+                phase.advance();
+            }
+        }
+        position = target;
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use Step::*;
+
+    fn rapid(x: f64) -> Line {
+        Line::command("G", 0, &[("X", x), ("Y", 0.0)])
+    }
+
+    fn cut(x: f64) -> Line {
+        Line::command("G", 1, &[("X", x), ("Y", 0.0), ("F", 1000.0)])
+    }
+
+    /// Dashes a path from the origin cutting along the X axis through each of `xs`
+    fn dashed(xs: &[f64], pattern: &[f64], offset: f64) -> Vec<Line> {
+        let (tool_on, tool_off) = testing::tool();
+        let mut lines = vec![rapid(0.0)];
+        lines.extend(tool_on.clone());
+        lines.extend(xs.iter().map(|&x| cut(x)));
+        lines.extend(tool_off.clone());
+        let options = DashOptions {
+            dimensions: &[None, None],
+            dpi: 96.0,
+            min_dash: 0.0,
+            tool_on: &tool_on,
+            tool_off: &tool_off,
+        };
+        dash_chunk(&lines, pattern, offset, &options)
+    }
+
+    #[derive(Clone, Copy)]
+    enum Step {
+        On,
+        Off,
+        Rapid(f64),
+        Cut(f64),
+    }
+
+    /// What a dashed path should come out as, with the tool sequences written out
+    fn expected(steps: &[Step]) -> Vec<Line> {
+        let (tool_on, tool_off) = testing::tool();
+        let mut lines = Vec::new();
+        for step in steps {
+            match *step {
+                Step::On => lines.extend(tool_on.clone()),
+                Step::Off => lines.extend(tool_off.clone()),
+                Step::Rapid(x) => lines.push(rapid(x)),
+                Step::Cut(x) => lines.push(cut(x)),
+            }
+        }
+        lines
+    }
+
+    #[test]
+    fn parse_dasharray_repeats_odd_length_lists() {
+        assert_eq!(parse_dasharray("5"), Some(vec![5.0, 5.0]));
+        assert_eq!(
+            parse_dasharray("1, 2 3"),
+            Some(vec![1.0, 2.0, 3.0, 1.0, 2.0, 3.0])
+        );
+        assert_eq!(parse_dasharray("4 2"), Some(vec![4.0, 2.0]));
+    }
+
+    #[test]
+    fn parse_dasharray_refuses_lists_without_length() {
+        assert_eq!(parse_dasharray("0"), None);
+        assert_eq!(parse_dasharray("0 0 0"), None);
+        assert_eq!(parse_dasharray(""), None);
+        assert_eq!(parse_dasharray("none"), None);
+        assert_eq!(parse_dasharray("4 -2"), None);
+    }
+
+    #[test]
+    fn merge_short_spans_folds_them_into_their_neighbours() {
+        assert_eq!(
+            merge_short_spans(vec![4.0, 0.5, 3.0, 2.0], 1.0),
+            Some(vec![7.5, 2.0])
+        );
+        // The short gap takes the dash after it along
+        assert_eq!(
+            merge_short_spans(vec![4.0, 2.0, 3.0, 0.5], 1.0),
+            Some(vec![4.0, 2.0, 3.5, 0.0])
+        );
+        assert_eq!(merge_short_spans(vec![4.0, 0.5], 1.0), None);
+    }
+
+    #[test]
+    fn phase_starts_at_the_offset() {
+        let pattern = [4.0, 2.0];
+        let phase = Phase::new(&pattern, 5.0);
+        assert!(!phase.is_dash());
+        assert_eq!(phase.remaining, 1.0);
+        // Offsets wrap around the pattern, either way
+        let phase = Phase::new(&pattern, -1.0);
+        assert!(!phase.is_dash());
+        assert_eq!(phase.remaining, 1.0);
+    }
+
+    #[test]
+    fn dashes_carry_on_across_segments() {
+        assert_eq!(
+            dashed(&[3.0, 9.0], &[4.0, 2.0], 0.0),
+            expected(&[
+                Rapid(0.0),
+                On,
+                Cut(3.0),
+                Cut(4.0),
+                Off,
+                Rapid(6.0),
+                On,
+                Cut(9.0),
+                Off
+            ])
+        );
+    }
+
+    #[test]
+    fn gaps_across_segments_need_one_travel() {
+        assert_eq!(
+            dashed(&[3.0, 9.0], &[1.0, 4.0], 0.0),
+            expected(&[
+                Rapid(0.0),
+                On,
+                Cut(1.0),
+                Off,
+                Rapid(5.0),
+                On,
+                Cut(6.0),
+                Off,
+                Rapid(9.0)
+            ])
+        );
+    }
+
+    #[test]
+    fn odd_length_dash_arrays_alternate_dash_and_gap() {
+        // 2 1 3 repeats as 2 1 3 2 1 3, so the 3 is a dash and then a gap
+        let pattern = parse_dasharray("2 1 3").unwrap();
+        assert_eq!(
+            dashed(&[9.0], &pattern, 0.0),
+            expected(&[
+                Rapid(0.0),
+                On,
+                Cut(2.0),
+                Off,
+                Rapid(3.0),
+                On,
+                Cut(6.0),
+                Off,
+                Rapid(8.0),
+                On,
+                Cut(9.0),
+                Off
+            ])
+        );
+    }
+
+    #[test]
+    fn a_dashed_line_is_cut_in_dashes_of_the_pattern() {
+        let doc = Document::parse(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">
+                <path d="M 0 50 L 100 50" stroke-dasharray="10 5"/>
+            </svg>"#,
+        )
+        .unwrap();
+        let (tool_on, tool_off) = testing::tool();
+        let dpi = crate::settings::Settings::default().conversion.dpi;
+        let lines = apply_dasharray(
+            testing::program(&doc),
+            &doc,
+            &DashOptions {
+                dimensions: &crate::dimensions(&doc, 1.0),
+                dpi,
+                min_dash: 0.0,
+                tool_on: &tool_on,
+                tool_off: &tool_off,
+            },
+        );
+
+        let point = |i: usize| [lines[i].value("X").unwrap(), lines[i].value("Y").unwrap()];
+        let lengths: Vec<f64> = crate::gcode::subpaths(&lines, &tool_on)
+            .iter()
+            .map(|subpath| {
+                subpath
+                    .windows(2)
+                    .map(|pair| {
+                        let (from, to) = (point(pair[0]), point(pair[1]));
+                        (to[0] - from[0]).hypot(to[1] - from[1])
+                    })
+                    .sum()
+            })
+            .collect();
+        // 10 on and 5 off along 100 units leaves the last dash ending at the line's end, every
+        // dash scaled like the line by the converter's 96 / dpi
+        assert_eq!(lengths.len(), 7, "{:?}", lengths);
+        for &length in &lengths {
+            assert!((length - 10.0 * 96.0 / dpi).abs() < 1e-6, "{:?}", lengths);
+        }
+    }
+}
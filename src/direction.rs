@@ -0,0 +1,202 @@
+use roxmltree::Document;
+
+use crate::{
+    elements,
+    gcode::{self, Line},
+};
+
+/// Direction closed paths are cut in, for milling with a clockwise (`M3`) spindle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Milling {
+    /// Outside profiles clockwise and holes counterclockwise, the tool on the left of the cut
+    Climb,
+    /// Outside profiles counterclockwise and holes clockwise, the tool on the right of the cut
+    Conventional,
+}
+
+/// Cuts every subpath from its end to its start
+pub fn reverse_paths(mut lines: Vec<Line>, tool_on: &[Line]) -> Vec<Line> {
+    for subpath in gcode::subpaths(&lines, tool_on) {
+        reverse(&mut lines, &subpath);
+    }
+    lines
+}
+
+/// Cuts the elements in the opposite order
+pub fn reverse_order(lines: Vec<Line>, doc: &Document) -> Vec<Line> {
+    let program = elements::split_program(doc, &lines);
+    let mut output = lines[program.prefix].to_vec();
+    for (range, _) in program.chunks.into_iter().rev() {
+        output.extend_from_slice(&lines[range]);
+    }
+    output.extend_from_slice(&lines[program.suffix]);
+    output
+}
+
+/// Reverses closed subpaths that run against the milling direction.
+///
+/// A subpath inside an odd number of other closed subpaths is a hole and is cut the opposite
+/// way to an outside profile. Open subpaths have no inside and are left as they are.
+pub fn orient(mut lines: Vec<Line>, tool_on: &[Line], milling: Milling) -> Vec<Line> {
+    let point = |line: &Line| [line.value("X").unwrap(), line.value("Y").unwrap()];
+    let closed: Vec<(Vec<usize>, Vec<[f64; 2]>)> = gcode::subpaths(&lines, tool_on)
+        .into_iter()
+        .map(|subpath| {
+            let points = subpath.iter().map(|&i| point(&lines[i])).collect();
+            (subpath, points)
+        })
+        .filter(|(_, points): &(_, Vec<[f64; 2]>)| {
+            points.len() > 3 && {
+                let (first, last) = (points[0], points[points.len() - 1]);
+                (last[0] - first[0]).hypot(last[1] - first[1]) <= 1e-6
+            }
+        })
+        .collect();
+
+    let backwards: Vec<&Vec<usize>> = closed
+        .iter()
+        .enumerate()
+        .filter(|(i, (_, points))| {
+            let depth = closed
+                .iter()
+                .enumerate()
+                .filter(|(j, (_, other))| j != i && contains(other, points[0]))
+                .count();
+            let hole = depth % 2 == 1;
+            let clockwise = signed_area(points) < 0.0;
+            clockwise != ((milling == Milling::Climb) != hole)
+        })
+        .map(|(_, (subpath, _))| subpath)
+        .collect();
+    for subpath in backwards {
+        reverse(&mut lines, subpath);
+    }
+    lines
+}
+
+/// Reverses a subpath in place, each cut retracing an original one and keeping its other words
+fn reverse(lines: &mut [Line], subpath: &[usize]) {
+    let point = |line: &Line| [line.value("X").unwrap(), line.value("Y").unwrap()];
+    let points: Vec<[f64; 2]> = subpath.iter().map(|&i| point(&lines[i])).collect();
+    let cuts: Vec<Line> = subpath[1..].iter().map(|&i| lines[i].clone()).collect();
+    let n = cuts.len();
+
+    let travel = &mut lines[subpath[0]];
+    travel.set_value("X", points[n][0]);
+    travel.set_value("Y", points[n][1]);
+    for (k, &i) in subpath[1..].iter().enumerate() {
+        let mut cut = cuts[n - 1 - k].clone();
+        cut.set_value("X", points[n - 1 - k][0]);
+        cut.set_value("Y", points[n - 1 - k][1]);
+        lines[i] = cut;
+    }
+}
+
+/// Twice the area enclosed by the points, positive when they run counterclockwise
+fn signed_area(points: &[[f64; 2]]) -> f64 {
+    (0..points.len())
+        .map(|i| {
+            let (a, b) = (points[i], points[(i + 1) % points.len()]);
+            a[0] * b[1] - b[0] * a[1]
+        })
+        .sum()
+}
+
+/// Whether the point is inside the polygon, by counting edge crossings
+fn contains(polygon: &[[f64; 2]], point: [f64; 2]) -> bool {
+    let mut inside = false;
+    for i in 0..polygon.len() {
+        let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+        if (a[1] > point[1]) != (b[1] > point[1])
+            && point[0] < a[0] + (point[1] - a[1]) / (b[1] - a[1]) * (b[0] - a[0])
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /// A program cutting each path from its first point through the others
+    fn program(paths: &[&[[f64; 2]]], tool_on: &[Line], tool_off: &[Line]) -> Vec<Line> {
+        let mut lines = Vec::new();
+        for points in paths {
+            let [x, y] = points[0];
+            lines.push(Line::command("G", 0, &[("X", x), ("Y", y)]));
+            lines.extend(tool_on.iter().cloned());
+            for &[x, y] in &points[1..] {
+                lines.push(Line::command("G", 1, &[("X", x), ("Y", y), ("F", 300.0)]));
+            }
+            lines.extend(tool_off.iter().cloned());
+        }
+        lines
+    }
+
+    /// The points each path's travel and cuts go to, in order
+    fn points(lines: &[Line], tool_on: &[Line]) -> Vec<Vec<[f64; 2]>> {
+        gcode::subpaths(lines, tool_on)
+            .into_iter()
+            .map(|subpath| {
+                subpath
+                    .into_iter()
+                    .map(|i| [lines[i].value("X").unwrap(), lines[i].value("Y").unwrap()])
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// A 10 by 10 square, counterclockwise from the origin
+    const SQUARE: [[f64; 2]; 5] = [
+        [0.0, 0.0],
+        [10.0, 0.0],
+        [10.0, 10.0],
+        [0.0, 10.0],
+        [0.0, 0.0],
+    ];
+    /// A 2 by 2 square inside it, also counterclockwise
+    const HOLE: [[f64; 2]; 5] = [[4.0, 4.0], [6.0, 4.0], [6.0, 6.0], [4.0, 6.0], [4.0, 4.0]];
+
+    #[test]
+    fn reverse_paths_cuts_each_path_from_its_end() {
+        let (tool_on, tool_off) = testing::tool();
+        let line = [[0.0, 0.0], [5.0, 0.0], [5.0, 5.0]];
+        let lines = program(&[&line, &SQUARE], &tool_on, &tool_off);
+        let reversed = reverse_paths(lines.clone(), &tool_on);
+        let mut expected = vec![line.to_vec(), SQUARE.to_vec()];
+        expected.iter_mut().for_each(|points| points.reverse());
+        assert_eq!(points(&reversed, &tool_on), expected);
+        // Each cut keeps the feedrate it had
+        assert_eq!(reversed.len(), lines.len());
+        assert!(reversed
+            .iter()
+            .filter(|line| line.is("G", 1) && line.value("X").is_some())
+            .all(|line| line.value("F") == Some(300.0)));
+    }
+
+    #[test]
+    fn climb_milling_cuts_profiles_clockwise_and_holes_counterclockwise() {
+        let (tool_on, tool_off) = testing::tool();
+        let lines = program(&[&SQUARE, &HOLE], &tool_on, &tool_off);
+        let oriented = orient(lines, &tool_on, Milling::Climb);
+        let paths = points(&oriented, &tool_on);
+        assert!(signed_area(&paths[0]) < 0.0);
+        assert_eq!(paths[1], HOLE);
+    }
+
+    #[test]
+    fn conventional_milling_cuts_profiles_counterclockwise_and_holes_clockwise() {
+        let (tool_on, tool_off) = testing::tool();
+        let line = [[20.0, 0.0], [30.0, 0.0]];
+        let lines = program(&[&SQUARE, &HOLE, &line], &tool_on, &tool_off);
+        let oriented = orient(lines, &tool_on, Milling::Conventional);
+        let paths = points(&oriented, &tool_on);
+        assert_eq!(paths[0], SQUARE);
+        assert!(signed_area(&paths[1]) < 0.0);
+        // Open paths have no inside to keep the tool on a side of
+        assert_eq!(paths[2], line);
+    }
+}
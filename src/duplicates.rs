@@ -0,0 +1,83 @@
+use roxmltree::Document;
+use std::collections::HashMap;
+
+use crate::{elements, gcode::Line, preprocess};
+
+/// Coordinates closer than this many millimeters count as the same when comparing paths
+const SAME: f64 = 1e-3;
+
+/// Finds elements that cut exactly the same moves as an earlier one, in either direction,
+/// printing each to stderr when `report` is set and dropping them all but the first when
+/// `remove` is.
+pub fn duplicate_paths(lines: Vec<Line>, doc: &Document, report: bool, remove: bool) -> Vec<Line> {
+    let program = elements::split_program(doc, &lines);
+    // First element cutting each normalised sequence of points
+    let mut seen = HashMap::new();
+    let mut duplicates = Vec::new();
+    for (index, (range, _)) in program.chunks.iter().enumerate() {
+        let Some(key) = key(&lines[range.clone()]) else {
+            continue;
+        };
+        match seen.get(&key) {
+            Some(&original) => duplicates.push((index, original)),
+            None => {
+                seen.insert(key, index);
+            }
+        }
+    }
+    if report {
+        for &(duplicate, original) in duplicates.iter() {
+            eprintln!(
+                "Duplicate path: {} repeats {}",
+                preprocess::describe(doc, &program.chunks[duplicate].1),
+                preprocess::describe(doc, &program.chunks[original].1)
+            );
+        }
+    }
+    if !remove || duplicates.is_empty() {
+        return lines;
+    }
+    if !report {
+        warning!("removing {} duplicate paths", duplicates.len());
+    }
+
+    let mut output = lines[program.prefix.clone()].to_vec();
+    for (index, (range, _)) in program.chunks.iter().enumerate() {
+        if !duplicates.iter().any(|&(duplicate, _)| duplicate == index) {
+            output.extend_from_slice(&lines[range.clone()]);
+        }
+    }
+    output.extend_from_slice(&lines[program.suffix]);
+    output
+}
+
+/// The points an element's lines travel to and cut through, rounded, and the same whichever
+/// way round they are cut. `None` when it cuts nothing.
+fn key(lines: &[Line]) -> Option<Vec<[i64; 2]>> {
+    let mut position = [None, None];
+    let mut points = Vec::new();
+    let mut cuts = false;
+    for line in lines {
+        if !(0..=3).any(|number| line.is("G", number)) {
+            continue;
+        }
+        position = [
+            line.value("X").or(position[0]),
+            line.value("Y").or(position[1]),
+        ];
+        let [Some(x), Some(y)] = position else {
+            continue;
+        };
+        cuts |= !line.is("G", 0);
+        let point = [(x / SAME).round() as i64, (y / SAME).round() as i64];
+        if points.last() != Some(&point) {
+            points.push(point);
+        }
+    }
+    if !cuts {
+        return None;
+    }
+    let mut reversed = points.clone();
+    reversed.reverse();
+    Some(points.min(reversed))
+}
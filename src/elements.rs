@@ -0,0 +1,196 @@
+use roxmltree::{Document, Node};
+use std::{ops::Range, str::FromStr};
+use svgtypes::{Length, LengthUnit, Transform, ViewBox};
+
+use crate::gcode::Line;
+
+pub const INKSCAPE_NS: &str = "http://www.inkscape.org/namespaces/inkscape";
+
+/// The DPI assumed by CSS and by the converter for absolute units
+const CSS_DEFAULT_DPI: f64 = 96.0;
+
+/// Finds the output lines produced for each SVG element.
+///
+/// The converter emits a comment naming the element and its ancestors (`svg > g#layer > path`)
+/// before that element's moves, which is matched against the document in traversal order.
+pub fn chunks<'a, 'input>(
+    doc: &'a Document<'input>,
+    lines: &[Line],
+) -> Vec<(Range<usize>, Node<'a, 'input>)> {
+    let nodes = rendered_nodes(doc);
+    let mut cursor = 0;
+    let mut starts = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let Line::Comment(comment) = line else {
+            continue;
+        };
+        if let Some(offset) = nodes[cursor..].iter().position(|(name, _)| name == comment) {
+            starts.push((i, nodes[cursor + offset].1));
+            cursor += offset + 1;
+        }
+    }
+
+    let mut chunks = Vec::with_capacity(starts.len());
+    for (j, (start, node)) in starts.iter().enumerate() {
+        let end = starts.get(j + 1).map_or(lines.len(), |(next, _)| *next);
+        chunks.push((*start..end, *node));
+    }
+    chunks
+}
+
+/// Elements in the order the converter visits them, with the name it gives them in comments
+pub fn rendered_nodes<'a, 'input>(doc: &'a Document<'input>) -> Vec<(String, Node<'a, 'input>)> {
+    fn visit<'a, 'input>(
+        node: Node<'a, 'input>,
+        stack: &mut Vec<String>,
+        nodes: &mut Vec<(String, Node<'a, 'input>)>,
+    ) {
+        let hidden = node
+            .attribute("style")
+            .is_some_and(|style| style.contains("display:none"));
+        if !node.is_element()
+            || hidden
+            || matches!(node.tag_name().name(), "defs" | "marker" | "symbol")
+        {
+            return;
+        }
+        let name = node_name(&node);
+        let mut path = stack.join(" > ");
+        if !path.is_empty() {
+            path += " > ";
+        }
+        path += &name;
+        nodes.push((path, node));
+
+        stack.push(name);
+        node.children().for_each(|child| visit(child, stack, nodes));
+        stack.pop();
+    }
+
+    let mut nodes = Vec::new();
+    visit(doc.root_element(), &mut Vec::new(), &mut nodes);
+    nodes
+}
+
+/// Element name as used in the converter's comments, e.g. `path#outline`
+pub fn node_name(node: &Node) -> String {
+    let mut name = node.tag_name().name().to_string();
+    if let Some(id) = node.attribute("id") {
+        name += "#";
+        name += id;
+    }
+    name
+}
+
+/// The label of an Inkscape layer, `None` for any other element
+pub fn layer_label<'a>(node: &Node<'a, '_>) -> Option<&'a str> {
+    if !node.has_tag_name("g") || node.attribute((INKSCAPE_NS, "groupmode")) != Some("layer") {
+        return None;
+    }
+    node.attribute((INKSCAPE_NS, "label"))
+}
+
+/// Looks up a presentation property from the element's `style` or attributes, then its ancestors
+pub fn inherited_property<'a>(node: &Node<'a, '_>, property: &str) -> Option<&'a str> {
+    node.ancestors()
+        .find_map(|ancestor| own_property(&ancestor, property))
+}
+
+/// A property set on the element itself, in its `style` or as an attribute, not inherited
+pub fn own_property<'a>(node: &Node<'a, '_>, property: &str) -> Option<&'a str> {
+    let from_style = node.attribute("style").and_then(|style| {
+        style.split(';').find_map(|declaration| {
+            let (name, value) = declaration.split_once(':')?;
+            (name.trim() == property).then(|| value.trim())
+        })
+    });
+    from_style.or_else(|| node.attribute(property))
+}
+
+/// Converts a length to user units using the converter's fixed 96 DPI for absolute units
+pub fn length_to_user_units(length: Length) -> f64 {
+    match length.unit {
+        LengthUnit::Mm => length.number * CSS_DEFAULT_DPI / 25.4,
+        LengthUnit::Cm => length.number * CSS_DEFAULT_DPI / 2.54,
+        LengthUnit::In => length.number * CSS_DEFAULT_DPI,
+        LengthUnit::Pt => length.number * CSS_DEFAULT_DPI / 72.0,
+        LengthUnit::Pc => length.number * CSS_DEFAULT_DPI / 6.0,
+        LengthUnit::Em | LengthUnit::Ex => length.number * 16.0,
+        LengthUnit::Px | LengthUnit::None | LengthUnit::Percent => length.number,
+    }
+}
+
+/// Approximate number of output millimeters per user unit at this element.
+///
+/// Combines the element's and its ancestors' transforms with any viewBox scaling,
+/// taking the root viewport size from the dimension override when given.
+pub fn user_unit_scale(node: &Node, dimensions: &[Option<Length>; 2], dpi: f64) -> f64 {
+    let mut scale = 25.4 / dpi;
+    for ancestor in node.ancestors().filter(|ancestor| ancestor.is_element()) {
+        if let Some(Ok(ts)) = ancestor.attribute("transform").map(Transform::from_str) {
+            scale *= (ts.a * ts.d - ts.b * ts.c).abs().sqrt();
+        }
+        if !ancestor.has_tag_name("svg") {
+            continue;
+        }
+        let Some(Ok(view_box)) = ancestor.attribute("viewBox").map(ViewBox::from_str) else {
+            continue;
+        };
+        let is_root = ancestor.parent().is_some_and(|parent| parent.is_root());
+        let viewport = ["width", "height"].map(|attr| {
+            ancestor
+                .attribute(attr)
+                .and_then(|value| Length::from_str(value).ok())
+                .filter(|length| length.unit != LengthUnit::Percent)
+                .map(length_to_user_units)
+        });
+        let viewport = match is_root {
+            true => [
+                dimensions[0].map(length_to_user_units).or(viewport[0]),
+                dimensions[1].map(length_to_user_units).or(viewport[1]),
+            ],
+            false => viewport,
+        };
+        let ratios = [
+            viewport[0].map(|w| w / view_box.w),
+            viewport[1].map(|h| h / view_box.h),
+        ];
+        scale *= match ratios {
+            [Some(x), Some(y)] => x.min(y),
+            [Some(ratio), None] | [None, Some(ratio)] => ratio,
+            [None, None] => 1.0,
+        };
+    }
+    scale
+}
+
+/// A converted program split into the lines before the first element, each element's lines,
+/// and the trailing tool-off and end sequence after the last cut
+pub struct Program<'a, 'input> {
+    pub prefix: Range<usize>,
+    pub chunks: Vec<(Range<usize>, Node<'a, 'input>)>,
+    pub suffix: Range<usize>,
+}
+
+/// Splits the program so element chunks can be reordered or repeated independently.
+///
+/// Every chunk begins with its tool-off and travel, so chunks are self-contained once the
+/// final tool-off is separated from the last one.
+pub fn split_program<'a, 'input>(doc: &'a Document<'input>, lines: &[Line]) -> Program<'a, 'input> {
+    let mut chunks = chunks(doc, lines);
+    let prefix = 0..chunks.first().map_or(lines.len(), |(range, _)| range.start);
+    let mut suffix = lines.len()..lines.len();
+    if let Some((last, _)) = chunks.last_mut() {
+        let cut_end = lines[last.clone()]
+            .iter()
+            .rposition(|line| line.is("G", 1) || line.is("G", 2) || line.is("G", 3))
+            .map_or(last.end, |i| last.start + i + 1);
+        suffix = cut_end..last.end;
+        last.end = cut_end;
+    }
+    Program {
+        prefix,
+        chunks,
+        suffix,
+    }
+}
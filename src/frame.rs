@@ -0,0 +1,54 @@
+use crate::gcode::Line;
+
+/// Settings for a job tracing the outline of the main program
+pub struct FrameOptions<'a> {
+    /// Lines setting up the machine, as at the start of the main program
+    pub preamble: &'a [Line],
+    pub tool_on: &'a [Line],
+    pub tool_off: &'a [Line],
+    /// Trace with the tool on at this power (S word) instead of travelling with it lifted
+    pub power: Option<f64>,
+    pub feedrate: f64,
+    /// Number of times to go around the outline
+    pub loops: usize,
+    /// Lines ending the program, as at the end of the main program
+    pub end: &'a [Line],
+}
+
+/// Builds a program going around the bounding box of the job, then returning to the origin
+pub fn trace(bounds: [[f64; 2]; 2], options: &FrameOptions) -> Vec<Line> {
+    let [min, max] = bounds;
+    let corners = [
+        [min[0], min[1]],
+        [max[0], min[1]],
+        [max[0], max[1]],
+        [min[0], max[1]],
+        [min[0], min[1]],
+    ];
+
+    let mut lines = options.preamble.to_vec();
+    lines.extend_from_slice(options.tool_off);
+    lines.push(Line::command("G", 0, &[("X", min[0]), ("Y", min[1])]));
+    if options.power.is_some() {
+        lines.extend_from_slice(options.tool_on);
+    }
+    for _ in 0..options.loops {
+        for (i, [x, y]) in corners.into_iter().skip(1).enumerate() {
+            lines.push(match options.power {
+                Some(power) if i == 0 => Line::command(
+                    "G",
+                    1,
+                    &[("X", x), ("Y", y), ("F", options.feedrate), ("S", power)],
+                ),
+                Some(_) => Line::command("G", 1, &[("X", x), ("Y", y)]),
+                None => Line::command("G", 0, &[("X", x), ("Y", y)]),
+            });
+        }
+    }
+    if options.power.is_some() {
+        lines.extend_from_slice(options.tool_off);
+    }
+    lines.push(Line::command("G", 0, &[("X", 0.0), ("Y", 0.0)]));
+    lines.extend_from_slice(options.end);
+    lines
+}
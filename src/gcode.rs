@@ -0,0 +1,410 @@
+use g_code::{
+    emit::{Field, Token, Value},
+    parse::snippet_parser,
+};
+use std::borrow::Cow;
+
+/// A single line of output: a command word with its arguments, or a standalone comment
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line {
+    Command(Vec<Field<'static>>),
+    Comment(String),
+}
+
+impl Line {
+    /// Builds a command line such as `G0 X1 Y2` from a command word and axis words
+    pub fn command(letters: &'static str, number: usize, words: &[(&'static str, f64)]) -> Self {
+        let mut fields = vec![Field {
+            letters: Cow::Borrowed(letters),
+            value: Value::Integer(number),
+        }];
+        for (letters, value) in words {
+            fields.push(Field {
+                letters: Cow::Borrowed(letters),
+                value: Value::Float(*value),
+            });
+        }
+        Line::Command(fields)
+    }
+
+    /// Whether this line's command word is e.g. `M3` for `is("M", 3)`
+    pub fn is(&self, letters: &str, number: usize) -> bool {
+        match self {
+            Line::Command(fields) => fields.first().is_some_and(|field| {
+                field.letters.eq_ignore_ascii_case(letters)
+                    && field.value.as_f64() == Some(number as f64)
+            }),
+            Line::Comment(_) => false,
+        }
+    }
+
+    /// Value of the first word with the given letter, if any
+    pub fn value(&self, letters: &str) -> Option<f64> {
+        match self {
+            Line::Command(fields) => fields
+                .iter()
+                .skip(1)
+                .find(|field| field.letters.eq_ignore_ascii_case(letters))
+                .and_then(|field| field.value.as_f64()),
+            Line::Comment(_) => None,
+        }
+    }
+
+    /// Sets the first word with the given letter, appending it if missing
+    pub fn set_value(&mut self, letters: &'static str, value: f64) {
+        if let Line::Command(fields) = self {
+            let value = Value::Float(value);
+            match fields
+                .iter_mut()
+                .skip(1)
+                .find(|field| field.letters.eq_ignore_ascii_case(letters))
+            {
+                Some(field) => field.value = value,
+                None => fields.push(Field {
+                    letters: Cow::Borrowed(letters),
+                    value,
+                }),
+            }
+        }
+    }
+
+    /// Sorts the words by the position of their letter in `order`, keeping the relative order of
+    /// words with the same letter and putting letters not in it last
+    pub fn reorder(&mut self, order: &[String]) {
+        if let Line::Command(fields) = self {
+            fields.sort_by_key(|field| {
+                order
+                    .iter()
+                    .position(|letters| letters.eq_ignore_ascii_case(&field.letters))
+                    .unwrap_or(order.len())
+            });
+        }
+    }
+}
+
+/// A straight move between two points in the XY plane
+#[derive(Debug, Clone, Copy)]
+pub struct Move {
+    pub from: [f64; 2],
+    pub to: [f64; 2],
+    /// Whether this is a cutting move (`G1`/`G2`/`G3`) rather than a `G0` travel
+    pub cutting: bool,
+}
+
+/// Reconstructs the XY moves of a program, treating arcs as straight chords.
+///
+/// Moves before the first fully known position are skipped.
+pub fn moves(lines: &[Line]) -> Vec<Move> {
+    let mut moves = Vec::new();
+    let mut position: [Option<f64>; 2] = [None, None];
+    for line in lines {
+        let target = [
+            line.value("X").or(position[0]),
+            line.value("Y").or(position[1]),
+        ];
+        let cutting = line.is("G", 1) || line.is("G", 2) || line.is("G", 3);
+        if let ([Some(x0), Some(y0)], [Some(x1), Some(y1)]) = (position, target) {
+            if (cutting || line.is("G", 0)) && target != position {
+                moves.push(Move {
+                    from: [x0, y0],
+                    to: [x1, y1],
+                    cutting,
+                });
+            }
+        }
+        position = target;
+    }
+    moves
+}
+
+/// Line indices of each subpath: the travel to its start followed by its cuts
+pub fn subpaths(lines: &[Line], tool_on: &[Line]) -> Vec<Vec<usize>> {
+    let mut subpaths = Vec::new();
+    let mut travel = None;
+    let mut current: Option<Vec<usize>> = None;
+    for (i, line) in lines.iter().enumerate() {
+        if lines[i..].starts_with(tool_on) {
+            subpaths.extend(current.take());
+            current = travel.map(|travel| vec![travel]);
+        } else if line.is("G", 1) && line.value("X").is_some() && line.value("Y").is_some() {
+            if let Some(current) = current.as_mut() {
+                current.push(i);
+            }
+        } else if line.is("G", 0) && line.value("X").is_some() {
+            subpaths.extend(current.take());
+            travel = Some(i);
+        }
+    }
+    subpaths.extend(current);
+    subpaths
+}
+
+/// How comments are written in the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CommentStyle {
+    /// `;comment`, running to the end of the line
+    Semicolon,
+    /// `(comment)`, for controllers that only accept parenthesized comments
+    Paren,
+}
+
+impl CommentStyle {
+    /// Formats a comment in this style
+    pub fn format(&self, comment: &str) -> String {
+        match self {
+            CommentStyle::Semicolon => format!(";{}", comment),
+            // Parenthesized comments can't nest, so inner parentheses become brackets
+            CommentStyle::Paren => format!("({})", comment.replace('(', "[").replace(')', "]")),
+        }
+    }
+}
+
+impl std::fmt::Display for Line {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Line::Command(fields) => {
+                for (i, field) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", field)?;
+                }
+                Ok(())
+            }
+            Line::Comment(comment) => write!(f, "{}", CommentStyle::Semicolon.format(comment)),
+        }
+    }
+}
+
+/// Leaves out words the controller remembers from earlier lines, for `--modal`.
+///
+/// Tracks the motion command and the printed X, Y and Z of moves, forgetting both at any other
+/// command so nothing is left out after a tool change, dwell or mode change.
+#[derive(Debug, Default)]
+pub struct Modal {
+    motion: Option<usize>,
+    position: [Option<String>; 3],
+}
+
+impl Modal {
+    pub fn apply(&mut self, line: Line) -> Line {
+        let Line::Command(fields) = line else {
+            return line;
+        };
+        let motion = fields
+            .first()
+            .filter(|field| field.letters.eq_ignore_ascii_case("G"))
+            .and_then(|field| field.value.as_f64())
+            .filter(|number| [0.0, 1.0, 2.0, 3.0].contains(number));
+        let Some(motion) = motion.map(|number| number as usize) else {
+            *self = Modal::default();
+            return Line::Command(fields);
+        };
+
+        let axis_of = |field: &Field| {
+            ["X", "Y", "Z"]
+                .iter()
+                .position(|axis| field.letters.eq_ignore_ascii_case(axis))
+        };
+        let mut keep = vec![true; fields.len()];
+        let mut first_axis = None;
+        for (i, field) in fields.iter().enumerate().skip(1) {
+            let Some(axis) = axis_of(field) else {
+                continue;
+            };
+            first_axis = first_axis.or(Some(i));
+            let printed = field.to_string();
+            // Arcs are written out in full, their end point only recorded
+            if motion <= 1 && self.position[axis].as_ref() == Some(&printed) {
+                keep[i] = false;
+            }
+            self.position[axis] = Some(printed);
+        }
+        // A move still needs an axis to move on
+        if let Some(i) = first_axis.filter(|_| !keep.iter().skip(1).any(|&kept| kept)) {
+            keep[i] = true;
+        }
+        if motion <= 1 && self.motion == Some(motion) && keep.iter().skip(1).any(|&kept| kept) {
+            keep[0] = false;
+        }
+        self.motion = Some(motion);
+        let mut kept = keep.into_iter();
+        Line::Command(
+            fields
+                .into_iter()
+                .filter(|_| kept.next().unwrap_or(true))
+                .collect(),
+        )
+    }
+}
+
+/// Lazily groups the converter's flat token stream into lines.
+///
+/// Argument words (axes, arc centers, feedrate, power and dwell) continue the current line,
+/// command words start a new one. Axis words are moves, so they don't continue an `M` or `T`
+/// command: `M5 Z3` is the `M5` and a separate `Z3` move in the motion mode in effect.
+pub fn assemble<'a, I>(tokens: I) -> impl Iterator<Item = Line> + 'a
+where
+    I: IntoIterator<Item = Token<'a>>,
+    I::IntoIter: 'a,
+{
+    let continues = |command: &Field, field: &Field| match field.letters.as_ref() {
+        "X" | "Y" | "Z" | "I" | "J" | "K" | "R" => !matches!(command.letters.as_ref(), "M" | "T"),
+        "F" | "S" | "P" => true,
+        _ => false,
+    };
+    let mut tokens = tokens.into_iter().peekable();
+    std::iter::from_fn(move || loop {
+        match tokens.next()? {
+            Token::Comment { inner, .. } => return Some(Line::Comment(inner.into_owned())),
+            Token::Field(field) => {
+                let mut fields = vec![field.into_owned()];
+                loop {
+                    match tokens.peek() {
+                        Some(Token::Flag(_)) => {}
+                        Some(Token::Field(field)) if continues(&fields[0], field) => {}
+                        _ => break,
+                    }
+                    if let Some(Token::Field(field)) = tokens.next() {
+                        fields.push(field.into_owned());
+                    }
+                }
+                return Some(Line::Command(fields));
+            }
+            Token::Flag(_) => {}
+        }
+    })
+}
+
+/// XOR of every byte of the line, as appended after `*` for controllers that verify transmission
+pub fn checksum(line: &str) -> u8 {
+    line.bytes()
+        .filter(|&b| b != b'*')
+        .fold(0, |sum, b| sum ^ b)
+}
+
+/// Parses a G-code snippet such as `M5 G0 Z3.0` into lines
+pub fn snippet_lines(snippet: &str) -> Vec<Line> {
+    let snippet = snippet_parser(snippet).expect("Could not parse snippet");
+    assemble(snippet.iter_emit_tokens()).collect()
+}
+
+/// Parses G-code written one command per line, such as `T2 M6`, keeping each line's words together
+pub fn template_lines(template: &str) -> Result<Vec<Line>, String> {
+    let mut lines = Vec::new();
+    for text in template.lines().filter(|text| !text.trim().is_empty()) {
+        let snippet = snippet_parser(text).map_err(|err| format!("{:?}", err))?;
+        let mut fields = Vec::new();
+        for token in snippet.iter_emit_tokens() {
+            match token {
+                Token::Field(field) => fields.push(field.into_owned()),
+                Token::Comment { inner, .. } => lines.push(Line::Comment(inner.into_owned())),
+                Token::Flag(_) => {}
+            }
+        }
+        if !fields.is_empty() {
+            lines.push(Line::Command(fields));
+        }
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn semicolon_comments_run_to_the_end_of_the_line() {
+        assert_eq!(CommentStyle::Semicolon.format("tool on"), ";tool on");
+        assert_eq!(
+            CommentStyle::Semicolon.format("<path> (outline)"),
+            ";<path> (outline)"
+        );
+    }
+
+    #[test]
+    fn paren_comments_turn_inner_parentheses_to_brackets() {
+        assert_eq!(CommentStyle::Paren.format("tool on"), "(tool on)");
+        assert_eq!(
+            CommentStyle::Paren.format("<path> (outline (inner))"),
+            "(<path> [outline [inner]])"
+        );
+    }
+
+    #[test]
+    fn assemble_keeps_plunges_cuts_and_retracts_on_their_own_lines() {
+        let lines = snippet_lines("M3 G0 Z-1 G1 X10 Y5 F300 M5 G0 Z3 M5 Z6");
+        let text: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        // The bare Z after an M command is a move of its own, not a word of the M5
+        let expected = ["M3", "G0 Z-1", "G1 X10 Y5 F300", "M5", "G0 Z3", "M5", "Z6"];
+        assert_eq!(
+            text,
+            expected.map(|line| template_lines(line).unwrap()[0].to_string())
+        );
+    }
+
+    /// The motion and position of each move as the controller reads the program, remembering
+    /// the motion command and the axes a line leaves out
+    fn interpret(lines: &[Line]) -> Vec<(usize, [Option<f64>; 3])> {
+        let (mut motion, mut position) = (None, [None; 3]);
+        let mut moves = Vec::new();
+        for line in lines {
+            let Line::Command(fields) = line else {
+                continue;
+            };
+            // Modal lines can start with their axes, which `Line::value` doesn't look at
+            let word = |letter: &str| {
+                fields
+                    .iter()
+                    .find(|field| field.letters == letter)
+                    .and_then(|field| field.value.as_f64())
+            };
+            let commanded = (0..=3).find(|&number| line.is("G", number));
+            let axes = ["X", "Y", "Z"].map(word);
+            if commanded.is_none() && (fields[0].letters == "M" || fields[0].letters == "G") {
+                continue;
+            }
+            motion = commanded.or(motion);
+            for (axis, value) in axes.into_iter().enumerate() {
+                position[axis] = value.or(position[axis]);
+            }
+            moves.push((motion.unwrap(), position));
+        }
+        moves
+    }
+
+    #[test]
+    fn modal_output_moves_the_same_as_the_full_output() {
+        let lines = template_lines(
+            "G0 X0 Y0\nG1 Z-1 F100\nG1 X10 Y0\nG1 X10 Y10\nG1 X10 Y10 F200\nG2 X0 Y10 I-5 J0\n\
+             G1 X0 Y0\nM5\nG0 Z3\nG0 X0 Y0",
+        )
+        .unwrap();
+        let mut modal = Modal::default();
+        let modal_lines: Vec<Line> = lines
+            .iter()
+            .cloned()
+            .map(|line| modal.apply(line))
+            .collect();
+        assert_eq!(interpret(&modal_lines), interpret(&lines));
+
+        let text: Vec<String> = modal_lines.iter().map(|line| line.to_string()).collect();
+        // A move that goes nowhere only keeps its new feedrate
+        let expected = [
+            "G0 X0 Y0",
+            "G1 Z-1 F100",
+            "X10",
+            "Y10",
+            "F200",
+            "G2 X0 Y10 I-5 J0",
+            "G1 Y0",
+            "M5",
+            "G0 Z3",
+            "X0 Y0",
+        ];
+        assert_eq!(
+            text,
+            expected.map(|line| template_lines(line).unwrap()[0].to_string())
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use crate::gcode::{self, Line};
+
+/// Follows every convex closed subpath, such as a circle or an oval, with a spiral from its
+/// outline into its middle, the turns `spacing` millimeters apart at their widest.
+///
+/// The spiral shrinks the outline towards its centroid a little more with every step around
+/// it, which makes it an Archimedean spiral for a circle. A subpath counts as closed when it
+/// ends within `tolerance` of where it started.
+pub fn spiral_infill(
+    lines: Vec<Line>,
+    tool_on: &[Line],
+    spacing: f64,
+    tolerance: f64,
+) -> Vec<Line> {
+    let point = |line: &Line| [line.value("X").unwrap(), line.value("Y").unwrap()];
+    // Spiral to add after the last cut of each subpath it fills
+    let mut spirals: Vec<(usize, Vec<Line>)> = Vec::new();
+    for subpath in gcode::subpaths(&lines, tool_on) {
+        let points: Vec<[f64; 2]> = subpath.iter().map(|&i| point(&lines[i])).collect();
+        let (first, last) = (points[0], points[points.len() - 1]);
+        if points.len() < 4 || distance(first, last) > tolerance || !convex(&points) {
+            continue;
+        }
+        let &end = subpath.last().unwrap();
+        spirals.push((end, spiral(&points, &lines[end], spacing)));
+    }
+
+    let mut output = Vec::with_capacity(lines.len());
+    let mut spirals = spirals.into_iter().peekable();
+    for (i, line) in lines.into_iter().enumerate() {
+        output.push(line);
+        if let Some((_, spiral)) = spirals.next_if(|(end, _)| *end == i) {
+            output.extend(spiral);
+        }
+    }
+    output
+}
+
+/// Cuts spiralling from the closed outline's start to its centroid, copying the words of `cut`
+fn spiral(points: &[[f64; 2]], cut: &Line, spacing: f64) -> Vec<Line> {
+    let center = centroid(points);
+    let reach = points
+        .iter()
+        .map(|&point| distance(center, point))
+        .fold(0.0, f64::max);
+    let turns = (reach / spacing).ceil().max(1.0) as usize;
+    // How far round the outline each point is, from 0 at the start to 1 back there
+    let mut along = vec![0.0];
+    for pair in points.windows(2) {
+        along.push(along[along.len() - 1] + distance(pair[0], pair[1]));
+    }
+    let perimeter = along[along.len() - 1];
+
+    let mut lines = Vec::with_capacity(turns * (points.len() - 1));
+    for turn in 0..turns {
+        for (point, along) in points.iter().zip(&along).skip(1) {
+            let shrink = 1.0 - (turn as f64 + along / perimeter) / turns as f64;
+            let mut line = cut.clone();
+            line.set_value("X", center[0] + (point[0] - center[0]) * shrink);
+            line.set_value("Y", center[1] + (point[1] - center[1]) * shrink);
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+/// Whether the closed outline turns the same way at every corner
+fn convex(points: &[[f64; 2]]) -> bool {
+    let mut turn = 0.0;
+    for i in 0..points.len() - 1 {
+        let (a, b, c) = (
+            points[i],
+            points[i + 1],
+            points[(i + 2) % (points.len() - 1)],
+        );
+        let cross = (b[0] - a[0]) * (c[1] - b[1]) - (b[1] - a[1]) * (c[0] - b[0]);
+        if cross.abs() <= 1e-9 {
+            continue;
+        }
+        if turn != 0.0 && cross.signum() != turn {
+            return false;
+        }
+        turn = cross.signum();
+    }
+    turn != 0.0
+}
+
+/// Center of the area the closed outline encloses
+fn centroid(points: &[[f64; 2]]) -> [f64; 2] {
+    let (mut area, mut x, mut y) = (0.0, 0.0, 0.0);
+    for pair in points.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        let cross = a[0] * b[1] - b[0] * a[1];
+        area += cross;
+        x += (a[0] + b[0]) * cross;
+        y += (a[1] + b[1]) * cross;
+    }
+    [x / (3.0 * area), y / (3.0 * area)]
+}
+
+fn distance(a: [f64; 2], b: [f64; 2]) -> f64 {
+    (b[0] - a[0]).hypot(b[1] - a[1])
+}
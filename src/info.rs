@@ -0,0 +1,153 @@
+use roxmltree::{Document, Node};
+use std::collections::BTreeSet;
+use svgtypes::{PathParser, PathSegment, PointsParser};
+
+use crate::{
+    elements::{self, INKSCAPE_NS},
+    tools,
+};
+
+/// Elements the converter turns into toolpaths
+pub const SHAPE_TAGS: [&str; 7] = [
+    "path", "rect", "circle", "ellipse", "line", "polyline", "polygon",
+];
+
+/// Rough number of bytes per emitted line of G-code
+const BYTES_PER_LINE: usize = 32;
+/// Rough number of lines a flattened curve segment turns into
+const LINES_PER_CURVE: usize = 24;
+
+/// Prints a summary of the document without converting it
+pub fn print(doc: &Document) {
+    let root = doc.root_element();
+    let shapes: Vec<Node> = elements::rendered_nodes(doc)
+        .into_iter()
+        .map(|(_, node)| node)
+        .filter(|node| SHAPE_TAGS.contains(&node.tag_name().name()))
+        .collect();
+    let layers = doc
+        .descendants()
+        .filter(|node| {
+            node.has_tag_name("g") && node.attribute((INKSCAPE_NS, "groupmode")) == Some("layer")
+        })
+        .count();
+    let strokes: BTreeSet<&str> = shapes
+        .iter()
+        .filter_map(|node| elements::inherited_property(node, "stroke"))
+        .filter(|stroke| *stroke != "none")
+        .collect();
+    let mut widths: Vec<f64> = shapes
+        .iter()
+        .map(|node| tools::stroke_width(node, &[None, None]))
+        .collect();
+    widths.sort_by(f64::total_cmp);
+    widths.dedup_by(|a, b| (*a - *b).abs() < 1e-6);
+    let count = |tag: &str| {
+        doc.descendants()
+            .filter(|node| node.has_tag_name(tag))
+            .count()
+    };
+    let estimate: usize = shapes.iter().map(estimated_lines).sum::<usize>() * BYTES_PER_LINE;
+
+    println!("Width: {}", root.attribute("width").unwrap_or("not set"));
+    println!("Height: {}", root.attribute("height").unwrap_or("not set"));
+    println!(
+        "viewBox: {}",
+        root.attribute("viewBox").unwrap_or("not set")
+    );
+    println!("Paths: {}", shapes.len());
+    println!("Layers: {}", layers);
+    match strokes.is_empty() {
+        true => println!("Stroke colours: none"),
+        false => println!(
+            "Stroke colours: {}",
+            strokes.into_iter().collect::<Vec<_>>().join(", ")
+        ),
+    }
+    println!(
+        "Stroke widths: {}",
+        widths
+            .iter()
+            .map(|width| format!("{:.3}mm", width))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    println!("Text elements: {}", count("text"));
+    println!("Image elements: {}", count("image"));
+    println!("Estimated G-code size: ~{} KB", estimate.div_ceil(1024));
+}
+
+/// Very rough count of the G-code lines a shape produces, curves being flattened into many lines
+fn estimated_lines(node: &Node) -> usize {
+    // Travel, tool on and tool off around every shape
+    let overhead = 5;
+    let segments = match node.tag_name().name() {
+        "path" => node.attribute("d").map_or(0, |d| {
+            PathParser::from(d)
+                .filter_map(Result::ok)
+                .map(|segment| match segment {
+                    PathSegment::CurveTo { .. }
+                    | PathSegment::SmoothCurveTo { .. }
+                    | PathSegment::Quadratic { .. }
+                    | PathSegment::SmoothQuadratic { .. }
+                    | PathSegment::EllipticalArc { .. } => LINES_PER_CURVE,
+                    _ => 1,
+                })
+                .sum()
+        }),
+        "rect" | "line" => 4,
+        "circle" | "ellipse" => 4 * LINES_PER_CURVE,
+        _ => node
+            .attribute("points")
+            .map_or(0, |points| PointsParser::from(points).count()),
+    };
+    overhead + segments
+}
+
+/// Fewest moves the converter can make of the document's shapes: one for each segment of their
+/// outlines, however little their curves are flattened
+pub fn minimum_moves(doc: &Document) -> usize {
+    elements::rendered_nodes(doc)
+        .into_iter()
+        .map(|(_, node)| match node.tag_name().name() {
+            "path" => node.attribute("d").map_or(0, |d| {
+                PathParser::from(d)
+                    .filter_map(Result::ok)
+                    .filter(|segment| !matches!(segment, PathSegment::ClosePath { .. }))
+                    .count()
+            }),
+            "rect" | "circle" | "ellipse" => 5,
+            "line" => 2,
+            "polyline" | "polygon" => node
+                .attribute("points")
+                .map_or(0, |points| PointsParser::from(points).count()),
+            _ => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn minimum_moves_is_no_more_than_the_converter_makes() {
+        let doc = Document::parse(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="20mm" height="20mm" viewBox="0 0 20 20">
+                <path d="M1 1 L5 1 L5 5 Z"/>
+                <rect x="6" y="6" width="4" height="4"/>
+                <circle cx="15" cy="15" r="3"/>
+                <line x1="1" y1="10" x2="5" y2="10"/>
+                <polyline points="1 15 3 17 5 15"/>
+            </svg>"#,
+        )
+        .unwrap();
+        let moves = testing::program(&doc)
+            .iter()
+            .filter(|line| (0..=3).any(|number| line.is("G", number)) && line.value("X").is_some())
+            .count();
+        assert_eq!(minimum_moves(&doc), 3 + 5 + 5 + 2 + 3);
+        assert!(minimum_moves(&doc) <= moves);
+    }
+}
@@ -0,0 +1,256 @@
+use roxmltree::{Document, Node};
+use serde::Deserialize;
+use std::{fs, ops::Range, path::Path};
+use svg2gcode::{svg2program, ConversionOptions};
+use toml::{Table, Value};
+
+use crate::{
+    elements,
+    gcode::{self, Line},
+    info::SHAPE_TAGS,
+    postprocess, preprocess,
+    select::Selector,
+    settings::Settings,
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobFile {
+    operation: Vec<Operation>,
+}
+
+/// One step of a `--job` file, e.g.
+///
+/// ```toml
+/// [[operation]]
+/// name = "engrave"
+/// layer = "engrave"
+/// passes = 2
+/// settings = { tool_on = "M3 S200", feedrate = 3000 }
+/// ```
+///
+/// The shapes it cuts are those matching every one of `layer`, `color` and `select` it gives.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Operation {
+    /// Name shown in the breakdown, the operation's number when not given
+    pub name: Option<String>,
+    /// Label or id of the Inkscape layer, or id of the group, the shapes are in
+    pub layer: Option<String>,
+    /// Stroke colour of the shapes, as the SVG writes it
+    pub color: Option<String>,
+    /// Simple CSS selector as for `--svg-subset`
+    pub select: Option<String>,
+    /// Times the shapes are cut over
+    #[serde(default = "one")]
+    pub passes: u32,
+    /// Settings for this operation, by dotted key or a bare name such as `feedrate`
+    #[serde(default)]
+    pub settings: Table,
+}
+
+fn one() -> u32 {
+    1
+}
+
+/// Lines one operation converted to, and what went into them
+pub struct Converted {
+    pub name: String,
+    pub shapes: usize,
+    pub passes: u32,
+    pub lines: Vec<Line>,
+}
+
+pub fn read(path: &Path) -> Vec<Operation> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) => panic!(
+            "Could not read job file: {}, failed with error: {}",
+            path.display(),
+            err
+        ),
+    };
+    let job: JobFile = match toml::from_str(&text) {
+        Ok(job) => job,
+        Err(err) => panic!(
+            "Could not parse job file: {}, failed with error: {}",
+            path.display(),
+            err
+        ),
+    };
+    if job.operation.is_empty() {
+        panic!(
+            "Could not use job file: {}, it has no [[operation]]s",
+            path.display()
+        );
+    }
+    job.operation
+}
+
+impl Operation {
+    fn label(&self, index: usize) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("operation {}", index + 1),
+        }
+    }
+
+    /// The base settings with this operation's on top
+    pub fn settings(&self, base: &Settings) -> Result<Settings, String> {
+        let mut settings = base.clone();
+        for (key, value) in flatten(&self.settings, "") {
+            settings.set_by_name(&key, &value)?;
+        }
+        Ok(settings)
+    }
+
+    fn matches(&self, shape: &Node, selector: Option<&Selector>) -> bool {
+        let in_layer = |layer: &str| {
+            shape.ancestors().any(|ancestor| {
+                (ancestor.has_tag_name("g") && ancestor.attribute("id") == Some(layer))
+                    || elements::layer_label(&ancestor) == Some(layer)
+            })
+        };
+        self.layer.as_deref().is_none_or(in_layer)
+            && self.color.as_deref().is_none_or(|color| {
+                elements::inherited_property(shape, "stroke")
+                    .is_some_and(|stroke| stroke.eq_ignore_ascii_case(color.trim()))
+            })
+            && selector.is_none_or(|selector| {
+                shape
+                    .ancestors()
+                    .any(|ancestor| selector.matches(&ancestor))
+            })
+    }
+
+    /// Removals of the shapes the operation doesn't cut, and how many it does
+    fn subset(
+        &self,
+        doc: &Document,
+        selector: Option<&Selector>,
+    ) -> (Vec<(Range<usize>, String)>, usize) {
+        let shapes: Vec<Node> = doc
+            .descendants()
+            .filter(|node| SHAPE_TAGS.contains(&node.tag_name().name()))
+            .collect();
+        let removals: Vec<(Range<usize>, String)> = shapes
+            .iter()
+            .filter(|shape| !self.matches(shape, selector))
+            .map(|shape| (shape.range(), String::new()))
+            .collect();
+        let kept = shapes.len() - removals.len();
+        (removals, kept)
+    }
+}
+
+/// Dotted keys and values of every setting in the table, its nested tables included
+fn flatten(table: &Table, prefix: &str) -> Vec<(String, String)> {
+    let mut settings = Vec::new();
+    for (name, value) in table {
+        let key = match prefix.is_empty() {
+            true => name.clone(),
+            false => format!("{}.{}", prefix, name),
+        };
+        match value {
+            Value::Table(table) => settings.extend(flatten(table, &key)),
+            Value::String(value) => settings.push((key, value.clone())),
+            value => settings.push((key, value.to_string())),
+        }
+    }
+    settings
+}
+
+/// Converts each operation's shapes in turn, every pass of it with its own settings.
+///
+/// Only the first pass starts with the preamble or `machine.begin`, and only the last ends
+/// with `machine.end`, so the operations run one after another in a single program. Each
+/// pass starts by turning the tool off and lifting it, moving on to its first shape.
+pub fn convert(
+    operations: &[Operation],
+    svg_xml: &str,
+    job_path: &Path,
+    base: &Settings,
+    parse: impl Fn(&str) -> Document,
+    dimensions: impl Fn(&Document) -> [Option<svgtypes::Length>; 2],
+) -> Vec<Converted> {
+    let doc = parse(svg_xml);
+    let mut converted = Vec::with_capacity(operations.len());
+    for (index, operation) in operations.iter().enumerate() {
+        let name = operation.label(index);
+        let fail = |err: String| -> ! {
+            panic!(
+                "Could not use job file: {}, {} failed with error: {}",
+                job_path.display(),
+                name,
+                err
+            )
+        };
+        if operation.layer.is_none() && operation.color.is_none() && operation.select.is_none() {
+            fail("it needs at least one of layer, color or select".to_string());
+        }
+        if operation.passes == 0 {
+            fail("passes must be at least 1".to_string());
+        }
+        let selector = operation
+            .select
+            .as_deref()
+            .map(|select| select.parse::<Selector>())
+            .transpose()
+            .unwrap_or_else(|err| fail(err));
+        let (removals, shapes) = operation.subset(&doc, selector.as_ref());
+        if shapes == 0 {
+            fail("its selection matches no shapes".to_string());
+        }
+        let settings = operation.settings(base).unwrap_or_else(|err| fail(err));
+
+        let subset_xml = preprocess::replace_ranges(svg_xml, &removals);
+        let subset = parse(&subset_xml);
+        let mut lines = Vec::new();
+        for pass in 0..operation.passes {
+            let first = index == 0 && pass == 0;
+            let last = index == operations.len() - 1 && pass == operation.passes - 1;
+            let mut settings = settings.clone();
+            if !first {
+                settings.machine.begin.clear();
+            }
+            if !last {
+                settings.machine.end.clear();
+            }
+            let program = svg2program(
+                &subset,
+                &crate::conversion_config(&settings),
+                ConversionOptions {
+                    dimensions: dimensions(&subset),
+                },
+                crate::machine(&settings),
+            );
+            let program: Vec<Line> = gcode::assemble(program).collect();
+            lines.extend(match first {
+                true => program,
+                false => postprocess::strip_preamble(program),
+            });
+        }
+        converted.push(Converted {
+            name,
+            shapes,
+            passes: operation.passes,
+            lines,
+        });
+    }
+    converted
+}
+
+/// Prints the shapes, passes and moves of each operation
+pub fn print(converted: &[Converted]) {
+    for operation in converted {
+        let moves = operation
+            .lines
+            .iter()
+            .filter(|line| (0..=3).any(|number| line.is("G", number)))
+            .count();
+        println!(
+            "{}: {} shapes, {} passes, {} moves",
+            operation.name, operation.shapes, operation.passes, moves
+        );
+    }
+}
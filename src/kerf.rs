@@ -0,0 +1,203 @@
+use roxmltree::Document;
+use svgtypes::Length;
+
+use crate::{
+    elements,
+    gcode::{self, Line},
+    tools,
+};
+
+/// Sharpest corner the miter offset follows before it is limited, as `1 + cos(angle)`
+const MITER_LIMIT: f64 = 0.2;
+
+/// Offsets every closed path outward by half its stroke width to compensate for the kerf.
+///
+/// Open paths have no inside or outside and are left as drawn.
+pub fn compensate(
+    mut lines: Vec<Line>,
+    doc: &Document,
+    dimensions: &[Option<Length>; 2],
+    tool_on: &[Line],
+) -> Vec<Line> {
+    let chunks: Vec<_> = elements::chunks(doc, &lines)
+        .into_iter()
+        .map(|(range, node)| (range, tools::stroke_width(&node, dimensions) / 2.0))
+        .collect();
+    for (range, offset) in chunks {
+        for subpath in gcode::subpaths(&lines[range.clone()], tool_on) {
+            let indices: Vec<usize> = subpath.iter().map(|i| range.start + i).collect();
+            offset_subpath(&mut lines, &indices, offset);
+        }
+    }
+    lines
+}
+
+fn offset_subpath(lines: &mut [Line], indices: &[usize], offset: f64) {
+    let point = |line: &Line| [line.value("X").unwrap(), line.value("Y").unwrap()];
+    let points: Vec<[f64; 2]> = indices.iter().map(|&i| point(&lines[i])).collect();
+
+    // Distinct vertices, and which vertex each line ends on
+    let mut vertices: Vec<[f64; 2]> = Vec::new();
+    let mut vertex_of = Vec::with_capacity(points.len());
+    for p in points.iter() {
+        if vertices.last().is_none_or(|last| distance(last, p) > 1e-9) {
+            vertices.push(*p);
+        }
+        vertex_of.push(vertices.len() - 1);
+    }
+    let closed = vertices.len() > 3 && distance(&vertices[0], vertices.last().unwrap()) <= 1e-6;
+    if !closed || offset <= 0.0 {
+        return;
+    }
+    vertices.pop();
+    let last = vertices.len();
+    for vertex in vertex_of.iter_mut() {
+        if *vertex == last {
+            *vertex = 0;
+        }
+    }
+
+    let area: f64 = (0..vertices.len())
+        .map(|i| {
+            let (a, b) = (vertices[i], vertices[(i + 1) % vertices.len()]);
+            a[0] * b[1] - b[0] * a[1]
+        })
+        .sum();
+    // Outward normals point right of the direction of travel on counterclockwise paths
+    let side = if area > 0.0 { 1.0 } else { -1.0 };
+    let normal = |a: &[f64; 2], b: &[f64; 2]| {
+        let length = distance(a, b);
+        [
+            side * (b[1] - a[1]) / length,
+            -side * (b[0] - a[0]) / length,
+        ]
+    };
+
+    let count = vertices.len();
+    let offset_vertices: Vec<[f64; 2]> = (0..count)
+        .map(|i| {
+            let previous = vertices[(i + count - 1) % count];
+            let next = vertices[(i + 1) % count];
+            let n1 = normal(&previous, &vertices[i]);
+            let n2 = normal(&vertices[i], &next);
+            let k = (1.0 + n1[0] * n2[0] + n1[1] * n2[1]).max(MITER_LIMIT);
+            [
+                vertices[i][0] + (n1[0] + n2[0]) * offset / k,
+                vertices[i][1] + (n1[1] + n2[1]) * offset / k,
+            ]
+        })
+        .collect();
+
+    for (&i, &vertex) in indices.iter().zip(vertex_of.iter()) {
+        lines[i].set_value("X", offset_vertices[vertex][0]);
+        lines[i].set_value("Y", offset_vertices[vertex][1]);
+    }
+}
+
+fn distance(a: &[f64; 2], b: &[f64; 2]) -> f64 {
+    (b[0] - a[0]).hypot(b[1] - a[1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{postprocess, testing};
+
+    fn moves(points: &[[f64; 2]]) -> Vec<Line> {
+        let mut lines = vec![Line::command(
+            "G",
+            0,
+            &[("X", points[0][0]), ("Y", points[0][1])],
+        )];
+        lines.extend(
+            points[1..]
+                .iter()
+                .map(|point| Line::command("G", 1, &[("X", point[0]), ("Y", point[1])])),
+        );
+        lines
+    }
+
+    fn offset(points: &[[f64; 2]], offset: f64) -> Vec<Line> {
+        let mut lines = moves(points);
+        let indices: Vec<usize> = (0..lines.len()).collect();
+        offset_subpath(&mut lines, &indices, offset);
+        lines
+    }
+
+    const SQUARE: [[f64; 2]; 5] = [
+        [0.0, 0.0],
+        [10.0, 0.0],
+        [10.0, 10.0],
+        [0.0, 10.0],
+        [0.0, 0.0],
+    ];
+
+    #[test]
+    fn closed_paths_grow_outward() {
+        let grown = [
+            [-1.0, -1.0],
+            [11.0, -1.0],
+            [11.0, 11.0],
+            [-1.0, 11.0],
+            [-1.0, -1.0],
+        ];
+        assert_eq!(offset(&SQUARE, 1.0), moves(&grown));
+        // Whichever way round they go
+        let mut clockwise = SQUARE;
+        clockwise.reverse();
+        let mut expected = grown;
+        expected.reverse();
+        assert_eq!(offset(&clockwise, 1.0), moves(&expected));
+    }
+
+    #[test]
+    fn repeated_points_move_with_their_vertex() {
+        let points = [
+            [0.0, 0.0],
+            [10.0, 0.0],
+            [10.0, 0.0],
+            [10.0, 10.0],
+            [0.0, 10.0],
+            [0.0, 0.0],
+        ];
+        let point = |line: &Line| [line.value("X"), line.value("Y")];
+        let offset = offset(&points, 1.0);
+        assert_eq!(point(&offset[1]), [Some(11.0), Some(-1.0)]);
+        assert_eq!(point(&offset[2]), point(&offset[1]));
+        assert_eq!(point(&offset[5]), point(&offset[0]));
+    }
+
+    #[test]
+    fn sharp_corners_are_limited() {
+        // Unlimited, the miter at the tip of the thin spike would reach 2000 past it
+        let spike = [[0.0, 0.0], [100.0, 0.05], [0.0, 0.1], [0.0, 0.0]];
+        let tip = offset(&spike, 1.0)[1].value("X").unwrap();
+        assert!(tip > 100.0 && tip <= 100.0 + 2.0 / MITER_LIMIT, "{}", tip);
+    }
+
+    #[test]
+    fn open_paths_and_zero_offsets_are_left_alone() {
+        let open = &SQUARE[..4];
+        assert_eq!(offset(open, 1.0), moves(open));
+        assert_eq!(offset(&SQUARE, 0.0), moves(&SQUARE));
+    }
+
+    #[test]
+    fn compensate_offsets_by_half_the_stroke_width() {
+        let doc = Document::parse(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">
+                <rect x="10" y="10" width="20" height="20" stroke-width="2"/>
+            </svg>"#,
+        )
+        .unwrap();
+        let (tool_on, _) = testing::tool();
+        let lines = testing::program(&doc);
+        let [min, max] = postprocess::bounds(&lines).unwrap();
+        let compensated = compensate(lines, &doc, &[None, None], &tool_on);
+        let [grown_min, grown_max] = postprocess::bounds(&compensated).unwrap();
+        for axis in 0..2 {
+            assert!((min[axis] - grown_min[axis] - 1.0).abs() < 1e-6);
+            assert!((grown_max[axis] - max[axis] - 1.0).abs() < 1e-6);
+        }
+    }
+}
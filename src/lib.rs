@@ -0,0 +1,381 @@
+//! Converts SVG text to G-code in memory, for embedding the converter in other programs.
+//!
+//! This is the conversion the `usGcode` command starts from, before its post-processing
+//! options: the document's shapes become cuts between the tool-on and tool-off sequences of
+//! the [`Settings`], with the output written as the command writes it by default.
+//!
+//! ```
+//! let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10mm" height="10mm">
+//!     <line x1="0" y1="0" x2="10" y2="10"/>
+//! </svg>"#;
+//! let conversion = usgcode::convert_str(svg, &usgcode::UsgOptions::default()).unwrap();
+//! assert!(conversion.gcode.lines().any(|line| line.starts_with("G1 ")));
+//! assert_eq!(conversion.stats.paths, 1);
+//! assert!(conversion.warnings.is_empty());
+//! ```
+
+use g_code::parse::{ast::Snippet, snippet_parser};
+use std::sync::atomic::{AtomicBool, Ordering};
+use svg2gcode::{
+    svg2program, ConversionConfig, ConversionOptions, Machine, SupportedFunctionality,
+};
+
+pub mod gcode;
+pub mod settings;
+
+use gcode::Line;
+use settings::Settings;
+
+/// What to convert with
+#[derive(Debug, Clone, Default)]
+pub struct UsgOptions {
+    /// Machine sequences, feedrate, tolerance and DPI, as in a config file
+    pub settings: Settings,
+    /// Keep the converter's comments naming the element each path comes from
+    pub keep_comments: bool,
+}
+
+/// A converted program
+#[derive(Debug, Clone)]
+pub struct Conversion {
+    /// The program, each line ending in a newline
+    pub gcode: String,
+    pub stats: Stats,
+    /// Problems with the document that didn't stop the conversion
+    pub warnings: Vec<Warning>,
+}
+
+/// Counts and distances of a converted program
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    /// Lines of G-code written
+    pub lines: usize,
+    /// Times the tool goes on to cut
+    pub paths: usize,
+    /// Millimeters cut, arcs counted as straight
+    pub cut_length: f64,
+    /// Millimeters travelled with the tool off
+    pub travel_length: f64,
+}
+
+/// A problem with the document that didn't stop the conversion
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// The root `<svg>` has no width or height, so user units are drawn at the settings' DPI
+    NoDimensions,
+    /// Nothing in the document draws a cut
+    NoGeometry,
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::NoDimensions => write!(
+                f,
+                "the svg has no width and height, drawing user units at the configured DPI"
+            ),
+            Warning::NoGeometry => write!(f, "the svg has no geometry to cut"),
+        }
+    }
+}
+
+/// Why a conversion failed
+#[derive(Debug)]
+pub enum UsgError {
+    /// The SVG isn't well-formed XML
+    Parse(roxmltree::Error),
+    /// One of the machine settings isn't G-code
+    Snippet {
+        setting: &'static str,
+        snippet: String,
+    },
+    /// The cancellation flag was set during the conversion
+    Cancelled,
+}
+
+impl std::fmt::Display for UsgError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UsgError::Parse(err) => {
+                write!(f, "could not parse the svg, failed with error: {}", err)
+            }
+            UsgError::Snippet { setting, snippet } => {
+                write!(f, "could not parse {} \"{}\" as G-code", setting, snippet)
+            }
+            UsgError::Cancelled => write!(f, "the conversion was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for UsgError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UsgError::Parse(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Stage of a conversion, reported to the progress callback of [`convert_str_with_progress`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Reading the XML
+    Parse,
+    /// Turning the document's shapes into moves
+    Convert,
+    /// Writing the moves out as text, a path at a time
+    Format,
+}
+
+/// Converts SVG text to G-code without touching the filesystem
+pub fn convert_str(svg: &str, opts: &UsgOptions) -> Result<Conversion, UsgError> {
+    convert_str_with_progress(svg, opts, |_, _| {}, &AtomicBool::new(false))
+}
+
+/// [`convert_str`], calling `progress` with each phase and how far through it is, from 0 to 1.
+///
+/// Setting `cancel` stops the conversion with [`UsgError::Cancelled`]. It is checked between
+/// phases and before each path is written; the converter turns the whole document into moves
+/// in one go, so a cancellation during that phase takes effect once it finishes.
+///
+/// ```
+/// use std::sync::atomic::AtomicBool;
+///
+/// let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10mm" height="10mm">
+///     <circle cx="5" cy="5" r="4"/>
+/// </svg>"#;
+/// let mut phases = Vec::new();
+/// let options = usgcode::UsgOptions::default();
+/// usgcode::convert_str_with_progress(
+///     svg,
+///     &options,
+///     |phase, done| {
+///         if done == 1.0 {
+///             phases.push(phase);
+///         }
+///     },
+///     &AtomicBool::new(false),
+/// )
+/// .unwrap();
+/// use usgcode::Phase::*;
+/// assert_eq!(phases, [Parse, Convert, Format]);
+/// ```
+pub fn convert_str_with_progress(
+    svg: &str,
+    opts: &UsgOptions,
+    mut progress: impl FnMut(Phase, f32),
+    cancel: &AtomicBool,
+) -> Result<Conversion, UsgError> {
+    let cancelled = || match cancel.load(Ordering::Relaxed) {
+        true => Err(UsgError::Cancelled),
+        false => Ok(()),
+    };
+    let mut warnings = Vec::new();
+
+    progress(Phase::Parse, 0.0);
+    let doc = roxmltree::Document::parse(svg).map_err(UsgError::Parse)?;
+    let root = doc.root_element();
+    if !root.has_attribute("width") || !root.has_attribute("height") {
+        warnings.push(Warning::NoDimensions);
+    }
+    progress(Phase::Parse, 1.0);
+    cancelled()?;
+
+    progress(Phase::Convert, 0.0);
+    let settings = &opts.settings;
+    let config = ConversionConfig {
+        tolerance: settings.conversion.tolerance,
+        feedrate: settings.conversion.feedrate,
+        dpi: settings.conversion.dpi,
+        origin: [Some(0.0), Some(0.0)],
+    };
+    let machine = &settings.machine;
+    let machine = Machine::new(
+        SupportedFunctionality {
+            circular_interpolation: false,
+        },
+        snippet("machine.tool_on", &machine.tool_on)?,
+        snippet("machine.tool_off", &machine.tool_off)?,
+        snippet("machine.begin", &machine.begin)?,
+        snippet("machine.end", &machine.end)?,
+    );
+    let tokens = svg2program(&doc, &config, ConversionOptions::default(), machine);
+    let lines: Vec<Line> = gcode::assemble(tokens)
+        .filter(|line| opts.keep_comments || !matches!(line, Line::Comment(_)))
+        .collect();
+    progress(Phase::Convert, 1.0);
+    cancelled()?;
+
+    progress(Phase::Format, 0.0);
+    let tool_on = gcode::snippet_lines(&settings.machine.tool_on);
+    let mut gcode = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        if lines[i..].starts_with(&tool_on) {
+            cancelled()?;
+            progress(Phase::Format, i as f32 / lines.len() as f32);
+        }
+        gcode.push_str(&line.to_string());
+        gcode.push('\n');
+    }
+
+    let mut stats = Stats {
+        lines: lines.len(),
+        paths: gcode::subpaths(&lines, &tool_on).len(),
+        ..Stats::default()
+    };
+    for m in gcode::moves(&lines) {
+        let length = (m.to[0] - m.from[0]).hypot(m.to[1] - m.from[1]);
+        match m.cutting {
+            true => stats.cut_length += length,
+            false => stats.travel_length += length,
+        }
+    }
+    if stats.paths == 0 {
+        warnings.push(Warning::NoGeometry);
+    }
+    progress(Phase::Format, 1.0);
+    Ok(Conversion {
+        gcode,
+        stats,
+        warnings,
+    })
+}
+
+/// Parses one of the machine settings, which may be left empty
+fn snippet<'a>(setting: &'static str, snippet: &'a str) -> Result<Option<Snippet<'a>>, UsgError> {
+    match snippet.is_empty() {
+        true => Ok(None),
+        false => snippet_parser(snippet)
+            .map(Some)
+            .map_err(|_| UsgError::Snippet {
+                setting,
+                snippet: snippet.to_string(),
+            }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn svg(body: &str) -> String {
+        format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">{}</svg>"#,
+            body
+        )
+    }
+
+    const SQUARES: &str = r#"<rect x="10" y="10" width="20" height="20"/>
+        <rect x="50" y="10" width="20" height="20"/>
+        <rect x="10" y="50" width="20" height="20"/>"#;
+
+    #[test]
+    fn convert_str_counts_the_program() {
+        let conversion = convert_str(&svg(SQUARES), &UsgOptions::default()).unwrap();
+        assert_eq!(conversion.stats.paths, 3);
+        assert_eq!(conversion.stats.lines, conversion.gcode.lines().count());
+        // Drawn at 96 user units to the dpi
+        let scale = 96.0 / Settings::default().conversion.dpi;
+        assert!((conversion.stats.cut_length - 3.0 * 80.0 * scale).abs() < 1e-6);
+        assert!(conversion.stats.travel_length > 0.0);
+        assert!(conversion.warnings.is_empty());
+        assert!(!conversion.gcode.contains(';'));
+    }
+
+    #[test]
+    fn convert_str_keeps_comments_when_asked() {
+        let options = UsgOptions {
+            keep_comments: true,
+            ..UsgOptions::default()
+        };
+        let conversion = convert_str(&svg(SQUARES), &options).unwrap();
+        assert!(conversion.gcode.lines().any(|line| line.starts_with(';')));
+    }
+
+    #[test]
+    fn convert_str_collects_warnings() {
+        let undimensioned =
+            r#"<svg xmlns="http://www.w3.org/2000/svg"><rect width="5" height="5"/></svg>"#;
+        let conversion = convert_str(undimensioned, &UsgOptions::default()).unwrap();
+        assert_eq!(conversion.warnings, [Warning::NoDimensions]);
+
+        let conversion = convert_str(&svg(""), &UsgOptions::default()).unwrap();
+        assert_eq!(conversion.warnings, [Warning::NoGeometry]);
+    }
+
+    #[test]
+    fn convert_str_fails_on_bad_input() {
+        assert!(matches!(
+            convert_str("<svg", &UsgOptions::default()),
+            Err(UsgError::Parse(_))
+        ));
+        let mut options = UsgOptions::default();
+        options.settings.machine.tool_on = "not g-code".to_string();
+        assert!(matches!(
+            convert_str(&svg(SQUARES), &options),
+            Err(UsgError::Snippet {
+                setting: "machine.tool_on",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn progress_goes_through_each_phase_in_order() {
+        let mut reports = Vec::new();
+        convert_str_with_progress(
+            &svg(SQUARES),
+            &UsgOptions::default(),
+            |phase, done| reports.push((phase, done)),
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        let phases: Vec<Phase> = reports.iter().map(|&(phase, _)| phase).collect();
+        let mut distinct = phases.clone();
+        distinct.dedup();
+        assert_eq!(distinct, [Phase::Parse, Phase::Convert, Phase::Format]);
+        // Each phase starts at 0 and ends at 1, moving forward a path at a time while formatting
+        for phase in distinct {
+            let done: Vec<f32> = reports
+                .iter()
+                .filter(|&&(reported, _)| reported == phase)
+                .map(|&(_, done)| done)
+                .collect();
+            assert_eq!((done[0], done[done.len() - 1]), (0.0, 1.0));
+            assert!(done.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", done);
+        }
+        assert_eq!(
+            phases
+                .iter()
+                .filter(|&&phase| phase == Phase::Format)
+                .count(),
+            5
+        );
+    }
+
+    #[test]
+    fn cancelling_stops_before_the_next_path() {
+        let cancel = AtomicBool::new(false);
+        let mut paths_after_cancelling = 0;
+        let result = convert_str_with_progress(
+            &svg(SQUARES),
+            &UsgOptions::default(),
+            |phase, done| {
+                if phase == Phase::Format && done > 0.0 {
+                    match cancel.load(Ordering::Relaxed) {
+                        true => paths_after_cancelling += 1,
+                        false => cancel.store(true, Ordering::Relaxed),
+                    }
+                }
+            },
+            &cancel,
+        );
+        assert!(matches!(result, Err(UsgError::Cancelled)));
+        assert_eq!(paths_after_cancelling, 0);
+
+        let cancelled = AtomicBool::new(true);
+        let result =
+            convert_str_with_progress(&svg(SQUARES), &UsgOptions::default(), |_, _| {}, &cancelled);
+        assert!(matches!(result, Err(UsgError::Cancelled)));
+    }
+}
@@ -0,0 +1,67 @@
+use std::str::FromStr;
+
+use crate::gcode::Line;
+
+const AXES: [&str; 3] = ["X", "Y", "Z"];
+
+/// Travel limits of the machine's axes, from `--machine-limits xmin,xmax,ymin,ymax,zmin,zmax`
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub min: [f64; 3],
+    pub max: [f64; 3],
+}
+
+impl FromStr for Limits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|err| err.to_string())?;
+        if values.iter().any(|value| !value.is_finite()) {
+            return Err("limits must be finite numbers".to_string());
+        }
+        let [x_min, x_max, y_min, y_max, z_min, z_max] = values[..] else {
+            return Err(format!(
+                "expected xmin,xmax,ymin,ymax,zmin,zmax such as 0,300,0,200,-5,5, got \"{}\"",
+                s
+            ));
+        };
+        let limits = Limits {
+            min: [x_min, y_min, z_min],
+            max: [x_max, y_max, z_max],
+        };
+        for (axis, letter) in AXES.into_iter().enumerate() {
+            if limits.min[axis] > limits.max[axis] {
+                return Err(format!(
+                    "the {} minimum must not exceed its maximum",
+                    letter
+                ));
+            }
+        }
+        Ok(limits)
+    }
+}
+
+/// Descriptions of the moves that end outside the limits, naming the first axis past them
+pub fn violations(lines: &[Line], limits: &Limits) -> Vec<String> {
+    lines
+        .iter()
+        .filter_map(|line| {
+            AXES.into_iter().enumerate().find_map(|(axis, letter)| {
+                let value = line.value(letter)?;
+                let limit = match value {
+                    value if value < limits.min[axis] => limits.min[axis],
+                    value if value > limits.max[axis] => limits.max[axis],
+                    _ => return None,
+                };
+                Some(format!(
+                    "\"{}\" goes past the machine's {} limit of {}",
+                    line, letter, limit
+                ))
+            })
+        })
+        .collect()
+}
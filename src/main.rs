@@ -1,25 +1,664 @@
-use clap::Parser;
+use clap::{CommandFactory, Parser, Subcommand};
 use g_code::parse::snippet_parser;
 use roxmltree::{self, ParsingOptions};
 use std::{
-    fs::{self, OpenOptions},
-    io::{Read, Write},
+    cell::Cell,
+    fs,
+    io::{self, Read, Write},
 };
 use svg2gcode::{
     self, svg2program, ConversionConfig, ConversionOptions, Machine, SupportedFunctionality,
 };
-use svgtypes;
+
+/// Prints a warning to stderr unless `--quiet` is set
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_quiet() {
+            eprintln!("Warning: {}", format_args!($($arg)*));
+        }
+    };
+}
+
+mod arcs;
+mod bench;
+mod clip;
+mod clippath;
+mod corner;
+mod dash;
+mod direction;
+mod duplicates;
+mod elements;
+mod frame;
+mod infill;
+mod info;
+mod job;
+mod kerf;
+mod limits;
+mod mcodes;
+mod nest;
+mod order;
+mod output;
+mod postprocess;
+mod preprocess;
+mod preview;
+#[cfg(feature = "raster")]
+mod raster;
+mod select;
+mod shapes;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "text")]
+mod text;
+mod tile;
+mod tools;
+mod zmap;
+
+use gcode::{CommentStyle, Line};
+use usgcode::{gcode, settings};
 
 #[derive(Parser, Debug)]
-#[command(version, about)]
+#[command(
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
-    input_path: std::path::PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Decimal number representing scale up or down of input data. Example: 'usGcode -s0.5 input.svg output.gcode' will produce gcode at half scale
-    #[arg(short, long)]
+    /// Print the version along with the versions of the libraries doing the conversion
+    #[arg(long, exclusive = true)]
+    version_info: bool,
+
+    /// Print a completion script for the given shell to stdout and exit
+    #[arg(long, value_name = "SHELL", exclusive = true)]
+    generate_completions: Option<clap_complete::Shell>,
+
+    #[arg(required_unless_present = "input_glob")]
+    input_path: Option<std::path::PathBuf>,
+
+    /// Convert every SVG matching a glob pattern, e.g. 'parts/*.svg', in alphabetical order. They
+    /// are combined into the one output file given, or converted separately into --output-dir
+    #[arg(long, value_name = "PATTERN")]
+    input_glob: Option<String>,
+
+    /// Directory to write a G-code file for each --input-glob match into, named after the SVG
+    #[arg(long, value_name = "DIR", requires = "input_glob")]
+    output_dir: Option<std::path::PathBuf>,
+
+    /// Decimal number or percentage representing scale up or down of input data. Example: 'usGcode -s0.5 input.svg output.gcode' or '-s50%' will produce gcode at half scale. A negative scale also mirrors the design left to right, flipping X only, e.g. '-s -1' for a mirror image at full size
+    #[arg(short, long, allow_hyphen_values = true, value_parser = parse_scale)]
     scale: Option<f64>,
 
-    output_path: std::path::PathBuf,
+    /// Override a setting by its dotted key, e.g. '--set conversion.tolerance=0.01'. Can be repeated
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    overrides: Vec<settings::Override>,
+
+    /// Don't start the program with the G21 (millimeters) and G90 (absolute positioning) preamble
+    #[arg(long)]
+    no_preamble: bool,
+
+    /// Keep comments in the output, including the SVG document's <title> and <desc> as a header
+    #[arg(long)]
+    keep_comments: bool,
+
+    /// Number every command line (N1, N2, ...)
+    #[arg(long)]
+    line_numbers: bool,
+
+    /// Append an XOR checksum (*<n>) to every line, as Marlin and Repetier verify. Implies --line-numbers
+    #[arg(long)]
+    gcode_checksum: bool,
+
+    /// Comment syntax used with --keep-comments
+    #[arg(long, value_enum, default_value_t = CommentStyle::Semicolon)]
+    comment_style: CommentStyle,
+
+    /// Controller dialect to write; fanuc wraps the program in % lines and only uses uppercase and parenthesized comments
+    #[arg(long, value_enum, default_value_t = output::Flavor::Generic)]
+    flavor: output::Flavor,
+
+    /// Program number written as an O word on the first line, e.g. 1234 for O1234
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=9999))]
+    program_number: Option<u32>,
+
+    /// Character set of the output; ascii spells out or replaces non-ASCII characters in comments
+    #[arg(long, value_enum, default_value_t = output::Encoding::Utf8)]
+    output_encoding: output::Encoding,
+
+    /// Write the program into this template file in place of its {{GCODE}} placeholder
+    #[arg(long, value_name = "FILE")]
+    output_template: Option<std::path::PathBuf>,
+
+    /// Leave out repeated G0/G1 words and coordinates that haven't changed since the previous move
+    #[arg(long)]
+    modal: bool,
+
+    /// Use --modal even with --gcode-checksum, whose resends normally need complete lines
+    #[arg(long, requires = "modal")]
+    force_modal: bool,
+
+    /// Order of the words within each line by letter, e.g. G,M,F,X,Y,Z; letters not listed keep their order at the end
+    #[arg(long, value_name = "LETTERS", value_delimiter = ',')]
+    word_order: Vec<String>,
+
+    /// Start the file with a UTF-8 byte order mark, for Windows editors that need one to detect the encoding
+    #[arg(long)]
+    bom: bool,
+
+    /// Ending written after every line
+    #[arg(long, value_enum, default_value_t = output::LineEnding::Lf)]
+    line_ending: output::LineEnding,
+
+    /// End lines with \r\n for controllers that need Windows line endings, the same as --line-ending crlf
+    #[arg(long, conflicts_with = "line_ending")]
+    windows_line_endings: bool,
+
+    /// Cut strokes with a stroke-dasharray as dashes, lifting the tool for each gap
+    #[arg(long)]
+    honor_dasharray: bool,
+
+    /// Dashes and gaps shorter than this many millimeters are merged with their neighbours
+    #[arg(long, default_value_t = 0.1, value_parser = parse_number::<f64>)]
+    min_dash: f64,
+
+    /// Change tools by stroke width, as comma-separated width=tool pairs in millimeters. Example: '--width-map 0.5=1,3=2'
+    #[arg(long, value_delimiter = ',')]
+    width_map: Vec<tools::WidthMapping>,
+
+    /// How far in millimeters a stroke width may be from a --width-map entry and still match
+    #[arg(long, default_value_t = 0.05, value_parser = parse_number::<f64>)]
+    width_tolerance: f64,
+
+    /// G-code changing tools between --width-map groups, with {n} standing for the tool number
+    #[arg(long, value_name = "GCODE", default_value = "T{n} M6")]
+    tool_change_command: String,
+
+    /// X position in millimeters to travel to before changing tools
+    #[arg(long, allow_hyphen_values = true, value_parser = parse_number::<f64>)]
+    tool_change_x: Option<f64>,
+
+    /// Y position in millimeters to travel to before changing tools
+    #[arg(long, allow_hyphen_values = true, value_parser = parse_number::<f64>)]
+    tool_change_y: Option<f64>,
+
+    /// Offset closed paths outward by half their stroke width to compensate for the cut's kerf
+    #[arg(long, conflicts_with = "width_map")]
+    width_as_kerf: bool,
+
+    /// Repair common structural problems before converting: a missing SVG namespace, repeated IDs and relative hrefs
+    #[arg(long)]
+    svg_fix: bool,
+
+    /// List every kind of SVG element and attribute the converter skips before converting
+    #[arg(long)]
+    report_unsupported_svg_features: bool,
+
+    /// Keep paths that never move from where they start, which are otherwise dropped
+    #[arg(long)]
+    keep_degenerate: bool,
+
+    /// List elements that cut the same moves as an earlier one, e.g. from copy and paste, to stderr
+    #[arg(long)]
+    report_duplicate_paths: bool,
+
+    /// Cut elements that repeat the moves of an earlier one only once
+    #[arg(long)]
+    remove_duplicate_paths: bool,
+
+    /// Fail instead of warning when the SVG uses features the converter doesn't support, such as text
+    #[arg(long)]
+    strict: bool,
+
+    /// Leave out the first N paths, e.g. to resume a job that stopped partway. With
+    /// --first-n-paths, the paths kept are counted after the skipped ones
+    #[arg(long, value_name = "N", value_parser = parse_number::<usize>)]
+    skip_n_paths: Option<usize>,
+
+    /// Read the input as plain or gzipped SVG, rather than telling from its first bytes
+    #[arg(long, value_enum, default_value_t = preprocess::InputFormat::Auto)]
+    input_format: preprocess::InputFormat,
+
+    /// Refuse SVG files larger than this, in bytes or with a K, M or G suffix
+    #[arg(long, value_name = "SIZE", default_value = "64M", value_parser = parse_size)]
+    max_input_size: u64,
+
+    /// Stop with an error when the conversion generates more than this many moves. Documents whose
+    /// shapes need more are refused before converting; the converter builds each program whole,
+    /// so past that this limits the size of the output rather than the memory used
+    #[arg(long, value_name = "N", default_value_t = 5_000_000, value_parser = parse_number::<usize>)]
+    max_moves: usize,
+
+    /// Read the SVG's DTD, expanding the entities it declares. Off by default, as entities can be
+    /// used to blow a small file up to an enormous one
+    #[arg(long)]
+    allow_dtd: bool,
+
+    /// After cutting a convex closed path such as a circle or oval, spiral in from it to its middle,
+    /// e.g. to engrave or pocket it
+    #[arg(long)]
+    spiral_infill: bool,
+
+    /// Distance in millimeters between the turns of --spiral-infill
+    #[arg(long, value_name = "MM", default_value_t = 1.0, value_parser = parse_positive)]
+    line_spacing: f64,
+
+    /// Rotate the design counterclockwise by this many degrees, e.g. to match stock clamped at
+    /// an angle
+    #[arg(long, value_name = "DEGREES", allow_hyphen_values = true, value_parser = parse_number::<f64>)]
+    rotate: Option<f64>,
+
+    /// Point to rotate the design about, rather than its middle
+    #[arg(long, value_name = "X,Y", requires = "rotate", allow_hyphen_values = true, value_parser = parse_position)]
+    rotate_about: Option<[f64; 2]>,
+
+    /// Stop after the first N paths, for trying out settings on part of a large file
+    #[arg(long, value_name = "N", value_parser = parse_number::<usize>)]
+    first_n_paths: Option<usize>,
+
+    /// Turn rects, circles, ellipses, lines, polylines and polygons into paths before converting,
+    /// warning about and removing those that draw nothing
+    #[arg(long)]
+    preflatten: bool,
+
+    /// Convert only the shapes matching a simple CSS selector, or inside an element that does,
+    /// e.g. 'path[stroke="#ff0000"]' or 'g#layer1 > path'
+    #[arg(long, value_name = "SELECTOR")]
+    svg_subset: Option<select::Selector>,
+
+    /// Convert only the shapes in the Inkscape layer with this label, as shown in Inkscape's
+    /// Layers panel. Can be repeated to keep several layers
+    #[arg(long, value_name = "LABEL")]
+    inkscape_layer: Vec<String>,
+
+    /// Convert the operations of a TOML job file in order, each cutting a layer, colour or
+    /// selection of the design with its own settings and number of passes
+    #[arg(long, value_name = "JOB_FILE")]
+    job: Option<std::path::PathBuf>,
+
+    /// Write the output even when the SVG has nothing to cut, instead of warning and skipping it
+    #[arg(long)]
+    allow_empty: bool,
+
+    /// Skip elements that can't be converted (e.g. malformed path data) with a warning, instead of failing
+    #[arg(long)]
+    ignore_errors: bool,
+
+    /// Cut every path from its end to its start
+    #[arg(long)]
+    reverse_paths: bool,
+
+    /// Cut the elements in the opposite order
+    #[arg(long)]
+    reverse_order: bool,
+
+    /// Reorder the elements to shorten the travels between them, nearest first and then improved with 2-opt
+    #[arg(long, conflicts_with = "reverse_order")]
+    optimize_rapid_order: bool,
+
+    /// Most sweeps the 2-opt improvement of --optimize-rapid-order makes
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 100,
+        requires = "optimize_rapid_order"
+    , value_parser = parse_number::<usize>)]
+    optimize_iterations: usize,
+
+    /// After 2-opt, search by branch and bound for the order with the shortest travel of all, for
+    /// jobs of up to --tsp-max-paths elements
+    #[arg(long, requires = "optimize_rapid_order")]
+    tsp_exact: bool,
+
+    /// Most elements --tsp-exact searches for the shortest order of, at most 64; larger jobs keep the 2-opt order
+    #[arg(long, value_name = "N", default_value_t = 50, requires = "tsp_exact", value_parser = parse_number::<usize>)]
+    tsp_max_paths: usize,
+
+    /// Cut straight on from a path to the next instead of lifting, when it starts within this
+    /// many millimeters of where the last one ended, 0 to always lift
+    #[arg(long, value_name = "MM", default_value_t = 0.0, value_parser = parse_number::<f64>)]
+    join_threshold: f64,
+
+    /// Climb mill with a clockwise spindle by cutting outside profiles clockwise and holes counterclockwise
+    #[arg(long, conflicts_with_all = ["reverse_paths", "conventional"])]
+    climb: bool,
+
+    /// Conventional mill with a clockwise spindle by cutting outside profiles counterclockwise and holes clockwise
+    #[arg(long, conflicts_with = "reverse_paths")]
+    conventional: bool,
+
+    /// Left edge of a window in millimeters that output is limited to, clipping cuts crossing its edges
+    #[arg(
+        long,
+        requires_all = ["clip_y", "clip_width", "clip_height"],
+        allow_hyphen_values = true,
+        value_parser = parse_number::<f64>
+    )]
+    clip_x: Option<f64>,
+
+    /// Bottom edge of the clip window in millimeters
+    #[arg(long, requires = "clip_x", allow_hyphen_values = true, value_parser = parse_number::<f64>)]
+    clip_y: Option<f64>,
+
+    /// Width of the clip window in millimeters
+    #[arg(long, requires = "clip_x", value_parser = parse_positive)]
+    clip_width: Option<f64>,
+
+    /// Height of the clip window in millimeters
+    #[arg(long, requires = "clip_x", value_parser = parse_positive)]
+    clip_height: Option<f64>,
+
+    /// Point of the document placed at the machine's origin; bottom-left is how the converter has always placed it
+    #[arg(long, value_enum, default_value_t = postprocess::Anchor::BottomLeft, conflicts_with = "move_to_origin")]
+    anchor: postprocess::Anchor,
+
+    /// Shift the toolpaths so the smallest X and Y of their bounding box are at 0,0
+    #[arg(long)]
+    move_to_origin: bool,
+
+    /// Slow down around corners sharper than an included angle in degrees, e.g. 90:0.5 to halve the feedrate
+    #[arg(long, value_name = "ANGLE:FACTOR")]
+    corner_slowdown: Option<corner::Slowdown>,
+
+    /// How far before and after a corner in millimeters --corner-slowdown slows down for
+    #[arg(long, value_name = "MM", default_value_t = 1.0, value_parser = parse_positive)]
+    corner_distance: f64,
+
+    /// M-codes to remove from the output, e.g. M7,M8 for a controller that rejects them
+    #[arg(long, value_name = "LIST", value_delimiter = ',')]
+    strip_mcodes: Vec<mcodes::MCode>,
+
+    /// Rewrite an M-code as another, keeping its words, e.g. M3=M106; may be repeated
+    #[arg(long, value_name = "FROM=TO")]
+    map_mcode: Vec<mcodes::Mapping>,
+
+    /// Travel limits of the machine's axes in millimeters, warning about every move past them
+    #[arg(
+        long,
+        value_name = "XMIN,XMAX,YMIN,YMAX,ZMIN,ZMAX",
+        allow_hyphen_values = true
+    )]
+    machine_limits: Option<limits::Limits>,
+
+    /// Fail instead of warning when a move goes past --machine-limits
+    #[arg(long, requires = "machine_limits")]
+    strict_limits: bool,
+
+    /// Replace runs of straight cuts lying on a circle with G2/G3 arcs, shrinking the output
+    #[arg(long)]
+    arc_fit_sequences: bool,
+
+    /// How far in millimeters cuts may stray from a circle and still be fitted by --arc-fit-sequences
+    #[arg(long, value_name = "MM", default_value_t = 0.01, value_parser = parse_positive)]
+    arc_tolerance: f64,
+
+    /// Split arcs turning through more than this many degrees into shorter ones, for controllers
+    /// that misbehave on long arcs or full circles
+    #[arg(long, value_name = "DEGREES", requires = "arc_fit_sequences", value_parser = parse_positive)]
+    max_arc_angle: Option<f64>,
+
+    /// Give arc centers as I/J offsets or as an R radius
+    #[arg(long, value_enum, default_value_t = arcs::ArcMode::Ij, requires = "arc_fit_sequences")]
+    arc_mode: arcs::ArcMode,
+
+    /// CSV of x,y,z heights probed on a grid across the work surface, to raise or lower every
+    /// move by, e.g. for engraving a warped PCB. Use with --max-segment-length to follow the
+    /// surface along long cuts
+    #[arg(long, value_name = "FILE")]
+    z_map: Option<std::path::PathBuf>,
+
+    /// Warn about cutting moves shorter than --resolution, likely noise from the conversion, and drop them
+    #[arg(long)]
+    coordinate_precision_validation: bool,
+
+    /// Smallest move the controller can make, in millimeters, for --coordinate-precision-validation
+    #[arg(long, value_name = "MM", default_value_t = 0.001, value_parser = parse_positive, requires = "coordinate_precision_validation")]
+    resolution: f64,
+
+    /// Split straight moves longer than this many millimeters into shorter ones along the same
+    /// line, for controllers that plan long moves badly
+    #[arg(long, value_name = "MM", value_parser = parse_positive)]
+    max_segment_length: Option<f64>,
+
+    /// Repeat the design in a grid of copies, e.g. 3x2 for three across and two up
+    #[arg(long, value_name = "COLUMNSxROWS", requires = "grid_spacing")]
+    grid: Option<tile::Grid>,
+
+    /// Gap between neighbouring copies of --grid in millimeters, across and up
+    #[arg(long, value_name = "X,Y", requires = "grid", value_parser = parse_spacing)]
+    grid_spacing: Option<[f64; 2]>,
+
+    /// Width of the machine's bed in millimeters, which the design, or its --grid copies, must fit in
+    #[arg(long, requires = "bed_height", value_parser = parse_positive)]
+    bed_width: Option<f64>,
+
+    /// Height of the machine's bed in millimeters
+    #[arg(long, requires = "bed_width", value_parser = parse_positive)]
+    bed_height: Option<f64>,
+
+    /// Distance in millimeters to keep the design from the origin and the edges of the bed, either
+    /// one for both axes or X,Y
+    #[arg(long, value_name = "MM", allow_hyphen_values = true, value_parser = parse_margin)]
+    margin: Option<[f64; 2]>,
+
+    /// Clip the paths to the bed, with a warning for each one that extends past it, instead of
+    /// failing when the design doesn't fit
+    #[arg(long, requires = "bed_width")]
+    svg_clip_to_bed: bool,
+
+    /// Pack the designs matched by --input-glob onto the bed side by side, tallest first along
+    /// rows from the origin, printing where each one goes. Fails, giving the bed size they would
+    /// fit on, when they don't all fit
+    #[arg(long, requires_all = ["input_glob", "bed_width"], conflicts_with_all = ["output_dir", "grid", "svg_clip_to_bed"])]
+    auto_nest: bool,
+
+    /// Gap in millimeters to leave between the designs packed by --auto-nest
+    #[arg(long, value_name = "MM", default_value_t = 5.0, requires = "auto_nest", value_parser = parse_number::<f64>)]
+    nest_margin: f64,
+
+    /// Let --auto-nest turn designs a quarter turn counterclockwise to pack them more tightly
+    #[arg(long, requires = "auto_nest")]
+    nest_allow_rotate: bool,
+
+    /// What to do with --grid copies that extend past the bed
+    #[arg(long, value_enum, default_value_t = tile::Overflow::Error)]
+    on_overflow: tile::Overflow,
+
+    /// Snap every X, Y and Z, and the centres of arcs, to the nearest multiple of this grid size in millimeters, e.g. the machine's step resolution
+    #[arg(long, value_name = "MM", value_parser = parse_positive)]
+    snap: Option<f64>,
+
+    /// Units the controller reads F words in; feedrates are configured in mm/min and converted
+    #[arg(long, value_enum, default_value_t = output::FeedrateUnits::MmPerMin)]
+    feedrate_units: output::FeedrateUnits,
+
+    /// Print a preview of the toolpath to the terminal, cuts bright and travels dim
+    #[arg(long)]
+    preview_term: bool,
+
+    /// Width of the terminal preview in characters, defaulting to the terminal's width
+    #[arg(long, requires = "preview_term", value_parser = parse_number::<usize>)]
+    preview_cols: Option<usize>,
+
+    /// Also write an SVG of the toolpath to this file, drawn to scale with travels in red, cuts in blue and the origin in green
+    #[arg(long, value_name = "FILE", conflicts_with = "input_glob")]
+    toolpath_preview_svg: Option<std::path::PathBuf>,
+
+    /// Label each path in the --toolpath-preview-svg with its place in the cutting order
+    #[arg(long, requires = "toolpath_preview_svg")]
+    number_paths: bool,
+
+    /// Also write a job tracing the bounding box of the toolpaths to this file, to check placement before cutting
+    #[arg(long, value_name = "OUTPUT_PATH", conflicts_with = "input_glob")]
+    frame: Option<std::path::PathBuf>,
+
+    /// Set the laser power (S word) of each element's cuts from its opacity, from 0 when transparent up to this power when opaque
+    #[arg(long, value_name = "MAX_POWER", value_parser = parse_number::<f64>)]
+    laser_power_map: Option<f64>,
+
+    /// Trace the frame with the tool on at this power (S word), e.g. a laser at low power, instead of with the tool lifted
+    #[arg(long, requires = "frame", value_parser = parse_number::<f64>)]
+    frame_power: Option<f64>,
+
+    /// Number of times to go around the frame
+    #[arg(long, default_value_t = 1, requires = "frame", value_parser = parse_number::<usize>)]
+    frame_loops: usize,
+
+    /// Feedrate in millimeters per minute for moves down into the material, such as the tool-on
+    /// plunge, instead of a rapid
+    #[arg(long, value_name = "MM_PER_MIN", value_parser = parse_positive)]
+    plunge_feedrate: Option<f64>,
+
+    /// Dwell for this many milliseconds (G4 P) after each path before moving to the next, e.g. to let the material cool
+    #[arg(long, value_name = "MS", value_parser = parse_number::<u64>)]
+    segment_dwell: Option<u64>,
+
+    /// Keep the spindle running between paths with only a travel between them, lifting and
+    /// lowering the tool without the M5 and M3 of the tool-off and tool-on sequences
+    #[arg(long)]
+    keep_spindle_running: bool,
+
+    /// Pause with M0 before each path after the first, e.g. for pen or material changes
+    #[arg(long)]
+    pause_between_paths: bool,
+
+    /// X position to park the tool at during pauses
+    #[arg(
+        long,
+        requires = "pause_between_paths",
+        allow_hyphen_values = true,
+        value_parser = parse_number::<f64>
+    )]
+    park_x: Option<f64>,
+
+    /// Y position to park the tool at during pauses
+    #[arg(
+        long,
+        requires = "pause_between_paths",
+        allow_hyphen_values = true,
+        value_parser = parse_number::<f64>
+    )]
+    park_y: Option<f64>,
+
+    /// Height the tool-off sequence lifts to between paths, e.g. a low hop to save time
+    #[arg(long, value_name = "Z", allow_hyphen_values = true, value_parser = parse_number::<f64>)]
+    travel_z: Option<f64>,
+
+    /// Height the tool-off sequence ending the job lifts to, e.g. high enough to clear clamps
+    #[arg(long, value_name = "Z", allow_hyphen_values = true, value_parser = parse_number::<f64>)]
+    park_z: Option<f64>,
+
+    /// Height the first travel of the job and the last lift go to, e.g. to clear clamps at the
+    /// edges of the stock, with --park-z taking over the last lift when given
+    #[arg(long, value_name = "Z", allow_hyphen_values = true, value_parser = parse_number::<f64>)]
+    clearance_z: Option<f64>,
+
+    /// Rapid to this X,Y position once the job is done, e.g. to bring the bed forward
+    #[arg(long, value_name = "X,Y", allow_hyphen_values = true, value_parser = parse_position)]
+    park: Option<[f64; 2]>,
+
+    /// Don't check that the program ends with the tool off and lifted, adding what's missing
+    #[arg(long)]
+    no_final_safety: bool,
+
+    /// Don't make sure an F is set on or before the first cutting move
+    #[arg(long)]
+    no_feed_first: bool,
+
+    /// Laser-engrave embedded <image> elements as scanlines after the vector paths
+    #[cfg(feature = "raster")]
+    #[arg(long)]
+    raster: bool,
+
+    /// Laser power (S word) used for the lightest pixels when rastering
+    #[cfg(feature = "raster")]
+    #[arg(long, default_value_t = 0.0, value_parser = parse_number::<f64>)]
+    min_power: f64,
+
+    /// Laser power (S word) used for black pixels when rastering
+    #[cfg(feature = "raster")]
+    #[arg(long, default_value_t = 1000.0, value_parser = parse_number::<f64>)]
+    max_power: f64,
+
+    /// Scanline resolution for rastering, in dots per inch
+    #[cfg(feature = "raster")]
+    #[arg(long, default_value_t = 254.0, value_parser = parse_number::<f64>)]
+    raster_dpi: f64,
+
+    /// Only print errors, leaving out warnings, progress and the success message
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Print more about the conversion, such as the scale each element's transforms flatten to
+    #[arg(short, long, conflicts_with = "quiet")]
+    verbose: bool,
+
+    /// Warn when the toolpath is larger than the document's declared width or height by more than this percentage
+    #[arg(long, value_name = "PERCENT", default_value_t = 5.0, value_parser = parse_number::<f64>)]
+    size_tolerance: f64,
+
+    /// Print how long each stage of the conversion took to stderr
+    #[arg(long)]
+    profile_conversion: bool,
+
+    /// Run the whole conversion but don't write the output, e.g. to check an SVG converts
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+
+    /// Convert <text> elements into outlines of their glyphs, using the font given by --font
+    #[cfg(feature = "text")]
+    #[arg(long)]
+    text_to_path: bool,
+
+    /// TrueType or OpenType font file used by --text-to-path
+    #[cfg(feature = "text")]
+    #[arg(long)]
+    font: Option<std::path::PathBuf>,
+
+    #[arg(required_unless_present_any = ["dry_run", "input_glob"])]
+    output_path: Option<std::path::PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the SVG's dimensions, path count, layers and colours without converting it
+    Info {
+        input_path: std::path::PathBuf,
+
+        /// Read the SVG's DTD, expanding the entities it declares
+        #[arg(long)]
+        allow_dtd: bool,
+    },
+    /// Time each phase of converting an SVG, with the default output options
+    Bench {
+        input_path: std::path::PathBuf,
+
+        /// Override a setting by its dotted key, as for a conversion. Can be repeated
+        #[arg(long = "set", value_name = "KEY=VALUE")]
+        overrides: Vec<settings::Override>,
+
+        /// Fit arcs to the converted cuts, within this many millimeters, as --arc-fit-sequences does
+        #[arg(long, value_name = "MM", value_parser = parse_positive)]
+        arc_tolerance: Option<f64>,
+
+        /// Number of times to run the conversion
+        #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+        iterations: u32,
+
+        /// Run with the settings of each TOML file, e.g. `[conversion]` `tolerance = 0.01`, and
+        /// compare them
+        #[arg(long, num_args = 2, value_names = ["CONFIG_A", "CONFIG_B"])]
+        compare: Option<Vec<std::path::PathBuf>>,
+
+        /// Print the results as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Read the SVG's DTD, expanding the entities it declares
+        #[arg(long)]
+        allow_dtd: bool,
+    },
 }
 
 fn sanitise_string(s: &str) -> String {
@@ -29,139 +668,2218 @@ fn sanitise_string(s: &str) -> String {
             os.push(c);
         }
     }
-    return os;
+    os
 }
 
-fn main() {
-    let args = Args::parse();
+/// The number without the underscores or thin spaces it may be grouped with, e.g. `10_000`
+fn ungrouped(s: &str) -> String {
+    s.chars()
+        .filter(|&c| !matches!(c, '_' | '\u{2009}' | '\u{202f}'))
+        .collect()
+}
 
-    let svg_file = fs::File::open(&args.input_path);
-    let mut svg_xml: String = String::new();
-    let _ = match svg_file {
-        Ok(mut file) => file.read_to_string(&mut svg_xml),
-        Err(err) => panic!(
-            "Could not open svg file: {}, failed with error: {}",
-            args.input_path.display(),
-            err
-        ),
+/// Parses a number that may be grouped by underscores or thin spaces
+fn parse_number<T>(s: &str) -> Result<T, String>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    ungrouped(s)
+        .trim()
+        .parse()
+        .map_err(|err: T::Err| err.to_string())
+}
+
+/// Parses a scale such as `0.5`, `50%` or `-1`, which must be finite and not zero
+fn parse_scale(s: &str) -> Result<f64, String> {
+    let s = &ungrouped(s);
+    let (number, divisor) = match s.trim().strip_suffix('%') {
+        Some(percentage) => (percentage, 100.0),
+        None => (s.trim(), 1.0),
     };
+    let scale = number
+        .trim()
+        .parse::<f64>()
+        .map_err(|err| format!("expected a number or percentage, {}", err))?
+        / divisor;
+    if !scale.is_finite() || scale == 0.0 {
+        return Err("scale must be a finite number other than zero".to_string());
+    }
+    Ok(scale)
+}
 
-    let doc: roxmltree::Document<'_> = match roxmltree::Document::parse_with_options(
-        svg_xml.as_str(),
-        ParsingOptions {
-            allow_dtd: true,
-            ..Default::default()
+/// Parses a finite number greater than zero
+fn parse_positive(s: &str) -> Result<f64, String> {
+    let s = &ungrouped(s);
+    match s.trim().parse::<f64>() {
+        Ok(value) if value.is_finite() && value > 0.0 => Ok(value),
+        Ok(_) => Err("must be a finite number greater than zero".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Largest SVG file read when no --max-input-size is given
+const DEFAULT_MAX_INPUT_SIZE: u64 = 64 << 20;
+
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = &ungrouped(s);
+    let trimmed = s.trim();
+    let (number, shift) = match trimmed.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&trimmed[..i], 10),
+        Some((i, 'm' | 'M')) => (&trimmed[..i], 20),
+        Some((i, 'g' | 'G')) => (&trimmed[..i], 30),
+        _ => (trimmed, 0),
+    };
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(1 << shift))
+        .ok_or_else(|| format!("expected a size such as 64M or 1048576, got \"{}\"", s))
+}
+
+fn parse_spacing(s: &str) -> Result<[f64; 2], String> {
+    let s = &ungrouped(s);
+    let gap = |value: &str| match value.trim().parse::<f64>() {
+        Ok(value) if value.is_finite() && value >= 0.0 => Ok(value),
+        Ok(_) => Err("gaps must be finite and zero or more".to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+    let (x, y) = s
+        .split_once(',')
+        .ok_or_else(|| format!("expected <x>,<y> such as 5,5, got \"{}\"", s))?;
+    Ok([gap(x)?, gap(y)?])
+}
+
+fn parse_margin(s: &str) -> Result<[f64; 2], String> {
+    let s = &ungrouped(s);
+    match s.contains(',') {
+        true => parse_spacing(s).map_err(|err| err.replace("gaps", "margins")),
+        false => match s.trim().parse::<f64>() {
+            Ok(margin) if margin.is_finite() && margin >= 0.0 => Ok([margin, margin]),
+            Ok(_) => Err("margins must be finite and zero or more".to_string()),
+            Err(err) => Err(err.to_string()),
         },
-    ) {
-        Ok(doc) => doc,
-        Err(err) => panic!(
-            "Could not parse svg file: {}, failed with error: {}",
-            args.input_path.display(),
-            err
-        ),
+    }
+}
+
+fn parse_position(s: &str) -> Result<[f64; 2], String> {
+    let s = &ungrouped(s);
+    let coordinate = |value: &str| match value.trim().parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        Ok(_) => Err("coordinates must be finite".to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+    let (x, y) = s
+        .split_once(',')
+        .ok_or_else(|| format!("expected <x>,<y> such as 0,200, got \"{}\"", s))?;
+    Ok([coordinate(x)?, coordinate(y)?])
+}
+
+/// Settings from the defaults, then those embedded in the document, then `--set` overrides
+fn build_settings(embedded: &[(String, String)], args: &Args) -> settings::Settings {
+    let mut settings = settings::Settings::default();
+    for (name, value) in embedded {
+        if let Err(err) = settings.set_by_name(name, value) {
+            warning!("ignoring the svg's embedded setting {}: {}", name, err);
+        }
+    }
+    apply_overrides(&mut settings, &args.overrides);
+    settings
+}
+
+fn apply_overrides(settings: &mut settings::Settings, overrides: &[settings::Override]) {
+    for entry in overrides {
+        if let Err(err) = settings.set(&entry.key, &entry.value) {
+            panic!(
+                "Could not apply --set {}, failed with error: {}",
+                entry.key, err
+            );
+        }
+    }
+}
+
+fn check_options(args: &Args, settings: &settings::Settings) {
+    let problems = validate(args, settings);
+    if !problems.is_empty() {
+        panic!("Invalid options:\n{}", problems.join("\n"));
+    }
+}
+
+/// Checks every numeric option and setting, describing each one that is out of range
+fn validate(args: &Args, settings: &settings::Settings) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut positive = |name: &str, value: f64| {
+        if !value.is_finite() || value <= 0.0 {
+            problems.push(format!(
+                "{} must be a number greater than zero, got {}",
+                name, value
+            ));
+        }
     };
+    positive("conversion.feedrate", settings.conversion.feedrate);
+    positive("conversion.tolerance", settings.conversion.tolerance);
+    positive("conversion.dpi", settings.conversion.dpi);
+    #[cfg(feature = "raster")]
+    positive("--raster-dpi", args.raster_dpi);
 
-    let scaling_factor = match args.scale {
-        Some(scale) => scale,
-        None => 1.0,
+    let mut non_negative = |name: &str, value: f64| {
+        if !value.is_finite() || value < 0.0 {
+            problems.push(format!("{} must be zero or more, got {}", name, value));
+        }
     };
+    non_negative("--min-dash", args.min_dash);
+    non_negative("--width-tolerance", args.width_tolerance);
+    non_negative("--join-threshold", args.join_threshold);
+    non_negative("--size-tolerance", args.size_tolerance);
+    non_negative("--nest-margin", args.nest_margin);
+    if let Some(power) = args.laser_power_map {
+        non_negative("--laser-power-map", power);
+    }
+    if let Some(power) = args.frame_power {
+        non_negative("--frame-power", power);
+    }
+    #[cfg(feature = "raster")]
+    {
+        non_negative("--min-power", args.min_power);
+        non_negative("--max-power", args.max_power);
+    }
+
+    if args.tsp_max_paths > order::EXACT_LIMIT {
+        problems.push(format!(
+            "--tsp-max-paths must be at most {}, got {}",
+            order::EXACT_LIMIT,
+            args.tsp_max_paths
+        ));
+    }
+
+    for (name, value) in [
+        ("--park-x", args.park_x),
+        ("--park-y", args.park_y),
+        ("--clip-x", args.clip_x),
+        ("--clip-y", args.clip_y),
+        ("--tool-change-x", args.tool_change_x),
+        ("--tool-change-y", args.tool_change_y),
+    ] {
+        if let Some(value) = value.filter(|value| !value.is_finite()) {
+            problems.push(format!("{} must be a finite number, got {}", name, value));
+        }
+    }
+    // Pauses are found by the first line of the tool-on sequence
+    if args.pause_between_paths && gcode::snippet_lines(&settings.machine.tool_on).is_empty() {
+        problems.push("machine.tool_on must not be empty with --pause-between-paths".to_string());
+    }
+    // The lowest the tool-on sequence takes the tool, which lifts have to clear
+    let work_z = gcode::snippet_lines(&settings.machine.tool_on)
+        .iter()
+        .filter_map(|line| line.value("Z"))
+        .reduce(f64::min);
+    for (name, height) in [
+        ("--travel-z", args.travel_z),
+        ("--clearance-z", args.clearance_z),
+        ("--park-z", args.park_z),
+    ] {
+        match (height, work_z) {
+            (Some(height), Some(work_z)) if height <= work_z => problems.push(format!(
+                "{} must be above the Z of machine.tool_on, {}, got {}",
+                name, work_z, height
+            )),
+            _ => {}
+        }
+    }
+    if let (Some(clearance), Some(travel)) = (args.clearance_z, args.travel_z) {
+        if clearance < travel {
+            problems.push(format!(
+                "--clearance-z must be at least --travel-z, {}, got {}",
+                travel, clearance
+            ));
+        }
+    }
+    if let Err(err) = gcode::template_lines(&args.tool_change_command.replace("{n}", "1")) {
+        problems.push(format!(
+            "--tool-change-command could not be parsed: {}",
+            err
+        ));
+    }
+    if args.flavor == output::Flavor::Fanuc {
+        if args.gcode_checksum {
+            problems.push("--gcode-checksum is not understood by fanuc controllers".to_string());
+        }
+        for (name, snippet) in [
+            ("machine.tool_on", &settings.machine.tool_on),
+            ("machine.tool_off", &settings.machine.tool_off),
+            ("machine.begin", &settings.machine.begin),
+            ("machine.end", &settings.machine.end),
+            ("--tool-change-command", &args.tool_change_command),
+        ] {
+            let words = gcode::template_lines(&snippet.replace("{n}", "1")).unwrap_or_default();
+            if let Some(word) = words.iter().find_map(output::non_fanuc_word) {
+                problems.push(format!(
+                    "{} uses the word {}, which fanuc controllers don't support",
+                    name, word
+                ));
+            }
+        }
+    }
+    if args.bom && args.output_encoding == output::Encoding::Ascii {
+        problems.push(
+            "--bom can't be written to a file limited to --output-encoding ascii".to_string(),
+        );
+    }
+    problems
+}
+
+/// Number of a root `width` or `height`, taken as millimeters
+fn document_length(value: &str, name: &str) -> f64 {
+    let value = &decimal_comma(value, name);
+    match sanitise_string(value).parse::<f64>() {
+        Ok(length) if length > 0.0 => length,
+        _ => panic!(
+            "Could not read the svg's {} \"{}\", expected a length greater than zero",
+            name, value
+        ),
+    }
+}
 
-    let doc_width = doc.root().first_child().unwrap().attribute("width");
-    let doc_height = doc.root().first_child().unwrap().attribute("height");
+/// Warns, or with `--strict` fails, when the converted toolpath is larger than the document's
+/// declared size by more than `--size-tolerance`
+fn check_size(
+    bounds: Option<[[f64; 2]; 2]>,
+    declared: [f64; 2],
+    args: &Args,
+    input_path: &std::path::Path,
+    transforms: &[String],
+) {
+    let Some([min, max]) = bounds else {
+        return;
+    };
+    let size = [max[0] - min[0], max[1] - min[1]];
+    let limit = 1.0 + args.size_tolerance / 100.0;
+    if size[0] <= declared[0] * limit && size[1] <= declared[1] * limit {
+        return;
+    }
+    let mut message = format!(
+        "the toolpath measures {:.3} x {:.3} mm, more than {}% larger than the document's declared {:.3} x {:.3} mm, so the output may not be the size you expect",
+        size[0], size[1], args.size_tolerance, declared[0], declared[1]
+    );
+    if !transforms.is_empty() {
+        message += &format!(
+            ", check the {} transforms listed with --verbose",
+            transforms.len()
+        );
+    }
+    if args.strict {
+        panic!(
+            "Could not convert svg file: {}, {}",
+            input_path.display(),
+            message
+        );
+    }
+    warning!("{}", message);
+}
 
-    let mut dimensions: [Option<svgtypes::Length>; 2] = [None, None];
+/// The length with a comma used as its decimal separator, as some exporters write it in
+/// their locale, replaced by a point. Only a single comma that can't be digit grouping is
+/// read as a decimal separator; any other comma fails.
+fn decimal_comma(value: &str, name: &str) -> String {
+    let Some((whole, fraction)) = value.split_once(',') else {
+        return value.to_string();
+    };
+    let digits = fraction.chars().take_while(char::is_ascii_digit).count();
+    let unambiguous = whole.ends_with(|c: char| c.is_ascii_digit())
+        && digits > 0
+        && digits != 3
+        && !fraction.contains(',')
+        && !value.contains('.');
+    if !unambiguous {
+        panic!(
+            "Could not read the svg's {} \"{}\", its comma could be a decimal separator or digit grouping, write the length with a point as the decimal separator",
+            name, value
+        );
+    }
+    let fixed = value.replacen(',', ".", 1);
+    warning!(
+        "the svg's {} \"{}\" uses a comma as its decimal separator, which isn't standard SVG, reading it as \"{}\"",
+        name,
+        value,
+        fixed
+    );
+    fixed
+}
 
-    if doc_width.is_some() && doc_height.is_some() {
-        dimensions = [
+/// The document's width and height in millimeters, scaled, when it gives both
+fn dimensions(doc: &roxmltree::Document, scaling_factor: f64) -> [Option<svgtypes::Length>; 2] {
+    let doc_width = doc.root_element().attribute("width");
+    let doc_height = doc.root_element().attribute("height");
+    match (doc_width, doc_height) {
+        (Some(doc_width), Some(doc_height)) => [
             Some(svgtypes::Length {
-                number: (sanitise_string(doc_width.unwrap()).parse::<f64>().unwrap()
-                    * scaling_factor),
+                number: document_length(doc_width, "width") * scaling_factor,
                 unit: svgtypes::LengthUnit::Mm,
             }),
             Some(svgtypes::Length {
-                number: (sanitise_string(doc_height.unwrap()).parse::<f64>().unwrap()
-                    * scaling_factor),
+                number: document_length(doc_height, "height") * scaling_factor,
                 unit: svgtypes::LengthUnit::Mm,
             }),
-        ]
+        ],
+        _ => [None, None],
     }
+}
 
-    let conversion_config = ConversionConfig {
-        tolerance: 0.001,
-        feedrate: 1000.0,
-        dpi: 100.0,
+fn conversion_config(settings: &settings::Settings) -> ConversionConfig {
+    ConversionConfig {
+        tolerance: settings.conversion.tolerance,
+        feedrate: settings.conversion.feedrate,
+        dpi: settings.conversion.dpi,
         origin: [Some(0.0), Some(0.0)],
-    };
+    }
+}
 
-    let machine = Machine::new(
+/// The machine the settings describe, with their tool and program snippets
+fn machine(settings: &settings::Settings) -> Machine<'_> {
+    Machine::new(
         SupportedFunctionality {
             circular_interpolation: false,
         },
-        Some(snippet_parser("M3 G0 Z0.0").expect("Could not parse tool start snippet")),
-        Some(snippet_parser("M5 G0 Z3.0").expect("Could not parse tool stop snippet")),
-        None,
-        None,
-    );
-
-    let conversion_options = ConversionOptions {
-        dimensions: dimensions,
-    };
-
-    let gcode = svg2program(&doc, &conversion_config, conversion_options, machine);
+        Some(
+            snippet_parser(&settings.machine.tool_on).expect("Could not parse tool start snippet"),
+        ),
+        Some(
+            snippet_parser(&settings.machine.tool_off).expect("Could not parse tool stop snippet"),
+        ),
+        (!settings.machine.begin.is_empty()).then(|| {
+            snippet_parser(&settings.machine.begin).expect("Could not parse program begin snippet")
+        }),
+        (!settings.machine.end.is_empty()).then(|| {
+            snippet_parser(&settings.machine.end).expect("Could not parse program end snippet")
+        }),
+    )
+}
 
-    match args.output_path.parent() {
-        Some(parent) => match fs::create_dir_all(parent) {
-            Ok(_) => (),
-            Err(err) => panic!(
-                "Could not create output file's parent directory(ies), faile with error: {}",
-                err
-            ),
-        },
-        None => (),
-    };
+/// Collects the text of the document's <title> and <desc> elements as header comment lines
+fn document_description(doc: &roxmltree::Document) -> Vec<String> {
+    let mut comments = Vec::new();
+    for tag in ["title", "desc"] {
+        let text = doc
+            .root_element()
+            .children()
+            .find(|child| child.has_tag_name(tag))
+            .and_then(|node| node.text());
+        if let Some(text) = text {
+            for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+                comments.push(format!("{}: {}", tag, line));
+            }
+        }
+    }
+    comments
+}
 
-    match args.output_path.try_exists() {
-        Ok(exists) => match exists {
-            true => fs::remove_file(&args.output_path)
-                .expect("Failed to remove existing file at provided output path"),
-            false => {}
+fn parse_document<'a>(
+    svg_xml: &'a str,
+    path: &std::path::Path,
+    allow_dtd: bool,
+) -> roxmltree::Document<'a> {
+    match roxmltree::Document::parse_with_options(
+        svg_xml,
+        ParsingOptions {
+            allow_dtd,
+            ..Default::default()
         },
-        Err(err) => panic!("{}", err),
-    };
-
-    let mut output_file = match OpenOptions::new()
-        .create(true)
-        .write(true)
-        .append(true)
-        .open(args.output_path.clone())
-    {
-        Ok(output) => output,
+    ) {
+        Ok(doc) => doc,
+        Err(roxmltree::Error::DtdDetected) => panic!(
+            "Could not parse svg file: {}, it has a DTD, which isn't read unless --allow-dtd is given",
+            path.display()
+        ),
         Err(err) => panic!(
-            "Could not create/open output file, failed with error: {}",
+            "Could not parse svg file: {}, failed with error: {}",
+            path.display(),
             err
         ),
-    };
+    }
+}
 
-    for line in gcode.iter() {
-        if line.to_string().starts_with("X")
-            || line.to_string().starts_with("Y")
-            || line.to_string().starts_with("Z")
-            || line.to_string().starts_with("F")
-        {
-            if let Err(err) = write!(output_file, " {}", line.to_string()) {
-                panic!("Couldn't write to file: {}", err);
+/// Applies the `--svg-fix` repairs, telling the user about each one
+fn fix_svg(svg_xml: String, path: &std::path::Path, args: &Args) -> String {
+    let base = path
+        .parent()
+        .and_then(|parent| fs::canonicalize(parent).ok())
+        .unwrap_or_default();
+    let (replacements, fixed) =
+        preprocess::fixes(&parse_document(&svg_xml, path, args.allow_dtd), &base);
+    if !args.quiet {
+        for fix in fixed.iter() {
+            eprintln!("Fixed: {}", fix);
+        }
+    }
+    match replacements.is_empty() {
+        true => svg_xml,
+        false => preprocess::replace_ranges(&svg_xml, &replacements),
+    }
+}
+
+/// Reads the SVG file, or stdin when the path is `-`, unless it's larger than `max_size` bytes, and cleans up anything that would trip up the XML parser
+fn read_svg(path: &std::path::Path, max_size: u64, format: preprocess::InputFormat) -> String {
+    let stdin = path == std::path::Path::new("-");
+    if let Some(metadata) = fs::metadata(path).ok().filter(|_| !stdin) {
+        if metadata.len() > max_size {
+            panic!(
+                "Could not open svg file: {}, it is {} bytes, over the --max-input-size of {} bytes",
+                path.display(),
+                metadata.len(),
+                max_size
+            );
+        }
+    }
+    let bytes = match stdin {
+        true => {
+            let mut bytes = Vec::new();
+            io::stdin()
+                .take(max_size + 1)
+                .read_to_end(&mut bytes)
+                .map(|_| bytes)
+        }
+        false => fs::read(path),
+    };
+    let bytes = match bytes {
+        Ok(bytes) if bytes.len() as u64 > max_size => panic!(
+            "Could not open svg file: {}, it is over the --max-input-size of {} bytes",
+            path.display(),
+            max_size
+        ),
+        Ok(bytes) => bytes,
+        Err(err) => panic!(
+            "Could not open svg file: {}, failed with error: {}",
+            path.display(),
+            err
+        ),
+    };
+    let bytes = match preprocess::decompress(bytes, format, max_size) {
+        Ok(bytes) => bytes,
+        Err(err) => panic!("Could not open svg file: {}, {}", path.display(), err),
+    };
+    let svg_xml = match preprocess::decode(&bytes) {
+        Ok(text) => text,
+        Err(err) => panic!(
+            "Could not decode svg file: {}, failed with error: {}",
+            path.display(),
+            err
+        ),
+    };
+
+    let (svg_xml, removed) = preprocess::strip_processing_instructions(&svg_xml);
+    if !removed.is_empty() {
+        warning!(
+            "removed unsupported XML processing instructions:\n  {}",
+            removed.join("\n  ")
+        );
+    }
+    svg_xml
+}
+
+fn main() {
+    let mut args = Args::parse();
+    // Without an input file, the one positional argument is the output file
+    if args.input_glob.is_some() && args.output_path.is_none() {
+        args.output_path = args.input_path.take();
+    }
+    output::set_quiet(args.quiet);
+    output::handle_interrupts();
+
+    if args.version_info {
+        println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        println!("svg2gcode {}", env!("DEP_VERSION_SVG2GCODE"));
+        println!("g-code {}", env!("DEP_VERSION_G_CODE"));
+        println!("roxmltree {}", env!("DEP_VERSION_ROXMLTREE"));
+        println!("svgtypes {}", env!("DEP_VERSION_SVGTYPES"));
+        return;
+    }
+
+    if let Some(shell) = args.generate_completions {
+        let mut command = Args::command();
+        let name = command.get_name().to_string();
+        clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+        return;
+    }
+
+    if let Some(Command::Info {
+        input_path,
+        allow_dtd,
+    }) = &args.command
+    {
+        let svg_xml = read_svg(input_path, DEFAULT_MAX_INPUT_SIZE, args.input_format);
+        info::print(&parse_document(&svg_xml, input_path, *allow_dtd));
+        return;
+    }
+    if let Some(Command::Bench {
+        input_path,
+        overrides,
+        arc_tolerance,
+        iterations,
+        compare,
+        json,
+        allow_dtd,
+    }) = &args.command
+    {
+        // Warnings would repeat on every run and get in the way of the results
+        output::set_quiet(true);
+        let runs: Vec<(String, settings::Settings)> = match compare {
+            Some(configs) => configs
+                .iter()
+                .map(|config| {
+                    let text = match fs::read_to_string(config) {
+                        Ok(text) => text,
+                        Err(err) => panic!(
+                            "Could not read config file: {}, failed with error: {}",
+                            config.display(),
+                            err
+                        ),
+                    };
+                    let mut settings = settings::Settings::default();
+                    if let Err(err) = settings.merge_toml(&text) {
+                        panic!(
+                            "Could not use config file: {}, failed with error: {}",
+                            config.display(),
+                            err
+                        );
+                    }
+                    apply_overrides(&mut settings, overrides);
+                    check_options(&args, &settings);
+                    (config.display().to_string(), settings)
+                })
+                .collect(),
+            None => {
+                let mut settings = settings::Settings::default();
+                apply_overrides(&mut settings, overrides);
+                check_options(&args, &settings);
+                vec![("default settings".to_string(), settings)]
             }
-        } else if line.to_string().starts_with(";") {
-            // ignore comments
-        } else {
-            if let Err(err) = write!(output_file, "\n{}", line.to_string()) {
-                panic!("Couldn't write to file: {}", err);
+        };
+        let options = bench::BenchOptions {
+            iterations: *iterations,
+            arc_tolerance: *arc_tolerance,
+            allow_dtd: *allow_dtd,
+        };
+        let reports: Vec<bench::Report> = runs
+            .into_iter()
+            .map(|(label, settings)| bench::run(label, input_path, &settings, &options, &args))
+            .collect();
+        match json {
+            true => println!("{}", bench::to_json(&reports)),
+            false => bench::print(&reports),
+        }
+        return;
+    }
+    // Validated before any file is read, and again once the document's own settings are known
+    let settings = build_settings(&[], &args);
+    check_options(&args, &settings);
+
+    if args.modal && args.gcode_checksum && !args.force_modal {
+        warning!("ignoring --modal with --gcode-checksum, add --force-modal to use it anyway");
+    }
+
+    let template = args.output_template.as_ref().map(|path| {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) => panic!(
+                "Could not read output template: {}, failed with error: {}",
+                path.display(),
+                err
+            ),
+        };
+        match output::Template::parse(&text, line_ending(&args)) {
+            Ok(template) => template,
+            Err(err) => panic!("Could not use output template: {}, {}", path.display(), err),
+        }
+    });
+
+    let Some(pattern) = &args.input_glob else {
+        let Some(input_path) = &args.input_path else {
+            unreachable!("input path is required without a subcommand");
+        };
+        let destination = Destination::File(args.output_path.as_deref());
+        convert(&args, input_path, settings, template.as_ref(), destination);
+        return;
+    };
+    if args.input_path.is_some() {
+        panic!("Could not use --input-glob, it takes the output file in place of an input file");
+    }
+    let inputs = glob_inputs(pattern);
+    if let Some(output_dir) = &args.output_dir {
+        if args.output_path.is_some() {
+            panic!("Could not use --output-dir, it takes the place of an output file");
+        }
+        for input_path in &inputs {
+            let output_path = output_dir
+                .join(input_path.file_stem().unwrap_or_default())
+                .with_extension("gcode");
+            let destination = Destination::File(Some(&output_path));
+            convert(
+                &args,
+                input_path,
+                settings.clone(),
+                template.as_ref(),
+                destination,
+            );
+        }
+        return;
+    }
+    if args.output_path.is_none() && !args.dry_run {
+        panic!("Could not use --input-glob, give an output file or --output-dir to write to");
+    }
+    let mut programs = Vec::new();
+    for (i, input_path) in inputs.iter().enumerate() {
+        let mut program = Vec::new();
+        let destination = Destination::Combined {
+            program: &mut program,
+            first: i == 0,
+            last: i == inputs.len() - 1,
+        };
+        convert(
+            &args,
+            input_path,
+            settings.clone(),
+            template.as_ref(),
+            destination,
+        );
+        programs.push(program);
+    }
+    let program = match args.auto_nest {
+        true => nest_programs(&inputs, programs, &args),
+        false => programs.concat(),
+    };
+    let Some(output_path) = args.output_path.as_ref().filter(|_| !args.dry_run) else {
+        return;
+    };
+    write_program(output_path, program, template.as_ref(), &args, || true);
+    if !args.quiet {
+        println!(
+            "Successfully created gcode from {} files at: {}",
+            inputs.len(),
+            output_path.display()
+        );
+    }
+}
+
+/// Packs the programs of the inputs onto the bed for --auto-nest, reporting where each goes
+fn nest_programs(
+    inputs: &[std::path::PathBuf],
+    programs: Vec<Vec<Line>>,
+    args: &Args,
+) -> Vec<Line> {
+    let (Some(width), Some(height)) = (args.bed_width, args.bed_height) else {
+        unreachable!("--auto-nest requires the bed size");
+    };
+    // The margin is kept clear all round the bed, and the designs are packed inside it
+    let margin = args.margin.unwrap_or([0.0, 0.0]);
+    let bounds: Vec<_> = programs
+        .iter()
+        .map(|lines| postprocess::bounds(lines))
+        .collect();
+    let sizes: Vec<[f64; 2]> = bounds
+        .iter()
+        .flatten()
+        .map(|[min, max]| [max[0] - min[0], max[1] - min[1]])
+        .collect();
+    let options = nest::NestOptions {
+        area: [width - 2.0 * margin[0], height - 2.0 * margin[1]],
+        gap: args.nest_margin,
+        allow_rotate: args.nest_allow_rotate,
+    };
+    // Suggested bed sizes go up to the next tenth of a millimeter
+    let tenths = |mm: f64| (mm * 10.0 - 1e-6).ceil() / 10.0;
+    let mut placements = match nest::pack(&sizes, &options) {
+        Ok(placements) => placements.into_iter(),
+        Err(needed) => panic!(
+            "Could not nest the designs on the {}x{}mm bed, they need a bed of at least {}x{}mm",
+            width,
+            height,
+            tenths(needed[0] + 2.0 * margin[0]),
+            tenths(needed[1] + 2.0 * margin[1])
+        ),
+    };
+
+    let mut nested = Vec::new();
+    for ((input_path, lines), bounds) in inputs.iter().zip(programs).zip(&bounds) {
+        // Programs left empty have nothing to place
+        if bounds.is_none() {
+            nested.extend(lines);
+            continue;
+        }
+        let mut placement = placements.next().unwrap();
+        placement.position = [
+            placement.position[0] + margin[0],
+            placement.position[1] + margin[1],
+        ];
+        // Moved by whole steps of the grid, the snapped moves stay on it
+        if let Some(grid) = args.snap {
+            placement.position = placement.position.map(|value| (value / grid).ceil() * grid);
+        }
+        if !args.quiet {
+            println!(
+                "Placed {} at X{} Y{}{}",
+                input_path.display(),
+                placement.position[0],
+                placement.position[1],
+                match placement.rotated {
+                    true => ", turned a quarter turn",
+                    false => "",
+                }
+            );
+        }
+        nested.extend(nest::place(lines, placement));
+    }
+    if args.preview_term {
+        let columns = args.preview_cols.unwrap_or_else(|| {
+            terminal_size::terminal_size().map_or(80, |(width, _)| width.0 as usize)
+        });
+        preview::print(&nested, columns, args.margin);
+    }
+    nested
+}
+
+/// Where a converted program goes
+enum Destination<'a> {
+    /// Its own file, not needed for a dry run
+    File(Option<&'a std::path::Path>),
+    /// After the programs of the inputs before it, only the first beginning the combined program
+    /// and only the last ending it
+    Combined {
+        program: &'a mut Vec<Line>,
+        first: bool,
+        last: bool,
+    },
+}
+
+/// Paths matching the pattern, in alphabetical order
+fn glob_inputs(pattern: &str) -> Vec<std::path::PathBuf> {
+    let paths = match glob::glob(pattern) {
+        Ok(paths) => paths,
+        Err(err) => panic!(
+            "Could not use --input-glob {}, failed with error: {}",
+            pattern, err
+        ),
+    };
+    let mut inputs: Vec<std::path::PathBuf> = paths
+        .filter_map(|path| match path {
+            Ok(path) => Some(path),
+            Err(err) => {
+                warning!("skipping {}", err);
+                None
             }
+        })
+        .collect();
+    if inputs.is_empty() {
+        panic!(
+            "Could not use --input-glob {}, it matches no files",
+            pattern
+        );
+    }
+    inputs.sort();
+    inputs
+}
+
+/// Converts one SVG, checking its embedded settings against the options
+fn convert(
+    args: &Args,
+    input_path: &std::path::Path,
+    settings: settings::Settings,
+    template: Option<&output::Template>,
+    destination: Destination,
+) {
+    let mut profile = output::Profile::start();
+    let svg_xml = read_svg(input_path, args.max_input_size, args.input_format);
+    let svg_xml = match args.svg_fix {
+        true => fix_svg(svg_xml, input_path, args),
+        false => svg_xml,
+    };
+    let doc = parse_document(&svg_xml, input_path, args.allow_dtd);
+
+    let embedded = settings::embedded(&doc);
+    let mut settings = match embedded.is_empty() {
+        true => settings,
+        false => {
+            let settings = build_settings(&embedded, args);
+            check_options(args, &settings);
+            settings
         }
+    };
+    let (first, last) = match &destination {
+        Destination::File(_) => (true, true),
+        Destination::Combined { first, last, .. } => (*first, *last),
+    };
+    if !first {
+        settings.machine.begin.clear();
+    }
+    if !last {
+        settings.machine.end.clear();
     }
 
-    println!(
-        "Successfully created gcode at: {}",
-        args.output_path.display()
-    );
+    let invalid = preprocess::invalid_elements(&doc);
+    let svg_xml = match invalid.is_empty() {
+        true => svg_xml,
+        false if args.ignore_errors => {
+            for (_, reason) in invalid.iter() {
+                warning!("skipping {}", reason);
+            }
+            let removals: Vec<_> = invalid
+                .into_iter()
+                .map(|(range, _)| (range, String::new()))
+                .collect();
+            preprocess::replace_ranges(&svg_xml, &removals)
+        }
+        false => panic!(
+            "Could not convert svg file: {}, failed on:\n{}\nUse --ignore-errors to skip these elements",
+            input_path.display(),
+            invalid
+                .iter()
+                .map(|(_, reason)| reason.as_str())
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+    };
+    let doc = parse_document(&svg_xml, input_path, args.allow_dtd);
+
+    let radii = preprocess::rect_radii(&svg_xml, &doc);
+    let svg_xml = match radii.is_empty() {
+        true => svg_xml,
+        false => preprocess::replace_ranges(&svg_xml, &radii),
+    };
+    let doc = parse_document(&svg_xml, input_path, args.allow_dtd);
+
+    let svg_xml = match args.preflatten {
+        true => preprocess::replace_ranges(&svg_xml, &shapes::preflatten(&svg_xml, &doc)),
+        false => svg_xml,
+    };
+    let doc = parse_document(&svg_xml, input_path, args.allow_dtd);
+
+    #[cfg(feature = "text")]
+    let svg_xml = match (args.text_to_path, &args.font) {
+        (true, Some(font_path)) => {
+            let font_data = match fs::read(font_path) {
+                Ok(data) => data,
+                Err(err) => panic!(
+                    "Could not open font file: {}, failed with error: {}",
+                    font_path.display(),
+                    err
+                ),
+            };
+            let face = match ttf_parser::Face::parse(&font_data, 0) {
+                Ok(face) => face,
+                Err(err) => panic!(
+                    "Could not parse font file: {}, failed with error: {}",
+                    font_path.display(),
+                    err
+                ),
+            };
+            preprocess::replace_ranges(&svg_xml, &text::outline_text(&doc, &face))
+        }
+        (true, None) => {
+            warning!("--text-to-path needs a --font, text will be skipped");
+            svg_xml
+        }
+        (false, _) => svg_xml,
+    };
+    #[cfg(feature = "text")]
+    let doc = parse_document(&svg_xml, input_path, args.allow_dtd);
+
+    let mut removals = match &args.svg_subset {
+        Some(selector) => select::subset(&doc, selector),
+        None => Vec::new(),
+    };
+    if !args.inkscape_layer.is_empty() {
+        match select::layer_subset(&doc, &args.inkscape_layer) {
+            Ok(outside) => removals.extend(outside),
+            Err(err) => panic!(
+                "Could not convert svg file: {}, {}",
+                input_path.display(),
+                err
+            ),
+        }
+        removals.sort_by_key(|(range, _)| range.start);
+        removals.dedup_by_key(|(range, _)| range.start);
+    }
+    let svg_xml = match removals.is_empty() {
+        true => svg_xml,
+        false => preprocess::replace_ranges(&svg_xml, &removals),
+    };
+    let doc = parse_document(&svg_xml, input_path, args.allow_dtd);
+
+    let clip_markers = clippath::markers(&svg_xml, &doc);
+    let svg_xml = match clip_markers.is_empty() {
+        true => svg_xml,
+        false => preprocess::replace_ranges(&svg_xml, &clip_markers),
+    };
+    let doc = parse_document(&svg_xml, input_path, args.allow_dtd);
+
+    #[cfg(feature = "raster")]
+    let rastering = args.raster;
+    #[cfg(not(feature = "raster"))]
+    let rastering = false;
+    if args.report_unsupported_svg_features {
+        let report = preprocess::unsupported_report(&doc, rastering);
+        match report.is_empty() {
+            true => println!("Unsupported SVG features: none"),
+            false => println!("Unsupported SVG features:\n  {}", report.join("\n  ")),
+        }
+    }
+    let unsupported = preprocess::unsupported_features(&doc, rastering);
+    if args.strict && !unsupported.is_empty() {
+        panic!(
+            "Could not convert svg file: {}, it uses unsupported features:\n{}",
+            input_path.display(),
+            unsupported.join("\n")
+        );
+    }
+    for feature in unsupported.iter() {
+        warning!("{}", feature);
+    }
+    let transforms = preprocess::transform_report(&doc);
+    if args.verbose {
+        for transform in transforms.iter() {
+            println!("Transform: {}", transform);
+        }
+    }
+
+    // A negative scale mirrors the converted program rather than the document's size
+    profile.stage("parse");
+
+    let scaling_factor = args.scale.unwrap_or(1.0).abs();
+
+    let conversion_config = conversion_config(&settings);
+    let tool_on = settings.machine.tool_on.as_str();
+    let tool_off = settings.machine.tool_off.as_str();
+    let machine = machine(&settings);
+
+    let dimensions = dimensions(&doc, scaling_factor);
+    let conversion_options = ConversionOptions { dimensions };
+
+    #[cfg(feature = "raster")]
+    let raster_lines = match args.raster {
+        true => raster::engrave(
+            &svg_xml,
+            &doc,
+            &conversion_config,
+            &conversion_options,
+            &machine,
+            &raster::RasterOptions {
+                min_power: args.min_power,
+                max_power: args.max_power,
+                dpi: args.raster_dpi,
+                feedrate: conversion_config.feedrate,
+            },
+        ),
+        false => Vec::new(),
+    };
+
+    let mut header: Vec<Line> = Vec::new();
+    if args.keep_comments {
+        header.extend(document_description(&doc).into_iter().map(Line::Comment));
+    }
+    let tool_on_lines = gcode::snippet_lines(tool_on);
+    let tool_off_lines = gcode::snippet_lines(tool_off);
+    // Set once the program cuts anywhere, checked once it's written
+    let cut_seen = Cell::new(false);
+    let minimum_moves = info::minimum_moves(&doc);
+    if minimum_moves > args.max_moves {
+        panic!(
+            "Could not convert svg file: {}, its shapes need at least {} moves, more than the --max-moves of {}",
+            input_path.display(),
+            minimum_moves,
+            args.max_moves
+        );
+    }
+    let mut lines: Box<dyn Iterator<Item = Line>> = match &args.job {
+        Some(job_path) => {
+            let operations = job::read(job_path);
+            let converted = job::convert(
+                &operations,
+                &svg_xml,
+                job_path,
+                &settings,
+                |xml| parse_document(xml, input_path, args.allow_dtd),
+                |doc| crate::dimensions(doc, scaling_factor),
+            );
+            if !args.quiet {
+                job::print(&converted);
+            }
+            let program: Vec<Line> = converted
+                .into_iter()
+                .flat_map(|operation| operation.lines)
+                .collect();
+            Box::new(header.into_iter().chain(program))
+        }
+        None => {
+            let gcode = svg2program(&doc, &conversion_config, conversion_options, machine);
+            Box::new(header.into_iter().chain(gcode::assemble(gcode)))
+        }
+    };
+    profile.stage("svg2program");
+    #[cfg(feature = "raster")]
+    if !raster_lines.is_empty() {
+        lines = Box::new(
+            lines
+                .chain(gcode::snippet_lines(tool_on))
+                .chain(raster_lines)
+                .chain(gcode::snippet_lines(tool_off)),
+        );
+    }
+
+    // Also checked as the program is assembled, for curves flattened into more moves
+    let mut moves = 0;
+    let max_moves = args.max_moves;
+    lines = Box::new(lines.inspect(move |line| {
+        if (0..=3).any(|number| line.is("G", number)) {
+            moves += 1;
+            if moves > max_moves {
+                panic!(
+                    "Could not convert svg file: {}, it generates more than the --max-moves of {} moves, try a larger tolerance with --set conversion.tolerance=<mm>",
+                    input_path.display(),
+                    max_moves
+                );
+            }
+        }
+    }));
+
+    let shapes = elements::rendered_nodes(&doc)
+        .into_iter()
+        .filter(|(_, node)| info::SHAPE_TAGS.contains(&node.tag_name().name()))
+        .count();
+    let progress = output::progress_bar(shapes);
+    if let Some(path_start) = tool_on_lines.first().cloned() {
+        let progress = progress.clone();
+        lines = Box::new(lines.inspect(move |line| {
+            if *line == path_start {
+                progress.inc(1);
+            }
+        }));
+    }
+
+    let clip_window = match (args.clip_x, args.clip_y, args.clip_width, args.clip_height) {
+        (Some(x), Some(y), Some(width), Some(height)) => Some(clip::Window {
+            min: [x, y],
+            max: [x + width, y + height],
+        }),
+        _ => None,
+    };
+    // Recorded as the program goes by, for the frame job written afterwards
+    let frame_bounds = Cell::new(None);
+    // Passes over the program, in the order they run
+    let mut passes = postprocess::Passes::default();
+    if clippath::has_markers(&doc) {
+        passes.program(|lines| clippath::apply(lines, &doc, &tool_on_lines, &tool_off_lines));
+    }
+    if let [Some(width), Some(height)] = dimensions {
+        let transforms = &transforms;
+        passes.stream(move |lines| {
+            postprocess::inspect_all(lines, postprocess::Bounds::include, move |bounds| {
+                check_size(
+                    bounds.get(),
+                    [width.number, height.number],
+                    args,
+                    input_path,
+                    transforms,
+                )
+            })
+        });
+    }
+    if doc
+        .descendants()
+        .any(|node| node.has_attribute("data-feedrate"))
+    {
+        passes.program(|lines| postprocess::apply_feedrates(lines, &doc));
+    }
+    if doc
+        .descendants()
+        .any(|node| node.has_attribute("data-depth"))
+    {
+        passes.program(|lines| postprocess::apply_depths(lines, &doc, &tool_on_lines));
+    }
+    if let Some(max_power) = args.laser_power_map {
+        let doc = &doc;
+        passes.program(move |lines| postprocess::apply_opacity_power(lines, doc, max_power));
+    }
+    if !args.keep_degenerate {
+        let (tool_on, tool_off) = (&tool_on_lines, &tool_off_lines);
+        let progress = progress.clone();
+        passes.stream(move |lines| {
+            Box::new(postprocess::drop_degenerate(
+                lines,
+                tool_on,
+                tool_off,
+                move |dropped| {
+                    if args.verbose && dropped > 0 {
+                        progress.suspend(|| println!("Removed {} zero-length paths", dropped));
+                    }
+                },
+            ))
+        });
+    }
+    if args.report_duplicate_paths || args.remove_duplicate_paths {
+        passes.program(|lines| {
+            duplicates::duplicate_paths(
+                lines,
+                &doc,
+                args.report_duplicate_paths,
+                args.remove_duplicate_paths,
+            )
+        });
+    }
+    if let Some(count) = args.skip_n_paths {
+        let (tool_on, tool_off) = (&tool_on_lines, &tool_off_lines);
+        passes.program(move |lines| postprocess::skip_paths(lines, tool_on, tool_off, count));
+    }
+    if let Some(count) = args.first_n_paths {
+        let (tool_on, tool_off) = (&tool_on_lines, &tool_off_lines);
+        passes.program(move |lines| postprocess::first_paths(lines, tool_on, tool_off, count));
+    }
+    if args.scale.is_some_and(|scale| scale < 0.0) {
+        passes.program(postprocess::mirror);
+    }
+    if let Some(degrees) = args.rotate {
+        passes.program(move |lines| postprocess::rotate(lines, degrees, args.rotate_about));
+    }
+    // A custom begin sequence takes over setting up units and positioning
+    if args.no_preamble || !settings.machine.begin.is_empty() || !first {
+        passes.program(postprocess::strip_preamble);
+    }
+    if !args.width_map.is_empty() {
+        passes.program(|lines| {
+            tools::group_by_width(
+                lines,
+                &doc,
+                &args.width_map,
+                args.width_tolerance,
+                &dimensions,
+                &tool_off_lines,
+                &tools::ToolChange {
+                    command: &args.tool_change_command,
+                    position: [args.tool_change_x, args.tool_change_y],
+                },
+            )
+        });
+    } else if args.width_as_kerf {
+        passes.program(|lines| kerf::compensate(lines, &doc, &dimensions, &tool_on_lines));
+    }
+    if args.spiral_infill {
+        let (tool_on, tolerance) = (&tool_on_lines, settings.conversion.tolerance);
+        passes.program(move |lines| {
+            infill::spiral_infill(lines, tool_on, args.line_spacing, tolerance)
+        });
+    }
+    if args.honor_dasharray {
+        passes.program(|lines| {
+            dash::apply_dasharray(
+                lines,
+                &doc,
+                &dash::DashOptions {
+                    dimensions: &dimensions,
+                    dpi: conversion_config.dpi,
+                    min_dash: args.min_dash,
+                    tool_on: &tool_on_lines,
+                    tool_off: &tool_off_lines,
+                },
+            )
+        });
+    }
+    if args.reverse_order {
+        passes.program(|lines| direction::reverse_order(lines, &doc));
+    }
+    if args.reverse_paths {
+        passes.program(|lines| direction::reverse_paths(lines, &tool_on_lines));
+    }
+    let milling = match (args.climb, args.conventional) {
+        (true, _) => Some(direction::Milling::Climb),
+        (_, true) => Some(direction::Milling::Conventional),
+        _ => None,
+    };
+    if let Some(milling) = milling {
+        let tool_on = &tool_on_lines;
+        passes.program(move |lines| direction::orient(lines, tool_on, milling));
+    }
+    if args.optimize_rapid_order {
+        let doc = &doc;
+        let progress = progress.clone();
+        passes.program(move |lines| {
+            let exact_max = args.tsp_exact.then_some(args.tsp_max_paths);
+            let (lines, travel) =
+                order::optimize(lines, doc, args.optimize_iterations, exact_max);
+            if args.verbose {
+                let shorter = |from: f64| match from > 0.0 {
+                    true => (1.0 - travel.optimized / from) * 100.0,
+                    false => 0.0,
+                };
+                progress.suspend(|| {
+                    println!(
+                        "Travel: {:.1} mm as drawn, {:.1} mm nearest first, {:.1} mm {} ({:.1}% shorter than as drawn, {:.1}% than nearest first)",
+                        travel.original,
+                        travel.greedy,
+                        travel.optimized,
+                        match travel.exact {
+                            true => "at the shortest",
+                            false => "after 2-opt",
+                        },
+                        shorter(travel.original),
+                        shorter(travel.greedy)
+                    )
+                });
+            }
+            lines
+        });
+    }
+    if args.join_threshold > 0.0 {
+        let (tool_on, tool_off) = (&tool_on_lines, &tool_off_lines);
+        let progress = progress.clone();
+        passes.program(move |lines| {
+            let (lines, joined) =
+                postprocess::join_paths(lines, tool_on, tool_off, args.join_threshold);
+            if !args.quiet {
+                progress.suspend(|| {
+                    println!(
+                        "Joined {} paths to the one before, skipping their lifts",
+                        joined
+                    )
+                });
+            }
+            lines
+        });
+    }
+    if let Some(window) = &clip_window {
+        passes.program(|lines| clip::clip(lines, window, &tool_on_lines, &tool_off_lines));
+    }
+    if args.anchor != postprocess::Anchor::BottomLeft {
+        // The converter puts the document's bottom left corner at the origin
+        let area = match dimensions {
+            [Some(width), Some(height)] => Some([[0.0, 0.0], [width.number, height.number]]),
+            _ => None,
+        };
+        passes.program(move |lines| {
+            let area = area.or_else(|| {
+                warning!("the svg has no width and height, anchoring to the toolpaths' bounds");
+                postprocess::bounds(&lines)
+            });
+            match area {
+                Some(area) => postprocess::anchor(lines, args.anchor, area),
+                None => lines,
+            }
+        });
+    }
+    if args.move_to_origin {
+        passes.program(postprocess::move_to_origin);
+    }
+    if let Some(margin) = args.margin {
+        passes.program(move |lines| postprocess::offset(lines, margin));
+    }
+    // The margin is kept clear on the far sides of the bed too
+    let margin = args.margin.unwrap_or([0.0, 0.0]);
+    let bed = args
+        .bed_width
+        .zip(args.bed_height)
+        .map(|(x, y)| [x - margin[0], y - margin[1]]);
+    if let (Some(grid), Some(spacing)) = (args.grid, args.grid_spacing) {
+        let tool_off = &tool_off_lines;
+        passes.program(move |lines| {
+            tile::tile(
+                lines,
+                &tile::TileOptions {
+                    grid,
+                    spacing,
+                    bed,
+                    overflow: args.on_overflow,
+                    tool_off,
+                    annotate: args.keep_comments,
+                },
+            )
+        });
+    }
+    if let Some(bed) = bed.filter(|_| args.svg_clip_to_bed) {
+        let (tool_on, tool_off) = (&tool_on_lines, &tool_off_lines);
+        passes.program(move |lines| {
+            let window = clip::Window {
+                min: [0.0, 0.0],
+                max: bed,
+            };
+            for path in clip::outside(&lines, &window, tool_on) {
+                warning!("clipping path {}, it extends past the bed", path + 1);
+            }
+            let lines = clip::clip(lines, &window, tool_on, tool_off);
+            clip::clamp_travels(lines, &window)
+        });
+    } else if let Some(bed) = bed.filter(|_| args.grid.is_none() && !args.auto_nest) {
+        passes.program(move |lines| {
+            if let Some([min, max]) = postprocess::bounds(&lines) {
+                if (0..2).any(|axis| min[axis] < 0.0 || max[axis] > bed[axis]) {
+                    panic!(
+                        "Could not fit the design on the bed, it spans X {} to {} and Y {} to {}, past the {}x{}mm bed with a margin of {},{}mm",
+                        min[0],
+                        max[0],
+                        min[1],
+                        max[1],
+                        bed[0] + margin[0],
+                        bed[1] + margin[1],
+                        margin[0],
+                        margin[1]
+                    );
+                }
+            }
+            lines
+        });
+    }
+    if let Some(slowdown) = &args.corner_slowdown {
+        passes.program(|lines| corner::slow_corners(lines, slowdown, args.corner_distance));
+    }
+    if let Some(ms) = args.segment_dwell {
+        let (tool_on, tool_off) = (&tool_on_lines, &tool_off_lines);
+        passes
+            .program(move |lines| postprocess::insert_dwells(lines, tool_on, tool_off, ms as f64));
+    }
+    if args.pause_between_paths {
+        passes.program(|lines| {
+            postprocess::insert_pauses(lines, &tool_on_lines[0], [args.park_x, args.park_y])
+        });
+    }
+    if let Some(plunge) = args.plunge_feedrate {
+        let cutting = settings.conversion.feedrate;
+        passes.program(move |lines| postprocess::plunge_feedrate(lines, plunge, cutting));
+    }
+    if args.coordinate_precision_validation {
+        passes.program(|lines| {
+            let (lines, short, dropped) = postprocess::drop_tiny_moves(lines, args.resolution);
+            if short > 0 {
+                warning!(
+                    "{} cutting moves are shorter than the --resolution of {} mm, dropped {} of them",
+                    short,
+                    args.resolution,
+                    dropped
+                );
+            }
+            lines
+        });
+    }
+    if args.arc_fit_sequences {
+        passes.program(|lines| arcs::fit_arcs(lines, args.arc_tolerance));
+    }
+    if let Some(degrees) = args.max_arc_angle {
+        passes.program(move |lines| arcs::split_arcs(lines, degrees));
+    }
+    if args.arc_mode == arcs::ArcMode::R {
+        passes.program(arcs::to_radius);
+    }
+    if let Some(max) = args.max_segment_length {
+        passes.program(move |lines| postprocess::split_long_moves(lines, max));
+    }
+    if args.travel_z.is_some() || args.park_z.is_some() || args.clearance_z.is_some() {
+        passes.program(|lines| {
+            postprocess::set_retract_heights(
+                lines,
+                &tool_off_lines,
+                postprocess::RetractHeights {
+                    travel: args.travel_z,
+                    clearance: args.clearance_z,
+                    park: args.park_z,
+                },
+            )
+        });
+    }
+    if !args.no_feed_first {
+        let feedrate = settings.conversion.feedrate;
+        passes.stream(move |lines| Box::new(postprocess::feed_first(lines, feedrate)));
+    }
+    if !args.no_final_safety {
+        let safe_height = args
+            .park_z
+            .or(args.clearance_z)
+            .or_else(|| postprocess::lift_height(&tool_off_lines));
+        let map = &args.map_mcode;
+        passes.stream(move |lines| Box::new(postprocess::finish_safely(lines, safe_height, map)));
+    }
+    if let Some(position) = args.park.filter(|_| last) {
+        passes.program(move |lines| postprocess::park(lines, position));
+    }
+    if args.keep_spindle_running {
+        let (tool_on, tool_off) = (&tool_on_lines, &tool_off_lines);
+        passes.program(move |lines| postprocess::keep_spindle_running(lines, tool_on, tool_off));
+    }
+    // After every pass looking for the tool-on and tool-off sequences by their Z
+    if let Some(path) = &args.z_map {
+        let map = zmap::read(path);
+        passes.program(move |lines| zmap::compensate(lines, &map));
+    }
+    // After every pass that looks for the tool on and off commands
+    if !args.strip_mcodes.is_empty() || !args.map_mcode.is_empty() {
+        passes.program(|lines| mcodes::rewrite(lines, &args.strip_mcodes, &args.map_mcode));
+    }
+    if let Some(limits) = &args.machine_limits {
+        passes.program(|lines| {
+            let violations = limits::violations(&lines, limits);
+            if args.strict_limits && !violations.is_empty() {
+                panic!(
+                    "Could not keep the program within --machine-limits, {} moves go past them, the first being {}",
+                    violations.len(),
+                    violations[0]
+                );
+            }
+            for violation in violations {
+                warning!("{}", violation);
+            }
+            lines
+        });
+    }
+    // --auto-nest previews the designs once they are packed
+    if args.preview_term && !args.auto_nest {
+        let columns = args.preview_cols.unwrap_or_else(|| {
+            terminal_size::terminal_size().map_or(80, |(width, _)| width.0 as usize)
+        });
+        let progress = progress.clone();
+        passes.program(move |lines| {
+            progress.suspend(|| preview::print(&lines, columns, args.margin));
+            lines
+        });
+    }
+    if let Some(path) = args.toolpath_preview_svg.as_deref() {
+        passes.program(move |lines| {
+            preview::write_svg(&lines, path, args.number_paths);
+            lines
+        });
+    }
+    if args.frame.is_some() {
+        let frame_bounds = &frame_bounds;
+        passes.stream(move |lines| {
+            postprocess::inspect_all(lines, postprocess::Bounds::include, |bounds| {
+                frame_bounds.set(bounds.get())
+            })
+        });
+    }
+    lines = passes.run(lines);
+    if let Some(grid) = args.snap {
+        let mut snap = postprocess::Snap::new(grid);
+        lines = Box::new(lines.map(move |line| snap.apply(line)));
+    }
+    if args.feedrate_units != output::FeedrateUnits::MmPerMin {
+        let units = args.feedrate_units;
+        lines = Box::new(lines.map(move |line| units.convert(line)));
+    }
+
+    lines = Box::new(lines.inspect(|line| {
+        if line.is("G", 1) || line.is("G", 2) || line.is("G", 3) {
+            cut_seen.set(true);
+        }
+    }));
+    let empty = || !args.allow_empty && !cut_seen.get();
+    let no_geometry = || {
+        if args.strict {
+            panic!(
+                "Could not convert svg file: {}, it has no geometry to cut",
+                input_path.display()
+            );
+        }
+        progress.finish_and_clear();
+        warning!(
+            "no toolpath was generated from {}, it has no geometry to cut",
+            input_path.display()
+        );
+    };
+
+    // The program is assembled lazily, so unless a pass collected it, assembling it is timed as
+    // part of writing it
+    profile.stage("post-process");
+
+    if args.dry_run {
+        let count = lines
+            .filter(|line| args.keep_comments || !matches!(line, Line::Comment(_)))
+            .count();
+        if empty() {
+            no_geometry();
+        }
+        progress.finish_and_clear();
+        if args.profile_conversion {
+            profile.print();
+        }
+        println!(
+            "Dry run: converted {} into {} lines of gcode, nothing was written",
+            input_path.display(),
+            count
+        );
+        return;
+    }
+    let output_path = match destination {
+        Destination::File(Some(output_path)) => output_path,
+        Destination::File(None) => unreachable!("output path is required without --dry-run"),
+        Destination::Combined { program, .. } => {
+            let start = program.len();
+            program.extend(lines);
+            if empty() {
+                program.truncate(start);
+                no_geometry();
+                warning!("not writing an empty program, pass --allow-empty to write it anyway");
+            }
+            progress.finish_and_clear();
+            if args.profile_conversion {
+                profile.print();
+            }
+            return;
+        }
+    };
+
+    if !write_program(output_path, lines, template, args, || !empty()) {
+        no_geometry();
+        warning!("not writing an empty program, pass --allow-empty to write it anyway");
+        return;
+    }
+    progress.finish_and_clear();
+    profile.stage("write");
+    if args.profile_conversion {
+        profile.print();
+    }
+
+    if !args.quiet {
+        println!("Successfully created gcode at: {}", output_path.display());
+    }
+
+    if let Some(frame_path) = &args.frame {
+        let Some(bounds) = frame_bounds.get() else {
+            warning!("the program has no moves, not writing a frame job");
+            return;
+        };
+        let preamble = match (args.no_preamble, settings.machine.begin.is_empty()) {
+            (true, _) => Vec::new(),
+            (false, true) => gcode::snippet_lines("G21 G90"),
+            (false, false) => gcode::snippet_lines(&settings.machine.begin),
+        };
+        let end = match settings.machine.end.is_empty() {
+            true => Vec::new(),
+            false => gcode::snippet_lines(&settings.machine.end),
+        };
+        let frame = frame::trace(
+            bounds,
+            &frame::FrameOptions {
+                preamble: &preamble,
+                tool_on: &tool_on_lines,
+                tool_off: &tool_off_lines,
+                power: args.frame_power,
+                feedrate: conversion_config.feedrate,
+                loops: args.frame_loops,
+                end: &end,
+            },
+        );
+        let frame: Vec<Line> = mcodes::rewrite(frame, &args.strip_mcodes, &args.map_mcode)
+            .into_iter()
+            .map(|line| args.feedrate_units.convert(line))
+            .collect();
+        write_program(frame_path, frame, None, args, || true);
+        if !args.quiet {
+            println!(
+                "Successfully created frame gcode at: {}",
+                frame_path.display()
+            );
+        }
+    }
+}
+
+fn line_ending(args: &Args) -> output::LineEnding {
+    match args.windows_line_endings {
+        true => output::LineEnding::Crlf,
+        false => args.line_ending,
+    }
+}
+
+/// Writes the program's lines, numbering and checksumming them if asked to.
+///
+/// Once every line is written, `keep` decides whether the file is kept, returning false and
+/// leaving any existing file in place when it isn't.
+fn write_program(
+    path: &std::path::Path,
+    lines: impl IntoIterator<Item = Line>,
+    template: Option<&output::Template>,
+    args: &Args,
+    keep: impl FnOnce() -> bool,
+) -> bool {
+    if let Some(parent) = path.parent() {
+        if let Err(err) = fs::create_dir_all(parent) {
+            panic!(
+                "Could not create output file's parent directory(ies), failed with error: {}",
+                err
+            );
+        }
+    };
+
+    let mut discarded = false;
+    let written = output::write_atomically(path, |output_file| {
+        match format_program(output_file, lines, template, args, keep)? {
+            true => Ok(()),
+            false => {
+                discarded = true;
+                Err(io::Error::other("the program is discarded"))
+            }
+        }
+    });
+    match written {
+        Err(_) if discarded => false,
+        Err(err) => panic!("Couldn't write to file: {}", err),
+        Ok(()) => true,
+    }
+}
+
+/// Formats the program into the output as the output options ask, with the template, the
+/// flavor's wrapping and the chosen line endings around and between its lines.
+///
+/// Returns false, having stopped before the template's closing text, when `keep` says the
+/// program is to be discarded.
+fn format_program(
+    output_file: &mut impl Write,
+    lines: impl IntoIterator<Item = Line>,
+    template: Option<&output::Template>,
+    args: &Args,
+    keep: impl FnOnce() -> bool,
+) -> io::Result<bool> {
+    let numbered = args.line_numbers || args.gcode_checksum;
+    let line_ending = line_ending(args);
+    let not_ascii = |text: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "\"{}\" is not plain ASCII, as --output-encoding ascii requires",
+                text
+            ),
+        )
+    };
+    let ascii = args.output_encoding == output::Encoding::Ascii;
+    let fanuc = args.flavor == output::Flavor::Fanuc;
+    let comment_style = match fanuc {
+        true => CommentStyle::Paren,
+        false => args.comment_style,
+    };
+    if args.bom {
+        output_file.write_all("\u{feff}".as_bytes())?;
+    }
+    // The tape start and program number come first, before the template's text
+    if fanuc {
+        write!(output_file, "%{}", line_ending.as_str())?;
+    }
+    if let Some(program_number) = args.program_number {
+        write!(
+            output_file,
+            "O{:04}{}",
+            program_number,
+            line_ending.as_str()
+        )?;
+    }
+    if let Some(template) = template {
+        if ascii && !template.before.is_ascii() {
+            return Err(not_ascii(&template.before));
+        }
+        output_file.write_all(template.before.as_bytes())?;
+    }
+    let mut number = 0;
+    let mut modal =
+        (args.modal && (!args.gcode_checksum || args.force_modal)).then(gcode::Modal::default);
+    for mut line in lines {
+        if let Some(modal) = modal.as_mut() {
+            line = modal.apply(line);
+        }
+        if !args.word_order.is_empty() {
+            line.reorder(&args.word_order);
+        }
+        let text = match &line {
+            Line::Comment(_) if !args.keep_comments => continue,
+            Line::Comment(comment) => {
+                let comment = match args.output_encoding {
+                    output::Encoding::Utf8 => comment.clone(),
+                    output::Encoding::Ascii => output::to_ascii(comment),
+                };
+                match fanuc {
+                    true => comment_style.format(&comment.to_uppercase()),
+                    false => comment_style.format(&comment),
+                }
+            }
+            Line::Command(_) if numbered => {
+                number += 1;
+                let text = format!("N{} {}", number, line);
+                match args.gcode_checksum {
+                    true => format!("{}*{}", text, gcode::checksum(&text)),
+                    false => text,
+                }
+            }
+            Line::Command(_) => line.to_string(),
+        };
+        if ascii && !text.is_ascii() {
+            return Err(not_ascii(&text));
+        }
+        write!(output_file, "{}{}", text, line_ending.as_str())?;
+    }
+    if !keep() {
+        return Ok(false);
+    }
+    if let Some(template) = template {
+        if ascii && !template.after.is_ascii() {
+            return Err(not_ascii(&template.after));
+        }
+        output_file.write_all(template.after.as_bytes())?;
+    }
+    // The tape end comes last, on a line of its own after the template's text
+    if fanuc {
+        if template.is_some_and(|template| {
+            !template.after.is_empty() && !template.after.ends_with(line_ending.as_str())
+        }) {
+            output_file.write_all(line_ending.as_str().as_bytes())?;
+        }
+        write!(output_file, "%{}", line_ending.as_str())?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gcode::Line;
+
+    /// What [`validate`] finds wrong with the command line arguments and settings
+    fn problems(arguments: &[&str], settings: &settings::Settings) -> Vec<String> {
+        let command_line = ["usGcode", "in.svg", "out.gcode"].iter().chain(arguments);
+        match Args::try_parse_from(command_line) {
+            Ok(args) => validate(&args, settings),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// The program as [`format_program`] writes it with the command line arguments
+    fn formatted(arguments: &[&str], program: &str) -> String {
+        let command_line = ["usGcode", "in.svg", "out.gcode"].iter().chain(arguments);
+        let args = Args::try_parse_from(command_line).unwrap_or_else(|err| panic!("{}", err));
+        let lines = gcode::template_lines(program).unwrap();
+        let mut output = Vec::new();
+        assert!(format_program(&mut output, lines, None, &args, || true).unwrap());
+        String::from_utf8(output).unwrap()
+    }
+
+    /// The one problem [`validate`] finds with the default settings
+    fn problem(arguments: &[&str]) -> String {
+        let problems = problems(arguments, &settings::Settings::default());
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        problems[0].clone()
+    }
+
+    /// The points a 100mm square drawing of `body` travels and cuts through, in user units.
+    ///
+    /// The converter's first cut of each path is to where the path starts.
+    fn move_points(body: &str) -> Vec<(usize, [f64; 2])> {
+        let xml = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">{}</svg>"#,
+            body
+        );
+        let scale = 96.0 / settings::Settings::default().conversion.dpi;
+        testing::program(&roxmltree::Document::parse(&xml).unwrap())
+            .iter()
+            .filter_map(|line| {
+                let command = [0, 1].into_iter().find(|&n| line.is("G", n))?;
+                let [x, y] = [line.value("X")?, line.value("Y")?];
+                // Back from millimetres with Y upwards to rounded user units
+                let point = [x / scale, 100.0 - y / scale].map(|v| (v * 1e6).round() / 1e6);
+                Some((command, point))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lines_convert_to_a_travel_and_a_cut() {
+        let doc = roxmltree::Document::parse(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">
+                <line x1="10" y1="20" x2="60" y2="70"/>
+            </svg>"#,
+        )
+        .unwrap();
+        let lines = testing::program(&doc);
+        let (tool_on, tool_off) = testing::tool();
+
+        // The converter flips Y and draws 96 user units to the dpi
+        let scale = 96.0 / settings::Settings::default().conversion.dpi;
+        let at = |x: f64, y: f64| [x * scale, (100.0 - y) * scale];
+        let moves: Vec<&Line> = lines
+            .iter()
+            .filter(|line| line.value("X").is_some())
+            .collect();
+        assert_eq!(moves.len(), 2, "{:?}", lines);
+        for (line, (command, [x, y])) in
+            moves.iter().zip([(0, at(10.0, 20.0)), (1, at(60.0, 70.0))])
+        {
+            assert!(line.is("G", command), "{:?}", line);
+            assert!((line.value("X").unwrap() - x).abs() < 1e-9, "{:?}", line);
+            assert!((line.value("Y").unwrap() - y).abs() < 1e-9, "{:?}", line);
+        }
+        // The tool goes on after the travel and off after the cut
+        let travel = lines.iter().position(|line| line == moves[0]).unwrap();
+        let cut = lines.iter().position(|line| line == moves[1]).unwrap();
+        assert_eq!(lines[travel + 1..cut], tool_on[..]);
+        assert!(lines[cut + 1..].starts_with(&tool_off));
+    }
+
+    #[test]
+    fn polylines_stay_open() {
+        assert_eq!(
+            move_points(r#"<polyline points="10,10 50,10 50,40"/>"#),
+            [
+                (0, [10.0, 10.0]),
+                (1, [10.0, 10.0]),
+                (1, [50.0, 10.0]),
+                (1, [50.0, 40.0])
+            ]
+        );
+    }
+
+    #[test]
+    fn polygons_close_back_to_their_first_point() {
+        assert_eq!(
+            move_points(r#"<polygon points="10,10 50,10 50,40"/>"#),
+            [
+                (0, [10.0, 10.0]),
+                (1, [10.0, 10.0]),
+                (1, [50.0, 10.0]),
+                (1, [50.0, 40.0]),
+                (1, [10.0, 10.0])
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_scale_takes_numbers_and_percentages() {
+        assert_eq!(parse_scale("0.5"), Ok(0.5));
+        assert_eq!(parse_scale("50%"), Ok(0.5));
+        assert_eq!(parse_scale("150%"), Ok(1.5));
+        assert_eq!(parse_scale(" 1_000 "), Ok(1000.0));
+        // Negative scales mirror the design
+        assert_eq!(parse_scale("-1"), Ok(-1.0));
+        assert_eq!(parse_scale("-50%"), Ok(-0.5));
+    }
+
+    #[test]
+    fn parse_scale_refuses_zero_and_non_numbers() {
+        for bad in ["0", "0%", "-0", "nan", "inf", "-inf", "half", "%", ""] {
+            assert!(parse_scale(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn parse_number_ignores_grouping() {
+        assert_eq!(parse_number::<usize>("5_000_000"), Ok(5_000_000));
+        assert_eq!(parse_number::<usize>("10\u{2009}000"), Ok(10_000));
+        assert_eq!(parse_number::<f64>(" -2.5 "), Ok(-2.5));
+        assert!(parse_number::<usize>("-1").is_err());
+        assert!(parse_number::<usize>("ten").is_err());
+    }
+
+    #[test]
+    fn parse_positive_refuses_zero_negative_and_infinite() {
+        assert_eq!(parse_positive("0.25"), Ok(0.25));
+        for bad in ["0", "-1", "inf", "nan", "x"] {
+            assert!(parse_positive(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn parse_size_takes_binary_suffixes() {
+        assert_eq!(parse_size("1048576"), Ok(1 << 20));
+        assert_eq!(parse_size("64k"), Ok(64 << 10));
+        assert_eq!(parse_size("64M"), Ok(64 << 20));
+        assert_eq!(parse_size("2g"), Ok(2 << 30));
+        for bad in ["", "M", "1.5M", "-1", "99999999999G"] {
+            assert!(parse_size(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn parse_spacing_and_margin_take_pairs() {
+        assert_eq!(parse_spacing("5, 2.5"), Ok([5.0, 2.5]));
+        assert_eq!(parse_spacing("0,0"), Ok([0.0, 0.0]));
+        for bad in ["5", "-1,5", "5,inf", "a,b"] {
+            assert!(parse_spacing(bad).is_err(), "{}", bad);
+        }
+        assert_eq!(parse_margin("3"), Ok([3.0, 3.0]));
+        assert_eq!(parse_margin("3,4"), Ok([3.0, 4.0]));
+        assert!(parse_margin("-3").is_err());
+        assert_eq!(
+            parse_margin("3,-4"),
+            Err("margins must be finite and zero or more".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_position_takes_any_finite_point() {
+        assert_eq!(parse_position("0,200"), Ok([0.0, 200.0]));
+        assert_eq!(parse_position("-10, -20.5"), Ok([-10.0, -20.5]));
+        for bad in ["0", "0,nan", "inf,0", "x,y"] {
+            assert!(parse_position(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn validate_accepts_the_defaults() {
+        assert_eq!(
+            problems(&[], &settings::Settings::default()),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn a_zero_or_infinite_scale_is_refused_with_the_arguments() {
+        for scale in ["0", "0%", "inf", "nan"] {
+            let command_line = ["usGcode", "-s", scale, "in.svg", "out.gcode"];
+            assert!(Args::try_parse_from(command_line).is_err(), "{}", scale);
+        }
+    }
+
+    #[test]
+    fn validate_refuses_settings_that_must_be_positive() {
+        let mut settings = settings::Settings::default();
+        settings.conversion.feedrate = 0.0;
+        settings.conversion.tolerance = -0.1;
+        settings.conversion.dpi = f64::NAN;
+        assert_eq!(
+            problems(&[], &settings),
+            [
+                "conversion.feedrate must be a number greater than zero, got 0",
+                "conversion.tolerance must be a number greater than zero, got -0.1",
+                "conversion.dpi must be a number greater than zero, got NaN",
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_refuses_negative_and_infinite_lengths() {
+        assert_eq!(
+            problem(&["--min-dash=-1"]),
+            "--min-dash must be zero or more, got -1"
+        );
+        assert_eq!(
+            problem(&["--join-threshold", "inf"]),
+            "--join-threshold must be zero or more, got inf"
+        );
+        assert_eq!(
+            problem(&[
+                "--input-glob",
+                "*.svg",
+                "--bed-width",
+                "100",
+                "--bed-height",
+                "100",
+                "--auto-nest",
+                "--nest-margin=-2"
+            ]),
+            "--nest-margin must be zero or more, got -2"
+        );
+        assert_eq!(
+            problem(&["--pause-between-paths", "--park-x", "inf"]),
+            "--park-x must be a finite number, got inf"
+        );
+    }
+
+    #[test]
+    fn validate_refuses_exact_ordering_past_its_limit() {
+        let limit = order::EXACT_LIMIT.to_string();
+        let past = (order::EXACT_LIMIT + 1).to_string();
+        let ordering = ["--optimize-rapid-order", "--tsp-exact", "--tsp-max-paths"];
+        assert!(problems(
+            &[&ordering[..], &[&limit]].concat(),
+            &settings::Settings::default()
+        )
+        .is_empty());
+        assert_eq!(
+            problem(&[&ordering[..], &[&past]].concat()),
+            format!("--tsp-max-paths must be at most {}, got {}", limit, past)
+        );
+    }
+
+    #[test]
+    fn validate_refuses_heights_the_tool_on_sequence_goes_below() {
+        // The default tool-on sequence cuts at Z0
+        assert_eq!(
+            problem(&["--travel-z", "0"]),
+            "--travel-z must be above the Z of machine.tool_on, 0, got 0"
+        );
+        assert_eq!(
+            problem(&["--travel-z", "5", "--clearance-z", "2"]),
+            "--clearance-z must be at least --travel-z, 5, got 2"
+        );
+    }
+
+    #[test]
+    fn validate_refuses_pauses_without_a_tool_on_sequence() {
+        let mut settings = settings::Settings::default();
+        settings.machine.tool_on = String::new();
+        assert_eq!(
+            problems(&["--pause-between-paths"], &settings),
+            ["machine.tool_on must not be empty with --pause-between-paths"]
+        );
+    }
+
+    #[test]
+    fn validate_refuses_what_the_output_cant_hold() {
+        assert!(problem(&["--tool-change-command", "T{n} M6 ("])
+            .starts_with("--tool-change-command could not be parsed"));
+        assert_eq!(
+            problem(&["--flavor", "fanuc", "--gcode-checksum"]),
+            "--gcode-checksum is not understood by fanuc controllers"
+        );
+        let mut settings = settings::Settings::default();
+        settings.machine.begin = "O100".to_string();
+        assert_eq!(
+            problems(&["--flavor", "fanuc"], &settings),
+            ["machine.begin uses the word O100, which fanuc controllers don't support"]
+        );
+        assert_eq!(
+            problem(&["--bom", "--output-encoding", "ascii"]),
+            "--bom can't be written to a file limited to --output-encoding ascii"
+        );
+    }
+
+    #[test]
+    fn fanuc_output_is_wrapped_numbered_and_uppercase() {
+        let program = ";Outline of the part\nG0 X1 Y2\nM3\nG1 X5 Y2 F300\nM5\nM30";
+        assert_eq!(
+            formatted(
+                &[
+                    "--flavor",
+                    "fanuc",
+                    "--program-number",
+                    "100",
+                    "--keep-comments"
+                ],
+                program
+            ),
+            "%\nO0100\n(OUTLINE OF THE PART)\nG0 X1 Y2\nM3\nG1 X5 Y2 F300\nM5\nM30\n%\n"
+        );
+    }
+
+    #[test]
+    fn word_order_sorts_the_words_of_each_line() {
+        let program = "G1 X5 Y2 F300\nG0 Z3\nM3 S1000";
+        assert_eq!(
+            formatted(&["--word-order", "F,G,Z"], program),
+            "F300 G1 X5 Y2\nG0 Z3\nM3 S1000\n"
+        );
+        // Left unset, the words are written as they are
+        assert_eq!(formatted(&[], program), format!("{}\n", program));
+    }
+
+    #[test]
+    fn every_line_ends_with_the_chosen_ending() {
+        let program = "G0 X1 Y2\nG1 X5 Y2 F300";
+        let lf = formatted(&[], program);
+        assert!(!lf.starts_with('\n') && lf.ends_with("F300\n"));
+        let crlf = formatted(&["--line-ending", "crlf"], program);
+        assert_eq!(crlf, "G0 X1 Y2\r\nG1 X5 Y2 F300\r\n");
+
+        // A template's own endings are made to match
+        let args =
+            Args::try_parse_from(["usGcode", "in.svg", "out.gcode", "--line-ending", "crlf"])
+                .unwrap();
+        let template =
+            output::Template::parse("G21\n{{GCODE}}\r\nM2", output::LineEnding::Crlf).unwrap();
+        let mut output = Vec::new();
+        let lines = gcode::template_lines(program).unwrap();
+        format_program(&mut output, lines, Some(&template), &args, || true).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "G21\r\nG0 X1 Y2\r\nG1 X5 Y2 F300\r\nM2"
+        );
+    }
+
+    #[test]
+    fn numbers_are_written_with_a_point_and_no_grouping() {
+        let args = Args::try_parse_from(["usGcode", "in.svg", "out.gcode"]).unwrap();
+        let lines = [
+            Line::command("G", 1, &[("X", 0.5), ("Y", 1000.25), ("F", 1500.0)]),
+            Line::command("G", 0, &[("X", -0.125)]),
+        ];
+        let mut output = Vec::new();
+        format_program(&mut output, lines, None, &args, || true).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "G1 X0.5 Y1000.25 F1500\nG0 X-0.125\n"
+        );
+    }
+
+    #[test]
+    fn the_title_and_description_head_the_program() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg">
+            <title>My Part (rev 2)</title>
+            <desc>
+                Cut from 3mm ply
+                Outline only
+            </desc>
+        </svg>"#;
+        let doc = roxmltree::Document::parse(svg).unwrap();
+        let header = document_description(&doc);
+        assert_eq!(
+            header,
+            [
+                "title: My Part (rev 2)",
+                "desc: Cut from 3mm ply",
+                "desc: Outline only"
+            ]
+        );
+        let args = Args::try_parse_from([
+            "usGcode",
+            "in.svg",
+            "out.gcode",
+            "--keep-comments",
+            "--comment-style",
+            "paren",
+        ])
+        .unwrap();
+        let mut output = Vec::new();
+        let lines = header.into_iter().take(1).map(Line::Comment);
+        format_program(&mut output, lines, None, &args, || true).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "(title: My Part [rev 2])\n"
+        );
+    }
+
+    #[test]
+    fn settings_embedded_in_the_svg_are_used_unless_overridden() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:usgcode="https://github.com/yabeniiii/usGcode-ifier">
+            <metadata><usgcode:settings feedrate="800" tolerance="0.01"/></metadata>
+            <usgcode:settings feedrate="5"/>
+        </svg>"#;
+        let doc = roxmltree::Document::parse(svg).unwrap();
+        let embedded = settings::embedded(&doc);
+        // Only the settings inside <metadata> count
+        assert_eq!(
+            embedded,
+            [
+                ("feedrate".to_string(), "800".to_string()),
+                ("tolerance".to_string(), "0.01".to_string())
+            ]
+        );
+
+        let args = Args::try_parse_from(["usGcode", "in.svg", "out.gcode"]).unwrap();
+        let settings = build_settings(&embedded, &args);
+        assert_eq!(settings.conversion.feedrate, 800.0);
+        assert_eq!(settings.conversion.tolerance, 0.01);
+
+        let command_line = [
+            "usGcode",
+            "in.svg",
+            "out.gcode",
+            "--set",
+            "conversion.feedrate=300",
+        ];
+        let settings = build_settings(&embedded, &Args::try_parse_from(command_line).unwrap());
+        assert_eq!(settings.conversion.feedrate, 300.0);
+        assert_eq!(settings.conversion.tolerance, 0.01);
+    }
+
+    #[test]
+    fn modal_output_is_off_with_checksums_unless_forced() {
+        let program = "G1 X5 Y2 F300\nG1 X5 Y4";
+        assert_eq!(formatted(&["--modal"], program), "G1 X5 Y2 F300\nY4\n");
+        let checksummed = formatted(&["--modal", "--gcode-checksum"], program);
+        assert!(checksummed
+            .lines()
+            .nth(1)
+            .unwrap()
+            .starts_with("N2 G1 X5 Y4*"));
+        let forced = formatted(&["--modal", "--gcode-checksum", "--force-modal"], program);
+        assert!(forced.lines().nth(1).unwrap().starts_with("N2 Y4*"));
+    }
 }
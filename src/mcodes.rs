@@ -0,0 +1,138 @@
+use g_code::emit::Value;
+use std::{collections::BTreeMap, str::FromStr};
+
+use crate::gcode::Line;
+
+/// An M-code such as `M7`, written with or without the `M`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MCode(pub usize);
+
+impl FromStr for MCode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let number = trimmed
+            .strip_prefix(['M', 'm'])
+            .unwrap_or(trimmed)
+            .parse()
+            .map_err(|_| format!("expected an M-code such as M7, got \"{}\"", s))?;
+        Ok(MCode(number))
+    }
+}
+
+/// A `--map-mcode` argument, e.g. `M3=M106`
+#[derive(Debug, Clone, Copy)]
+pub struct Mapping {
+    pub from: MCode,
+    pub to: MCode,
+}
+
+impl FromStr for Mapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <from>=<to> such as M3=M106, got \"{}\"", s))?;
+        Ok(Mapping {
+            from: from.parse()?,
+            to: to.parse()?,
+        })
+    }
+}
+
+/// Rewrites mapped M-codes, then removes stripped ones.
+///
+/// A mapped command keeps its words, so `M3 S500` mapped to `M106` becomes `M106 S500`. A
+/// stripped command is removed along with its words, with a warning counting each code removed.
+pub fn rewrite(lines: Vec<Line>, strip: &[MCode], map: &[Mapping]) -> Vec<Line> {
+    let mut stripped: BTreeMap<MCode, (usize, usize)> = BTreeMap::new();
+    let mut output = Vec::with_capacity(lines.len());
+    for mut line in lines {
+        if let Line::Command(fields) = &mut line {
+            let code = fields
+                .first()
+                .filter(|field| field.letters.eq_ignore_ascii_case("M"))
+                .and_then(|field| field.value.as_f64())
+                .map(|number| MCode(number as usize));
+            let code = match map.iter().find(|mapping| Some(mapping.from) == code) {
+                Some(mapping) => {
+                    fields[0].value = Value::Integer(mapping.to.0);
+                    Some(mapping.to)
+                }
+                None => code,
+            };
+            if let Some(code) = code.filter(|code| strip.contains(code)) {
+                let (commands, words) = stripped.entry(code).or_default();
+                *commands += 1;
+                *words += fields.len() - 1;
+                continue;
+            }
+        }
+        output.push(line);
+    }
+    for (MCode(number), (commands, words)) in stripped {
+        match words {
+            0 => warning!("removed {} M{} commands", commands, number),
+            _ => warning!(
+                "removed {} M{} commands, dropping the {} words given with them",
+                commands,
+                number,
+                words
+            ),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcode;
+
+    fn rewritten(program: &str, strip: &[&str], map: &[&str]) -> Vec<String> {
+        let strip: Vec<MCode> = strip.iter().map(|code| code.parse().unwrap()).collect();
+        let map: Vec<Mapping> = map.iter().map(|mapping| mapping.parse().unwrap()).collect();
+        rewrite(gcode::template_lines(program).unwrap(), &strip, &map)
+            .iter()
+            .map(|line| line.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn stripped_codes_are_removed_with_their_words() {
+        assert_eq!(
+            rewritten("M7\nM3 S500\nG1 X5 F100\nM9", &["M7", "9"], &[]),
+            ["M3 S500", "G1 X5 F100"]
+        );
+    }
+
+    #[test]
+    fn mapped_codes_are_rewritten_keeping_their_words() {
+        assert_eq!(
+            rewritten("M3 S500\nG1 X5 F100\nM5", &[], &["M3=M106", "M5=M107"]),
+            ["M106 S500", "G1 X5 F100", "M107"]
+        );
+    }
+
+    #[test]
+    fn codes_are_stripped_after_mapping() {
+        // M4 becomes M3, which is then stripped, while the M3 mapped away is kept
+        assert_eq!(
+            rewritten("M4 S200\nM3 S500", &["M3"], &["M4=M3", "M3=M106"]),
+            ["M106 S500"]
+        );
+    }
+
+    #[test]
+    fn m_codes_parse_with_or_without_the_m() {
+        assert_eq!("m106".parse::<MCode>(), Ok(MCode(106)));
+        assert_eq!(" 7 ".parse::<MCode>(), Ok(MCode(7)));
+        assert_eq!(
+            "G1".parse::<MCode>(),
+            Err("expected an M-code such as M7, got \"G1\"".to_string())
+        );
+        assert!("M3".parse::<Mapping>().is_err());
+    }
+}
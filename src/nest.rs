@@ -0,0 +1,192 @@
+use crate::{gcode::Line, postprocess};
+
+/// Where `--auto-nest` puts a design
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placement {
+    /// Lower left corner of the design's bounding box
+    pub position: [f64; 2],
+    /// Whether the design is turned a quarter turn counterclockwise
+    pub rotated: bool,
+}
+
+/// Settings for packing designs onto the bed
+pub struct NestOptions {
+    /// Width and height of the area to pack into, in millimeters
+    pub area: [f64; 2],
+    /// Gap to leave between neighbouring designs
+    pub gap: f64,
+    pub allow_rotate: bool,
+}
+
+/// Packs designs of the given sizes into the area, returning where each goes.
+///
+/// Designs are placed tallest first along shelves from the origin, starting a new shelf above
+/// the last when the next design doesn't fit beside the others. When they don't all fit, returns
+/// the smallest area, at least as wide as the one given, that they would pack into.
+pub fn pack(sizes: &[[f64; 2]], options: &NestOptions) -> Result<Vec<Placement>, [f64; 2]> {
+    let (placements, used) = shelves(sizes, options.area[0], options);
+    if used[0] <= options.area[0] + 1e-9 && used[1] <= options.area[1] + 1e-9 {
+        return Ok(placements);
+    }
+    // Each design needs a shelf at least as wide as its narrowest side it can be laid on
+    let widest = sizes
+        .iter()
+        .map(|&[width, height]| match options.allow_rotate {
+            true => width.min(height),
+            false => width,
+        })
+        .fold(options.area[0], f64::max);
+    let (_, used) = shelves(sizes, widest, options);
+    Err([widest, used[1]])
+}
+
+/// Lays the designs onto shelves of the given width, returning their placements and the width
+/// and height they take up
+fn shelves(sizes: &[[f64; 2]], width: f64, options: &NestOptions) -> (Vec<Placement>, [f64; 2]) {
+    // Laid flat to keep the shelves low, unless only standing up fits the width
+    let laid: Vec<(bool, [f64; 2])> = sizes
+        .iter()
+        .map(|&[w, h]| {
+            let rotated = options.allow_rotate && h <= width && (w > width || w < h);
+            match rotated {
+                true => (true, [h, w]),
+                false => (false, [w, h]),
+            }
+        })
+        .collect();
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by(|&a, &b| laid[b].1[1].total_cmp(&laid[a].1[1]));
+
+    let mut placements = vec![
+        Placement {
+            position: [0.0, 0.0],
+            rotated: false,
+        };
+        sizes.len()
+    ];
+    let mut used = [0.0_f64, 0.0_f64];
+    let (mut x, mut shelf, mut shelf_height) = (0.0, 0.0, 0.0_f64);
+    let mut first_on_shelf = true;
+    for i in order {
+        let (rotated, size) = laid[i];
+        if !first_on_shelf && x + size[0] > width + 1e-9 {
+            shelf += shelf_height + options.gap;
+            (x, shelf_height) = (0.0, 0.0);
+        }
+        placements[i] = Placement {
+            position: [x, shelf],
+            rotated,
+        };
+        used = [used[0].max(x + size[0]), used[1].max(shelf + size[1])];
+        x += size[0] + options.gap;
+        shelf_height = shelf_height.max(size[1]);
+        first_on_shelf = false;
+    }
+    (placements, used)
+}
+
+/// Moves a program to its placement, turning it first if the placement says so
+pub fn place(lines: Vec<Line>, placement: Placement) -> Vec<Line> {
+    let lines = match placement.rotated {
+        true => postprocess::rotate(lines, 90.0, None),
+        false => lines,
+    };
+    let Some([min, _]) = postprocess::bounds(&lines) else {
+        return lines;
+    };
+    postprocess::offset(
+        lines,
+        [
+            placement.position[0] - min[0],
+            placement.position[1] - min[1],
+        ],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(area: [f64; 2], allow_rotate: bool) -> NestOptions {
+        NestOptions {
+            area,
+            gap: 2.0,
+            allow_rotate,
+        }
+    }
+
+    fn at(x: f64, y: f64) -> Placement {
+        Placement {
+            position: [x, y],
+            rotated: false,
+        }
+    }
+
+    fn turned(x: f64, y: f64) -> Placement {
+        Placement {
+            position: [x, y],
+            rotated: true,
+        }
+    }
+
+    #[test]
+    fn pack_fills_shelves_tallest_first() {
+        let sizes = [[40.0, 30.0], [60.0, 20.0], [30.0, 50.0]];
+        assert_eq!(
+            pack(&sizes, &options([100.0, 100.0], false)),
+            Ok(vec![at(32.0, 0.0), at(0.0, 52.0), at(0.0, 0.0)])
+        );
+    }
+
+    #[test]
+    fn pack_lays_designs_flat_when_allowed_to_turn_them() {
+        let sizes = [[40.0, 30.0], [60.0, 20.0], [30.0, 50.0]];
+        assert_eq!(
+            pack(&sizes, &options([100.0, 100.0], true)),
+            Ok(vec![at(0.0, 0.0), at(0.0, 32.0), turned(42.0, 0.0)])
+        );
+        // Standing a design up that is too wide for the bed any other way
+        assert_eq!(
+            pack(&[[120.0, 20.0]], &options([100.0, 150.0], true)),
+            Ok(vec![turned(0.0, 0.0)])
+        );
+    }
+
+    #[test]
+    fn pack_gives_the_area_designs_would_fit_in() {
+        let sizes = [[40.0, 30.0], [60.0, 20.0], [30.0, 50.0]];
+        // Two shelves, 50 and 20 high, with the gap between them
+        assert_eq!(
+            pack(&sizes, &options([80.0, 60.0], false)),
+            Err([80.0, 72.0])
+        );
+        // Wider than the bed, the area is as wide as the widest design
+        assert_eq!(
+            pack(&[[120.0, 20.0]], &options([100.0, 100.0], false)),
+            Err([120.0, 20.0])
+        );
+    }
+
+    #[test]
+    fn place_moves_the_design_to_its_corner() {
+        let lines = vec![
+            Line::command("G", 0, &[("X", 10.0), ("Y", 10.0)]),
+            Line::command("G", 1, &[("X", 40.0), ("Y", 20.0)]),
+        ];
+        assert_eq!(
+            place(lines.clone(), at(5.0, 5.0)),
+            vec![
+                Line::command("G", 0, &[("X", 5.0), ("Y", 5.0)]),
+                Line::command("G", 1, &[("X", 35.0), ("Y", 15.0)]),
+            ]
+        );
+        // A quarter turn counterclockwise stands the 30 by 10 design up
+        let placed = place(lines, turned(5.0, 5.0));
+        assert_eq!(
+            postprocess::bounds(&placed),
+            Some([[5.0, 5.0], [15.0, 35.0]])
+        );
+        assert_eq!(placed[1].value("X"), Some(5.0));
+        assert_eq!(placed[1].value("Y"), Some(35.0));
+    }
+}
@@ -0,0 +1,502 @@
+use roxmltree::Document;
+
+use crate::{elements, gcode::Line};
+
+/// Most elements [`optimize`] searches for the shortest order of all, as the search keeps the
+/// set of paths it has taken in the bits of a `u64`
+pub const EXACT_LIMIT: usize = 64;
+
+/// Most partial orders the search for the shortest order extends before it settles for the
+/// shortest found so far
+const SEARCH_BUDGET: usize = 1_000_000;
+
+/// Total travel of the program as it was, after the nearest-first ordering and after 2-opt,
+/// or the shortest possible when `exact`
+pub struct Travel {
+    pub original: f64,
+    pub greedy: f64,
+    pub optimized: f64,
+    pub exact: bool,
+}
+
+/// Reorders the elements to shorten the travels between them, each still cut the way it was.
+///
+/// Starting from where the program is before the first element, the nearest element start is
+/// taken each time, then segments of that order are reversed while any reversal shortens the
+/// total, for at most `iterations` sweeps over every segment. With `exact_max`, jobs of at most
+/// that many elements are then searched for the shortest order of all, settling for the
+/// shortest found when the search runs out of tries.
+pub fn optimize(
+    lines: Vec<Line>,
+    doc: &Document,
+    iterations: usize,
+    exact_max: Option<usize>,
+) -> (Vec<Line>, Travel) {
+    let program = elements::split_program(doc, &lines);
+    let home = end_point(&lines[program.prefix.clone()], [0.0, 0.0]);
+    // Elements without moves keep their place at the front, there is nothing to travel to
+    let (mut output, mut paths) = (lines[program.prefix.clone()].to_vec(), Vec::new());
+    for (range, _) in program.chunks.iter() {
+        let chunk = &lines[range.clone()];
+        match start_point(chunk) {
+            Some(start) => paths.push(Path {
+                range: range.clone(),
+                start,
+                end: end_point(chunk, start),
+            }),
+            None => output.extend_from_slice(chunk),
+        }
+    }
+
+    let original: Vec<usize> = (0..paths.len()).collect();
+    let mut order = nearest_first(&paths, home);
+    let greedy = travel(&paths, &order, home);
+    two_opt(&paths, &mut order, home, iterations);
+    let exact = match exact_max {
+        Some(max) if paths.len() > max => {
+            warning!(
+                "ordering {} elements with 2-opt, more than the --tsp-max-paths of {} to search exactly",
+                paths.len(),
+                max
+            );
+            false
+        }
+        Some(_) => {
+            let finished;
+            (order, finished) = shortest(&paths, order, home, SEARCH_BUDGET);
+            if !finished {
+                warning!(
+                    "stopped searching for the shortest order of {} elements after {} tries, keeping the shortest found",
+                    paths.len(),
+                    SEARCH_BUDGET
+                );
+            }
+            finished
+        }
+        None => false,
+    };
+    let travel = Travel {
+        original: travel(&paths, &original, home),
+        greedy,
+        optimized: travel(&paths, &order, home),
+        exact,
+    };
+
+    for &i in order.iter() {
+        output.extend_from_slice(&lines[paths[i].range.clone()]);
+    }
+    output.extend_from_slice(&lines[program.suffix]);
+    (output, travel)
+}
+
+/// An element's lines, with where its travel goes to and where it leaves the tool
+struct Path {
+    range: std::ops::Range<usize>,
+    start: [f64; 2],
+    end: [f64; 2],
+}
+
+fn nearest_first(paths: &[Path], home: [f64; 2]) -> Vec<usize> {
+    let mut left: Vec<usize> = (0..paths.len()).collect();
+    let mut order = Vec::with_capacity(paths.len());
+    let mut position = home;
+    while !left.is_empty() {
+        let (index, _) = left
+            .iter()
+            .enumerate()
+            .map(|(index, &i)| (index, distance(position, paths[i].start)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        let next = left.swap_remove(index);
+        position = paths[next].end;
+        order.push(next);
+    }
+    order
+}
+
+/// Reverses the part of the order from `i` to `j` wherever that shortens the travel.
+///
+/// The paths keep their direction, so the reversed part is costed by travelling from each
+/// path's end back to the start of the one before it: `forward` and `backward` hold the running
+/// totals of those travels for the current order.
+fn two_opt(paths: &[Path], order: &mut [usize], home: [f64; 2], iterations: usize) {
+    let n = order.len();
+    let (mut forward, mut backward) = (vec![0.0; n], vec![0.0; n]);
+    let totals = |order: &[usize], forward: &mut [f64], backward: &mut [f64]| {
+        for k in 1..n {
+            let (a, b) = (&paths[order[k - 1]], &paths[order[k]]);
+            forward[k] = forward[k - 1] + distance(a.end, b.start);
+            backward[k] = backward[k - 1] + distance(b.end, a.start);
+        }
+    };
+    totals(order, &mut forward, &mut backward);
+    for _ in 0..iterations {
+        let mut improved = false;
+        for i in 0..n {
+            for j in i + 1..n {
+                let before = match i {
+                    0 => home,
+                    _ => paths[order[i - 1]].end,
+                };
+                let (first, last) = (&paths[order[i]], &paths[order[j]]);
+                let mut current = distance(before, first.start) + forward[j] - forward[i];
+                let mut reversed = distance(before, last.start) + backward[j] - backward[i];
+                if let Some(&after) = order.get(j + 1) {
+                    current += distance(last.end, paths[after].start);
+                    reversed += distance(first.end, paths[after].start);
+                }
+                if reversed < current - 1e-9 {
+                    order[i..=j].reverse();
+                    totals(order, &mut forward, &mut backward);
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+}
+
+/// The order with the least travel of all, by branch and bound starting from `order`, and
+/// whether the search finished within `budget` partial orders.
+///
+/// Orders are built up a path at a time, trying the nearest paths next first. A partial order
+/// is given up once its travel plus the least the paths left could add is no shorter than the
+/// best order so far. That starts out as `order`, the 2-opt one, so it is only replaced by an
+/// order that is shorter, rather than by an equal one.
+fn shortest(
+    paths: &[Path],
+    order: Vec<usize>,
+    home: [f64; 2],
+    budget: usize,
+) -> (Vec<usize>, bool) {
+    let mut search = Search {
+        from_home: paths
+            .iter()
+            .map(|path| distance(home, path.start))
+            .collect(),
+        between: paths
+            .iter()
+            .map(|from| {
+                paths
+                    .iter()
+                    .map(|to| distance(from.end, to.start))
+                    .collect()
+            })
+            .collect(),
+        best: travel(paths, &order, home),
+        shortest: order,
+        order: Vec::with_capacity(paths.len()),
+        budget,
+    };
+    let all = match paths.len() {
+        64 => u64::MAX,
+        count => (1 << count) - 1,
+    };
+    search.extend(None, all, 0.0);
+    let finished = search.budget > 0;
+    (search.shortest, finished)
+}
+
+/// State of the search in [`shortest`]
+struct Search {
+    /// Travel from home to each path, and from the end of each to the start of every other
+    from_home: Vec<f64>,
+    between: Vec<Vec<f64>>,
+    best: f64,
+    shortest: Vec<usize>,
+    /// The partial order being extended
+    order: Vec<usize>,
+    budget: usize,
+}
+
+impl Search {
+    fn cost(&self, from: Option<usize>, to: usize) -> f64 {
+        match from {
+            Some(from) => self.between[from][to],
+            None => self.from_home[to],
+        }
+    }
+
+    /// Tries every way of taking the paths in `left` after `last`, having travelled `travel`
+    fn extend(&mut self, last: Option<usize>, left: u64, travel: f64) {
+        if left == 0 {
+            if travel < self.best - 1e-9 {
+                self.best = travel;
+                self.shortest = self.order.clone();
+            }
+            return;
+        }
+        if self.budget == 0 {
+            return;
+        }
+        self.budget -= 1;
+        if travel + self.least(last, left) >= self.best - 1e-9 {
+            return;
+        }
+        let mut next: Vec<(f64, usize)> = bits(left).map(|to| (self.cost(last, to), to)).collect();
+        next.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (cost, to) in next {
+            self.order.push(to);
+            self.extend(Some(to), left & !(1 << to), travel + cost);
+            self.order.pop();
+        }
+    }
+
+    /// Least travel taking the paths in `left` after `last` can add: each of them has to be
+    /// travelled to from `last` or another of them, and `last` and all but one of them have to
+    /// be travelled from to another of them
+    fn least(&self, last: Option<usize>, left: u64) -> f64 {
+        let into: f64 = bits(left)
+            .map(|to| {
+                bits(left)
+                    .filter(|&from| from != to)
+                    .map(|from| self.between[from][to])
+                    .fold(self.cost(last, to), f64::min)
+            })
+            .sum();
+        let from_last = bits(left)
+            .map(|to| self.cost(last, to))
+            .fold(f64::INFINITY, f64::min);
+        let from_left = bits(left).map(|from| {
+            bits(left)
+                .filter(|&to| to != from)
+                .map(|to| self.between[from][to])
+                .fold(f64::INFINITY, f64::min)
+        });
+        // All but the largest, as the path taken last travels nowhere after it
+        let (from_left, _) = from_left.fold((0.0, 0.0), |(sum, most): (f64, f64), out| {
+            (sum + most.min(out), most.max(out))
+        });
+        into.max(from_last + from_left)
+    }
+}
+
+/// The indices of the bits set
+fn bits(mut set: u64) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        let bit = (set != 0).then(|| set.trailing_zeros() as usize)?;
+        set &= set - 1;
+        Some(bit)
+    })
+}
+
+fn travel(paths: &[Path], order: &[usize], home: [f64; 2]) -> f64 {
+    let mut position = home;
+    let mut total = 0.0;
+    for &i in order {
+        total += distance(position, paths[i].start);
+        position = paths[i].end;
+    }
+    total
+}
+
+/// Where the first move that gives both X and Y goes
+fn start_point(lines: &[Line]) -> Option<[f64; 2]> {
+    lines
+        .iter()
+        .filter(|line| (0..=3).any(|number| line.is("G", number)))
+        .find_map(|line| Some([line.value("X")?, line.value("Y")?]))
+}
+
+/// Where the lines leave the tool, starting from `from`
+fn end_point(lines: &[Line], from: [f64; 2]) -> [f64; 2] {
+    let mut position = from;
+    for line in lines
+        .iter()
+        .filter(|line| (0..=3).any(|number| line.is("G", number)))
+    {
+        position = [
+            line.value("X").unwrap_or(position[0]),
+            line.value("Y").unwrap_or(position[1]),
+        ];
+    }
+    position
+}
+
+fn distance(a: [f64; 2], b: [f64; 2]) -> f64 {
+    (b[0] - a[0]).hypot(b[1] - a[1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOME: [f64; 2] = [0.0, 0.0];
+
+    fn path(start: [f64; 2], end: [f64; 2]) -> Path {
+        Path {
+            range: 0..0,
+            start,
+            end,
+        }
+    }
+
+    /// Paths that start and end at the same point on the X axis
+    fn points(xs: &[f64]) -> Vec<Path> {
+        xs.iter().map(|&x| path([x, 0.0], [x, 0.0])).collect()
+    }
+
+    /// Paths going every which way, the same each run
+    fn scattered(count: usize) -> Vec<Path> {
+        let mut seed = 12345u64;
+        let mut next = || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as f64 / (1u64 << 31) as f64 * 100.0
+        };
+        (0..count)
+            .map(|_| path([next(), next()], [next(), next()]))
+            .collect()
+    }
+
+    #[test]
+    fn nearest_first_takes_the_closest_start_each_time() {
+        let paths = vec![
+            path([5.0, 0.0], [9.0, 0.0]),
+            path([1.0, 0.0], [2.0, 0.0]),
+            path([10.0, 0.0], [20.0, 0.0]),
+        ];
+        assert_eq!(nearest_first(&paths, HOME), [1, 0, 2]);
+        assert_eq!(travel(&paths, &[1, 0, 2], HOME), 1.0 + 3.0 + 1.0);
+    }
+
+    #[test]
+    fn two_opt_turns_back_for_the_path_behind_home() {
+        // Taking the nearest path first leaves the one behind home to come back for
+        let paths = points(&[1.0, -2.0, 4.0, 5.0]);
+        let mut order = nearest_first(&paths, HOME);
+        assert_eq!(order, [0, 1, 2, 3]);
+        assert_eq!(travel(&paths, &order, HOME), 11.0);
+        two_opt(&paths, &mut order, HOME, 100);
+        assert_eq!(order, [1, 0, 2, 3]);
+        assert_eq!(travel(&paths, &order, HOME), 9.0);
+    }
+
+    #[test]
+    fn two_opt_leaves_no_reversal_that_shortens_the_travel() {
+        // The paths keep their direction, so a reversal only pays off as the backward totals say
+        let paths = scattered(12);
+        let mut order = nearest_first(&paths, HOME);
+        let greedy = travel(&paths, &order, HOME);
+        two_opt(&paths, &mut order, HOME, 1000);
+        let optimized = travel(&paths, &order, HOME);
+        assert!(optimized <= greedy);
+        for i in 0..order.len() {
+            for j in i + 1..order.len() {
+                let mut reversed = order.clone();
+                reversed[i..=j].reverse();
+                assert!(travel(&paths, &reversed, HOME) >= optimized - 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn two_opt_stops_after_its_iterations() {
+        let paths = scattered(12);
+        let mut order = nearest_first(&paths, HOME);
+        let greedy = order.clone();
+        two_opt(&paths, &mut order, HOME, 0);
+        assert_eq!(order, greedy);
+    }
+
+    #[test]
+    fn ordering_no_paths_or_one() {
+        let mut order = nearest_first(&[], HOME);
+        two_opt(&[], &mut order, HOME, 100);
+        assert!(order.is_empty());
+
+        let one = points(&[3.0]);
+        let mut order = nearest_first(&one, HOME);
+        two_opt(&one, &mut order, HOME, 100);
+        assert_eq!(order, [0]);
+    }
+
+    fn permutations(order: &mut Vec<usize>, k: usize, each: &mut impl FnMut(&[usize])) {
+        if k == order.len() {
+            return each(order);
+        }
+        for i in k..order.len() {
+            order.swap(k, i);
+            permutations(order, k + 1, each);
+            order.swap(k, i);
+        }
+    }
+
+    /// The 2-opt order, as [`optimize`] starts the search from
+    fn after_two_opt(paths: &[Path]) -> Vec<usize> {
+        let mut order = nearest_first(paths, HOME);
+        two_opt(paths, &mut order, HOME, 100);
+        order
+    }
+
+    #[test]
+    fn shortest_beats_nearest_first_along_a_line() {
+        let paths = points(&[1.0, -2.0, 4.0, 5.0]);
+        let (order, finished) = shortest(&paths, vec![0, 1, 2, 3], HOME, SEARCH_BUDGET);
+        assert!(finished);
+        assert_eq!(order, [1, 0, 2, 3]);
+        assert_eq!(travel(&paths, &order, HOME), 9.0);
+    }
+
+    #[test]
+    fn shortest_matches_trying_every_order() {
+        let paths = scattered(8);
+        let mut least = f64::INFINITY;
+        permutations(&mut (0..paths.len()).collect(), 0, &mut |order| {
+            least = least.min(travel(&paths, order, HOME))
+        });
+        let two_opt = after_two_opt(&paths);
+        assert!(travel(&paths, &two_opt, HOME) > least + 1e-9);
+        let (order, finished) = shortest(&paths, two_opt, HOME, SEARCH_BUDGET);
+        assert!(finished);
+        assert!((travel(&paths, &order, HOME) - least).abs() < 1e-9);
+    }
+
+    #[test]
+    fn shortest_of_no_paths_or_one() {
+        assert_eq!(
+            shortest(&[], Vec::new(), HOME, SEARCH_BUDGET),
+            (Vec::new(), true)
+        );
+        assert_eq!(
+            shortest(&points(&[3.0]), vec![0], HOME, SEARCH_BUDGET),
+            (vec![0], true)
+        );
+    }
+
+    #[test]
+    fn an_equally_short_order_is_kept() {
+        // Either side of home first travels 1 + 2
+        let paths = points(&[-1.0, 1.0]);
+        assert_eq!(shortest(&paths, vec![0, 1], HOME, SEARCH_BUDGET).0, [0, 1]);
+        assert_eq!(shortest(&paths, vec![1, 0], HOME, SEARCH_BUDGET).0, [1, 0]);
+    }
+
+    #[test]
+    fn shortest_settles_for_the_best_found_when_out_of_tries() {
+        let paths = scattered(12);
+        let two_opt = after_two_opt(&paths);
+        let (order, finished) = shortest(&paths, two_opt.clone(), HOME, 10);
+        assert!(!finished);
+        assert!(travel(&paths, &order, HOME) <= travel(&paths, &two_opt, HOME));
+        let mut sorted = order.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..paths.len()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn shortest_finishes_on_many_paths_in_a_line() {
+        // Starting from the 2-opt order, which is already the shortest here, the bound has to
+        // rule out every other order
+        let xs: Vec<f64> = (0..EXACT_LIMIT).map(|i| i as f64).collect();
+        let paths = points(&xs);
+        let two_opt = after_two_opt(&paths);
+        assert_eq!(
+            shortest(&paths, two_opt.clone(), HOME, SEARCH_BUDGET),
+            (two_opt, true)
+        );
+    }
+}
@@ -0,0 +1,346 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::gcode::Line;
+
+/// Temporary file currently being written, removed if the process is interrupted
+static PENDING: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Whether `--quiet` was given, silencing warnings and progress
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Exit code for a process stopped by SIGINT, following the shell convention of 128 + signal
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Discards any partially written output when interrupted by Ctrl-C or SIGTERM, then exits
+pub fn handle_interrupts() {
+    let result = ctrlc::set_handler(|| {
+        if let Some(path) = PENDING.lock().ok().and_then(|pending| pending.clone()) {
+            let _ = fs::remove_file(path);
+        }
+        eprintln!("Aborted, partial output discarded");
+        std::process::exit(INTERRUPTED_EXIT_CODE);
+    });
+    if let Err(err) = result {
+        warning!("could not install interrupt handler: {}", err);
+    }
+}
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Bar counting the paths converted, drawn on stderr only when it is a terminal and not quiet
+pub fn progress_bar(paths: usize) -> ProgressBar {
+    if is_quiet() {
+        return ProgressBar::hidden();
+    }
+    let bar = ProgressBar::new(paths as u64);
+    if let Ok(style) = ProgressStyle::with_template("{bar:40} {pos}/{len} paths") {
+        bar.set_style(style);
+    }
+    bar
+}
+
+/// Writes a file by writing a temporary file beside it and renaming that over the destination.
+///
+/// An interrupted or failed write leaves any existing file at `path` untouched.
+pub fn write_atomically<F>(path: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let temp_path = temp_path(path);
+    set_pending(Some(temp_path.clone()));
+    let _discard = Discard(&temp_path);
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            write(&mut writer)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()
+        })
+        .and_then(|_| fs::rename(&temp_path, path))
+}
+
+/// Removes the temporary file however writing it ends, including a panic while the program is
+/// assembled, once it's no longer renamed into place
+struct Discard<'a>(&'a Path);
+
+impl Drop for Discard<'_> {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(self.0);
+        set_pending(None);
+    }
+}
+
+fn set_pending(path: Option<PathBuf>) {
+    if let Ok(mut pending) = PENDING.lock() {
+        *pending = path;
+    }
+}
+
+/// Hidden file in the destination's directory, so the rename never crosses filesystems
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map_or("output".into(), |name| name.to_string_lossy());
+    path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()))
+}
+
+/// Ending written after every line of G-code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LineEnding {
+    /// `\n`
+    Lf,
+    /// `\r\n`, for controllers that need Windows line endings, such as some over serial
+    Crlf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+}
+
+/// Controller dialect the output is written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Flavor {
+    /// Whatever the converter and the other options produce
+    Generic,
+    /// Wrapped in `%` lines, with parenthesized comments and nothing in lowercase
+    Fanuc,
+}
+
+/// What the controller takes an `F` word to mean
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FeedrateUnits {
+    /// Millimeters per minute, as feedrates are configured
+    MmPerMin,
+    /// Millimeters per second
+    MmPerSec,
+}
+
+impl FeedrateUnits {
+    /// Rewrites the line's feedrate, given in millimeters per minute, in these units
+    pub fn convert(self, mut line: Line) -> Line {
+        if let (FeedrateUnits::MmPerSec, Some(feedrate)) = (self, line.value("F")) {
+            line.set_value("F", (feedrate / 60.0 * 1e9).round() / 1e9);
+        }
+        line
+    }
+}
+
+/// The first word of the line a Fanuc controller wouldn't accept, if any: one with an address
+/// longer than a letter, or an `O` program number inside the program
+pub fn non_fanuc_word(line: &Line) -> Option<String> {
+    let Line::Command(fields) = line else {
+        return None;
+    };
+    fields
+        .iter()
+        .find(|field| field.letters.len() != 1 || field.letters.eq_ignore_ascii_case("O"))
+        .map(|field| field.to_string())
+}
+
+/// Character set the written G-code is restricted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Encoding {
+    Utf8,
+    /// Plain 7-bit ASCII, for controllers that reject any other byte
+    Ascii,
+}
+
+/// Replaces non-ASCII characters with the closest ASCII spelling, or `?` when there is none
+pub fn to_ascii(text: &str) -> String {
+    let mut ascii = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_ascii() {
+            ascii.push(c);
+            continue;
+        }
+        let replacement = match c {
+            'À'..='Å' => "A",
+            'à'..='å' => "a",
+            'Æ' => "AE",
+            'æ' => "ae",
+            'Ç' => "C",
+            'ç' => "c",
+            'È'..='Ë' => "E",
+            'è'..='ë' => "e",
+            'Ì'..='Ï' => "I",
+            'ì'..='ï' => "i",
+            'Ð' => "D",
+            'ð' => "d",
+            'Ñ' => "N",
+            'ñ' => "n",
+            'Ò'..='Ö' | 'Ø' => "O",
+            'ò'..='ö' | 'ø' => "o",
+            'Ù'..='Ü' => "U",
+            'ù'..='ü' => "u",
+            'Ý' => "Y",
+            'ý' | 'ÿ' => "y",
+            'Þ' => "Th",
+            'þ' => "th",
+            'ß' => "ss",
+            'Œ' => "OE",
+            'œ' => "oe",
+            '×' => "x",
+            '°' => "deg",
+            'µ' => "u",
+            '‘' | '’' | '′' => "'",
+            '“' | '”' | '″' => "\"",
+            '‐'..='—' | '−' => "-",
+            '…' => "...",
+            '\u{a0}' => " ",
+            _ => "?",
+        };
+        ascii.push_str(replacement);
+    }
+    ascii
+}
+
+/// Placeholder in an `--output-template` file that the program is written in place of
+const TEMPLATE_PLACEHOLDER: &str = "{{GCODE}}";
+
+/// Text written around the program, from an `--output-template` file
+#[derive(Debug, Clone)]
+pub struct Template {
+    pub before: String,
+    pub after: String,
+}
+
+impl Template {
+    /// Splits the template at its one placeholder, with its line endings changed to `ending`
+    pub fn parse(text: &str, ending: LineEnding) -> Result<Self, String> {
+        let text = text.replace("\r\n", "\n");
+        let (before, after) = match text.split(TEMPLATE_PLACEHOLDER).collect::<Vec<_>>()[..] {
+            [before, after] => (before, after),
+            [_] => return Err(format!("it has no {} placeholder", TEMPLATE_PLACEHOLDER)),
+            _ => {
+                return Err(format!(
+                    "it has more than one {} placeholder",
+                    TEMPLATE_PLACEHOLDER
+                ))
+            }
+        };
+        // The program starts on a line of its own and ends with its own line ending
+        let before = match before.is_empty() || before.ends_with('\n') {
+            true => before.to_string(),
+            false => format!("{}\n", before),
+        };
+        let after = after.strip_prefix('\n').unwrap_or(after);
+        Ok(Template {
+            before: before.replace('\n', ending.as_str()),
+            after: after.replace('\n', ending.as_str()),
+        })
+    }
+}
+
+/// Time spent in each stage of a conversion, for `--profile-conversion`
+pub struct Profile {
+    start: Instant,
+    stages: Vec<(&'static str, Duration)>,
+}
+
+impl Profile {
+    pub fn start() -> Self {
+        Profile {
+            start: Instant::now(),
+            stages: Vec::new(),
+        }
+    }
+
+    /// Records the time since the last stage ended as the named stage
+    pub fn stage(&mut self, name: &'static str) {
+        let now = Instant::now();
+        self.stages.push((name, now - self.start));
+        self.start = now;
+    }
+
+    /// Prints each stage's time and the total to stderr
+    pub fn print(&self) {
+        eprintln!("Conversion profile:");
+        for (name, time) in self.stages.iter() {
+            eprintln!("  {:<14}{:>10.3}ms", name, time.as_secs_f64() * 1000.0);
+        }
+        let total: Duration = self.stages.iter().map(|(_, time)| *time).sum();
+        eprintln!("  {:<14}{:>10.3}ms", "total", total.as_secs_f64() * 1000.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for a test's files
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("usgcode-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn a_failed_write_leaves_the_existing_file() {
+        let dir = temp_dir("atomic-failure");
+        let path = dir.join("out.gcode");
+        fs::write(&path, "G0 X1\n").unwrap();
+        let written = write_atomically(&path, |file| {
+            file.write_all(b"G0 X2\n")?;
+            Err(io::Error::other("the disk is full"))
+        });
+        let kept = fs::read_to_string(&path).unwrap();
+        let files = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(written.unwrap_err().to_string(), "the disk is full");
+        assert_eq!(kept, "G0 X1\n");
+        // The temporary file is gone too
+        assert_eq!(files, 1);
+    }
+
+    #[test]
+    fn a_finished_write_replaces_the_file() {
+        let dir = temp_dir("atomic-success");
+        let path = dir.join("out.gcode");
+        fs::write(&path, "G0 X1\n").unwrap();
+        write_atomically(&path, |file| file.write_all(b"G0 X2\n")).unwrap();
+        let replaced = fs::read_to_string(&path).unwrap();
+        let files = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(replaced, "G0 X2\n");
+        assert_eq!(files, 1);
+    }
+
+    #[test]
+    fn feedrates_convert_to_millimeters_per_second() {
+        let line = Line::command("G", 1, &[("X", 5.0), ("F", 1200.0)]);
+        assert_eq!(
+            FeedrateUnits::MmPerSec.convert(line.clone()).value("F"),
+            Some(20.0)
+        );
+        assert_eq!(FeedrateUnits::MmPerMin.convert(line.clone()), line);
+        // Lines without a feedrate are left alone
+        let travel = Line::command("G", 0, &[("X", 5.0)]);
+        assert_eq!(FeedrateUnits::MmPerSec.convert(travel.clone()), travel);
+    }
+}
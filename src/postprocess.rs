@@ -0,0 +1,1517 @@
+use g_code::emit::Value;
+use roxmltree::Document;
+use std::collections::VecDeque;
+
+use crate::{
+    elements,
+    gcode::Line,
+    mcodes::{MCode, Mapping},
+};
+
+/// The program's lines as they are assembled
+pub type Lines<'a> = Box<dyn Iterator<Item = Line> + 'a>;
+
+/// A transformation of the program
+enum Pass<'a> {
+    /// Needs the whole program at once
+    Program(Box<dyn FnOnce(Vec<Line>) -> Vec<Line> + 'a>),
+    /// Works through the program a line at a time
+    Stream(Box<dyn FnOnce(Lines<'a>) -> Lines<'a> + 'a>),
+}
+
+/// Passes every line to `inspect` as it goes by, then what it gathered to `finished` once they
+/// all have
+pub fn inspect_all<'a, T: Default + 'a>(
+    mut lines: Lines<'a>,
+    mut inspect: impl FnMut(&mut T, &Line) + 'a,
+    finished: impl FnOnce(T) + 'a,
+) -> Lines<'a> {
+    let (mut state, mut finished) = (Some(T::default()), Some(finished));
+    Box::new(std::iter::from_fn(move || match lines.next() {
+        Some(line) => {
+            if let Some(state) = state.as_mut() {
+                inspect(state, &line);
+            }
+            Some(line)
+        }
+        None => {
+            if let (Some(state), Some(finished)) = (state.take(), finished.take()) {
+                finished(state);
+            }
+            None
+        }
+    }))
+}
+
+/// Transformations of the program in the order they run.
+///
+/// The program is only collected for the passes that need it whole, so with only line by line
+/// passes it's written as it's assembled.
+#[derive(Default)]
+pub struct Passes<'a>(Vec<Pass<'a>>);
+
+impl<'a> Passes<'a> {
+    pub fn program(&mut self, pass: impl FnOnce(Vec<Line>) -> Vec<Line> + 'a) {
+        self.0.push(Pass::Program(Box::new(pass)));
+    }
+
+    pub fn stream(&mut self, pass: impl FnOnce(Lines<'a>) -> Lines<'a> + 'a) {
+        self.0.push(Pass::Stream(Box::new(pass)));
+    }
+
+    pub fn run(self, mut lines: Lines<'a>) -> Lines<'a> {
+        let mut program: Option<Vec<Line>> = None;
+        for pass in self.0 {
+            match pass {
+                Pass::Program(pass) => {
+                    let whole = match program.take() {
+                        Some(program) => program,
+                        None => {
+                            std::mem::replace(&mut lines, Box::new(std::iter::empty())).collect()
+                        }
+                    };
+                    program = Some(pass(whole));
+                }
+                Pass::Stream(pass) => {
+                    if let Some(program) = program.take() {
+                        lines = Box::new(program.into_iter());
+                    }
+                    lines = pass(lines);
+                }
+            }
+        }
+        match program {
+            Some(program) => Box::new(program.into_iter()),
+            None => lines,
+        }
+    }
+}
+
+/// Inserts an `M0` pause before every path after the first.
+///
+/// When a park position is given, the pause is bracketed by a travel to it and a return,
+/// so the gantry is out of the way while the operator swaps pens or material.
+pub fn insert_pauses(lines: Vec<Line>, tool_on: &Line, park: [Option<f64>; 2]) -> Vec<Line> {
+    let mut output = Vec::with_capacity(lines.len());
+    let mut position = [None, None];
+    let mut paths = 0;
+
+    for line in lines {
+        if &line == tool_on {
+            if paths > 0 {
+                output.extend(pause(position, park));
+            }
+            paths += 1;
+        }
+        for (axis, letter) in ["X", "Y"].iter().enumerate() {
+            if let Some(value) = line.value(letter) {
+                position[axis] = Some(value);
+            }
+        }
+        output.push(line);
+    }
+    output
+}
+
+/// Replaces the feedrate of cutting moves for elements (or groups) with a `data-feedrate`
+pub fn apply_feedrates(mut lines: Vec<Line>, doc: &Document) -> Vec<Line> {
+    for (range, node) in elements::chunks(doc, &lines) {
+        let Some(feedrate) = node
+            .ancestors()
+            .find_map(|ancestor| ancestor.attribute("data-feedrate"))
+        else {
+            continue;
+        };
+        let feedrate = match feedrate.trim().parse::<f64>() {
+            Ok(feedrate) if feedrate.is_finite() && feedrate > 0.0 => feedrate,
+            _ => {
+                warning!(
+                    "ignoring invalid data-feedrate \"{}\" on <{}>, using the global feedrate",
+                    feedrate,
+                    elements::node_name(&node)
+                );
+                continue;
+            }
+        };
+        for line in lines[range].iter_mut() {
+            if line.is("G", 1) || line.is("G", 2) || line.is("G", 3) {
+                line.set_value("F", feedrate);
+            }
+        }
+    }
+    lines
+}
+
+/// Sets the laser power (S word) of every element's cutting moves to `max_power` scaled by its
+/// opacity, which multiplies through the groups it is in
+pub fn apply_opacity_power(mut lines: Vec<Line>, doc: &Document, max_power: f64) -> Vec<Line> {
+    for (range, node) in elements::chunks(doc, &lines) {
+        let mut opacity = 1.0;
+        for ancestor in node.ancestors().filter(|ancestor| ancestor.is_element()) {
+            let Some(value) = elements::own_property(&ancestor, "opacity") else {
+                continue;
+            };
+            let parsed = match value.strip_suffix('%') {
+                Some(percent) => percent.trim().parse::<f64>().map(|percent| percent / 100.0),
+                None => value.parse::<f64>(),
+            };
+            match parsed {
+                Ok(value) if value.is_finite() => opacity *= value.clamp(0.0, 1.0),
+                _ => warning!(
+                    "ignoring invalid opacity \"{}\" on <{}>",
+                    value,
+                    elements::node_name(&ancestor)
+                ),
+            }
+        }
+        let power = (max_power * opacity * 1000.0).round() / 1000.0;
+        for line in lines[range].iter_mut() {
+            if line.is("G", 1) || line.is("G", 2) || line.is("G", 3) {
+                line.set_value("S", power);
+            }
+        }
+    }
+    lines
+}
+
+/// Lowers the tool to `data-depth` millimeters below zero for elements (or groups) that have one,
+/// replacing the Z of their tool-on sequence
+pub fn apply_depths(mut lines: Vec<Line>, doc: &Document, tool_on: &[Line]) -> Vec<Line> {
+    if !tool_on.iter().any(|line| line.value("Z").is_some()) {
+        warning!("data-depth has no effect as the tool-on sequence doesn't set a Z");
+        return lines;
+    }
+    for (range, node) in elements::chunks(doc, &lines) {
+        let Some(depth) = node
+            .ancestors()
+            .find_map(|ancestor| ancestor.attribute("data-depth"))
+        else {
+            continue;
+        };
+        let depth = match depth.trim().parse::<f64>() {
+            Ok(depth) if depth.is_finite() && depth >= 0.0 => depth,
+            _ => {
+                warning!(
+                    "ignoring invalid data-depth \"{}\" on <{}>, using the tool-on depth",
+                    depth,
+                    elements::node_name(&node)
+                );
+                continue;
+            }
+        };
+        let mut i = range.start;
+        while i < range.end {
+            if !lines[i..range.end].starts_with(tool_on) {
+                i += 1;
+                continue;
+            }
+            for line in lines[i..i + tool_on.len()].iter_mut() {
+                if line.value("Z").is_some() {
+                    line.set_value("Z", -depth);
+                }
+            }
+            i += tool_on.len();
+        }
+    }
+    lines
+}
+
+/// Inserts a `G4` dwell after every tool-off that ends a path and is followed by another path
+pub fn insert_dwells(lines: Vec<Line>, tool_on: &[Line], tool_off: &[Line], ms: f64) -> Vec<Line> {
+    let path_starts: Vec<usize> = (0..lines.len())
+        .filter(|&i| lines[i..].starts_with(tool_on))
+        .collect();
+    let mut output = Vec::with_capacity(lines.len());
+    let mut cutting = false;
+    let mut i = 0;
+    while i < lines.len() {
+        if lines[i..].starts_with(tool_on) {
+            cutting = true;
+        } else if lines[i..].starts_with(tool_off) {
+            output.extend_from_slice(tool_off);
+            i += tool_off.len();
+            if cutting && path_starts.last().is_some_and(|&start| start > i) {
+                output.push(Line::command("G", 4, &[("P", ms)]));
+            }
+            cutting = false;
+            continue;
+        }
+        output.push(lines[i].clone());
+        i += 1;
+    }
+    output
+}
+
+/// Joins each path to the next one starting within `threshold` millimeters of where it ends,
+/// cutting across the gap instead of lifting, travelling and lowering the tool again.
+///
+/// Paths are only joined when nothing but the tool-off, the travel and the tool-on sequence
+/// comes between them, and the cuts either side have the same feedrate and other words, so a
+/// change of tool, power or depth keeps its lift. Returns the program and how many lifts were
+/// left out.
+pub fn join_paths(
+    lines: Vec<Line>,
+    tool_on: &[Line],
+    tool_off: &[Line],
+    threshold: f64,
+) -> (Vec<Line>, usize) {
+    let mut output: Vec<Line> = Vec::with_capacity(lines.len());
+    let mut joined = 0;
+    let mut i = 0;
+    while i < lines.len() {
+        let travel = i + tool_off.len();
+        let next = travel + 1 + tool_on.len();
+        let join = match (
+            straight_cut(
+                output
+                    .iter()
+                    .rev()
+                    .find(|line| matches!(line, Line::Command(_))),
+            ),
+            lines.get(travel),
+            straight_cut(lines.get(next)),
+        ) {
+            (Some((last, end)), Some(travel_line), Some((next_cut, _)))
+                if lines[i..].starts_with(tool_off)
+                    && travel_line.is("G", 0)
+                    && lines[travel + 1..].starts_with(tool_on)
+                    && other_words(last) == other_words(next_cut) =>
+            {
+                match (travel_line.value("X"), travel_line.value("Y")) {
+                    (Some(x), Some(y)) if (x - end[0]).hypot(y - end[1]) <= threshold => {
+                        // Paths that touch need no cut between them
+                        let mut bridge = last.clone();
+                        bridge.set_value("X", x);
+                        bridge.set_value("Y", y);
+                        Some(((x, y) != (end[0], end[1])).then_some(bridge))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        match join {
+            Some(bridge) => {
+                output.extend(bridge);
+                joined += 1;
+                i = next;
+            }
+            None => {
+                output.push(lines[i].clone());
+                i += 1;
+            }
+        }
+    }
+    (output, joined)
+}
+
+/// A straight cut and the point it ends at
+fn straight_cut(line: Option<&Line>) -> Option<(&Line, [f64; 2])> {
+    let line = line.filter(|line| line.is("G", 1))?;
+    Some((line, [line.value("X")?, line.value("Y")?]))
+}
+
+/// Words of a command other than its X and Y, as written
+fn other_words(line: &Line) -> Vec<String> {
+    match line {
+        Line::Command(fields) => fields
+            .iter()
+            .filter(|field| {
+                !field.letters.eq_ignore_ascii_case("X") && !field.letters.eq_ignore_ascii_case("Y")
+            })
+            .map(|field| field.to_string())
+            .collect(),
+        Line::Comment(_) => Vec::new(),
+    }
+}
+
+/// Drops moves shorter than `resolution` in X, Y and Z together, below what the controller can
+/// position to, returning how many moves were that short and how many of them were dropped.
+///
+/// The distance is measured from the last move kept, so dropping several in a row never puts
+/// the tool further than `resolution` off. A short move is kept when it has a word, such as
+/// a feedrate, that the next move doesn't repeat.
+pub fn drop_tiny_moves(lines: Vec<Line>, resolution: f64) -> (Vec<Line>, usize, usize) {
+    let is_move = |line: &Line| (0..=3).any(|number| line.is("G", number));
+    let words = |line: &Line| -> Vec<String> {
+        match line {
+            Line::Command(fields) => fields
+                .iter()
+                .filter(|field| {
+                    !["X", "Y", "Z"]
+                        .iter()
+                        .any(|axis| field.letters.eq_ignore_ascii_case(axis))
+                })
+                .map(|field| field.to_string())
+                .collect(),
+            Line::Comment(_) => Vec::new(),
+        }
+    };
+    let (mut short_moves, mut dropped) = (0, 0);
+    let mut output = Vec::with_capacity(lines.len());
+    let mut kept: [Option<f64>; 3] = [None; 3];
+    for (i, line) in lines.iter().enumerate() {
+        if !is_move(line) {
+            output.push(line.clone());
+            continue;
+        }
+        let target = [
+            line.value("X").or(kept[0]),
+            line.value("Y").or(kept[1]),
+            line.value("Z").or(kept[2]),
+        ];
+        let deltas: Option<Vec<f64>> = (0..3)
+            .map(|axis| match (kept[axis], target[axis]) {
+                (Some(from), Some(to)) => Some(to - from),
+                (None, None) => Some(0.0),
+                _ => None,
+            })
+            .collect();
+        let short = deltas.is_some_and(|deltas| {
+            deltas.iter().map(|delta| delta * delta).sum::<f64>().sqrt() < resolution
+        }) && line.is("G", 1);
+        if short {
+            short_moves += 1;
+            let next = lines[i + 1..].iter().find(|line| is_move(line));
+            if next.is_some_and(|next| words(next) == words(line)) {
+                dropped += 1;
+                continue;
+            }
+        }
+        kept = target;
+        output.push(line.clone());
+    }
+    (output, short_moves, dropped)
+}
+
+/// Leaves the spindle running from one path to the next when only travels come between the
+/// tool-off and the tool-on sequence, dropping their `M3`, `M4` and `M5` but keeping their Z
+/// moves. Anything else in between, such as a tool change, pause or dwell, still stops it.
+pub fn keep_spindle_running(lines: Vec<Line>, tool_on: &[Line], tool_off: &[Line]) -> Vec<Line> {
+    let spindle = |line: &Line| matches!(m_code(line), Some(MCode(3..=5)));
+    let mut output = Vec::with_capacity(lines.len());
+    let mut started = false;
+    // Start of the tool-on sequence whose spindle command is left out
+    let mut running_into = None;
+    let mut i = 0;
+    while i < lines.len() {
+        if lines[i..].starts_with(tool_on) {
+            let sequence = &lines[i..i + tool_on.len()];
+            match running_into == Some(i) {
+                true => output.extend(sequence.iter().filter(|line| !spindle(line)).cloned()),
+                false => output.extend_from_slice(sequence),
+            }
+            started = true;
+            i += tool_on.len();
+            continue;
+        }
+        if started && lines[i..].starts_with(tool_off) {
+            let after = i + tool_off.len();
+            let next = (after..lines.len()).find(|&j| {
+                lines[j..].starts_with(tool_on)
+                    || !(lines[j].is("G", 0) || matches!(lines[j], Line::Comment(_)))
+            });
+            let sequence = &lines[i..after];
+            match next.filter(|&j| lines[j..].starts_with(tool_on)) {
+                Some(next) => {
+                    output.extend(sequence.iter().filter(|line| !spindle(line)).cloned());
+                    running_into = Some(next);
+                }
+                None => output.extend_from_slice(sequence),
+            }
+            i = after;
+            continue;
+        }
+        output.push(lines[i].clone());
+        i += 1;
+    }
+    output
+}
+
+/// Keeps the first `count` paths, from one tool-on to the tool-off after it, dropping the
+/// travel and cuts of the rest but keeping the lines that end the program
+pub fn first_paths(
+    lines: Vec<Line>,
+    tool_on: &[Line],
+    tool_off: &[Line],
+    count: usize,
+) -> Vec<Line> {
+    let starts = path_starts(&lines, tool_on);
+    let Some(&next) = starts.get(count) else {
+        return lines;
+    };
+    warning!(
+        "converting only the first {} of {} paths",
+        count,
+        starts.len()
+    );
+    let cut = lifted_before(&lines, tool_off, next);
+    let end = lifted_before(&lines, tool_off, lines.len()).max(cut);
+    let mut output = lines[..cut].to_vec();
+    output.extend_from_slice(&lines[end..]);
+    output
+}
+
+/// Drops the first `count` paths, keeping the lines before them and going on from the travel to
+/// the next, e.g. to resume a job that stopped partway through
+pub fn skip_paths(
+    lines: Vec<Line>,
+    tool_on: &[Line],
+    tool_off: &[Line],
+    count: usize,
+) -> Vec<Line> {
+    let starts = path_starts(&lines, tool_on);
+    if count == 0 || starts.is_empty() {
+        return lines;
+    }
+    let first = lifted_before(&lines, tool_off, starts[0]);
+    let resume = match starts.get(count) {
+        Some(&next) => {
+            warning!("skipping the first {} of {} paths", count, starts.len());
+            lifted_before(&lines, tool_off, next)
+        }
+        None => {
+            warning!("skipping all {} paths", starts.len());
+            lifted_before(&lines, tool_off, lines.len())
+        }
+    };
+    let mut output = lines[..first].to_vec();
+    output.extend_from_slice(&lines[resume.max(first)..]);
+    output
+}
+
+/// Drops paths whose cuts never leave the point they start at, from their tool-on to their
+/// last move, calling `finished` with how many were dropped. These only plunge and lift again.
+///
+/// The tool-off after such a path follows the next element's comment, so it is left in place
+/// with the comment for the element it belongs to. Only the lines of a path that hasn't cut
+/// anywhere yet are held back.
+pub fn drop_degenerate<'a>(
+    lines: impl Iterator<Item = Line> + 'a,
+    tool_on: &'a [Line],
+    tool_off: &'a [Line],
+    finished: impl FnOnce(usize) + 'a,
+) -> impl Iterator<Item = Line> + 'a {
+    enum Path {
+        Lifted,
+        /// Since its tool-on, with where that left the tool
+        Unmoved([Option<f64>; 2]),
+        Cutting,
+    }
+    let ends_with = |held: &VecDeque<Line>, sequence: &[Line]| {
+        !sequence.is_empty()
+            && held.len() >= sequence.len()
+            && held.range(held.len() - sequence.len()..).eq(sequence)
+    };
+    let is_move = |line: &Line| (0..=3).any(|number| line.is("G", number));
+
+    let mut lines = lines.fuse();
+    let mut finished = Some(finished);
+    let (mut path, mut position, mut dropped) = (Path::Lifted, [None, None], 0);
+    // Lines that may still be part of a sequence or a path to drop, and lines that aren't
+    let (mut held, mut ready) = (VecDeque::new(), VecDeque::new());
+    std::iter::from_fn(move || loop {
+        if let Some(line) = ready.pop_front() {
+            return Some(line);
+        }
+        let Some(line) = lines.next() else {
+            if let Some(finished) = finished.take() {
+                finished(dropped);
+            }
+            ready.extend(held.drain(..));
+            if ready.is_empty() {
+                return None;
+            }
+            continue;
+        };
+        if is_move(&line) {
+            position = [
+                line.value("X").or(position[0]),
+                line.value("Y").or(position[1]),
+            ];
+        }
+        let cuts = is_move(&line) && !line.is("G", 0);
+        held.push_back(line);
+        match path {
+            Path::Lifted if ends_with(&held, tool_on) => {
+                ready.extend(held.drain(..held.len() - tool_on.len()));
+                path = Path::Unmoved(position);
+                continue;
+            }
+            Path::Unmoved(_) if ends_with(&held, tool_off) => {
+                let off = held.len() - tool_off.len();
+                let end = (tool_on.len()..off)
+                    .rev()
+                    .find(|&i| is_move(&held[i]))
+                    .map_or(tool_on.len(), |i| i + 1);
+                held.drain(..end);
+                dropped += 1;
+                path = Path::Lifted;
+            }
+            Path::Unmoved(from) if cuts && position != from => path = Path::Cutting,
+            Path::Unmoved(_) => continue,
+            Path::Cutting if ends_with(&held, tool_off) => path = Path::Lifted,
+            Path::Cutting | Path::Lifted => {}
+        }
+        // Hold on to enough of the last lines for the sequence they may be the start of
+        let partial = match path {
+            Path::Cutting => tool_off.len(),
+            _ => tool_on.len(),
+        };
+        let release = held.len().saturating_sub(partial.saturating_sub(1));
+        ready.extend(held.drain(..release));
+    })
+}
+
+fn path_starts(lines: &[Line], tool_on: &[Line]) -> Vec<usize> {
+    (0..lines.len())
+        .filter(|&i| lines[i..].starts_with(tool_on))
+        .collect()
+}
+
+/// Index just after the last tool-off sequence before `end`, or 0 when there isn't one
+fn lifted_before(lines: &[Line], tool_off: &[Line], end: usize) -> usize {
+    (0..end)
+        .rev()
+        .find(|&i| lines[i..].starts_with(tool_off))
+        .map_or(0, |i| i + tool_off.len())
+}
+
+/// Makes every move straight down into the material, such as the tool-on plunge, a `G1` at
+/// the plunge feedrate, setting the cutting feedrate again on the cut after it
+pub fn plunge_feedrate(mut lines: Vec<Line>, plunge: f64, cutting: f64) -> Vec<Line> {
+    let mut z = None;
+    let mut feed = cutting;
+    let mut restore = false;
+    for line in lines.iter_mut() {
+        let planar = line.value("X").is_some() || line.value("Y").is_some();
+        let moving = (0..=3).any(|number| line.is("G", number));
+        match line.value("Z") {
+            Some(to) if moving && !planar && z.is_some_and(|from| to < from) => {
+                *line = Line::command("G", 1, &[("Z", to), ("F", plunge)]);
+                restore = true;
+            }
+            _ if moving && !line.is("G", 0) => {
+                feed = line.value("F").unwrap_or(feed);
+                if restore && line.value("F").is_none() {
+                    line.set_value("F", feed);
+                }
+                restore = false;
+            }
+            _ => {}
+        }
+        z = line.value("Z").or(z);
+    }
+    lines
+}
+
+/// Gives the first cutting move the cutting feedrate when no line before it sets an F, since
+/// some controllers reject or misread a feed move before any feedrate
+pub fn feed_first(lines: impl Iterator<Item = Line>, feedrate: f64) -> impl Iterator<Item = Line> {
+    let mut set = false;
+    lines.map(move |mut line| {
+        if !set && line.value("F").is_some() {
+            set = true;
+        } else if !set && (1..=3).any(|number| line.is("G", number)) {
+            line.set_value("F", feedrate);
+            set = true;
+        }
+        line
+    })
+}
+
+/// Splits every straight move longer than `max` millimeters into equal moves along the same
+/// line, none longer than `max`, each keeping the other words of the move
+pub fn split_long_moves(lines: Vec<Line>, max: f64) -> Vec<Line> {
+    const AXES: [&str; 3] = ["X", "Y", "Z"];
+    let mut output = Vec::with_capacity(lines.len());
+    let mut position = [None; 3];
+    for line in lines {
+        let straight = line.is("G", 0) || line.is("G", 1);
+        let target: [Option<f64>; 3] =
+            std::array::from_fn(|axis| line.value(AXES[axis]).or(position[axis]));
+        let moved: Vec<usize> = (0..3)
+            .filter(|&axis| line.value(AXES[axis]).is_some())
+            .collect();
+        let length = moved
+            .iter()
+            .map(|&axis| match (position[axis], target[axis]) {
+                (Some(from), Some(to)) => Some((to - from).powi(2)),
+                _ => None,
+            })
+            .sum::<Option<f64>>()
+            .map(f64::sqrt);
+        if let Some(length) = length.filter(|&length| straight && length > max) {
+            let steps = (length / max).ceil() as usize;
+            for step in 1..steps {
+                let mut part = line.clone();
+                for &axis in &moved {
+                    let (from, to) = (position[axis].unwrap(), target[axis].unwrap());
+                    part.set_value(AXES[axis], from + (to - from) * step as f64 / steps as f64);
+                }
+                output.push(part);
+            }
+        }
+        position = target;
+        output.push(line);
+    }
+    output
+}
+
+/// Smallest and largest X and Y reached by the program's moves
+pub fn bounds(lines: &[Line]) -> Option<[[f64; 2]; 2]> {
+    let mut bounds = Bounds::default();
+    lines.iter().for_each(|line| bounds.include(line));
+    bounds.get()
+}
+
+/// [`bounds`] gathered a line at a time
+#[derive(Debug, Default)]
+pub struct Bounds([Option<[f64; 2]>; 2]);
+
+impl Bounds {
+    pub fn include(&mut self, line: &Line) {
+        for (axis, letter) in ["X", "Y"].iter().enumerate() {
+            if let Some(value) = line.value(letter) {
+                self.0[axis] = Some(match self.0[axis] {
+                    Some([min, max]) => [min.min(value), max.max(value)],
+                    None => [value, value],
+                });
+            }
+        }
+    }
+
+    pub fn get(&self) -> Option<[[f64; 2]; 2]> {
+        match self.0 {
+            [Some(x), Some(y)] => Some([[x[0], y[0]], [x[1], y[1]]]),
+            _ => None,
+        }
+    }
+}
+
+/// Shifts every move so the program's smallest X and Y are at the origin
+pub fn move_to_origin(mut lines: Vec<Line>) -> Vec<Line> {
+    let Some([min, _]) = bounds(&lines) else {
+        return lines;
+    };
+    for line in lines.iter_mut() {
+        for (axis, letter) in ["X", "Y"].into_iter().enumerate() {
+            if let Some(value) = line.value(letter) {
+                line.set_value(letter, value - min[axis]);
+            }
+        }
+    }
+    lines
+}
+
+/// Mirrors every move left to right, within the area the program already covers.
+///
+/// Only X flips, so arcs turn the other way round their mirrored centres.
+pub fn mirror(mut lines: Vec<Line>) -> Vec<Line> {
+    let Some([min, max]) = bounds(&lines) else {
+        return lines;
+    };
+    for line in lines.iter_mut() {
+        if let Some(x) = line.value("X") {
+            line.set_value("X", min[0] + max[0] - x);
+        }
+        if let Some(i) = line.value("I") {
+            line.set_value("I", -i);
+        }
+        let turned = match (line.is("G", 2), line.is("G", 3)) {
+            (true, _) => 3,
+            (_, true) => 2,
+            _ => continue,
+        };
+        if let Line::Command(fields) = line {
+            fields[0].value = Value::Integer(turned);
+        }
+    }
+    lines
+}
+
+/// Rotates every move counterclockwise by `degrees` about a point, the middle of the program's
+/// moves unless given.
+///
+/// Right angles swap and negate coordinates instead of multiplying them, so axis-aligned lines
+/// stay exactly aligned. Arc centers are rotated along with the moves.
+pub fn rotate(mut lines: Vec<Line>, degrees: f64, about: Option<[f64; 2]>) -> Vec<Line> {
+    let Some(center) = about.or_else(|| {
+        bounds(&lines).map(|[min, max]| [(min[0] + max[0]) / 2.0, (min[1] + max[1]) / 2.0])
+    }) else {
+        return lines;
+    };
+    let degrees = degrees.rem_euclid(360.0);
+    let (sin, cos) = match degrees {
+        0.0 => (0.0, 1.0),
+        90.0 => (1.0, 0.0),
+        180.0 => (0.0, -1.0),
+        270.0 => (-1.0, 0.0),
+        _ => degrees.to_radians().sin_cos(),
+    };
+    let turn = |[x, y]: [f64; 2]| [x * cos - y * sin, x * sin + y * cos];
+
+    let mut position = [None, None];
+    for line in lines.iter_mut() {
+        let (x, y) = (line.value("X"), line.value("Y"));
+        if x.is_some() || y.is_some() {
+            position = [x.or(position[0]), y.or(position[1])];
+            if let [Some(x), Some(y)] = position {
+                let [x, y] = turn([x - center[0], y - center[1]]);
+                line.set_value("X", x + center[0]);
+                line.set_value("Y", y + center[1]);
+            }
+        }
+        if let (Some(i), Some(j)) = (line.value("I"), line.value("J")) {
+            let [i, j] = turn([i, j]);
+            line.set_value("I", i);
+            line.set_value("J", j);
+        }
+    }
+    lines
+}
+
+/// Moves every move by the offset, e.g. to leave a margin around the design
+pub fn offset(mut lines: Vec<Line>, offset: [f64; 2]) -> Vec<Line> {
+    for line in lines.iter_mut() {
+        for (axis, letter) in ["X", "Y"].into_iter().enumerate() {
+            if let Some(value) = line.value(letter) {
+                line.set_value(letter, value + offset[axis]);
+            }
+        }
+    }
+    lines
+}
+
+/// Point of the document placed at the machine's origin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Anchor {
+    BottomLeft,
+    TopLeft,
+    BottomRight,
+    TopRight,
+    Center,
+}
+
+/// Shifts the program so the anchor point of the area, usually the document's, is at 0,0
+pub fn anchor(mut lines: Vec<Line>, anchor: Anchor, [min, max]: [[f64; 2]; 2]) -> Vec<Line> {
+    let [x, y] = match anchor {
+        Anchor::BottomLeft => [0.0, 0.0],
+        Anchor::TopLeft => [0.0, 1.0],
+        Anchor::BottomRight => [1.0, 0.0],
+        Anchor::TopRight => [1.0, 1.0],
+        Anchor::Center => [0.5, 0.5],
+    };
+    let offset = [
+        -(min[0] + x * (max[0] - min[0])),
+        -(min[1] + y * (max[1] - min[1])),
+    ];
+    for line in lines.iter_mut() {
+        for (axis, letter) in ["X", "Y"].into_iter().enumerate() {
+            if let Some(value) = line.value(letter) {
+                line.set_value(letter, value + offset[axis]);
+            }
+        }
+    }
+    lines
+}
+
+/// Snaps X, Y and Z to the nearest multiple of a grid size, for `--snap`.
+///
+/// Arc centres are snapped too, with I and J measured from the snapped start of the arc, so the
+/// centre lands on the grid with the ends rather than moving with the start's rounding.
+#[derive(Debug)]
+pub struct Snap {
+    grid: f64,
+    /// Where the last move ended, before snapping
+    position: [Option<f64>; 2],
+}
+
+impl Snap {
+    pub fn new(grid: f64) -> Self {
+        Snap {
+            grid,
+            position: [None, None],
+        }
+    }
+
+    pub fn apply(&mut self, mut line: Line) -> Line {
+        let from = self.position;
+        for (axis, letter) in ["X", "Y"].into_iter().enumerate() {
+            if let Some(value) = line.value(letter) {
+                self.position[axis] = Some(value);
+            }
+        }
+        let arc = line.is("G", 2) || line.is("G", 3);
+        if let (true, [Some(x), Some(y)]) = (arc, from) {
+            let offset = [line.value("I"), line.value("J")];
+            if offset.iter().any(Option::is_some) {
+                let centre = [x + offset[0].unwrap_or(0.0), y + offset[1].unwrap_or(0.0)];
+                let offset = |centre: f64, from: f64| {
+                    ((self.round(centre) - self.round(from)) * 1e9).round() / 1e9
+                };
+                line.set_value("I", offset(centre[0], x));
+                line.set_value("J", offset(centre[1], y));
+            }
+        }
+        for letter in ["X", "Y", "Z"] {
+            if let Some(value) = line.value(letter) {
+                line.set_value(letter, self.round(value));
+            }
+        }
+        line
+    }
+
+    fn round(&self, value: f64) -> f64 {
+        let snapped = (value / self.grid).round() * self.grid;
+        // Drop the floating point noise multiplying by the grid leaves, e.g. 0.15000000000000002
+        (snapped * 1e9).round() / 1e9
+    }
+}
+
+/// Removes the `G21` and `G90` directives the converter starts every program with
+pub fn strip_preamble(mut lines: Vec<Line>) -> Vec<Line> {
+    for number in [21, 90] {
+        let first_command = lines
+            .iter()
+            .position(|line| matches!(line, Line::Command(_)));
+        if let Some(i) = first_command.filter(|&i| lines[i].is("G", number)) {
+            lines.remove(i);
+        }
+    }
+    lines
+}
+
+/// Makes sure the program ends with the tool off and lifted.
+///
+/// The tool is tracked through `M3`, `M4` and `M5`, and whatever `--map-mcode` maps them to,
+/// wherever they appear. If it's left on an `M5` is added, and if the last Z is below the safe
+/// height, or the program's highest Z when there isn't one, a rapid up to it. Both go before
+/// an `M2` or `M30` ending the program, which is held back until a command follows it.
+pub fn finish_safely<'a>(
+    lines: impl Iterator<Item = Line> + 'a,
+    safe_height: Option<f64>,
+    map: &'a [Mapping],
+) -> impl Iterator<Item = Line> + 'a {
+    let codes = |codes: &[usize]| -> Vec<MCode> {
+        let codes: Vec<MCode> = codes.iter().map(|&code| MCode(code)).collect();
+        let mapped = map
+            .iter()
+            .filter(|mapping| codes.contains(&mapping.from))
+            .map(|mapping| mapping.to);
+        codes.iter().copied().chain(mapped).collect()
+    };
+    let (on, off) = (codes(&[3, 4]), codes(&[5]));
+    let mut lines = lines.fuse();
+    let mut tool_on = false;
+    let (mut z, mut highest) = (None, None);
+    // The program end and any comments after it, and lines ready to go out
+    let (mut end, mut ready) = (Vec::new(), VecDeque::new());
+    let mut finished = false;
+    std::iter::from_fn(move || loop {
+        if let Some(line) = ready.pop_front() {
+            return Some(line);
+        }
+        if finished {
+            return None;
+        }
+        let Some(line) = lines.next() else {
+            finished = true;
+            if tool_on {
+                warning!("the program leaves the tool on, adding an M5 to turn it off");
+                ready.push_back(Line::command("M", 5, &[]));
+            }
+            if let (Some(safe), Some(last)) = (safe_height.or(highest), z) {
+                if last < safe {
+                    warning!(
+                        "the program ends with the tool at Z{}, adding a lift to Z{}",
+                        last,
+                        safe
+                    );
+                    ready.push_back(Line::command("G", 0, &[("Z", safe)]));
+                }
+            }
+            ready.extend(end.drain(..));
+            continue;
+        };
+        if let Some(code) = m_code(&line) {
+            if on.contains(&code) {
+                tool_on = true;
+            } else if off.contains(&code) {
+                tool_on = false;
+            }
+        }
+        if let Some(value) = line.value("Z") {
+            z = Some(value);
+            highest = Some(highest.map_or(value, |highest: f64| highest.max(value)));
+        }
+        match line {
+            Line::Command(_) if matches!(m_code(&line), Some(MCode(2 | 30))) => {
+                ready.extend(end.drain(..));
+                end.push(line);
+            }
+            Line::Command(_) => {
+                ready.extend(end.drain(..));
+                ready.push_back(line);
+            }
+            Line::Comment(_) if !end.is_empty() => end.push(line),
+            Line::Comment(_) => ready.push_back(line),
+        }
+    })
+}
+
+/// Highest Z the tool-off sequence lifts to
+pub fn lift_height(tool_off: &[Line]) -> Option<f64> {
+    tool_off
+        .iter()
+        .filter_map(|line| line.value("Z"))
+        .reduce(f64::max)
+}
+
+/// Heights tool-off sequences lift to, each leaving the sequence's own Z when not given
+#[derive(Debug, Clone, Copy)]
+pub struct RetractHeights {
+    /// Between paths
+    pub travel: Option<f64>,
+    /// Before the first travel of the job, and for the last lift unless `park` is given
+    pub clearance: Option<f64>,
+    /// For the last lift, ending the job
+    pub park: Option<f64>,
+}
+
+/// Sets the Z that tool-off sequences lift to: the clearance height for the first one, ahead
+/// of the first travel, the park or clearance height for the last one ending the job, and the
+/// travel height for the others
+pub fn set_retract_heights(
+    mut lines: Vec<Line>,
+    tool_off: &[Line],
+    heights: RetractHeights,
+) -> Vec<Line> {
+    if lift_height(tool_off).is_none() {
+        warning!(
+            "--travel-z, --clearance-z and --park-z have no effect as the tool-off sequence doesn't set a Z"
+        );
+        return lines;
+    }
+    let retracts: Vec<usize> = (0..lines.len())
+        .filter(|&i| lines[i..].starts_with(tool_off))
+        .collect();
+    for (n, &start) in retracts.iter().enumerate() {
+        let height = match (n == 0, n + 1 == retracts.len()) {
+            (_, true) => heights.park.or(heights.clearance),
+            (true, false) => heights.clearance.or(heights.travel),
+            (false, false) => heights.travel,
+        };
+        let Some(height) = height else {
+            continue;
+        };
+        for line in lines[start..start + tool_off.len()].iter_mut() {
+            if line.value("Z").is_some() {
+                line.set_value("Z", height);
+            }
+        }
+    }
+    lines
+}
+
+/// Travels to the park position once the job is done
+pub fn park(lines: Vec<Line>, [x, y]: [f64; 2]) -> Vec<Line> {
+    append_before_end(lines, vec![Line::command("G", 0, &[("X", x), ("Y", y)])])
+}
+
+/// Adds lines to the end of the program, but before an `M2` or `M30` ending it
+fn append_before_end(mut lines: Vec<Line>, finish: Vec<Line>) -> Vec<Line> {
+    let end = lines
+        .iter()
+        .rposition(|line| matches!(line, Line::Command(_)))
+        .filter(|&i| matches!(m_code(&lines[i]), Some(MCode(2 | 30))))
+        .unwrap_or(lines.len());
+    lines.splice(end..end, finish);
+    lines
+}
+
+/// The M-code a line commands, if it's an M command
+fn m_code(line: &Line) -> Option<MCode> {
+    match line {
+        Line::Command(fields) => fields
+            .first()
+            .filter(|field| field.letters.eq_ignore_ascii_case("M"))
+            .and_then(|field| field.value.as_f64())
+            .map(|number| MCode(number as usize)),
+        Line::Comment(_) => None,
+    }
+}
+
+fn pause(position: [Option<f64>; 2], park: [Option<f64>; 2]) -> Vec<Line> {
+    let travel = |target: [Option<f64>; 2]| {
+        let words: Vec<_> = ["X", "Y"]
+            .into_iter()
+            .zip(target)
+            .filter_map(|(letter, value)| value.map(|value| (letter, value)))
+            .collect();
+        Line::command("G", 0, &words)
+    };
+
+    if park == [None, None] {
+        return vec![Line::command("M", 0, &[])];
+    }
+    let mut lines = vec![travel(park), Line::command("M", 0, &[])];
+    let back = [park[0].and(position[0]), park[1].and(position[1])];
+    if back != [None, None] {
+        lines.push(travel(back));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dash, gcode, settings::Settings, testing};
+
+    /// A zero-length path followed by a dashed one
+    const DEGENERATE_THEN_DASHED: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">
+        <path d="M10 10 L10 10"/>
+        <path d="M20 20 L80 20" stroke-dasharray="10 10"/>
+    </svg>"#;
+
+    /// Runs [`drop_degenerate`] over the lines, with how many it dropped
+    fn without_degenerate(lines: Vec<Line>) -> (Vec<Line>, usize) {
+        let (tool_on, tool_off) = testing::tool();
+        let dropped = std::cell::Cell::new(0);
+        let lines = drop_degenerate(lines.into_iter(), &tool_on, &tool_off, |count| {
+            dropped.set(count)
+        })
+        .collect();
+        (lines, dropped.get())
+    }
+
+    fn starts(lines: &[Line], sequence: &[Line]) -> usize {
+        (0..lines.len())
+            .filter(|&i| lines[i..].starts_with(sequence))
+            .count()
+    }
+
+    #[test]
+    fn drop_degenerate_removes_paths_that_stay_put() {
+        let (tool_on, _) = testing::tool();
+        let doc = Document::parse(DEGENERATE_THEN_DASHED).unwrap();
+        let (lines, dropped) = without_degenerate(testing::program(&doc));
+        assert_eq!(dropped, 1);
+        assert_eq!(starts(&lines, &tool_on), 1);
+    }
+
+    #[test]
+    fn drop_degenerate_keeps_the_next_elements_comment() {
+        let doc = Document::parse(DEGENERATE_THEN_DASHED).unwrap();
+        let (lines, _) = without_degenerate(testing::program(&doc));
+        let comments = lines
+            .iter()
+            .filter(|line| matches!(line, Line::Comment(_)))
+            .count();
+        assert_eq!(comments, 2);
+        assert_eq!(elements::chunks(&doc, &lines).len(), 2);
+    }
+
+    #[test]
+    fn drop_degenerate_keeps_paths_that_cut() {
+        let doc = Document::parse(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="10mm" height="10mm" viewBox="0 0 10 10">
+                <path d="M1 1 L5 1"/>
+                <path d="M1 5 L5 5"/>
+            </svg>"#,
+        )
+        .unwrap();
+        let program = testing::program(&doc);
+        let (lines, dropped) = without_degenerate(program.clone());
+        assert_eq!(dropped, 0);
+        assert_eq!(lines, program);
+    }
+
+    #[test]
+    fn dashes_after_a_dropped_degenerate_path() {
+        let (tool_on, tool_off) = testing::tool();
+        let doc = Document::parse(DEGENERATE_THEN_DASHED).unwrap();
+        let (lines, _) = without_degenerate(testing::program(&doc));
+        let dimensions = crate::dimensions(&doc, 1.0);
+        let lines = dash::apply_dasharray(
+            lines,
+            &doc,
+            &dash::DashOptions {
+                dimensions: &dimensions,
+                dpi: Settings::default().conversion.dpi,
+                min_dash: 0.0,
+                tool_on: &tool_on,
+                tool_off: &tool_off,
+            },
+        );
+        // 20 to 80 in dashes of 10 with gaps of 10
+        assert_eq!(starts(&lines, &tool_on), 3);
+    }
+
+    #[test]
+    fn data_feedrates_set_the_feedrate_of_each_paths_cuts() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">
+            <path d="M10 10 L20 10" data-feedrate="500"/>
+            <g data-feedrate="800"><path d="M10 20 L20 20"/></g>
+            <path d="M10 30 L20 30" data-feedrate="fast"/>
+        </svg>"#;
+        let doc = Document::parse(svg).unwrap();
+        let lines = apply_feedrates(testing::program(&doc), &doc);
+        let feedrates: Vec<Vec<f64>> = elements::chunks(&doc, &lines)
+            .into_iter()
+            .map(|(range, _)| {
+                lines[range]
+                    .iter()
+                    .filter(|line| line.is("G", 1) && line.value("X").is_some())
+                    .filter_map(|line| line.value("F"))
+                    .collect()
+            })
+            .filter(|feedrates: &Vec<f64>| !feedrates.is_empty())
+            .collect();
+        let global = Settings::default().conversion.feedrate;
+        assert_eq!(feedrates, [[500.0], [800.0], [global]]);
+    }
+
+    #[test]
+    fn data_depths_lower_each_shape_to_its_own_depth() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">
+            <rect x="10" y="10" width="10" height="10" data-depth="1.5"/>
+            <rect x="30" y="10" width="10" height="10" data-depth="0.5"/>
+            <rect x="50" y="10" width="10" height="10"/>
+        </svg>"#;
+        let doc = Document::parse(svg).unwrap();
+        let (tool_on, _) = testing::tool();
+        let lines = apply_depths(testing::program(&doc), &doc, &tool_on);
+        let plunges: Vec<f64> = (0..lines.len())
+            .filter(|&i| lines[i..].starts_with(&tool_on[..1]))
+            .filter_map(|i| {
+                lines[i..i + tool_on.len()]
+                    .iter()
+                    .find_map(|line| line.value("Z"))
+            })
+            .collect();
+        let global = tool_on.iter().find_map(|line| line.value("Z")).unwrap();
+        assert_eq!(plunges, [-1.5, -0.5, global]);
+    }
+
+    #[test]
+    fn feed_first_sets_the_feedrate_on_the_first_cut() {
+        let lines = gcode::template_lines("G0 X1 Y1\nG1 Z-1\nG1 X2 F500\nG1 X3").unwrap();
+        let lines: Vec<Line> = feed_first(lines.into_iter(), 1000.0).collect();
+        assert_eq!(lines[1].value("F"), Some(1000.0));
+        assert_eq!(lines[2].value("F"), Some(500.0));
+        assert_eq!(lines[3].value("F"), None);
+    }
+
+    #[test]
+    fn feed_first_leaves_an_earlier_feedrate() {
+        let lines = gcode::template_lines("G0 X1 Y1 F800\nG1 X2").unwrap();
+        let fed: Vec<Line> = feed_first(lines.clone().into_iter(), 1000.0).collect();
+        assert_eq!(fed, lines);
+    }
+
+    #[test]
+    fn finish_safely_turns_off_and_lifts_before_the_program_end() {
+        let lines = gcode::template_lines("G0 Z3\nM3\nG1 Z-1 F100\nG1 X5\nM2\n;done").unwrap();
+        let finished: Vec<Line> = finish_safely(lines.into_iter(), None, &[]).collect();
+        let text: Vec<String> = finished.iter().map(|line| line.to_string()).collect();
+        assert_eq!(
+            text[4..],
+            ["M5", "G0 Z3", "M2", ";done"]
+                .map(|line| gcode::template_lines(line).unwrap()[0].to_string())
+        );
+    }
+
+    #[test]
+    fn finish_safely_leaves_a_safe_program() {
+        let lines = gcode::template_lines("M3\nG1 X5 F100\nM5\nG0 Z3\nM2").unwrap();
+        let finished: Vec<Line> = finish_safely(lines.clone().into_iter(), None, &[]).collect();
+        assert_eq!(finished, lines);
+    }
+
+    #[test]
+    fn finish_safely_follows_mapped_tool_codes() {
+        let map = ["M3=M106", "M5=M107"].map(|mapping| mapping.parse::<Mapping>().unwrap());
+        // The program turns the mapped tool on and never off again
+        let lines = gcode::template_lines("G0 Z3\nM106\nG1 Z-1 F100\nG1 X5\nM30").unwrap();
+        let finished: Vec<Line> = finish_safely(lines.into_iter(), Some(5.0), &map).collect();
+        assert!(finished[4].is("M", 5));
+        assert_eq!(finished[5], Line::command("G", 0, &[("Z", 5.0)]));
+        assert!(finished[6].is("M", 30));
+
+        let lines = gcode::template_lines("M106\nG1 X5 F100\nM107\nG0 Z5\nM30").unwrap();
+        let finished: Vec<Line> =
+            finish_safely(lines.clone().into_iter(), Some(5.0), &map).collect();
+        assert_eq!(finished, lines);
+    }
+
+    #[test]
+    fn passes_run_in_order_whichever_kind() {
+        let mut passes = Passes::default();
+        passes.program(|mut lines| {
+            lines.push(Line::command("G", 0, &[("X", 1.0)]));
+            lines
+        });
+        passes.stream(|lines| {
+            Box::new(lines.map(|mut line| {
+                line.set_value("X", line.value("X").unwrap() * 10.0);
+                line
+            }))
+        });
+        passes.program(|mut lines| {
+            lines.push(Line::command("G", 0, &[("X", 2.0)]));
+            lines
+        });
+        let lines: Vec<Line> = passes
+            .run(Box::new(std::iter::once(Line::command(
+                "G",
+                0,
+                &[("X", 0.5)],
+            ))))
+            .collect();
+        let xs: Vec<f64> = lines.iter().filter_map(|line| line.value("X")).collect();
+        assert_eq!(xs, [5.0, 10.0, 2.0]);
+    }
+
+    #[test]
+    fn mirror_flips_x_within_the_programs_bounds() {
+        let lines = gcode::template_lines("G0 X10 Y0\nG1 X30 Y0\nG1 X10 Y20").unwrap();
+        let mirrored = mirror(lines.clone());
+        assert_eq!(bounds(&mirrored), bounds(&lines));
+        let points: Vec<[Option<f64>; 2]> = mirrored
+            .iter()
+            .map(|line| [line.value("X"), line.value("Y")])
+            .collect();
+        assert_eq!(
+            points,
+            [
+                [Some(30.0), Some(0.0)],
+                [Some(10.0), Some(0.0)],
+                [Some(30.0), Some(20.0)]
+            ]
+        );
+    }
+
+    #[test]
+    fn mirror_turns_arcs_the_other_way() {
+        // Clockwise over the top of the circle about 5,0, then back counterclockwise below it
+        let lines = gcode::template_lines("G0 X0 Y0\nG2 X10 Y0 I5 J0\nG3 X0 Y0 I-5 J0").unwrap();
+        let mirrored = mirror(lines);
+        assert!(mirrored[1].is("G", 3) && mirrored[2].is("G", 2));
+        assert_eq!(mirrored[1].value("X"), Some(0.0));
+        assert_eq!(mirrored[1].value("I"), Some(-5.0));
+        assert_eq!(mirrored[2].value("I"), Some(5.0));
+        assert_eq!(mirrored[2].value("J"), Some(0.0));
+    }
+
+    #[test]
+    fn pauses_park_the_tool_and_return_to_where_it_left_off() {
+        let lines = gcode::template_lines("M3\nG1 X5 Y5 F100\nM5\nG0 X20 Y10\nM3\nG1 X30").unwrap();
+        let tool_on = Line::command("M", 3, &[]);
+        let paused = insert_pauses(lines, &tool_on, [Some(0.0), Some(200.0)]);
+        let text: Vec<String> = paused.iter().map(|line| line.to_string()).collect();
+        assert_eq!(
+            text[4..8],
+            ["G0 X0 Y200", "M0", "G0 X20 Y10", "M3"]
+                .map(|line| gcode::template_lines(line).unwrap()[0].to_string())
+        );
+        // No pause before the first path
+        assert_eq!(paused[0], tool_on);
+    }
+
+    #[test]
+    fn pauses_without_a_park_position_stay_put() {
+        let lines = gcode::template_lines("M3\nG1 X5 F100\nM5\nG0 X20\nM3\nG1 X30").unwrap();
+        let tool_on = Line::command("M", 3, &[]);
+        let paused = insert_pauses(lines, &tool_on, [None, None]);
+        assert_eq!(paused.len(), 7);
+        assert!(paused[4].is("M", 0) && paused[5] == tool_on);
+    }
+
+    #[test]
+    fn snap_rounds_to_the_nearest_multiple() {
+        let mut snap = Snap::new(0.05);
+        let line = snap.apply(Line::command(
+            "G",
+            1,
+            &[("X", 1.03), ("Y", 1.02), ("Z", -0.274)],
+        ));
+        assert_eq!(line.value("X"), Some(1.05));
+        assert_eq!(line.value("Y"), Some(1.0));
+        assert_eq!(line.value("Z"), Some(-0.25));
+    }
+
+    #[test]
+    fn snap_moves_arc_centres_onto_the_grid() {
+        let mut snap = Snap::new(0.05);
+        snap.apply(Line::command("G", 0, &[("X", 0.32), ("Y", 0.11)]));
+        // About 5.33,0.11, which snaps to 5.35,0.1, from a start snapped to 0.3,0.1
+        let arc = snap.apply(Line::command(
+            "G",
+            2,
+            &[("X", 10.34), ("Y", 0.11), ("I", 5.01), ("J", 0.0)],
+        ));
+        assert_eq!(arc.value("X"), Some(10.35));
+        assert_eq!(arc.value("I"), Some(5.05));
+        assert_eq!(arc.value("J"), Some(0.0));
+    }
+
+    #[test]
+    fn split_long_moves_cuts_a_move_into_equal_parts() {
+        let lines = gcode::template_lines("G0 X0 Y0\nG1 X10 Y0 F100").unwrap();
+        let split = split_long_moves(lines, 3.0);
+        let xs: Vec<f64> = split[1..]
+            .iter()
+            .filter_map(|line| line.value("X"))
+            .collect();
+        assert_eq!(xs, [2.5, 5.0, 7.5, 10.0]);
+        assert!(split[1..]
+            .iter()
+            .all(|line| line.is("G", 1) && line.value("F") == Some(100.0)));
+    }
+
+    #[test]
+    fn plunges_run_at_the_plunge_feedrate_and_the_next_cut_restores_it() {
+        let lines = gcode::template_lines("G0 Z3\nG0 X1 Y1\nG0 Z-1\nG1 X5 Y1\nG1 X5 Y5").unwrap();
+        let lines = plunge_feedrate(lines, 50.0, 300.0);
+        assert_eq!(lines[2], Line::command("G", 1, &[("Z", -1.0), ("F", 50.0)]));
+        assert_eq!(lines[3].value("F"), Some(300.0));
+        assert_eq!(lines[4].value("F"), None);
+        // Lifting is left a rapid
+        assert!(lines[0].is("G", 0));
+    }
+
+    #[test]
+    fn a_quarter_turn_swaps_the_bounds() {
+        let lines = gcode::template_lines("G0 X0 Y0\nG1 X40 Y0\nG1 X40 Y10").unwrap();
+        let turned = rotate(lines, 90.0, None);
+        // 40 wide and 10 high about the middle at 20,5
+        assert_eq!(bounds(&turned), Some([[15.0, -15.0], [25.0, 25.0]]));
+        assert_eq!(turned[1].value("X"), Some(25.0));
+        assert_eq!(turned[1].value("Y"), Some(25.0));
+    }
+
+    #[test]
+    fn rotate_turns_arc_centres_with_the_moves() {
+        let lines = gcode::template_lines("G0 X0 Y0\nG2 X10 Y0 I5 J0").unwrap();
+        let turned = rotate(lines, 90.0, Some([0.0, 0.0]));
+        assert_eq!(turned[1].value("X"), Some(0.0));
+        assert_eq!(turned[1].value("Y"), Some(10.0));
+        assert_eq!(turned[1].value("I"), Some(0.0));
+        assert_eq!(turned[1].value("J"), Some(5.0));
+    }
+
+    #[test]
+    fn abutting_paths_join_into_one_pen_down() {
+        let (tool_on, tool_off) = testing::tool();
+        let mut lines = Vec::new();
+        // The four sides of a square, each its own path starting where the last one ended
+        let corners = [
+            [0.0, 0.0],
+            [10.0, 0.0],
+            [10.0, 10.0],
+            [0.0, 10.0],
+            [0.0, 0.0],
+        ];
+        for side in corners.windows(2) {
+            let ([x, y], [to_x, to_y]) = (side[0], side[1]);
+            lines.push(Line::command("G", 0, &[("X", x), ("Y", y)]));
+            lines.extend(tool_on.iter().cloned());
+            lines.push(Line::command(
+                "G",
+                1,
+                &[("X", to_x), ("Y", to_y), ("F", 300.0)],
+            ));
+            lines.extend(tool_off.iter().cloned());
+        }
+        let (joined, count) = join_paths(lines, &tool_on, &tool_off, 0.1);
+        assert_eq!(count, 3);
+        assert_eq!(starts(&joined, &tool_on), 1);
+        assert_eq!(starts(&joined, &tool_off), 1);
+        let cuts = joined.iter().filter(|line| line.is("G", 1)).count();
+        assert_eq!(cuts, 4);
+    }
+
+    #[test]
+    fn retract_heights_go_on_the_first_middle_and_last_lifts() {
+        let tool_off = gcode::template_lines("M5\nG0 Z1").unwrap();
+        let lift = "M5\nG0 Z1\nG0 X10\nM3\nG1 X20 F100\n";
+        let lines =
+            gcode::template_lines(&format!("M3\nG1 X5 F100\n{}{}M5\nG0 Z1", lift, lift)).unwrap();
+        let heights = RetractHeights {
+            travel: Some(2.0),
+            clearance: Some(10.0),
+            park: Some(30.0),
+        };
+        let lifted = set_retract_heights(lines.clone(), &tool_off, heights);
+        let zs: Vec<f64> = lifted.iter().filter_map(|line| line.value("Z")).collect();
+        assert_eq!(zs, [10.0, 2.0, 30.0]);
+
+        // The last lift falls back to the clearance height, and unset heights leave the Z
+        let heights = RetractHeights {
+            travel: None,
+            clearance: Some(10.0),
+            park: None,
+        };
+        let lifted = set_retract_heights(lines, &tool_off, heights);
+        let zs: Vec<f64> = lifted.iter().filter_map(|line| line.value("Z")).collect();
+        assert_eq!(zs, [10.0, 1.0, 10.0]);
+    }
+
+    #[test]
+    fn each_anchor_moves_its_corner_of_the_area_to_the_origin() {
+        // A 40 by 20 rectangle in an area from 10,10 to 110,60
+        let lines =
+            gcode::template_lines("G0 X20 Y20\nG1 X60 Y20\nG1 X60 Y40\nG1 X20 Y40").unwrap();
+        let area = [[10.0, 10.0], [110.0, 60.0]];
+        for (corner, expected) in [
+            (Anchor::BottomLeft, [[10.0, 10.0], [50.0, 30.0]]),
+            (Anchor::TopLeft, [[10.0, -40.0], [50.0, -20.0]]),
+            (Anchor::BottomRight, [[-90.0, 10.0], [-50.0, 30.0]]),
+            (Anchor::TopRight, [[-90.0, -40.0], [-50.0, -20.0]]),
+            (Anchor::Center, [[-40.0, -15.0], [0.0, 5.0]]),
+        ] {
+            let anchored = anchor(lines.clone(), corner, area);
+            assert_eq!(bounds(&anchored), Some(expected), "{:?}", corner);
+        }
+    }
+
+    #[test]
+    fn the_spindle_runs_once_per_tool_group() {
+        let doc = Document::parse(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">
+                <rect x="10" y="10" width="10" height="10"/>
+                <rect x="30" y="10" width="10" height="10"/>
+                <rect x="50" y="10" width="10" height="10"/>
+            </svg>"#,
+        )
+        .unwrap();
+        let (tool_on, tool_off) = testing::tool();
+        let mut lines = testing::program(&doc);
+        let count =
+            |lines: &[Line], number| lines.iter().filter(|line| line.is("M", number)).count();
+
+        // One M3 and the M5 ending the job, besides the converter's M5 ahead of the first travel
+        let running = keep_spindle_running(lines.clone(), &tool_on, &tool_off);
+        assert_eq!((count(&running, 3), count(&running, 5)), (1, 2));
+        // The tool is still lifted and lowered around every travel
+        let zs = |lines: &[Line]| {
+            lines
+                .iter()
+                .filter(|line| line.value("Z").is_some())
+                .count()
+        };
+        assert_eq!(zs(&running), zs(&lines));
+
+        // A tool change after the second square starts a new group, the first one being the lift
+        // ahead of the first travel
+        let path_ends: Vec<usize> = (0..lines.len())
+            .filter(|&i| lines[i..].starts_with(&tool_off))
+            .collect();
+        let change = path_ends[2] + tool_off.len();
+        lines.insert(change, Line::command("T", 2, &[]));
+        lines.insert(change + 1, Line::command("M", 6, &[]));
+        let running = keep_spindle_running(lines, &tool_on, &tool_off);
+        assert_eq!((count(&running, 3), count(&running, 5)), (2, 3));
+    }
+}
@@ -0,0 +1,719 @@
+use encoding_rs::{Encoding, UTF_8};
+use flate2::read::GzDecoder;
+use roxmltree::{Document, Node, NodeId};
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::Read,
+    ops::Range,
+    path::Path,
+    str::FromStr,
+};
+use svgtypes::{AspectRatio, Length, LengthUnit, PathParser, Transform, ViewBox};
+
+use crate::{
+    clippath,
+    elements::{self, length_to_user_units},
+};
+
+const SVG_NS: &str = "http://www.w3.org/2000/svg";
+const XLINK_NS: &str = "http://www.w3.org/1999/xlink";
+
+/// How to read the input file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum InputFormat {
+    /// Gzipped SVG when it starts with the gzip magic bytes, plain SVG otherwise
+    Auto,
+    Svg,
+    /// Gzipped SVG, as saved with the .svgz extension
+    Svgz,
+}
+
+/// The SVG bytes of the input, decompressed when it is gzipped. Decompressing stops with an
+/// error past `max_size` bytes.
+pub fn decompress(bytes: Vec<u8>, format: InputFormat, max_size: u64) -> Result<Vec<u8>, String> {
+    let gzipped = match format {
+        InputFormat::Auto => bytes.starts_with(&[0x1f, 0x8b]),
+        InputFormat::Svg => false,
+        InputFormat::Svgz => true,
+    };
+    if !gzipped {
+        return Ok(bytes);
+    }
+    let mut svg = Vec::new();
+    GzDecoder::new(bytes.as_slice())
+        .take(max_size + 1)
+        .read_to_end(&mut svg)
+        .map_err(|err| format!("could not decompress it as gzip ({})", err))?;
+    if svg.len() as u64 > max_size {
+        return Err(format!(
+            "it decompresses to over the --max-input-size of {} bytes",
+            max_size
+        ));
+    }
+    Ok(svg)
+}
+
+/// Decodes the raw file into text, dropping any byte order mark.
+///
+/// A BOM decides the encoding, then the XML declaration's `encoding`, falling back to UTF-8.
+pub fn decode(bytes: &[u8]) -> Result<String, String> {
+    let (encoding, bytes) = match Encoding::for_bom(bytes) {
+        Some((encoding, bom_length)) => (encoding, &bytes[bom_length..]),
+        None => (declared_encoding(bytes)?.unwrap_or(UTF_8), bytes),
+    };
+    encoding
+        .decode_without_bom_handling_and_without_replacement(bytes)
+        .map(|text| text.into_owned())
+        .ok_or_else(|| format!("file is not valid {}", encoding.name()))
+}
+
+/// Encoding named by the `<?xml ... encoding="..."?>` declaration, if there is one
+fn declared_encoding(bytes: &[u8]) -> Result<Option<&'static Encoding>, String> {
+    // The declaration is ASCII in every encoding a declaration can be read from without a BOM
+    let Some(declaration) = bytes
+        .strip_prefix(b"<?xml")
+        .and_then(|rest| rest.split(|&b| b == b'>').next())
+    else {
+        return Ok(None);
+    };
+    let declaration = String::from_utf8_lossy(declaration);
+    let Some((_, rest)) = declaration.split_once("encoding") else {
+        return Ok(None);
+    };
+    let label = rest
+        .trim_start_matches(|c: char| c.is_whitespace() || c == '=')
+        .trim_start_matches(['"', '\''])
+        .split(['"', '\''])
+        .next()
+        .unwrap_or("");
+    Encoding::for_label(label.as_bytes())
+        .map(Some)
+        .ok_or_else(|| format!("unsupported encoding \"{}\" declared", label))
+}
+
+/// Removes XML processing instructions other than the `<?xml ...?>` declaration.
+///
+/// Some exporters add instructions such as `<?xml-stylesheet ...?>` ahead of the root element.
+/// Returns the cleaned text and the instructions that were removed.
+pub fn strip_processing_instructions(xml: &str) -> (String, Vec<String>) {
+    let mut output = String::with_capacity(xml.len());
+    let mut removed = Vec::new();
+    let mut rest = xml;
+
+    while let Some(start) = rest.find('<') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        // Comments and CDATA sections are copied verbatim, whatever they contain
+        let skip = [("<!--", "-->"), ("<![CDATA[", "]]>")]
+            .into_iter()
+            .find(|(open, _)| rest.starts_with(open));
+        if let Some((_, close)) = skip {
+            let end = rest.find(close).map_or(rest.len(), |i| i + close.len());
+            output.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+
+        if rest.starts_with("<?") {
+            let end = rest.find("?>").map_or(rest.len(), |i| i + 2);
+            let instruction = &rest[..end];
+            let target = instruction[2..]
+                .split(|c: char| c.is_whitespace() || c == '?')
+                .next()
+                .unwrap_or("");
+            if target == "xml" {
+                output.push_str(instruction);
+            } else {
+                removed.push(instruction.to_string());
+            }
+            rest = &rest[end..];
+            continue;
+        }
+
+        output.push('<');
+        rest = &rest[1..];
+    }
+    output.push_str(rest);
+
+    (output, removed)
+}
+
+/// Finds elements the converter would fail on, along with the reason.
+///
+/// These are elements with malformed path data, transforms, view boxes or aspect ratios,
+/// or a transform that collapses geometry to nothing. The root element is not checked since
+/// skipping it would leave nothing to convert.
+pub fn invalid_elements(doc: &Document) -> Vec<(Range<usize>, String)> {
+    let mut invalid = Vec::new();
+    for node in doc.root_element().descendants().skip(1) {
+        if !node.is_element() {
+            continue;
+        }
+        let mut errors = Vec::new();
+        if node.has_tag_name("path") {
+            if let Some(Err(err)) = node
+                .attribute("d")
+                .and_then(|d| PathParser::from(d).find(|segment| segment.is_err()))
+            {
+                errors.push(format!("invalid path data ({})", err));
+            }
+        }
+        match node.attribute("transform").map(Transform::from_str) {
+            Some(Err(err)) => errors.push(format!("invalid transform ({})", err)),
+            Some(Ok(ts)) if ts.a * ts.d - ts.b * ts.c == 0.0 => {
+                errors.push("transform is not invertible".to_string())
+            }
+            _ => {}
+        }
+        if let Some(Err(err)) = node.attribute("viewBox").map(ViewBox::from_str) {
+            errors.push(format!("invalid viewBox ({})", err));
+        }
+        if let Some(Err(err)) = node
+            .attribute("preserveAspectRatio")
+            .map(AspectRatio::from_str)
+        {
+            errors.push(format!("invalid preserveAspectRatio ({})", err));
+        }
+
+        if !errors.is_empty() {
+            invalid.push((
+                node.range(),
+                format!("{}: {}", describe(doc, &node), errors.join(", ")),
+            ));
+        }
+    }
+    invalid
+}
+
+/// Finds rendered content the converter skips or only partly supports.
+///
+/// `<image>` is only reported when it won't be rastered.
+pub fn unsupported_features(doc: &Document, rastering: bool) -> Vec<String> {
+    let mut unsupported = Vec::new();
+    for (_, node) in elements::rendered_nodes(doc) {
+        let mut reasons = Vec::new();
+        match node.tag_name().name() {
+            "text" => reasons.push("text is not converted"),
+            "image" if !rastering => reasons.push("images are not converted"),
+            "use" => reasons.push("<use> references are not followed"),
+            "foreignObject" => reasons.push("foreign content is not converted"),
+            "clipPath" => reasons.push("clip path contents are cut as ordinary shapes"),
+            _ => {}
+        }
+        for (attribute, reason) in [
+            ("mask", "mask is not applied"),
+            ("transform-origin", "transform-origin is ignored"),
+        ] {
+            if node
+                .attribute(attribute)
+                .is_some_and(|value| value != "none")
+            {
+                reasons.push(reason);
+            }
+        }
+        if !reasons.is_empty() {
+            unsupported.push(format!("{}: {}", describe(doc, &node), reasons.join(", ")));
+        }
+    }
+    unsupported
+}
+
+/// Elements the converter ignores anywhere in the document, with what that means for the output
+const IGNORED_ELEMENTS: [(&[&str], &str); 9] = [
+    (&["text", "tspan", "textPath"], "text is not converted"),
+    (&["image"], "images are not converted"),
+    (&["use"], "references are not followed"),
+    (&["foreignObject"], "foreign content is not converted"),
+    (&["mask"], "masking is not applied"),
+    (
+        &["linearGradient", "radialGradient", "pattern"],
+        "paint is ignored, shapes are cut along their outlines",
+    ),
+    (&["filter"], "filters are ignored"),
+    (
+        &["animate", "animateMotion", "animateTransform", "set"],
+        "animations are ignored, shapes are cut as they first appear",
+    ),
+    (
+        &["style"],
+        "style sheets are not applied, only style attributes",
+    ),
+];
+
+/// Presentation attributes the converter ignores
+const IGNORED_ATTRIBUTES: [(&str, &str); 6] = [
+    ("mask", "mask is not applied"),
+    ("filter", "filter is ignored"),
+    ("marker-start", "markers are not drawn"),
+    ("marker-mid", "markers are not drawn"),
+    ("marker-end", "markers are not drawn"),
+    ("transform-origin", "transform-origin is ignored"),
+];
+
+/// Every kind of element and attribute in the document that the converter skips, each with how
+/// often it appears and where it first does, for `--report-unsupported-svg-features`
+pub fn unsupported_report(doc: &Document, rastering: bool) -> Vec<String> {
+    // Feature, effect, count and where it first appears, in order of first appearance
+    let mut found: Vec<(String, &str, usize, usize)> = Vec::new();
+    let mut record = |feature: String, effect: &'static str, node: &Node| match found
+        .iter_mut()
+        .find(|(existing, ..)| *existing == feature)
+    {
+        Some((_, _, count, _)) => *count += 1,
+        None => found.push((feature, effect, 1, node.range().start)),
+    };
+    for node in doc.descendants().filter(|node| node.is_element()) {
+        let name = node.tag_name().name();
+        if let Some((_, effect)) = IGNORED_ELEMENTS
+            .iter()
+            .find(|(names, _)| names.contains(&name) && !(rastering && name == "image"))
+        {
+            record(format!("<{}>", name), effect, &node);
+        }
+        for (attribute, effect) in IGNORED_ATTRIBUTES {
+            if node
+                .attribute(attribute)
+                .is_some_and(|value| value != "none")
+            {
+                record(format!("{} attribute", attribute), effect, &node);
+            }
+        }
+    }
+    found
+        .into_iter()
+        .map(|(feature, effect, count, start)| {
+            format!(
+                "{}: {} ({} found, first at {})",
+                feature,
+                effect,
+                count,
+                doc.text_pos_at(start)
+            )
+        })
+        .collect()
+}
+
+/// Each element with a `transform`, what it gives and the scale it flattens to together with
+/// its ancestors' transforms, e.g. `<g#logo> at 3:5: scale(2), flattened to a scale of X 2 Y 2`
+pub fn transform_report(doc: &Document) -> Vec<String> {
+    let round = |scale: f64| (scale * 1000.0).round() / 1000.0;
+    let mut report = Vec::new();
+    for node in doc.descendants().filter(|node| node.is_element()) {
+        let Some(Ok(own)) = node.attribute("transform").map(Transform::from_str) else {
+            continue;
+        };
+        if clippath::is_marker(&node) {
+            continue;
+        }
+        let mut combined = own;
+        let mut nested = 0;
+        for ancestor in node
+            .ancestors()
+            .skip(1)
+            .filter(|ancestor| ancestor.is_element())
+        {
+            if let Some(Ok(outer)) = ancestor.attribute("transform").map(Transform::from_str) {
+                combined = Transform::new(
+                    outer.a * combined.a + outer.c * combined.b,
+                    outer.b * combined.a + outer.d * combined.b,
+                    outer.a * combined.c + outer.c * combined.d,
+                    outer.b * combined.c + outer.d * combined.d,
+                    outer.a * combined.e + outer.c * combined.f + outer.e,
+                    outer.b * combined.e + outer.d * combined.f + outer.f,
+                );
+                nested += 1;
+            }
+        }
+        let mut line = format!(
+            "{}: {}, flattened to a scale of X {} Y {}",
+            describe(doc, &node),
+            node.attribute("transform").unwrap_or_default().trim(),
+            round(combined.a.hypot(combined.b)),
+            round(combined.c.hypot(combined.d)),
+        );
+        match nested {
+            0 => {}
+            1 => line += " with 1 enclosing transform",
+            nested => line += &format!(" with {} enclosing transforms", nested),
+        }
+        report.push(line);
+    }
+    report
+}
+
+/// Element name and source position for messages, e.g. `<path#outline> at 3:5`
+pub fn describe(doc: &Document, node: &Node) -> String {
+    format!(
+        "<{}> at {}",
+        elements::node_name(node),
+        doc.text_pos_at(node.range().start)
+    )
+}
+
+/// Replaces byte ranges of the document text, which must be sorted and not overlap
+pub fn replace_ranges(xml: &str, replacements: &[(Range<usize>, String)]) -> String {
+    let mut output = String::with_capacity(xml.len());
+    let mut cursor = 0;
+    for (range, replacement) in replacements {
+        if range.start < cursor {
+            // Nested inside an element that was already replaced
+            continue;
+        }
+        output.push_str(&xml[cursor..range.start]);
+        output.push_str(replacement);
+        cursor = range.end;
+    }
+    output.push_str(&xml[cursor..]);
+    output
+}
+
+/// Repairs structural problems that make parts of an SVG silently disappear, for `--svg-fix`.
+///
+/// Declares the SVG namespace on a root element missing it, renames repeated IDs with a `_N`
+/// suffix, and resolves relative hrefs against `base`, the directory of the file. Returns
+/// replacement start tags and a description of each fix.
+pub fn fixes(doc: &Document, base: &Path) -> (Vec<(Range<usize>, String)>, Vec<String>) {
+    // New attribute values for each element, in document order
+    let mut attributes: BTreeMap<usize, (NodeId, Vec<(String, String)>)> = BTreeMap::new();
+    let mut fixed = Vec::new();
+    let mut set = |node: &Node, name: String, value: String| {
+        attributes
+            .entry(node.range().start)
+            .or_insert_with(|| (node.id(), Vec::new()))
+            .1
+            .push((name, value));
+    };
+
+    let root = doc.root_element();
+    if root.tag_name().namespace().is_none() && root.has_tag_name("svg") {
+        fixed.push("added the missing SVG namespace declaration".to_string());
+        set(&root, "xmlns".to_string(), SVG_NS.to_string());
+    }
+
+    let ids: HashSet<&str> = doc
+        .descendants()
+        .filter_map(|node| node.attribute("id"))
+        .collect();
+    let mut seen = HashSet::new();
+    let mut taken = HashSet::new();
+    for node in doc.descendants() {
+        let Some(id) = node.attribute("id") else {
+            continue;
+        };
+        if seen.insert(id) {
+            continue;
+        }
+        let renamed = (1..)
+            .map(|n| format!("{}_{}", id, n))
+            .find(|renamed| !ids.contains(renamed.as_str()) && !taken.contains(renamed))
+            .unwrap();
+        fixed.push(format!(
+            "renamed the repeated id \"{}\" of {} to \"{}\"",
+            id,
+            describe(doc, &node),
+            renamed
+        ));
+        taken.insert(renamed.clone());
+        set(&node, "id".to_string(), renamed);
+    }
+
+    for node in doc.descendants() {
+        let (name, href) = match (node.attribute((XLINK_NS, "href")), node.attribute("href")) {
+            (Some(href), _) => match node.lookup_prefix(XLINK_NS) {
+                Some(prefix) => (format!("{}:href", prefix), href),
+                None => continue,
+            },
+            (None, Some(href)) => ("href".to_string(), href),
+            (None, None) => continue,
+        };
+        let external =
+            !(href.starts_with('#') || href.contains(':') || Path::new(href).is_absolute());
+        if external && !href.is_empty() {
+            let resolved = base.join(href).display().to_string();
+            fixed.push(format!(
+                "resolved the href \"{}\" of {} to \"{}\"",
+                href,
+                describe(doc, &node),
+                resolved
+            ));
+            set(
+                &node,
+                name,
+                resolved.replace('&', "&amp;").replace('"', "&quot;"),
+            );
+        }
+    }
+
+    let replacements = attributes
+        .into_values()
+        .filter_map(|(id, attributes)| {
+            let node = doc.get_node(id)?;
+            let attributes: Vec<(&str, String)> = attributes
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.clone()))
+                .collect();
+            Some(rewrite_start_tag(doc.input_text(), &node, &attributes))
+        })
+        .collect();
+    (replacements, fixed)
+}
+
+/// Makes rounded rectangle radii explicit so corners are rounded per the SVG spec.
+///
+/// A single specified radius applies to both axes, and radii are clamped to half the
+/// rectangle's width and height. Returns replacement start tags for affected rectangles.
+pub fn rect_radii(xml: &str, doc: &Document) -> Vec<(Range<usize>, String)> {
+    let mut replacements = Vec::new();
+    for node in doc.descendants().filter(|node| node.has_tag_name("rect")) {
+        let length = |attr: &str| {
+            node.attribute(attr)
+                .and_then(|value| Length::from_str(value).ok())
+                .filter(|length| length.number >= 0.0)
+        };
+        let (rx, ry) = match (length("rx"), length("ry")) {
+            (None, None) => continue,
+            (Some(rx), None) => (rx, rx),
+            (None, Some(ry)) => (ry, ry),
+            (Some(rx), Some(ry)) => (rx, ry),
+        };
+        let lengths = [length("width"), length("height"), Some(rx), Some(ry)];
+        let radii = match lengths {
+            [Some(width), Some(height), ..]
+                if lengths
+                    .iter()
+                    .flatten()
+                    .all(|length| length.unit != LengthUnit::Percent) =>
+            {
+                let half = |length: Length| length_to_user_units(length) / 2.0;
+                [
+                    length_to_user_units(rx).min(half(width)),
+                    length_to_user_units(ry).min(half(height)),
+                ]
+                .map(|radius| radius.to_string())
+            }
+            // Percentages depend on the viewport, leave those to the converter
+            _ => [rx, ry].map(|length| display_length(&length)),
+        };
+        let [rx, ry] = radii;
+        replacements.push(rewrite_start_tag(xml, &node, &[("rx", rx), ("ry", ry)]));
+    }
+    replacements
+}
+
+fn display_length(length: &Length) -> String {
+    let unit = match length.unit {
+        LengthUnit::None => "",
+        LengthUnit::Em => "em",
+        LengthUnit::Ex => "ex",
+        LengthUnit::Px => "px",
+        LengthUnit::In => "in",
+        LengthUnit::Cm => "cm",
+        LengthUnit::Mm => "mm",
+        LengthUnit::Pt => "pt",
+        LengthUnit::Pc => "pc",
+        LengthUnit::Percent => "%",
+    };
+    format!("{}{}", length.number, unit)
+}
+
+/// Builds a replacement for an element's start tag with some attributes set to new values.
+///
+/// Other attributes are copied verbatim, so namespaces and quoting are preserved.
+pub fn rewrite_start_tag(
+    xml: &str,
+    node: &Node,
+    attributes: &[(&str, String)],
+) -> (Range<usize>, String) {
+    retag_start(xml, node, None, &[], attributes)
+}
+
+/// Replacements turning an element into one named `name`, without the attributes in `dropped`
+/// and with `attributes` set. Its children are kept.
+pub fn retag(
+    xml: &str,
+    node: &Node,
+    name: &str,
+    dropped: &[&str],
+    attributes: &[(&str, String)],
+) -> Vec<(Range<usize>, String)> {
+    let (start_tag, rewritten) = retag_start(xml, node, Some(name), dropped, attributes);
+    let mut replacements = vec![(start_tag.clone(), rewritten)];
+    let end = node.range().end;
+    if start_tag.end < end {
+        if let Some(close) = xml[start_tag.end..end].rfind("</") {
+            replacements.push((start_tag.end + close..end, format!("</{}>", name)));
+        }
+    }
+    replacements
+}
+
+fn retag_start(
+    xml: &str,
+    node: &Node,
+    name: Option<&str>,
+    dropped: &[&str],
+    attributes: &[(&str, String)],
+) -> (Range<usize>, String) {
+    let start = node.range().start;
+    let mut end = start;
+    let mut quote = None;
+    for (i, c) in xml[start..].char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if open == c => quote = None,
+            (None, '>') => {
+                end = start + i + 1;
+                break;
+            }
+            _ => {}
+        }
+    }
+    let tag = &xml[start..end];
+    let self_closing = tag.ends_with("/>");
+    let inner = tag[1..tag.len() - if self_closing { 2 } else { 1 }].trim_end();
+
+    let name_end = inner
+        .find(|c: char| c.is_whitespace())
+        .unwrap_or(inner.len());
+    let mut rewritten = format!("<{}", name.unwrap_or(&inner[..name_end]));
+    let mut rest = inner[name_end..].trim_start();
+    while let Some(eq) = rest.find('=') {
+        let name = rest[..eq].trim();
+        let value_start = rest[eq + 1..].trim_start();
+        let Some(open) = value_start.chars().next() else {
+            break;
+        };
+        let value_end = value_start[1..]
+            .find(open)
+            .map_or(value_start.len(), |i| i + 2);
+        if !attributes.iter().any(|(replaced, _)| *replaced == name) && !dropped.contains(&name) {
+            rewritten.push_str(&format!(" {}={}", name, &value_start[..value_end]));
+        }
+        rest = value_start[value_end..].trim_start();
+    }
+    for (name, value) in attributes {
+        rewritten.push_str(&format!(" {}=\"{}\"", name, value));
+    }
+    rewritten.push_str(if self_closing { "/>" } else { ">" });
+    (start..end, rewritten)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The rx and ry of the first rectangle once its radii are made explicit
+    fn radii(rect: &str) -> [f64; 2] {
+        let xml = format!(r#"<svg xmlns="http://www.w3.org/2000/svg">{}</svg>"#, rect);
+        let doc = Document::parse(&xml).unwrap();
+        let xml = replace_ranges(&xml, &rect_radii(&xml, &doc));
+        let doc = Document::parse(&xml).unwrap();
+        let rect = doc
+            .descendants()
+            .find(|node| node.has_tag_name("rect"))
+            .unwrap();
+        ["rx", "ry"].map(|attr| rect.attribute(attr).unwrap().parse().unwrap())
+    }
+
+    #[test]
+    fn rect_radii_applies_a_single_radius_to_both_axes() {
+        assert_eq!(
+            radii(r#"<rect width="40" height="20" rx="5"/>"#),
+            [5.0, 5.0]
+        );
+        assert_eq!(
+            radii(r#"<rect width="40" height="20" ry="5"/>"#),
+            [5.0, 5.0]
+        );
+        assert_eq!(
+            radii(r#"<rect width="40" height="20" rx="4" ry="6"/>"#),
+            [4.0, 6.0]
+        );
+    }
+
+    #[test]
+    fn rect_radii_clamps_to_half_the_width_and_height() {
+        assert_eq!(
+            radii(r#"<rect width="40" height="20" rx="15"/>"#),
+            [15.0, 10.0]
+        );
+        assert_eq!(
+            radii(r#"<rect width="40" height="20" rx="30"/>"#),
+            [20.0, 10.0]
+        );
+    }
+
+    #[test]
+    fn rect_radii_converts_units() {
+        let [rx, ry] = radii(r#"<rect width="40" height="20" rx="1mm"/>"#);
+        let mm = 96.0 / 25.4;
+        assert!(
+            (rx - mm).abs() < 1e-9 && (ry - mm).abs() < 1e-9,
+            "{} {}",
+            rx,
+            ry
+        );
+        // Clamping compares lengths in the same units
+        assert_eq!(
+            radii(r#"<rect width="40" height="20" rx="1in"/>"#),
+            [20.0, 10.0]
+        );
+    }
+
+    #[test]
+    fn rect_radii_leaves_rectangles_without_radii() {
+        let xml = r#"<svg xmlns="http://www.w3.org/2000/svg"><rect width="40" height="20"/></svg>"#;
+        assert!(rect_radii(xml, &Document::parse(xml).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn decode_drops_a_utf8_byte_order_mark() {
+        assert_eq!(decode(b"\xef\xbb\xbf<svg/>").unwrap(), "<svg/>");
+    }
+
+    #[test]
+    fn decode_reads_utf16_after_its_byte_order_mark() {
+        let bytes: Vec<u8> = [0xff, 0xfe]
+            .into_iter()
+            .chain("<svg>é</svg>".encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        assert_eq!(decode(&bytes).unwrap(), "<svg>é</svg>");
+    }
+
+    #[test]
+    fn decode_reads_the_encoding_the_declaration_names() {
+        let bytes = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><svg>caf\xe9</svg>";
+        assert_eq!(
+            decode(bytes).unwrap(),
+            "<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><svg>caf\u{e9}</svg>"
+        );
+        // Without a declaration the same bytes aren't valid UTF-8
+        assert_eq!(
+            decode(b"<svg>caf\xe9</svg>"),
+            Err("file is not valid UTF-8".to_string())
+        );
+        assert_eq!(
+            decode(b"<?xml encoding=\"klingon\"?><svg/>"),
+            Err("unsupported encoding \"klingon\" declared".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid_elements_gives_each_failing_element_and_why() {
+        let xml = r#"<svg xmlns="http://www.w3.org/2000/svg" transform="scale(0)"><path d="M0 0 L10 x"/><g transform="scale(0)"><path d="M0 0 L10 10"/></g><rect viewBox="0 0 -1 1"/></svg>"#;
+        let doc = Document::parse(xml).unwrap();
+        let invalid = invalid_elements(&doc);
+        let reasons: Vec<&str> = invalid.iter().map(|(_, reason)| reason.as_str()).collect();
+        // The root is left alone, even with a transform that collapses it
+        assert_eq!(
+            reasons,
+            [
+                "<path> at 1:62: invalid path data (invalid number at position 10)",
+                "<g> at 1:84: transform is not invertible",
+                "<rect> at 1:135: invalid viewBox (viewBox has a negative or zero size)",
+            ]
+        );
+        assert_eq!(&xml[invalid[0].0.clone()], r#"<path d="M0 0 L10 x"/>"#);
+    }
+}
@@ -0,0 +1,222 @@
+use std::{fs, io::IsTerminal, path::Path};
+
+use crate::gcode::{self, Line, Move};
+
+/// Character cells are roughly twice as tall as they are wide
+const CELL_ASPECT: f64 = 2.0;
+const BRIGHT: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// What has been drawn at a dot; cuts are drawn over travels
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Dot {
+    Empty,
+    Travel,
+    Cut,
+}
+
+/// Prints the toolpath to the terminal in `columns` characters, followed by its bounding box.
+///
+/// Uses braille characters, with 2x4 dots per cell, unless the locale isn't UTF-8.
+pub fn print(lines: &[Line], columns: usize, margin: Option<[f64; 2]>) {
+    let moves = gcode::moves(lines);
+    let Some(first) = moves.first() else {
+        println!("Preview: the program has no moves");
+        return;
+    };
+    let (mut min, mut max) = (first.from, first.from);
+    for point in moves.iter().flat_map(|m| [m.from, m.to]) {
+        for axis in 0..2 {
+            min[axis] = min[axis].min(point[axis]);
+            max[axis] = max[axis].max(point[axis]);
+        }
+    }
+    let size = [max[0] - min[0], max[1] - min[1]];
+
+    let unicode = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|value| !value.is_empty()))
+        .is_some_and(|locale| {
+            let locale = locale.to_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        });
+    let dots_per_cell = match unicode {
+        true => [2, 4],
+        false => [1, 1],
+    };
+    let width = columns.max(1) * dots_per_cell[0];
+    // Square dots for braille, where 4 dots by 2 match the cell's shape
+    let dot_aspect = CELL_ASPECT * dots_per_cell[0] as f64 / dots_per_cell[1] as f64;
+    let scale = match size[0] > 0.0 {
+        true => (width - 1) as f64 / size[0],
+        false => 1.0,
+    };
+    let height = (size[1] * scale / dot_aspect).ceil() as usize + 1;
+    let mut dots = vec![vec![Dot::Empty; width]; height];
+    for m in moves.iter() {
+        draw(&mut dots, m, min, max[1], [scale, scale / dot_aspect]);
+    }
+
+    let styled = std::io::stdout().is_terminal();
+    let rows = height.div_ceil(dots_per_cell[1]);
+    for row in 0..rows {
+        let mut text = String::new();
+        let mut current_style = Dot::Empty;
+        for column in 0..columns {
+            let mut bits = 0u32;
+            let mut style = Dot::Empty;
+            for dy in 0..dots_per_cell[1] {
+                for dx in 0..dots_per_cell[0] {
+                    let dot = dots
+                        .get(row * dots_per_cell[1] + dy)
+                        .and_then(|dots| dots.get(column * dots_per_cell[0] + dx))
+                        .copied()
+                        .unwrap_or(Dot::Empty);
+                    if dot != Dot::Empty {
+                        bits |= braille_bit(dx, dy);
+                    }
+                    if dot > style {
+                        style = dot;
+                    }
+                }
+            }
+            if styled && style != current_style && style != Dot::Empty {
+                text.push_str(RESET);
+                text.push_str(if style == Dot::Cut { BRIGHT } else { DIM });
+                current_style = style;
+            }
+            text.push(match (unicode, style) {
+                (true, Dot::Empty) => ' ',
+                (true, _) => char::from_u32(0x2800 + bits).unwrap_or(' '),
+                (false, Dot::Cut) => '#',
+                (false, Dot::Travel) => '.',
+                (false, Dot::Empty) => ' ',
+            });
+        }
+        if styled && current_style != Dot::Empty {
+            text.push_str(RESET);
+        }
+        println!("{}", text.trim_end());
+    }
+    println!(
+        "Bounding box: X {:.3} to {:.3}, Y {:.3} to {:.3} ({:.3} x {:.3} mm)",
+        min[0], max[0], min[1], max[1], size[0], size[1]
+    );
+    if let Some(margin) = margin {
+        println!(
+            "With the margin: X {:.3} to {:.3}, Y {:.3} to {:.3} ({:.3} x {:.3} mm)",
+            min[0] - margin[0],
+            max[0] + margin[0],
+            min[1] - margin[1],
+            max[1] + margin[1],
+            size[0] + 2.0 * margin[0],
+            size[1] + 2.0 * margin[1]
+        );
+    }
+}
+
+/// Marks the dots along a move, with the top row at the largest Y
+fn draw(dots: &mut [Vec<Dot>], m: &Move, min: [f64; 2], max_y: f64, scale: [f64; 2]) {
+    let to_dot = |point: [f64; 2]| {
+        [
+            (point[0] - min[0]) * scale[0],
+            (max_y - point[1]) * scale[1],
+        ]
+    };
+    let (from, to) = (to_dot(m.from), to_dot(m.to));
+    let steps = (to[0] - from[0]).abs().max((to[1] - from[1]).abs()).ceil() as usize + 1;
+    let kind = if m.cutting { Dot::Cut } else { Dot::Travel };
+    for step in 0..=steps {
+        let t = step as f64 / steps as f64;
+        let x = (from[0] + (to[0] - from[0]) * t).round() as usize;
+        let y = (from[1] + (to[1] - from[1]) * t).round() as usize;
+        if let Some(dot) = dots.get_mut(y).and_then(|row| row.get_mut(x)) {
+            if kind > *dot {
+                *dot = kind;
+            }
+        }
+    }
+}
+
+/// Bit of the braille pattern for the dot at column `dx` and row `dy` of a cell
+fn braille_bit(dx: usize, dy: usize) -> u32 {
+    match (dx, dy) {
+        (0, 3) => 0x40,
+        (1, 3) => 0x80,
+        (0, dy) => 1 << dy,
+        (_, dy) => 1 << (dy + 3),
+    }
+}
+
+/// Writes the toolpath as an SVG drawn to scale in millimeters, travels in red and cuts in
+/// blue, with a green dot at the origin. Arcs are drawn as straight chords.
+///
+/// With `number_paths`, each path is labelled with its place in the cutting order at its start.
+pub fn write_svg(lines: &[Line], path: &Path, number_paths: bool) {
+    let moves = gcode::moves(lines);
+    let (mut min, mut max) = ([0.0f64; 2], [0.0f64; 2]);
+    for point in moves.iter().flat_map(|m| [m.from, m.to]) {
+        for axis in 0..2 {
+            min[axis] = min[axis].min(point[axis]);
+            max[axis] = max[axis].max(point[axis]);
+        }
+    }
+    // Room around the toolpath so the origin dot and the outermost moves aren't cut off
+    let pad = ((max[0] - min[0]).max(max[1] - min[1]) / 50.0).max(1.0);
+    let (min, max) = ([min[0] - pad, min[1] - pad], [max[0] + pad, max[1] + pad]);
+    let size = [max[0] - min[0], max[1] - min[1]];
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w:.3}mm\" height=\"{h:.3}mm\" viewBox=\"{x:.3} {y:.3} {w:.3} {h:.3}\">\n",
+        x = min[0],
+        y = -max[1],
+        w = size[0],
+        h = size[1]
+    );
+    // Consecutive moves of the same kind, joined end to end, make up one polyline
+    let mut runs: Vec<(bool, Vec<[f64; 2]>)> = Vec::new();
+    for m in moves.iter() {
+        match runs.last_mut() {
+            Some((cutting, points)) if *cutting == m.cutting && points.last() == Some(&m.from) => {
+                points.push(m.to)
+            }
+            _ => runs.push((m.cutting, vec![m.from, m.to])),
+        }
+    }
+    let mut labels = String::new();
+    for (cutting, points) in runs {
+        if cutting && number_paths {
+            labels.push_str(&format!(
+                "  <text x=\"{:.3}\" y=\"{:.3}\" font-size=\"{:.3}\" fill=\"black\">{}</text>\n",
+                points[0][0],
+                -points[0][1],
+                pad * 2.0,
+                labels.lines().count() + 1
+            ));
+        }
+        // SVG's Y axis points down, the machine's up
+        let points: Vec<String> = points
+            .iter()
+            .map(|point| format!("{:.3},{:.3}", point[0], -point[1]))
+            .collect();
+        svg.push_str(&format!(
+            "  <polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"1\" vector-effect=\"non-scaling-stroke\"/>\n",
+            points.join(" "),
+            if cutting { "blue" } else { "red" }
+        ));
+    }
+    svg.push_str(&labels);
+    svg.push_str(&format!(
+        "  <circle cx=\"0\" cy=\"0\" r=\"{:.3}\" fill=\"green\"/>\n</svg>\n",
+        pad / 2.0
+    ));
+
+    if let Err(err) = fs::write(path, svg) {
+        panic!(
+            "Could not write toolpath preview: {}, failed with error: {}",
+            path.display(),
+            err
+        );
+    }
+}
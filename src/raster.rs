@@ -0,0 +1,393 @@
+use base64::Engine;
+use image::GrayAlphaImage;
+use roxmltree::{Document, Node, ParsingOptions};
+use svg2gcode::{svg2program, ConversionConfig, ConversionOptions, Machine};
+
+use crate::{
+    gcode::{self, Line},
+    preprocess,
+};
+
+const XLINK_NS: &str = "http://www.w3.org/1999/xlink";
+const PROBE_ID: &str = "usgcode-raster-";
+
+/// Settings for engraving embedded raster images
+pub struct RasterOptions {
+    /// Laser power (S word) for white pixels
+    pub min_power: f64,
+    /// Laser power (S word) for black pixels
+    pub max_power: f64,
+    /// Scanline resolution in dots per inch
+    pub dpi: f64,
+    pub feedrate: f64,
+}
+
+/// An embedded image and where its top-left, top-right and bottom-left corners land on the machine
+struct PlacedImage {
+    pixels: GrayAlphaImage,
+    origin: [f64; 2],
+    u: [f64; 2],
+    v: [f64; 2],
+}
+
+/// Generates serpentine scanline moves for every embedded `<image>` in the document.
+///
+/// Placement is found by converting a probe copy of the document where each image is swapped
+/// for a rectangle with the same geometry, so transforms, viewBox and dpi handling match the
+/// vector conversion exactly.
+pub fn engrave(
+    svg_xml: &str,
+    doc: &Document,
+    config: &ConversionConfig,
+    options: &ConversionOptions,
+    machine: &Machine,
+    raster: &RasterOptions,
+) -> Vec<Line> {
+    let images: Vec<Node> = doc
+        .descendants()
+        .filter(|node| node.has_tag_name("image"))
+        .collect();
+    if images.is_empty() {
+        return Vec::new();
+    }
+
+    let probes: Vec<_> = images
+        .iter()
+        .enumerate()
+        .map(|(i, image)| {
+            let mut rect = format!("<rect id=\"{}{}\"", PROBE_ID, i);
+            for attr in ["x", "y", "width", "height", "transform"] {
+                if let Some(value) = image.attribute(attr) {
+                    rect.push_str(&format!(" {}=\"{}\"", attr, value));
+                }
+            }
+            rect.push_str("/>");
+            (image.range(), rect)
+        })
+        .collect();
+    let probe_xml = preprocess::replace_ranges(svg_xml, &probes);
+
+    let probe_doc = match Document::parse_with_options(
+        &probe_xml,
+        ParsingOptions {
+            allow_dtd: true,
+            ..Default::default()
+        },
+    ) {
+        Ok(doc) => doc,
+        Err(err) => panic!(
+            "Could not locate embedded images, failed with error: {}",
+            err
+        ),
+    };
+
+    let unshifted = ConversionConfig {
+        origin: [None, None],
+        ..config.clone()
+    };
+    let probe: Vec<Line> = gcode::assemble(svg2program(
+        &probe_doc,
+        &unshifted,
+        options.clone(),
+        machine.clone(),
+    ))
+    .collect();
+    let offset = origin_offset(doc, config, options, machine);
+
+    let mut lines = Vec::new();
+    for (i, node) in images.iter().enumerate() {
+        let Some(corners) = probe_corners(&probe, i) else {
+            warning!("could not place embedded image {}, skipping it", i);
+            continue;
+        };
+        let Some(pixels) = decode(node) else {
+            warning!("could not decode embedded image {}, skipping it", i);
+            continue;
+        };
+        let corners = corners.map(|[x, y]| [x + offset[0], y + offset[1]]);
+        let placed = fit(
+            pixels,
+            corners,
+            node.attribute("preserveAspectRatio").unwrap_or(""),
+        );
+        lines.extend(scan(&placed, raster));
+    }
+    lines
+}
+
+/// Translation applied by the converter's origin handling, found by comparing the first move
+/// of the real conversion to one without an origin
+fn origin_offset(
+    doc: &Document,
+    config: &ConversionConfig,
+    options: &ConversionOptions,
+    machine: &Machine,
+) -> [f64; 2] {
+    let first_move = |config: &ConversionConfig| {
+        gcode::assemble(svg2program(doc, config, options.clone(), machine.clone()))
+            .find_map(|line| Some([line.value("X")?, line.value("Y")?]))
+    };
+    let unshifted = ConversionConfig {
+        origin: [None, None],
+        ..config.clone()
+    };
+    match (first_move(config), first_move(&unshifted)) {
+        (Some(shifted), Some(unshifted)) => [shifted[0] - unshifted[0], shifted[1] - unshifted[1]],
+        _ => [0.0, 0.0],
+    }
+}
+
+fn probe_corners(probe: &[Line], index: usize) -> Option<[[f64; 2]; 3]> {
+    let id = format!("#{}{}", PROBE_ID, index);
+    let start = probe
+        .iter()
+        .position(|line| matches!(line, Line::Comment(comment) if comment.ends_with(&id)))?;
+    let points: Vec<[f64; 2]> = probe[start..]
+        .iter()
+        .filter_map(|line| Some([line.value("X")?, line.value("Y")?]))
+        .take(4)
+        .collect();
+    match points.as_slice() {
+        [top_left, top_right, _, bottom_left] => Some([*top_left, *top_right, *bottom_left]),
+        _ => None,
+    }
+}
+
+fn decode(node: &Node) -> Option<GrayAlphaImage> {
+    let href = node
+        .attribute((XLINK_NS, "href"))
+        .or_else(|| node.attribute("href"))?;
+    let (_, data) = href.strip_prefix("data:")?.split_once(";base64,")?;
+    let data: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .ok()?;
+    Some(image::load_from_memory(&bytes).ok()?.into_luma_alpha8())
+}
+
+/// Shrinks the image box to the area actually covered by the image per `preserveAspectRatio`
+fn fit(pixels: GrayAlphaImage, corners: [[f64; 2]; 3], preserve: &str) -> PlacedImage {
+    let [origin, right, down] = corners;
+    let u = [right[0] - origin[0], right[1] - origin[1]];
+    let v = [down[0] - origin[0], down[1] - origin[1]];
+    let box_w = u[0].hypot(u[1]);
+    let box_h = v[0].hypot(v[1]);
+    let (px_w, px_h) = (pixels.width() as f64, pixels.height() as f64);
+
+    let mut words = preserve.split_whitespace();
+    let align = words.next().unwrap_or("xMidYMid");
+    if align == "none" || box_w == 0.0 || box_h == 0.0 {
+        return PlacedImage {
+            pixels,
+            origin,
+            u,
+            v,
+        };
+    }
+    let scale = match words.next() {
+        Some("slice") => (box_w / px_w).max(box_h / px_h),
+        _ => (box_w / px_w).min(box_h / px_h),
+    };
+    let (fit_w, fit_h) = (px_w * scale / box_w, px_h * scale / box_h);
+    let align_x = if align.starts_with("xMin") {
+        0.0
+    } else if align.starts_with("xMax") {
+        1.0
+    } else {
+        0.5
+    };
+    let align_y = if align.ends_with("YMin") {
+        0.0
+    } else if align.ends_with("YMax") {
+        1.0
+    } else {
+        0.5
+    };
+    let (start_u, start_v) = ((1.0 - fit_w) * align_x, (1.0 - fit_h) * align_y);
+    PlacedImage {
+        pixels,
+        origin: [
+            origin[0] + u[0] * start_u + v[0] * start_v,
+            origin[1] + u[1] * start_u + v[1] * start_v,
+        ],
+        u: [u[0] * fit_w, u[1] * fit_w],
+        v: [v[0] * fit_h, v[1] * fit_h],
+    }
+}
+
+impl PlacedImage {
+    /// Laser power at a machine position, or `None` outside the image or on white pixels
+    fn power_at(&self, point: [f64; 2], raster: &RasterOptions) -> Option<f64> {
+        let det = self.u[0] * self.v[1] - self.u[1] * self.v[0];
+        if det == 0.0 {
+            return None;
+        }
+        let d = [point[0] - self.origin[0], point[1] - self.origin[1]];
+        let s = (d[0] * self.v[1] - d[1] * self.v[0]) / det;
+        let t = (self.u[0] * d[1] - self.u[1] * d[0]) / det;
+        if !(0.0..1.0).contains(&s) || !(0.0..1.0).contains(&t) {
+            return None;
+        }
+        let x = (s * self.pixels.width() as f64) as u32;
+        let y = (t * self.pixels.height() as f64) as u32;
+        let [luma, alpha] = self.pixels.get_pixel(x, y).0;
+        // Transparent pixels are treated as the white background
+        let darkness = (1.0 - luma as f64 / 255.0) * (alpha as f64 / 255.0);
+        let power = (raster.min_power + darkness * (raster.max_power - raster.min_power)).round();
+        match darkness > 0.0 && power > raster.min_power.round() {
+            true => Some(power),
+            false => None,
+        }
+    }
+
+    fn bounds(&self) -> [[f64; 2]; 2] {
+        let corners = [
+            self.origin,
+            [self.origin[0] + self.u[0], self.origin[1] + self.u[1]],
+            [self.origin[0] + self.v[0], self.origin[1] + self.v[1]],
+            [
+                self.origin[0] + self.u[0] + self.v[0],
+                self.origin[1] + self.u[1] + self.v[1],
+            ],
+        ];
+        let mut bounds = [[f64::INFINITY; 2], [f64::NEG_INFINITY; 2]];
+        for corner in corners {
+            for axis in 0..2 {
+                bounds[0][axis] = bounds[0][axis].min(corner[axis]);
+                bounds[1][axis] = bounds[1][axis].max(corner[axis]);
+            }
+        }
+        bounds
+    }
+}
+
+/// Serpentine scan of the image's bounding box, rapid-traversing white spans.
+///
+/// Each scanline is split into runs of equal power, emitted as single `G1` moves.
+fn scan(image: &PlacedImage, raster: &RasterOptions) -> Vec<Line> {
+    let step = 25.4 / raster.dpi;
+    let [min, max] = image.bounds();
+    let columns = ((max[0] - min[0]) / step).ceil() as usize;
+    let rows = ((max[1] - min[1]) / step).ceil() as usize;
+
+    let mut lines = Vec::new();
+    for row in 0..rows {
+        let y = max[1] - (row as f64 + 0.5) * step;
+        let powers: Vec<Option<f64>> = (0..columns)
+            .map(|column| image.power_at([min[0] + (column as f64 + 0.5) * step, y], raster))
+            .collect();
+
+        // Runs of (start column, end column, power) going left to right
+        let mut runs: Vec<(usize, usize, f64)> = Vec::new();
+        for (column, power) in powers.iter().enumerate() {
+            match (runs.last_mut(), power) {
+                (Some(run), Some(power)) if run.1 == column && run.2 == *power => run.1 += 1,
+                (_, Some(power)) => runs.push((column, column + 1, *power)),
+                (_, None) => {}
+            }
+        }
+        let edge = |column: usize| min[0] + column as f64 * step;
+
+        let reversed = row % 2 == 1;
+        if reversed {
+            runs.reverse();
+        }
+        let mut position = None;
+        for (start, end, power) in runs {
+            let (from, to) = match reversed {
+                false => (edge(start), edge(end)),
+                true => (edge(end), edge(start)),
+            };
+            if position != Some(from) {
+                lines.push(Line::command("G", 0, &[("X", from), ("Y", y)]));
+            }
+            lines.push(Line::command(
+                "G",
+                1,
+                &[("X", to), ("F", raster.feedrate), ("S", power)],
+            ));
+            position = Some(to);
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use image::{GrayImage, ImageFormat, Luma};
+
+    /// A 100mm document holding a 40 by 20 image, black on its left half and white on its right
+    fn half_black() -> String {
+        let pixels = GrayImage::from_fn(4, 2, |x, _| Luma([if x < 2 { 0 } else { 255 }]));
+        let mut png = std::io::Cursor::new(Vec::new());
+        pixels.write_to(&mut png, ImageFormat::Png).unwrap();
+        let data = base64::engine::general_purpose::STANDARD.encode(png.into_inner());
+        format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">
+                <image x="0" y="0" width="40" height="20" href="data:image/png;base64,{}"/>
+            </svg>"#,
+            data
+        )
+    }
+
+    fn engraved(svg: &str) -> Vec<Line> {
+        let settings = Settings::default();
+        let doc = Document::parse(svg).unwrap();
+        let options = ConversionOptions {
+            dimensions: crate::dimensions(&doc, 1.0),
+        };
+        engrave(
+            svg,
+            &doc,
+            &crate::conversion_config(&settings),
+            &options,
+            &crate::machine(&settings),
+            &RasterOptions {
+                min_power: 0.0,
+                max_power: 1000.0,
+                // A scanline every millimeter
+                dpi: 25.4,
+                feedrate: 2000.0,
+            },
+        )
+    }
+
+    #[test]
+    fn dark_pixels_are_scanned_at_full_power_and_white_ones_skipped() {
+        let lines = engraved(&half_black());
+        let cuts: Vec<&Line> = lines.iter().filter(|line| line.is("G", 1)).collect();
+        // 20 units is 19.2mm at the converter's scale, so 19 scanlines, each one run of black
+        assert_eq!(cuts.len(), 19);
+        assert!(cuts.iter().all(|cut| cut.value("S") == Some(1000.0)));
+        // The white half is never cut across, as it's 19.2mm from the left edge
+        let xs: Vec<f64> = lines.iter().filter_map(|line| line.value("X")).collect();
+        assert!(xs.iter().all(|&x| (0.0..=19.2 + 1.0).contains(&x)));
+    }
+
+    #[test]
+    fn scanlines_go_back_and_forth() {
+        let lines = engraved(&half_black());
+        let ends: Vec<f64> = lines
+            .iter()
+            .filter(|line| line.is("G", 1))
+            .filter_map(|line| line.value("X"))
+            .collect();
+        let starts: Vec<f64> = lines
+            .iter()
+            .filter(|line| line.is("G", 0))
+            .filter_map(|line| line.value("X"))
+            .collect();
+        assert_eq!(starts.len(), ends.len());
+        assert!(ends[0] > starts[0] && ends[1] < starts[1]);
+        assert_eq!(ends[0], starts[1]);
+        assert_eq!(ends[1], starts[0]);
+    }
+
+    #[test]
+    fn documents_without_images_engrave_nothing() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg"><path d="M0 0 L10 10"/></svg>"#;
+        assert!(engraved(svg).is_empty());
+    }
+}
@@ -0,0 +1,319 @@
+use roxmltree::{Document, Node};
+use std::{iter::Peekable, ops::Range, str::FromStr};
+
+use crate::{elements, info::SHAPE_TAGS};
+
+/// A simplified CSS selector for `--svg-subset`, such as `path[stroke="#ff0000"]` or
+/// `g#layer1 > path`.
+///
+/// Compound selectors of an element name, `#id`, `.class` and `[attribute]` or
+/// `[attribute=value]` tests can be joined by descendant (space) and child (`>`) combinators,
+/// and several selectors separated by commas.
+#[derive(Debug, Clone)]
+pub struct Selector(Vec<Vec<(Combinator, Compound)>>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Compound {
+    /// Element name, any element when not given or `*`
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+    attributes: Vec<(String, Option<String>)>,
+}
+
+impl FromStr for Selector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|selector| parse_complex(selector.trim()))
+            .collect::<Result<_, _>>()
+            .map(Selector)
+    }
+}
+
+type Chars<'a> = Peekable<std::str::Chars<'a>>;
+
+fn parse_complex(s: &str) -> Result<Vec<(Combinator, Compound)>, String> {
+    let mut chars = s.chars().peekable();
+    let mut parts = Vec::new();
+    let mut combinator = Combinator::Descendant;
+    loop {
+        let mut spaced = false;
+        while chars.next_if(|c| c.is_whitespace()).is_some() {
+            spaced = true;
+        }
+        match chars.peek() {
+            None => break,
+            Some('>') => {
+                if parts.is_empty() || combinator == Combinator::Child {
+                    return Err(format!("misplaced \">\" in \"{}\"", s));
+                }
+                chars.next();
+                combinator = Combinator::Child;
+                continue;
+            }
+            Some(_) if !parts.is_empty() && !spaced && combinator == Combinator::Descendant => {
+                return Err(format!("unexpected character in \"{}\"", s));
+            }
+            Some(_) => {}
+        }
+        parts.push((combinator, parse_compound(&mut chars, s)?));
+        combinator = Combinator::Descendant;
+    }
+    if parts.is_empty() {
+        return Err(format!("empty selector in \"{}\"", s));
+    }
+    if combinator == Combinator::Child {
+        return Err(format!("\">\" needs an element after it in \"{}\"", s));
+    }
+    Ok(parts)
+}
+
+fn parse_compound(chars: &mut Chars, s: &str) -> Result<Compound, String> {
+    let name = |chars: &mut Chars| {
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ':')) {
+            name.push(c);
+        }
+        match name.is_empty() {
+            true => Err(format!("expected a name in \"{}\"", s)),
+            false => Ok(name),
+        }
+    };
+
+    let mut compound = Compound::default();
+    if chars.next_if_eq(&'*').is_none() && chars.peek().is_some_and(|c| c.is_alphabetic()) {
+        compound.tag = Some(name(chars)?);
+    }
+    loop {
+        match chars.peek() {
+            Some('#') => {
+                chars.next();
+                compound.id = Some(name(chars)?);
+            }
+            Some('.') => {
+                chars.next();
+                compound.classes.push(name(chars)?);
+            }
+            Some('[') => {
+                chars.next();
+                let attribute = name(chars)?;
+                let value = match chars.next() {
+                    Some(']') => None,
+                    Some('=') => {
+                        let mut value = String::new();
+                        match chars.next_if(|&c| c == '"' || c == '\'') {
+                            Some(quote) => loop {
+                                match chars.next() {
+                                    Some(c) if c == quote => break,
+                                    Some(c) => value.push(c),
+                                    None => return Err(format!("unclosed quote in \"{}\"", s)),
+                                }
+                            },
+                            None => value = name(chars)?,
+                        }
+                        if chars.next() != Some(']') {
+                            return Err(format!("expected \"]\" in \"{}\"", s));
+                        }
+                        Some(value)
+                    }
+                    _ => return Err(format!("expected \"]\" or \"=\" in \"{}\"", s)),
+                };
+                compound.attributes.push((attribute, value));
+            }
+            Some(c) if c.is_whitespace() || *c == '>' => break,
+            None => break,
+            Some(c) => return Err(format!("unexpected \"{}\" in \"{}\"", c, s)),
+        }
+    }
+    Ok(compound)
+}
+
+impl Selector {
+    pub fn matches(&self, node: &Node) -> bool {
+        self.0.iter().any(|parts| matches_complex(node, parts))
+    }
+}
+
+impl Compound {
+    fn matches(&self, node: &Node) -> bool {
+        node.is_element()
+            && self
+                .tag
+                .as_ref()
+                .is_none_or(|tag| node.tag_name().name() == tag)
+            && self
+                .id
+                .as_ref()
+                .is_none_or(|id| node.attribute("id") == Some(id))
+            && self.classes.iter().all(|class| {
+                node.attribute("class")
+                    .is_some_and(|classes| classes.split_whitespace().any(|c| c == class))
+            })
+            && self.attributes.iter().all(|(name, value)| {
+                match (node.attribute(name.as_str()), value) {
+                    (Some(actual), Some(value)) => actual == value,
+                    (actual, None) => actual.is_some(),
+                    (None, Some(_)) => false,
+                }
+            })
+    }
+}
+
+fn matches_complex(node: &Node, parts: &[(Combinator, Compound)]) -> bool {
+    let Some(((combinator, last), rest)) = parts.split_last() else {
+        return true;
+    };
+    if !last.matches(node) {
+        return false;
+    }
+    if rest.is_empty() {
+        return true;
+    }
+    match combinator {
+        Combinator::Child => node
+            .parent_element()
+            .is_some_and(|parent| matches_complex(&parent, rest)),
+        Combinator::Descendant => node
+            .ancestors()
+            .skip(1)
+            .any(|ancestor| matches_complex(&ancestor, rest)),
+    }
+}
+
+/// Removals of every shape that neither matches the selector nor is inside an element that does
+pub fn subset(doc: &Document, selector: &Selector) -> Vec<(Range<usize>, String)> {
+    doc.descendants()
+        .filter(|node| SHAPE_TAGS.contains(&node.tag_name().name()))
+        .filter(|node| !node.ancestors().any(|ancestor| selector.matches(&ancestor)))
+        .map(|node| (node.range(), String::new()))
+        .collect()
+}
+
+/// Removals of every shape outside the Inkscape layers with one of the labels. Fails naming
+/// the labels no layer has, and the labels there are.
+pub fn layer_subset(
+    doc: &Document,
+    labels: &[String],
+) -> Result<Vec<(Range<usize>, String)>, String> {
+    let layers: Vec<&str> = doc
+        .descendants()
+        .filter_map(|node| elements::layer_label(&node))
+        .collect();
+    let missing: Vec<&str> = labels
+        .iter()
+        .map(String::as_str)
+        .filter(|label| !layers.contains(label))
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!(
+            "it has no Inkscape layer labelled \"{}\", its layers are: {}",
+            missing.join("\", \""),
+            match layers.is_empty() {
+                true => "none".to_string(),
+                false => format!("\"{}\"", layers.join("\", \"")),
+            }
+        ));
+    }
+    Ok(doc
+        .descendants()
+        .filter(|node| SHAPE_TAGS.contains(&node.tag_name().name()))
+        .filter(|node| {
+            !node.ancestors().any(|ancestor| {
+                elements::layer_label(&ancestor)
+                    .is_some_and(|label| labels.iter().any(|l| l == label))
+            })
+        })
+        .map(|node| (node.range(), String::new()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOC: &str = r##"<svg xmlns="http://www.w3.org/2000/svg">
+        <g id="layer1" class="cut outline">
+            <path id="red" stroke="#ff0000" d="M0 0 L1 1"/>
+            <g id="inner">
+                <path id="blue" stroke="#0000ff" class="cut" d="M0 0 L1 1"/>
+            </g>
+        </g>
+        <rect id="loose" class="engrave" width="1" height="1"/>
+    </svg>"##;
+
+    /// Ids of the elements the selector matches
+    fn matching(selector: &str) -> Vec<String> {
+        let selector = Selector::from_str(selector).unwrap();
+        let doc = Document::parse(DOC).unwrap();
+        doc.descendants()
+            .filter(|node| selector.matches(node))
+            .filter_map(|node| node.attribute("id").map(str::to_string))
+            .collect()
+    }
+
+    #[test]
+    fn selector_matches_names_ids_and_classes() {
+        assert_eq!(matching("path"), ["red", "blue"]);
+        assert_eq!(matching("#blue"), ["blue"]);
+        assert_eq!(matching("path#blue"), ["blue"]);
+        assert_eq!(matching("rect#blue"), Vec::<String>::new());
+        assert_eq!(matching(".cut"), ["layer1", "blue"]);
+        assert_eq!(matching(".cut.outline"), ["layer1"]);
+        assert_eq!(matching("*.engrave"), ["loose"]);
+    }
+
+    #[test]
+    fn selector_matches_attributes() {
+        assert_eq!(matching("[stroke]"), ["red", "blue"]);
+        assert_eq!(matching(r##"path[stroke="#ff0000"]"##), ["red"]);
+        assert_eq!(matching("[stroke='#0000ff']"), ["blue"]);
+        assert_eq!(matching("[class=engrave]"), ["loose"]);
+    }
+
+    #[test]
+    fn selector_matches_descendants_and_children() {
+        assert_eq!(matching("#layer1 path"), ["red", "blue"]);
+        assert_eq!(matching("#layer1 > path"), ["red"]);
+        assert_eq!(matching("#layer1>g>path"), ["blue"]);
+        assert_eq!(matching("svg > rect, #inner path"), ["blue", "loose"]);
+    }
+
+    #[test]
+    fn selector_refuses_what_it_does_not_understand() {
+        for bad in [
+            "",
+            "path,",
+            "> path",
+            "g >",
+            "g > > path",
+            "path + rect",
+            "path ~ rect",
+            "[stroke",
+            "[stroke=\"red]",
+            "[stroke~=red]",
+            "#",
+        ] {
+            assert!(Selector::from_str(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn subset_removes_shapes_outside_the_selection() {
+        let doc = Document::parse(DOC).unwrap();
+        let removed: Vec<&str> = subset(&doc, &Selector::from_str("#inner").unwrap())
+            .into_iter()
+            .map(|(range, _)| &DOC[range])
+            .collect();
+        assert_eq!(removed.len(), 2);
+        assert!(removed[0].contains("id=\"red\""));
+        assert!(removed[1].contains("id=\"loose\""));
+    }
+}
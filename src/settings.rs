@@ -0,0 +1,204 @@
+use roxmltree::Document;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use toml::{Table, Value};
+
+/// Namespace of the `<settings>` element an SVG's `<metadata>` can carry its own settings in
+pub const METADATA_NS: &str = "https://github.com/yabeniiii/usGcode-ifier";
+
+/// Every conversion setting, addressable by dotted keys such as `conversion.tolerance`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    pub machine: MachineSettings,
+    pub conversion: ConversionSettings,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MachineSettings {
+    /// G-code emitted to start cutting
+    pub tool_on: String,
+    /// G-code emitted to stop cutting and lift
+    pub tool_off: String,
+    /// G-code emitted at the start of the program in place of the `G21 G90` preamble, if not empty
+    pub begin: String,
+    /// G-code emitted at the end of the program, if not empty
+    pub end: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConversionSettings {
+    /// Maximum deviation in millimeters when flattening curves
+    pub tolerance: f64,
+    /// Cutting feedrate in millimeters per minute
+    pub feedrate: f64,
+    /// DPI used for absolute units
+    pub dpi: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            machine: MachineSettings {
+                tool_on: "M3 G0 Z0.0".to_string(),
+                tool_off: "M5 G0 Z3.0".to_string(),
+                begin: String::new(),
+                end: String::new(),
+            },
+            conversion: ConversionSettings {
+                tolerance: 0.001,
+                feedrate: 1000.0,
+                dpi: 100.0,
+            },
+        }
+    }
+}
+
+/// A `--set` argument, e.g. `conversion.tolerance=0.01`
+#[derive(Debug, Clone)]
+pub struct Override {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for Override {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected key=value, got \"{}\"", s))?;
+        Ok(Override {
+            key: key.trim().to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+impl Settings {
+    /// Sets the value at a dotted key, parsing it as the type the setting already has
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let mut root = Value::try_from(&*self).map_err(|err| err.to_string())?;
+        let valid_keys = keys(&root);
+        let invalid_key = || {
+            format!(
+                "unknown setting \"{}\", valid keys are: {}",
+                key,
+                valid_keys.join(", ")
+            )
+        };
+
+        let mut slot = &mut root;
+        for part in key.split('.') {
+            slot = match slot {
+                Value::Table(table) if table.contains_key(part) => table.get_mut(part).unwrap(),
+                _ => return Err(invalid_key()),
+            };
+        }
+        let mismatch = |expected: &str| {
+            format!(
+                "invalid value \"{}\" for {}, expected {}; valid keys are: {}",
+                value,
+                key,
+                expected,
+                valid_keys.join(", ")
+            )
+        };
+        *slot = match slot {
+            Value::String(_) => Value::String(value.to_string()),
+            Value::Float(_) => {
+                Value::Float(value.trim().parse().map_err(|_| mismatch("a number"))?)
+            }
+            Value::Integer(_) => {
+                Value::Integer(value.trim().parse().map_err(|_| mismatch("an integer"))?)
+            }
+            Value::Boolean(_) => Value::Boolean(
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|_| mismatch("true or false"))?,
+            ),
+            // Whole sections can't be replaced from the command line
+            _ => return Err(invalid_key()),
+        };
+        *self = root.try_into().map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    /// Sets the value of a dotted key, or a bare name such as `feedrate` that only one key ends in
+    pub fn set_by_name(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let name = name.replace('-', "_");
+        if name.contains('.') {
+            return self.set(&name, value);
+        }
+        let root = Value::try_from(&*self).map_err(|err| err.to_string())?;
+        let suffix = format!(".{}", name);
+        let matching: Vec<String> = keys(&root)
+            .into_iter()
+            .filter(|key| key.ends_with(&suffix))
+            .collect();
+        match matching.as_slice() {
+            [key] => self.set(key, value),
+            _ => self.set(&name, value),
+        }
+    }
+
+    /// Sets every value a TOML document gives, such as `[conversion]` `tolerance = 0.01`,
+    /// leaving the others as they are
+    pub fn merge_toml(&mut self, text: &str) -> Result<(), String> {
+        let root = Value::Table(text.parse::<Table>().map_err(|err| err.to_string())?);
+        for key in keys(&root) {
+            let value = key
+                .split('.')
+                .try_fold(&root, |value, part| value.get(part))
+                .expect("every key leads to a value");
+            match value {
+                Value::String(value) => self.set(&key, value)?,
+                value => self.set(&key, &value.to_string())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Name and value of each setting given by `<settings>` elements in the document's `<metadata>`,
+/// e.g. `<usgcode:settings feedrate="800"/>` with `usgcode` bound to [`METADATA_NS`]
+pub fn embedded(doc: &Document) -> Vec<(String, String)> {
+    doc.descendants()
+        .filter(|node| node.has_tag_name((METADATA_NS, "settings")))
+        .filter(|node| {
+            node.ancestors()
+                .any(|ancestor| ancestor.has_tag_name("metadata"))
+        })
+        .flat_map(|node| {
+            node.attributes()
+                .filter(|attribute| attribute.namespace().is_none())
+                .map(|attribute| (attribute.name().to_string(), attribute.value().to_string()))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Dotted keys of every individual setting
+fn keys(value: &Value) -> Vec<String> {
+    fn visit(table: &Table, prefix: &str, keys: &mut Vec<String>) {
+        for (name, value) in table {
+            let key = match prefix.is_empty() {
+                true => name.clone(),
+                false => format!("{}.{}", prefix, name),
+            };
+            match value {
+                Value::Table(table) => visit(table, &key, keys),
+                _ => keys.push(key),
+            }
+        }
+    }
+
+    let mut keys = Vec::new();
+    if let Value::Table(table) = value {
+        visit(table, "", &mut keys);
+    }
+    keys
+}
@@ -0,0 +1,291 @@
+use roxmltree::{Document, Node};
+use std::{ops::Range, str::FromStr};
+use svgtypes::{Length, LengthUnit};
+
+use crate::{
+    elements::length_to_user_units,
+    preprocess::{self, describe},
+};
+
+/// Attributes each basic shape is drawn from, which its path no longer needs
+const GEOMETRY: [(&str, &[&str]); 6] = [
+    ("rect", &["x", "y", "width", "height", "rx", "ry"]),
+    ("circle", &["cx", "cy", "r"]),
+    ("ellipse", &["cx", "cy", "rx", "ry"]),
+    ("line", &["x1", "y1", "x2", "y2"]),
+    ("polyline", &["points"]),
+    ("polygon", &["points"]),
+];
+
+/// Replacements turning every basic shape into a `<path>` drawing the same outline, for
+/// `--preflatten`.
+///
+/// Shapes that draw nothing, such as a rect without a width or a polyline of one point, are
+/// warned about and removed. Shapes sized in percentages depend on the viewport and are left
+/// to the converter.
+pub fn preflatten(xml: &str, doc: &Document) -> Vec<(Range<usize>, String)> {
+    let mut replacements = Vec::new();
+    for node in doc.descendants() {
+        let Some((_, dropped)) = GEOMETRY.iter().find(|(tag, _)| node.has_tag_name(*tag)) else {
+            continue;
+        };
+        match path_data(doc, &node, dropped) {
+            Ok(Some(d)) => {
+                replacements.extend(preprocess::retag(xml, &node, "path", dropped, &[("d", d)]))
+            }
+            Ok(None) => {}
+            Err(reason) => {
+                warning!("removing {}, {}", describe(doc, &node), reason);
+                replacements.push((node.range(), String::new()));
+            }
+        }
+    }
+    replacements
+}
+
+/// The path data of a basic shape, `None` when it has to be left as it is and an error when it
+/// draws nothing
+fn path_data(doc: &Document, node: &Node, geometry: &[&str]) -> Result<Option<String>, String> {
+    let parsed = |attr: &str| {
+        node.attribute(attr)
+            .and_then(|value| Length::from_str(value).ok())
+    };
+    if geometry
+        .iter()
+        .any(|attr| parsed(attr).is_some_and(|length| length.unit == LengthUnit::Percent))
+    {
+        return Ok(None);
+    }
+    let length = |attr: &str| parsed(attr).map(length_to_user_units);
+
+    let d = match node.tag_name().name() {
+        "rect" => {
+            let (x, y) = (length("x").unwrap_or(0.0), length("y").unwrap_or(0.0));
+            let (width, height) = (length("width"), length("height"));
+            let (rx, ry) = (length("rx"), length("ry"));
+            let (Some(width), Some(height)) = (width, height) else {
+                return Err("it has no width or height".to_string());
+            };
+            if width <= 0.0 || height <= 0.0 {
+                return Err(format!("it is {} by {} and draws nothing", width, height));
+            }
+            // A missing radius is the same as the other, and neither is more than half the side
+            let (rx, ry) = match (rx.filter(|rx| *rx >= 0.0), ry.filter(|ry| *ry >= 0.0)) {
+                (None, None) => (0.0, 0.0),
+                (Some(rx), None) => (rx, rx),
+                (None, Some(ry)) => (ry, ry),
+                (Some(rx), Some(ry)) => (rx, ry),
+            };
+            let (rx, ry) = (rx.min(width / 2.0), ry.min(height / 2.0));
+            match rx > 0.0 && ry > 0.0 {
+                false => format!("M{} {} H{} V{} H{} Z", x, y, x + width, y + height, x),
+                true => {
+                    let arc =
+                        |to_x: f64, to_y: f64| format!("A{} {} 0 0 1 {} {}", rx, ry, to_x, to_y);
+                    format!(
+                        "M{} {} H{} {} V{} {} H{} {} V{} {} Z",
+                        x + rx,
+                        y,
+                        x + width - rx,
+                        arc(x + width, y + ry),
+                        y + height - ry,
+                        arc(x + width - rx, y + height),
+                        x + rx,
+                        arc(x, y + height - ry),
+                        y + ry,
+                        arc(x + rx, y)
+                    )
+                }
+            }
+        }
+        "circle" | "ellipse" => {
+            let (cx, cy) = (length("cx").unwrap_or(0.0), length("cy").unwrap_or(0.0));
+            let (rx, ry) = match node.has_tag_name("circle") {
+                true => {
+                    let r = length("r");
+                    (r, r)
+                }
+                false => {
+                    let (rx, ry) = (length("rx"), length("ry"));
+                    (rx.or(ry), ry.or(rx))
+                }
+            };
+            let (Some(rx), Some(ry)) = (rx.filter(|rx| *rx > 0.0), ry.filter(|ry| *ry > 0.0))
+            else {
+                return Err("its radius is zero or missing and it draws nothing".to_string());
+            };
+            format!(
+                "M{} {} A{} {} 0 1 1 {} {} A{} {} 0 1 1 {} {} Z",
+                cx + rx,
+                cy,
+                rx,
+                ry,
+                cx - rx,
+                cy,
+                rx,
+                ry,
+                cx + rx,
+                cy
+            )
+        }
+        "line" => {
+            let [x1, y1, x2, y2] = ["x1", "y1", "x2", "y2"].map(|attr| length(attr).unwrap_or(0.0));
+            if x1 == x2 && y1 == y2 {
+                return Err("it has zero length".to_string());
+            }
+            format!("M{} {} L{} {}", x1, y1, x2, y2)
+        }
+        _ => {
+            let points = points(doc, node);
+            if points.len() < 2 {
+                return Err(format!("it has {} points and draws nothing", points.len()));
+            }
+            let mut d = format!("M{} {}", points[0][0], points[0][1]);
+            for point in &points[1..] {
+                d += &format!(" L{} {}", point[0], point[1]);
+            }
+            if node.has_tag_name("polygon") {
+                d += " Z";
+            }
+            d
+        }
+    };
+    Ok(Some(d))
+}
+
+/// The points of a polyline or polygon, separated by any mix of commas and whitespace. As SVG
+/// renders them, reading stops at anything that isn't a number and an odd last coordinate is
+/// left out, both with a warning.
+fn points(doc: &Document, node: &Node) -> Vec<[f64; 2]> {
+    let text = node.attribute("points").unwrap_or_default();
+    let mut numbers = Vec::new();
+    for token in text
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|token| !token.is_empty())
+    {
+        match token.parse::<f64>() {
+            Ok(number) if number.is_finite() => numbers.push(number),
+            _ => {
+                warning!(
+                    "{} has \"{}\" in its points, reading only the points before it",
+                    describe(doc, node),
+                    token
+                );
+                break;
+            }
+        }
+    }
+    if numbers.len() % 2 == 1 {
+        warning!(
+            "{} has an odd number of coordinates in its points, leaving out the last",
+            describe(doc, node)
+        );
+    }
+    numbers
+        .chunks_exact(2)
+        .map(|pair| [pair[0], pair[1]])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The path data of the one basic shape in an otherwise empty document
+    fn d(shape: &str) -> Result<Option<String>, String> {
+        let svg = format!(r#"<svg xmlns="http://www.w3.org/2000/svg">{}</svg>"#, shape);
+        let doc = Document::parse(&svg).unwrap();
+        let (node, geometry) = doc
+            .descendants()
+            .find_map(|node| {
+                let (_, geometry) = GEOMETRY.iter().find(|(tag, _)| node.has_tag_name(*tag))?;
+                Some((node, geometry))
+            })
+            .unwrap();
+        path_data(&doc, &node, geometry)
+    }
+
+    fn path(shape: &str) -> String {
+        d(shape).unwrap().unwrap()
+    }
+
+    #[test]
+    fn rect_outline() {
+        assert_eq!(
+            path(r#"<rect x="1" y="2" width="3" height="4"/>"#),
+            "M1 2 H4 V6 H1 Z"
+        );
+        assert_eq!(
+            path(r#"<rect width="1in" height="72pt"/>"#),
+            "M0 0 H96 V96 H0 Z"
+        );
+    }
+
+    #[test]
+    fn rect_rounds_its_corners() {
+        // A missing ry is the same as rx
+        assert_eq!(
+            path(r#"<rect x="1" y="2" width="3" height="4" rx="1"/>"#),
+            "M2 2 H3 A1 1 0 0 1 4 3 V5 A1 1 0 0 1 3 6 H2 A1 1 0 0 1 1 5 V3 A1 1 0 0 1 2 2 Z"
+        );
+        // Neither radius is more than half its side
+        assert_eq!(
+            path(r#"<rect width="3" height="4" rx="10"/>"#),
+            "M1.5 0 H1.5 A1.5 2 0 0 1 3 2 V2 A1.5 2 0 0 1 1.5 4 H1.5 A1.5 2 0 0 1 0 2 V2 A1.5 2 0 0 1 1.5 0 Z"
+        );
+    }
+
+    #[test]
+    fn rect_without_area_draws_nothing() {
+        assert!(d(r#"<rect width="3"/>"#).is_err());
+        assert!(d(r#"<rect width="0" height="4"/>"#).is_err());
+        assert!(d(r#"<rect width="-3" height="4"/>"#).is_err());
+    }
+
+    #[test]
+    fn circle_and_ellipse_are_two_half_arcs() {
+        assert_eq!(
+            path(r#"<circle cx="5" cy="5" r="2"/>"#),
+            "M7 5 A2 2 0 1 1 3 5 A2 2 0 1 1 7 5 Z"
+        );
+        assert_eq!(
+            path(r#"<ellipse cx="5" cy="5" rx="3" ry="1"/>"#),
+            "M8 5 A3 1 0 1 1 2 5 A3 1 0 1 1 8 5 Z"
+        );
+        // A missing radius is the same as the other
+        assert_eq!(
+            path(r#"<ellipse ry="2"/>"#),
+            "M2 0 A2 2 0 1 1 -2 0 A2 2 0 1 1 2 0 Z"
+        );
+        assert!(d(r#"<circle cx="5" cy="5"/>"#).is_err());
+        assert!(d(r#"<circle r="0"/>"#).is_err());
+    }
+
+    #[test]
+    fn line_is_a_single_segment() {
+        assert_eq!(path(r#"<line x2="3" y2="4"/>"#), "M0 0 L3 4");
+        assert!(d(r#"<line x1="3" y1="4" x2="3" y2="4"/>"#).is_err());
+    }
+
+    #[test]
+    fn polyline_and_polygon_join_their_points() {
+        assert_eq!(
+            path(r#"<polyline points="0,0 1 1, 2 0"/>"#),
+            "M0 0 L1 1 L2 0"
+        );
+        assert_eq!(
+            path(r#"<polygon points="0,0 1,1 2,0"/>"#),
+            "M0 0 L1 1 L2 0 Z"
+        );
+        // An odd last coordinate and anything after a bad number are left out
+        assert_eq!(path(r#"<polyline points="0,0 1,1 2"/>"#), "M0 0 L1 1");
+        assert_eq!(path(r#"<polyline points="0,0 1,1 x 2,0"/>"#), "M0 0 L1 1");
+        assert!(d(r#"<polyline points="0,0"/>"#).is_err());
+    }
+
+    #[test]
+    fn shapes_sized_in_percentages_are_left_alone() {
+        assert_eq!(d(r#"<rect width="50%" height="4"/>"#), Ok(None));
+        assert_eq!(d(r#"<circle r="10%"/>"#), Ok(None));
+    }
+}
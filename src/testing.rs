@@ -0,0 +1,30 @@
+//! Helpers shared by the unit tests
+
+use roxmltree::Document;
+use svg2gcode::{svg2program, ConversionOptions};
+
+use crate::{gcode::Line, settings::Settings};
+
+/// The program the converter makes of a document with the default settings
+pub fn program(doc: &Document) -> Vec<Line> {
+    let settings = Settings::default();
+    let options = ConversionOptions {
+        dimensions: crate::dimensions(doc, 1.0),
+    };
+    let tokens = svg2program(
+        doc,
+        &crate::conversion_config(&settings),
+        options,
+        crate::machine(&settings),
+    );
+    crate::gcode::assemble(tokens).collect()
+}
+
+/// The default tool-on and tool-off sequences
+pub fn tool() -> (Vec<Line>, Vec<Line>) {
+    let settings = Settings::default();
+    (
+        crate::gcode::snippet_lines(&settings.machine.tool_on),
+        crate::gcode::snippet_lines(&settings.machine.tool_off),
+    )
+}
@@ -0,0 +1,208 @@
+use roxmltree::{Document, Node};
+use std::{fmt::Write, ops::Range, str::FromStr};
+use svgtypes::Length;
+use ttf_parser::{Face, GlyphId, OutlineBuilder};
+
+use crate::elements::{self, length_to_user_units};
+
+/// Font size in user units when none is set, the CSS `medium` size
+const DEFAULT_FONT_SIZE: f64 = 16.0;
+
+/// Replacements turning every `<text>` element into a `<path>` of its glyph outlines.
+///
+/// The path keeps the text's attributes such as `id`, `transform` and `style`, so it is placed
+/// and named the same way the text would have been.
+pub fn outline_text(doc: &Document, face: &Face) -> Vec<(Range<usize>, String)> {
+    doc.descendants()
+        .filter(|node| node.has_tag_name("text"))
+        .map(|node| {
+            let mut layout = Layout {
+                face,
+                pen: [0.0, 0.0],
+                previous_space: true,
+                d: String::new(),
+            };
+            layout.element(&node);
+            let replacement = match layout.d.is_empty() {
+                true => String::new(),
+                false => path_element(&node, &layout.d),
+            };
+            (node.range(), replacement)
+        })
+        .collect()
+}
+
+/// Lays out the characters of a text element and collects their outlines as path data
+struct Layout<'a, 'face> {
+    face: &'a Face<'face>,
+    pen: [f64; 2],
+    /// Whether the last laid out character was a space, for collapsing whitespace
+    previous_space: bool,
+    d: String,
+}
+
+impl Layout<'_, '_> {
+    fn element(&mut self, node: &Node) {
+        // Only the first of a list of positions is used, moving the whole run
+        let first = |attr: &str| {
+            node.attribute(attr)
+                .and_then(|list| list.split([' ', ',']).find(|value| !value.is_empty()))
+                .and_then(|value| Length::from_str(value).ok())
+                .map(length_to_user_units)
+        };
+        for (axis, (absolute, relative)) in [("x", "dx"), ("y", "dy")].into_iter().enumerate() {
+            if let Some(value) = first(absolute) {
+                self.pen[axis] = value;
+            }
+            self.pen[axis] += first(relative).unwrap_or(0.0);
+        }
+        let font_size = elements::inherited_property(node, "font-size")
+            .and_then(|size| Length::from_str(size).ok())
+            .map_or(DEFAULT_FONT_SIZE, length_to_user_units);
+
+        for child in node.children() {
+            if child.is_element() {
+                if child.has_tag_name("tspan") {
+                    self.element(&child);
+                }
+            } else if let Some(text) = child.text() {
+                self.run(text, font_size);
+            }
+        }
+    }
+
+    fn run(&mut self, text: &str, font_size: f64) {
+        let scale = font_size / self.face.units_per_em() as f64;
+        for c in text.chars() {
+            let c = match c.is_whitespace() {
+                true if self.previous_space => continue,
+                true => ' ',
+                false => c,
+            };
+            self.previous_space = c == ' ';
+            let glyph = self.face.glyph_index(c).unwrap_or(GlyphId(0));
+            self.face.outline_glyph(
+                glyph,
+                &mut Outline {
+                    d: &mut self.d,
+                    origin: self.pen,
+                    scale,
+                },
+            );
+            self.pen[0] += self.face.glyph_hor_advance(glyph).unwrap_or(0) as f64 * scale;
+        }
+    }
+}
+
+/// Writes glyph outlines as SVG path data, flipping the font's upward Y axis
+struct Outline<'a> {
+    d: &'a mut String,
+    origin: [f64; 2],
+    scale: f64,
+}
+
+impl Outline<'_> {
+    fn point(&self, x: f32, y: f32) -> (f64, f64) {
+        (
+            self.origin[0] + x as f64 * self.scale,
+            self.origin[1] - y as f64 * self.scale,
+        )
+    }
+}
+
+impl OutlineBuilder for Outline<'_> {
+    fn move_to(&mut self, x: f32, y: f32) {
+        let (x, y) = self.point(x, y);
+        let _ = write!(self.d, "M{} {} ", x, y);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let (x, y) = self.point(x, y);
+        let _ = write!(self.d, "L{} {} ", x, y);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (x1, y1) = self.point(x1, y1);
+        let (x, y) = self.point(x, y);
+        let _ = write!(self.d, "Q{} {} {} {} ", x1, y1, x, y);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (x1, y1) = self.point(x1, y1);
+        let (x2, y2) = self.point(x2, y2);
+        let (x, y) = self.point(x, y);
+        let _ = write!(self.d, "C{} {} {} {} {} {} ", x1, y1, x2, y2, x, y);
+    }
+
+    fn close(&mut self) {
+        self.d.push_str("Z ");
+    }
+}
+
+/// A `<path>` with the text element's plain attributes, minus those only meaningful for text
+fn path_element(node: &Node, d: &str) -> String {
+    let mut path = format!("<path d=\"{}\"", d.trim_end());
+    for attribute in node.attributes() {
+        if attribute.namespace().is_some()
+            || matches!(attribute.name(), "x" | "y" | "dx" | "dy" | "rotate" | "d")
+        {
+            continue;
+        }
+        let value = attribute
+            .value()
+            .replace('&', "&amp;")
+            .replace('"', "&quot;")
+            .replace('<', "&lt;");
+        let _ = write!(path, " {}=\"{}\"", attribute.name(), value);
+    }
+    path.push_str("/>");
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preprocess, testing};
+
+    /// ttf-parser's demo font, whose only glyph is an `A`, 1000 units to the em
+    const FONT: &[u8] = include_bytes!("../tests/fonts/demo.ttf");
+
+    /// The document with its text outlined in the test font
+    fn outlined(svg: &str) -> String {
+        let doc = Document::parse(svg).unwrap();
+        let face = Face::parse(FONT, 0).unwrap();
+        preprocess::replace_ranges(svg, &outline_text(&doc, &face))
+    }
+
+    #[test]
+    fn a_glyph_is_outlined_and_cut() {
+        let svg = outlined(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">
+                <text id="label" x="10" y="50" font-size="20">A</text>
+            </svg>"#,
+        );
+        let doc = Document::parse(&svg).unwrap();
+        let path = doc
+            .descendants()
+            .find(|node| node.has_tag_name("path"))
+            .unwrap();
+        assert_eq!(path.attribute("id"), Some("label"));
+        assert!(!doc.descendants().any(|node| node.has_tag_name("text")));
+        // The outside of the A and the hole in it
+        let d = path.attribute("d").unwrap();
+        assert_eq!(d.matches('M').count(), 2);
+        // Its baseline at y 50, and 20 units over the font's 1000 unit em
+        assert!(d.contains("M13.46 44.66"));
+
+        let lines = testing::program(&doc);
+        assert!(lines.iter().any(|line| line.is("G", 1)));
+    }
+
+    #[test]
+    fn text_without_glyphs_is_dropped() {
+        let svg = outlined(
+            r#"<svg xmlns="http://www.w3.org/2000/svg"><text x="10" y="50">   </text></svg>"#,
+        );
+        assert_eq!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg"></svg>"#);
+    }
+}
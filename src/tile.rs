@@ -0,0 +1,223 @@
+use std::str::FromStr;
+
+use crate::{gcode::Line, postprocess};
+
+/// Number of copies across and up for `--grid`, e.g. `3x2`
+#[derive(Debug, Clone, Copy)]
+pub struct Grid {
+    pub columns: usize,
+    pub rows: usize,
+}
+
+impl FromStr for Grid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected <columns>x<rows> such as 3x2, got \"{}\"", s);
+        let count = |value: &str| match value.trim().parse::<usize>() {
+            Ok(count) if count > 0 => Ok(count),
+            _ => Err(invalid()),
+        };
+        let (columns, rows) = s.split_once(['x', 'X']).ok_or_else(invalid)?;
+        Ok(Grid {
+            columns: count(columns)?,
+            rows: count(rows)?,
+        })
+    }
+}
+
+/// What to do with copies that would land outside the bed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Overflow {
+    /// Stop without writing anything
+    Error,
+    /// Leave those copies out, warning about each
+    Drop,
+}
+
+pub struct TileOptions<'a> {
+    pub grid: Grid,
+    /// Gap between neighbouring copies in millimeters
+    pub spacing: [f64; 2],
+    /// Width and height of the bed, when copies should be checked against it
+    pub bed: Option<[f64; 2]>,
+    pub overflow: Overflow,
+    pub tool_off: &'a [Line],
+    /// Whether to label each copy with a comment
+    pub annotate: bool,
+}
+
+/// Repeats the program's moves in a grid, each copy offset by the design's size plus the spacing.
+///
+/// Copies are visited row by row, alternating direction so the travel between them stays short,
+/// and each one starts with the tool lifted. The lines before the first move and after the last
+/// lift are kept once, around all the copies.
+pub fn tile(lines: Vec<Line>, options: &TileOptions) -> Vec<Line> {
+    let Some([min, max]) = postprocess::bounds(&lines) else {
+        return lines;
+    };
+    let planar = |line: &Line| line.value("X").is_some() || line.value("Y").is_some();
+    let start = lines.iter().position(planar).unwrap_or(0);
+    let mut end = lines.iter().rposition(planar).map_or(start, |i| i + 1);
+    let lifted = lines[end..].starts_with(options.tool_off);
+    if lifted {
+        end += options.tool_off.len();
+    }
+
+    let Grid { columns, rows } = options.grid;
+    let pitch = [
+        max[0] - min[0] + options.spacing[0],
+        max[1] - min[1] + options.spacing[1],
+    ];
+    let mut output = lines[..start].to_vec();
+    let mut placed = 0;
+    for row in 0..rows {
+        for step in 0..columns {
+            let column = match row % 2 {
+                0 => step,
+                _ => columns - 1 - step,
+            };
+            let offset = [column as f64 * pitch[0], row as f64 * pitch[1]];
+            if let Some(bed) = options.bed {
+                let outside = (0..2).any(|axis| {
+                    min[axis] + offset[axis] < 0.0 || max[axis] + offset[axis] > bed[axis]
+                });
+                if outside {
+                    match options.overflow {
+                        Overflow::Error => panic!(
+                            "Could not tile the design, the copy in column {} row {} extends past the {}x{}mm bed",
+                            column + 1,
+                            row + 1,
+                            bed[0],
+                            bed[1]
+                        ),
+                        Overflow::Drop => {
+                            warning!(
+                                "the copy in column {} row {} extends past the bed, leaving it out",
+                                column + 1,
+                                row + 1
+                            );
+                            continue;
+                        }
+                    }
+                }
+            }
+
+            placed += 1;
+            if options.annotate {
+                output.push(Line::Comment(format!(
+                    "Copy {} (column {} row {})",
+                    placed,
+                    column + 1,
+                    row + 1
+                )));
+            }
+            for line in &lines[start..end] {
+                let mut line = line.clone();
+                for (axis, letter) in ["X", "Y"].into_iter().enumerate() {
+                    if let Some(value) = line.value(letter) {
+                        line.set_value(letter, value + offset[axis]);
+                    }
+                }
+                output.push(line);
+            }
+            if !lifted {
+                output.extend_from_slice(options.tool_off);
+            }
+        }
+    }
+    if placed == 0 {
+        warning!("no copy of the design fits on the bed");
+    }
+    output.extend_from_slice(&lines[end..]);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /// A 10 by 5 mm L from the origin, between the begin and end lines of a program
+    fn design() -> Vec<Line> {
+        let (tool_on, tool_off) = testing::tool();
+        let mut lines = vec![Line::command("G", 21, &[])];
+        lines.push(Line::command("G", 0, &[("X", 0.0), ("Y", 0.0)]));
+        lines.extend(tool_on);
+        lines.push(Line::command("G", 1, &[("X", 10.0), ("Y", 0.0)]));
+        lines.push(Line::command("G", 1, &[("X", 10.0), ("Y", 5.0)]));
+        lines.extend(tool_off);
+        lines.push(Line::command("M", 2, &[]));
+        lines
+    }
+
+    fn options(tool_off: &[Line], bed: Option<[f64; 2]>) -> TileOptions<'_> {
+        TileOptions {
+            grid: Grid {
+                columns: 2,
+                rows: 2,
+            },
+            spacing: [2.0, 3.0],
+            bed,
+            overflow: Overflow::Drop,
+            tool_off,
+            annotate: false,
+        }
+    }
+
+    /// Where each copy starts
+    fn starts(lines: &[Line]) -> Vec<[f64; 2]> {
+        lines
+            .iter()
+            .filter(|line| line.is("G", 0) && line.value("X").is_some())
+            .map(|line| [line.value("X").unwrap(), line.value("Y").unwrap()])
+            .collect()
+    }
+
+    #[test]
+    fn grid_parses_columns_and_rows() {
+        let grid = Grid::from_str("3x2").unwrap();
+        assert_eq!((grid.columns, grid.rows), (3, 2));
+        let grid = Grid::from_str(" 4 X 1 ").unwrap();
+        assert_eq!((grid.columns, grid.rows), (4, 1));
+    }
+
+    #[test]
+    fn grid_refuses_anything_else() {
+        for bad in ["3", "3x", "x2", "0x2", "3x0", "-1x2", "1.5x2", "3by2"] {
+            let err = Grid::from_str(bad).unwrap_err();
+            assert!(err.contains("such as 3x2"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn tile_steps_back_and_forth_across_the_rows() {
+        let (_, tool_off) = testing::tool();
+        let tiled = tile(design(), &options(&tool_off, None));
+        assert_eq!(
+            starts(&tiled),
+            [[0.0, 0.0], [12.0, 0.0], [12.0, 8.0], [0.0, 8.0]]
+        );
+        // The begin and end lines are kept once, around the copies
+        assert_eq!(tiled.iter().filter(|line| line.is("G", 21)).count(), 1);
+        assert_eq!(tiled.iter().filter(|line| line.is("M", 2)).count(), 1);
+    }
+
+    #[test]
+    fn tile_leaves_out_copies_past_the_bed() {
+        let (_, tool_off) = testing::tool();
+        let tiled = tile(design(), &options(&tool_off, Some([20.0, 20.0])));
+        assert_eq!(starts(&tiled), [[0.0, 0.0], [0.0, 8.0]]);
+    }
+
+    #[test]
+    #[should_panic(expected = "column 2 row 1 extends past the 20x20mm bed")]
+    fn tile_stops_at_copies_past_the_bed() {
+        let (_, tool_off) = testing::tool();
+        let options = TileOptions {
+            overflow: Overflow::Error,
+            ..options(&tool_off, Some([20.0, 20.0]))
+        };
+        tile(design(), &options);
+    }
+}
@@ -0,0 +1,196 @@
+use roxmltree::{Document, Node};
+use std::str::FromStr;
+use svgtypes::Length;
+
+use crate::{
+    elements::{self, length_to_user_units},
+    gcode::{self, Line},
+};
+
+/// Stroke widths are measured as drawn, at the CSS DPI rather than the conversion DPI
+const DRAWN_DPI: f64 = 96.0;
+
+/// A `--width-map` entry assigning a tool to a stroke width in millimeters
+#[derive(Debug, Clone)]
+pub struct WidthMapping {
+    pub width: f64,
+    pub tool: usize,
+}
+
+impl FromStr for WidthMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (width, tool) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected width=tool, got \"{}\"", s))?;
+        Ok(WidthMapping {
+            width: width
+                .trim()
+                .parse()
+                .map_err(|err| format!("invalid width \"{}\": {}", width, err))?,
+            tool: tool
+                .trim()
+                .parse()
+                .map_err(|err| format!("invalid tool number \"{}\": {}", tool, err))?,
+        })
+    }
+}
+
+/// Resolved stroke width of an element in millimeters, defaulting to one user unit
+pub fn stroke_width(node: &Node, dimensions: &[Option<Length>; 2]) -> f64 {
+    let width = elements::inherited_property(node, "stroke-width")
+        .and_then(|width| Length::from_str(width).ok())
+        .map_or(1.0, length_to_user_units);
+    width * elements::user_unit_scale(node, dimensions, DRAWN_DPI)
+}
+
+/// How tools are changed between groups
+pub struct ToolChange<'a> {
+    /// G-code with `{n}` standing for the tool number, one command per line
+    pub command: &'a str,
+    /// Where to travel to before changing tools, for either axis
+    pub position: [Option<f64>; 2],
+}
+
+impl ToolChange<'_> {
+    /// Lines changing to the given tool, e.g. `T2 M6`
+    pub fn lines(&self, tool: usize) -> Result<Vec<Line>, String> {
+        let mut lines = Vec::new();
+        let words: Vec<(&'static str, f64)> = ["X", "Y"]
+            .into_iter()
+            .zip(self.position)
+            .filter_map(|(letter, value)| Some((letter, value?)))
+            .collect();
+        if !words.is_empty() {
+            lines.push(Line::command("G", 0, &words));
+        }
+        lines.extend(gcode::template_lines(
+            &self.command.replace("{n}", &tool.to_string()),
+        )?);
+        Ok(lines)
+    }
+}
+
+/// Reorders elements into groups by the tool their stroke width maps to, changing tools between
+/// groups. Elements whose width isn't mapped keep the current tool and are cut first.
+pub fn group_by_width(
+    lines: Vec<Line>,
+    doc: &Document,
+    mappings: &[WidthMapping],
+    tolerance: f64,
+    dimensions: &[Option<Length>; 2],
+    tool_off: &[Line],
+    change: &ToolChange,
+) -> Vec<Line> {
+    let program = elements::split_program(doc, &lines);
+    let mut unmapped = Vec::new();
+    let mut groups: Vec<(Option<usize>, Vec<std::ops::Range<usize>>)> = Vec::new();
+    for (range, node) in program.chunks {
+        let width = stroke_width(&node, dimensions);
+        let tool = mappings
+            .iter()
+            .find(|mapping| (mapping.width - width).abs() <= tolerance)
+            .map(|mapping| mapping.tool);
+        if tool.is_none()
+            && !unmapped
+                .iter()
+                .any(|w: &f64| (w - width).abs() <= tolerance)
+        {
+            unmapped.push(width);
+        }
+        match groups
+            .iter_mut()
+            .find(|(group_tool, _)| *group_tool == tool)
+        {
+            Some((_, ranges)) => ranges.push(range),
+            None => groups.push((tool, vec![range])),
+        }
+    }
+    if !unmapped.is_empty() {
+        warning!(
+            "no tool mapped for stroke widths {}, using the current tool",
+            unmapped
+                .iter()
+                .map(|width| format!("{:.3}mm", width))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    // Unmapped elements first, then tools in ascending order
+    groups.sort_by_key(|(tool, _)| *tool);
+
+    let mut output = lines[program.prefix].to_vec();
+    for (tool, ranges) in groups {
+        for (i, range) in ranges.into_iter().enumerate() {
+            let chunk = &lines[range];
+            match tool {
+                Some(tool) if i == 0 => {
+                    // Change tools only once the previous one is off and lifted
+                    let after_off = (0..chunk.len())
+                        .find(|&j| chunk[j..].starts_with(tool_off))
+                        .map_or(0, |j| j + tool_off.len());
+                    output.extend_from_slice(&chunk[..after_off]);
+                    match change.lines(tool) {
+                        Ok(lines) => output.extend(lines),
+                        Err(err) => panic!(
+                            "Could not parse --tool-change-command, failed with error: {}",
+                            err
+                        ),
+                    }
+                    output.extend_from_slice(&chunk[after_off..]);
+                }
+                _ => output.extend_from_slice(chunk),
+            }
+        }
+    }
+    output.extend_from_slice(&lines[program.suffix]);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn tools_change_between_their_groups() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">
+            <path d="M10 10 L20 10" stroke-width="2"/>
+            <path d="M10 20 L20 20" stroke-width="1"/>
+            <path d="M10 30 L20 30" stroke-width="2"/>
+        </svg>"#;
+        let doc = Document::parse(svg).unwrap();
+        let (_, tool_off) = testing::tool();
+        let mappings: Vec<WidthMapping> = ["1=1", "2=2"].map(|m| m.parse().unwrap()).to_vec();
+        let lines = group_by_width(
+            testing::program(&doc),
+            &doc,
+            &mappings,
+            0.05,
+            &crate::dimensions(&doc, 1.0),
+            &tool_off,
+            &ToolChange {
+                command: "T{n} M6",
+                position: [Some(0.0), Some(200.0)],
+            },
+        );
+        let text: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        let position = |wanted: &str| text.iter().position(|line| line == wanted).unwrap();
+        let (first, second) = (position("T1 M6"), position("T2 M6"));
+        assert_eq!(text[second - 1], "G0 X0 Y200");
+        // The thinner path 20 units down is cut with tool 1, then both others with tool 2
+        let cut_ys = |range: std::ops::Range<usize>| -> Vec<f64> {
+            lines[range]
+                .iter()
+                .filter(|line| line.is("G", 1))
+                .filter_map(|line| line.value("Y"))
+                .map(|y| (y * 10.0).round() / 10.0)
+                .collect()
+        };
+        assert_eq!(cut_ys(first..second), [76.8]);
+        assert_eq!(cut_ys(second..lines.len()), [86.4, 67.2]);
+        // Tool 2 is only brought in once tool 1 is off
+        assert_eq!(lines[second - 1 - tool_off.len()..second - 1], tool_off[..]);
+    }
+}
@@ -0,0 +1,235 @@
+use std::{fs, path::Path};
+
+use crate::gcode::Line;
+
+/// Heights probed across the work surface on a grid, for `--z-map`
+#[derive(Debug, Clone)]
+pub struct ZMap {
+    xs: Vec<f64>,
+    ys: Vec<f64>,
+    /// Height at each X for the first Y, then each X for the next Y, and so on
+    heights: Vec<f64>,
+}
+
+/// How close two probed coordinates have to be to count as the same grid line
+const SAME: f64 = 1e-6;
+
+pub fn read(path: &Path) -> ZMap {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) => panic!(
+            "Could not read z-map file: {}, failed with error: {}",
+            path.display(),
+            err
+        ),
+    };
+    match ZMap::parse(&text) {
+        Ok(map) => map,
+        Err(err) => panic!(
+            "Could not use z-map file: {}, failed with error: {}",
+            path.display(),
+            err
+        ),
+    }
+}
+
+impl ZMap {
+    /// Reads `x,y,z` lines, one per probed point, skipping blank lines, `#` comments and a
+    /// header. The points have to cover every X at every Y.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut points = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values: Result<Vec<f64>, _> =
+                line.split(',').map(|value| value.trim().parse()).collect();
+            match values {
+                Ok(values) if values.len() == 3 && values.iter().all(|v| v.is_finite()) => {
+                    points.push([values[0], values[1], values[2]])
+                }
+                // A header naming the columns
+                Err(_) if points.is_empty() => {}
+                _ => {
+                    return Err(format!(
+                        "line {} \"{}\" isn't an x,y,z point",
+                        number + 1,
+                        line
+                    ))
+                }
+            }
+        }
+
+        let grid_lines = |axis: usize| {
+            let mut values: Vec<f64> = points.iter().map(|point| point[axis]).collect();
+            values.sort_by(f64::total_cmp);
+            values.dedup_by(|a, b| (*a - *b).abs() <= SAME);
+            values
+        };
+        let (xs, ys) = (grid_lines(0), grid_lines(1));
+        if xs.len() < 2 || ys.len() < 2 {
+            return Err("it needs points at two or more X and Y positions".to_string());
+        }
+        let mut heights = vec![None; xs.len() * ys.len()];
+        for [x, y, z] in points {
+            let column = xs
+                .iter()
+                .position(|&grid| (grid - x).abs() <= SAME)
+                .unwrap();
+            let row = ys
+                .iter()
+                .position(|&grid| (grid - y).abs() <= SAME)
+                .unwrap();
+            heights[row * xs.len() + column] = Some(z);
+        }
+        let heights = heights
+            .into_iter()
+            .enumerate()
+            .map(|(i, height)| {
+                height.ok_or_else(|| {
+                    format!(
+                        "it has no height at X{} Y{}, the points need to form a grid",
+                        xs[i % xs.len()],
+                        ys[i / xs.len()]
+                    )
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(ZMap { xs, ys, heights })
+    }
+
+    /// Height of the surface at a point, interpolated between the four probed around it, and
+    /// taken from the nearest edge outside the probed area
+    pub fn height(&self, x: f64, y: f64) -> f64 {
+        let cell = |grid: &[f64], value: f64| {
+            let i = grid
+                .windows(2)
+                .position(|pair| value < pair[1])
+                .unwrap_or(grid.len() - 2);
+            let t = ((value - grid[i]) / (grid[i + 1] - grid[i])).clamp(0.0, 1.0);
+            (i, t)
+        };
+        let ((column, tx), (row, ty)) = (cell(&self.xs, x), cell(&self.ys, y));
+        let at = |column: usize, row: usize| self.heights[row * self.xs.len() + column];
+        let bottom = at(column, row) * (1.0 - tx) + at(column + 1, row) * tx;
+        let top = at(column, row + 1) * (1.0 - tx) + at(column + 1, row + 1) * tx;
+        bottom * (1.0 - ty) + top * ty
+    }
+}
+
+/// Raises or lowers every move by the height of the surface where it ends.
+///
+/// Moves are straight between their ends, so long cuts only follow the surface in between when
+/// they are split up, e.g. with `--max-segment-length`.
+pub fn compensate(mut lines: Vec<Line>, map: &ZMap) -> Vec<Line> {
+    // Where the program puts the tool, before compensation
+    let mut position = [None; 3];
+    for line in lines.iter_mut() {
+        if !(0..=3).any(|number| line.is("G", number)) {
+            continue;
+        }
+        for (axis, letter) in ["X", "Y", "Z"].into_iter().enumerate() {
+            if let Some(value) = line.value(letter) {
+                position[axis] = Some(value);
+            }
+        }
+        if let [Some(x), Some(y), Some(z)] = position {
+            line.set_value("Z", z + map.height(x, y));
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2 by 3 grid rising 0.1 per millimeter in X and 0.2 in Y
+    const SLOPE: &str = "x,y,z
+# probed at 10 mm spacing
+0,0,0
+10,0,1
+0,10,2
+
+10,10,3
+0,20,4
+10,20,5
+";
+
+    fn assert_near(value: f64, expected: f64) {
+        assert!(
+            (value - expected).abs() < 1e-9,
+            "{} is not {}",
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn parse_reads_the_grid_in_any_order() {
+        let map = ZMap::parse(SLOPE).unwrap();
+        assert_eq!(map.xs, [0.0, 10.0]);
+        assert_eq!(map.ys, [0.0, 10.0, 20.0]);
+        assert_eq!(map.heights, [0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+
+        let shuffled = "10,20,5\n0,0,0\n0,10,2\n10,0,1\n0,20,4\n10,10,3\n";
+        assert_eq!(ZMap::parse(shuffled).unwrap().heights, map.heights);
+    }
+
+    #[test]
+    fn parse_refuses_points_that_are_not_a_grid() {
+        let err = ZMap::parse("0,0,0\n10,0,1\n0,10,2\n").unwrap_err();
+        assert!(err.contains("X10 Y10"), "{}", err);
+        assert!(ZMap::parse("0,0,0\n0,10,1\n").is_err());
+        assert!(ZMap::parse("0,0,0\n10,0\n0,10,2\n10,10,3\n").is_err());
+        assert!(ZMap::parse("0,0,0\n10,0,inf\n0,10,2\n10,10,3\n").is_err());
+        // A header can only come before the points
+        let reversed: String = SLOPE
+            .lines()
+            .rev()
+            .map(|line| format!("{}\n", line))
+            .collect();
+        assert!(ZMap::parse(&reversed).is_err());
+    }
+
+    #[test]
+    fn height_is_the_probed_height_at_probed_points() {
+        let map = ZMap::parse(SLOPE).unwrap();
+        assert_near(map.height(0.0, 0.0), 0.0);
+        assert_near(map.height(10.0, 10.0), 3.0);
+        assert_near(map.height(10.0, 20.0), 5.0);
+    }
+
+    #[test]
+    fn height_interpolates_between_probed_points() {
+        let map = ZMap::parse(SLOPE).unwrap();
+        assert_near(map.height(5.0, 0.0), 0.5);
+        assert_near(map.height(2.5, 15.0), 3.25);
+    }
+
+    #[test]
+    fn height_outside_the_grid_is_taken_from_its_edge() {
+        let map = ZMap::parse(SLOPE).unwrap();
+        assert_near(map.height(-5.0, -5.0), 0.0);
+        assert_near(map.height(15.0, 25.0), 5.0);
+        assert_near(map.height(5.0, 30.0), 4.5);
+    }
+
+    #[test]
+    fn compensate_moves_by_the_height_where_they_end() {
+        let map = ZMap::parse(SLOPE).unwrap();
+        let lines = vec![
+            Line::command("G", 0, &[("X", 5.0), ("Y", 0.0)]),
+            Line::command("G", 0, &[("Z", -1.0)]),
+            Line::command("G", 1, &[("X", 10.0), ("Y", 10.0)]),
+            Line::command("M", 5, &[]),
+        ];
+        let compensated = compensate(lines, &map);
+        // Nothing to raise before the program gives a height
+        assert_eq!(compensated[0].value("Z"), None);
+        assert_near(compensated[1].value("Z").unwrap(), -0.5);
+        assert_near(compensated[2].value("Z").unwrap(), 2.0);
+        assert_eq!(compensated[3], Line::command("M", 5, &[]));
+    }
+}
@@ -0,0 +1,56 @@
+use std::{
+    fs,
+    process::{Command, Output},
+};
+
+/// A square 28.8mm across, at the converter's scale of 0.96mm to the unit
+const SQUARE: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">
+    <rect x="10" y="10" width="30" height="30"/>
+</svg>"#;
+
+/// Converts the square onto a 40mm bed with the margin
+fn convert(margin: &str) -> Output {
+    let dir = std::env::temp_dir().join(format!("usgcode-bed-{}-{}", margin, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("square.svg");
+    fs::write(&input, SQUARE).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_usGcode"))
+        .args(["--quiet", "--move-to-origin", "--bed-width", "40"])
+        .args(["--bed-height", "40", "--margin", margin])
+        .arg(&input)
+        .arg(dir.join("square.gcode"))
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    output
+}
+
+#[test]
+fn the_design_fits_with_the_margin_all_round_it() {
+    // 28.8mm and two 5.5mm margins is 39.8mm
+    assert!(convert("5.5").status.success());
+    let output = convert("5.7");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Could not fit the design on the bed")
+            && stderr.contains("past the 40x40mm bed with a margin of 5.7,5.7mm"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn margins_can_differ_across_and_up_the_bed() {
+    assert!(convert("5.5,1").status.success());
+    assert!(!convert("1,5.7").status.success());
+}
+
+#[test]
+fn negative_margins_are_refused() {
+    let output = convert("-1");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("margins must be finite and zero or more")
+    );
+}
@@ -0,0 +1,53 @@
+use std::{fs, path::Path, process::Command};
+
+const DRAWING: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="80mm" viewBox="0 0 100 80">
+    <title>Bracket</title>
+    <g stroke="red">
+        <rect x="5" y="5" width="40" height="30" rx="4"/>
+        <circle cx="70" cy="20" r="12"/>
+    </g>
+    <path stroke="blue" d="M 10 60 C 30 40 60 80 90 60"/>
+    <polygon points="60,70 80,50 95,75"/>
+    <ellipse cx="25" cy="55" rx="12.5" ry="7.25"/>
+</svg>"#;
+
+/// Converts the drawing into `dir`, with the environment variables set, and returns the G-code
+fn convert(dir: &Path, env: &[(&str, &str)]) -> Vec<u8> {
+    fs::create_dir_all(dir).unwrap();
+    let (input, output) = (dir.join("drawing.svg"), dir.join("drawing.gcode"));
+    fs::write(&input, DRAWING).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_usGcode"))
+        .args([
+            "--quiet",
+            "--keep-comments",
+            "--optimize-rapid-order",
+            "--arc-fit-sequences",
+        ])
+        .arg(&input)
+        .arg(&output)
+        .envs(env.iter().copied())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    fs::read(output).unwrap()
+}
+
+#[test]
+fn converting_twice_writes_the_same_bytes() {
+    let root = std::env::temp_dir().join(format!("usgcode-deterministic-{}", std::process::id()));
+    let first = convert(&root.join("first"), &[]);
+    // Somewhere else, with other temporary files and a locale writing decimal commas
+    let other_tmp = root.join("tmp");
+    fs::create_dir_all(&other_tmp).unwrap();
+    let second = convert(
+        &root.join("second"),
+        &[
+            ("TMPDIR", other_tmp.to_str().unwrap()),
+            ("LC_ALL", "de_DE.UTF-8"),
+        ],
+    );
+    fs::remove_dir_all(&root).unwrap();
+
+    assert!(!first.is_empty());
+    assert!(first == second, "the two conversions differ");
+}
@@ -0,0 +1,57 @@
+use std::{
+    fs,
+    process::{Command, Output},
+};
+
+/// A document with nothing to cut, only definitions
+const DEFS_ONLY: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">
+    <defs><circle id="dot" r="5"/></defs>
+</svg>"#;
+
+/// Converts the document with the arguments, returning what the process printed and the
+/// output it wrote, if any
+fn convert(name: &str, args: &[&str]) -> (Output, Option<String>) {
+    let dir = std::env::temp_dir().join(format!("usgcode-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (input, output) = (dir.join("empty.svg"), dir.join("empty.gcode"));
+    fs::write(&input, DEFS_ONLY).unwrap();
+    let result = Command::new(env!("CARGO_BIN_EXE_usGcode"))
+        .args(args)
+        .arg(&input)
+        .arg(&output)
+        .output()
+        .unwrap();
+    let written = fs::read_to_string(&output).ok();
+    fs::remove_dir_all(&dir).unwrap();
+    (result, written)
+}
+
+#[test]
+fn nothing_is_written_without_geometry() {
+    let (output, written) = convert("empty", &[]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("no toolpath was generated from") && stderr.contains("no geometry to cut"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("not writing an empty program, pass --allow-empty"));
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Successfully created"));
+    assert_eq!(written, None);
+}
+
+#[test]
+fn allow_empty_writes_the_program_anyway() {
+    let (output, written) = convert("allow-empty", &["--allow-empty"]);
+    assert!(output.status.success());
+    assert!(written.is_some_and(|gcode| gcode.starts_with("G21")));
+}
+
+#[test]
+fn strict_refuses_a_document_without_geometry() {
+    let (output, written) = convert("empty-strict", &["--strict"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("it has no geometry to cut"));
+    assert_eq!(written, None);
+}
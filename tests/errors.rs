@@ -0,0 +1,63 @@
+use std::{
+    fs,
+    process::{Command, Output},
+};
+
+/// A square, a path with malformed data and a group scaled to nothing
+const BROKEN: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">
+    <rect x="10" y="10" width="30" height="30"/>
+    <path d="M0 0 L10 x"/>
+    <g transform="scale(0)"><rect width="10" height="10"/></g>
+</svg>"#;
+
+/// Converts the document with the arguments, returning what the process printed and the
+/// output it wrote, if any
+fn convert(name: &str, svg: &str, args: &[&str]) -> (Output, Option<String>) {
+    let dir = std::env::temp_dir().join(format!("usgcode-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (input, output) = (dir.join("broken.svg"), dir.join("broken.gcode"));
+    fs::write(&input, svg).unwrap();
+    let result = Command::new(env!("CARGO_BIN_EXE_usGcode"))
+        .args(args)
+        .arg(&input)
+        .arg(&output)
+        .output()
+        .unwrap();
+    let written = fs::read_to_string(&output).ok();
+    fs::remove_dir_all(&dir).unwrap();
+    (result, written)
+}
+
+#[test]
+fn every_failing_element_is_reported_before_giving_up() {
+    let (output, written) = convert("invalid", BROKEN, &[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("failed on:\n<path> at 3:5: invalid path data (invalid number at position 10)\n<g> at 4:5: transform is not invertible\nUse --ignore-errors to skip these elements"),
+        "{}",
+        stderr
+    );
+    assert_eq!(written, None);
+}
+
+#[test]
+fn ignore_errors_skips_the_failing_elements() {
+    let (output, written) = convert("ignore-errors", BROKEN, &["--ignore-errors"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Warning: skipping <path> at 3:5: invalid path data")
+            && stderr.contains("Warning: skipping <g> at 4:5: transform is not invertible"),
+        "{}",
+        stderr
+    );
+    // Only the square is left to cut
+    let square = BROKEN
+        .lines()
+        .filter(|line| !line.contains("<path") && !line.contains("<g"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let (_, alone) = convert("ignore-errors-alone", &square, &[]);
+    assert_eq!(written, alone);
+}
@@ -0,0 +1,46 @@
+use std::{fs, process::Command};
+
+/// Three squares, so there is a travel between paths as well as a first and last one
+const SQUARES: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">
+    <rect x="10" y="10" width="10" height="10"/>
+    <rect x="30" y="10" width="10" height="10"/>
+    <rect x="50" y="10" width="10" height="10"/>
+</svg>"#;
+
+/// The Z each tool-off lifts to, converting the squares with the arguments
+fn lifts(name: &str, args: &[&str]) -> Vec<String> {
+    let dir = std::env::temp_dir().join(format!("usgcode-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (input, output) = (dir.join("squares.svg"), dir.join("squares.gcode"));
+    fs::write(&input, SQUARES).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_usGcode"))
+        .arg("--quiet")
+        .args(args)
+        .arg(&input)
+        .arg(&output)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let gcode = fs::read_to_string(&output).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    let lines: Vec<&str> = gcode.lines().collect();
+    lines
+        .windows(2)
+        .filter(|pair| pair[0] == "M5")
+        .map(|pair| pair[1].to_string())
+        .collect()
+}
+
+#[test]
+fn travels_between_paths_lift_to_the_travel_height() {
+    // The lift ahead of the first travel and the one ending the job clear the clamps
+    assert_eq!(
+        lifts("travel-z", &["--travel-z", "5", "--clearance-z", "20"]),
+        ["G0 Z20", "G0 Z5", "G0 Z5", "G0 Z20"]
+    );
+}
+
+#[test]
+fn the_tool_off_height_is_kept_without_retract_heights() {
+    assert_eq!(lifts("no-travel-z", &[]), ["G0 Z3."; 4]);
+}
@@ -0,0 +1,62 @@
+#![cfg(unix)]
+
+use std::{
+    fs,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Enough small paths that writing them out takes a while
+fn many_paths() -> String {
+    let paths: String = (0..100_000)
+        .map(|i| {
+            format!(
+                r#"<path d="M{} {} l1 1 l1 -1 l1 1"/>"#,
+                i % 100,
+                i / 100 % 100
+            )
+        })
+        .collect();
+    format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">{}</svg>"#,
+        paths
+    )
+}
+
+#[test]
+fn an_interrupt_discards_the_partial_output() {
+    let dir = std::env::temp_dir().join(format!("usgcode-interrupt-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (input, output) = (dir.join("many.svg"), dir.join("out.gcode"));
+    fs::write(&input, many_paths()).unwrap();
+    fs::write(&output, "G0 X1\n").unwrap();
+
+    let child = Command::new(env!("CARGO_BIN_EXE_usGcode"))
+        .arg("--quiet")
+        .arg(&input)
+        .arg(&output)
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Interrupt once the temporary file is being written
+    let temp = dir.join(format!(".out.gcode.{}.tmp", child.id()));
+    let start = Instant::now();
+    while !temp.exists() && start.elapsed() < Duration::from_secs(120) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(temp.exists(), "the output was never started");
+    let sent = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(sent.success());
+    let result = child.wait_with_output().unwrap();
+    let (kept, temp_left) = (fs::read_to_string(&output).unwrap(), temp.exists());
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(result.status.code(), Some(130));
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Aborted, partial output discarded"));
+    assert!(!temp_left);
+    assert_eq!(kept, "G0 X1\n");
+}
@@ -0,0 +1,94 @@
+use std::{
+    fs,
+    process::{Command, Output},
+};
+
+/// Converts the document with the arguments, returning what the process printed
+fn convert(name: &str, svg: &str, args: &[&str]) -> Output {
+    let dir = std::env::temp_dir().join(format!("usgcode-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.svg");
+    fs::write(&input, svg).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_usGcode"))
+        .arg("--quiet")
+        .args(args)
+        .arg(&input)
+        .arg(dir.join("output.gcode"))
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    output
+}
+
+fn document(content: &str) -> String {
+    format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">{}</svg>"#,
+        content
+    )
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn a_dense_path_over_the_move_limit_is_refused() {
+    let points: String = (0..2000)
+        .map(|i| format!(" L{} {}", i % 100, i % 7))
+        .collect();
+    let svg = document(&format!(r#"<path d="M0 0{}"/>"#, points));
+    let output = convert("max-moves", &svg, &["--max-moves", "1000"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output)
+            .contains("its shapes need at least 2001 moves, more than the --max-moves of 1000"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(convert("max-moves-ok", &svg, &["--max-moves", "5000"])
+        .status
+        .success());
+}
+
+#[test]
+fn curves_flattened_past_the_move_limit_are_refused() {
+    let svg = document(r#"<circle cx="50" cy="50" r="40"/>"#);
+    let output = convert("max-moves-curve", &svg, &["--max-moves", "10"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("generates more than the --max-moves of 10 moves"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(stderr(&output).contains("try a larger tolerance"));
+}
+
+#[test]
+fn entities_are_not_expanded_unless_the_dtd_is_allowed() {
+    let svg = format!(
+        r#"<!DOCTYPE svg [<!ENTITY square "<rect x='10' y='10' width='30' height='30'/>">]>{}"#,
+        document("&square;")
+    );
+    let output = convert("dtd", &svg, &[]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("it has a DTD, which isn't read unless --allow-dtd is given"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(convert("dtd-allowed", &svg, &["--allow-dtd"])
+        .status
+        .success());
+}
+
+#[test]
+fn files_over_the_input_size_limit_are_refused() {
+    let svg = document(r#"<rect x="10" y="10" width="30" height="30"/>"#);
+    let output = convert("max-input-size", &svg, &["--max-input-size", "100"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("over the --max-input-size of 100 bytes"),
+        "{}",
+        stderr(&output)
+    );
+}
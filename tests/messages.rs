@@ -0,0 +1,49 @@
+use std::{
+    fs,
+    process::{Command, Output},
+};
+
+const SQUARE: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">
+    <rect x="10" y="10" width="30" height="30"/>
+</svg>"#;
+
+/// Converts the document with the arguments, returning what the process printed
+fn convert(name: &str, svg: &str, args: &[&str]) -> Output {
+    let dir = std::env::temp_dir().join(format!("usgcode-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("square.svg");
+    fs::write(&input, svg).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_usGcode"))
+        .args(args)
+        .arg(&input)
+        .arg(dir.join("square.gcode"))
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    output
+}
+
+#[test]
+fn no_progress_bar_is_drawn_when_stderr_is_not_a_terminal() {
+    let output = convert("progress", SQUARE, &[]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Successfully created gcode"));
+}
+
+#[test]
+fn quiet_prints_nothing_on_success() {
+    // The warning the skipped text would give is silenced too
+    let labelled = SQUARE.replace("</svg>", "<text x=\"10\" y=\"60\">Part 7</text></svg>");
+    let output = convert("quiet", &labelled, &["--quiet"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn quiet_and_verbose_are_refused_together() {
+    let output = convert("quiet-verbose", SQUARE, &["--quiet", "--verbose"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used with"));
+}
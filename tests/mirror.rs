@@ -0,0 +1,90 @@
+use std::{fs, path::Path, process::Command};
+
+/// An L shape, with a circle to fit arcs to
+const DRAWING: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="50mm" viewBox="0 0 100 50">
+    <path d="M 10 10 L 60 10 L 60 20 L 20 20 L 20 40 L 10 40 Z"/>
+    <circle cx="80" cy="25" r="10"/>
+</svg>"#;
+
+/// The commands and XY points of the moves the drawing converts to with the arguments
+fn moves(dir: &Path, name: &str, args: &[&str]) -> Vec<(String, [f64; 2])> {
+    let (input, output) = (dir.join("drawing.svg"), dir.join(name));
+    fs::write(&input, DRAWING).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_usGcode"))
+        .arg("--quiet")
+        .args(args)
+        .arg(&input)
+        .arg(&output)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let gcode = fs::read_to_string(output).unwrap();
+    let mut position = [None, None];
+    let mut moves = Vec::new();
+    for line in gcode.lines() {
+        let mut words = line.split_whitespace();
+        let Some(command) = words
+            .next()
+            .filter(|word| ["G0", "G1", "G2", "G3"].contains(word))
+        else {
+            continue;
+        };
+        for word in words {
+            let (letter, value) = word.split_at(1);
+            let axis = ["X", "Y"].iter().position(|axis| *axis == letter);
+            if let Some(axis) = axis {
+                position[axis] = Some(value.parse::<f64>().unwrap());
+            }
+        }
+        if let [Some(x), Some(y)] = position {
+            moves.push((command.to_string(), [x, y]));
+        }
+    }
+    moves
+}
+
+fn bounds(moves: &[(String, [f64; 2])]) -> [[f64; 2]; 2] {
+    moves.iter().fold(
+        [[f64::INFINITY; 2], [f64::NEG_INFINITY; 2]],
+        |[min, max], (_, [x, y])| {
+            [
+                [min[0].min(*x), min[1].min(*y)],
+                [max[0].max(*x), max[1].max(*y)],
+            ]
+        },
+    )
+}
+
+#[test]
+fn a_negative_scale_mirrors_at_full_size() {
+    let dir = std::env::temp_dir().join(format!("usgcode-mirror-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let fit = ["--arc-fit-sequences"];
+    let plain = moves(&dir, "plain.gcode", &fit);
+    let mirrored = moves(&dir, "mirrored.gcode", &["-s", "-1", fit[0]]);
+    fs::remove_dir_all(&dir).unwrap();
+
+    // The same size in the same place, with every X flipped across the middle
+    let [min, max] = bounds(&plain);
+    let mirrored_bounds = bounds(&mirrored);
+    for (corner, expected) in mirrored_bounds.iter().zip([min, max]) {
+        assert!((0..2).all(|axis| (corner[axis] - expected[axis]).abs() < 1e-3));
+    }
+    assert_eq!(plain.len(), mirrored.len());
+    for ((command, [x, y]), (mirrored_command, [mirrored_x, mirrored_y])) in
+        plain.iter().zip(&mirrored)
+    {
+        assert!((min[0] + max[0] - x - mirrored_x).abs() < 1e-3);
+        assert!((y - mirrored_y).abs() < 1e-3);
+        // The circle's arcs go round it the other way
+        let turned = match command.as_str() {
+            "G2" => "G3",
+            "G3" => "G2",
+            other => other,
+        };
+        assert_eq!(mirrored_command, turned);
+    }
+    assert!(plain
+        .iter()
+        .any(|(command, _)| command == "G2" || command == "G3"));
+}
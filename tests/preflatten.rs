@@ -0,0 +1,142 @@
+use std::{
+    fs,
+    process::{Command, Output},
+};
+
+/// Converts the document with the arguments, returning what the process printed and the
+/// program it wrote
+fn convert(name: &str, svg: &str, args: &[&str]) -> (Output, String) {
+    let dir = std::env::temp_dir().join(format!("usgcode-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (input, path) = (dir.join("shapes.svg"), dir.join("shapes.gcode"));
+    fs::write(&input, svg).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_usGcode"))
+        .args(args)
+        .arg(&input)
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let gcode = fs::read_to_string(&path).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    (output, gcode)
+}
+
+fn document(content: &str) -> String {
+    format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">{}</svg>"#,
+        content
+    )
+}
+
+/// The program of the shape converted as it is and once preflattened
+fn both(name: &str, shape: &str) -> (String, String) {
+    let svg = document(shape);
+    let (_, converted) = convert(name, &svg, &["--quiet"]);
+    let (_, preflattened) = convert(
+        &format!("{}-preflattened", name),
+        &svg,
+        &["--quiet", "--preflatten"],
+    );
+    (converted, preflattened)
+}
+
+/// The X and Y of every move, leaving out cuts to where the tool already is
+fn points(gcode: &str) -> Vec<[f64; 2]> {
+    let mut points: Vec<[f64; 2]> = Vec::new();
+    for line in gcode.lines() {
+        let word = |letter: char| {
+            line.split_whitespace()
+                .find_map(|word| word.strip_prefix(letter)?.parse::<f64>().ok())
+        };
+        let (Some(x), Some(y)) = (word('X'), word('Y')) else {
+            continue;
+        };
+        if points.last() != Some(&[x, y]) {
+            points.push([x, y]);
+        }
+    }
+    points
+}
+
+fn bounds(points: &[[f64; 2]]) -> [f64; 4] {
+    points.iter().fold(
+        [
+            f64::INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+        ],
+        |[min_x, min_y, max_x, max_y], [x, y]| {
+            [min_x.min(*x), min_y.min(*y), max_x.max(*x), max_y.max(*y)]
+        },
+    )
+}
+
+#[test]
+fn rects_and_lines_convert_the_same_preflattened() {
+    for (name, shape) in [
+        ("rect", r#"<rect x="5" y="5" width="20" height="10"/>"#),
+        (
+            "rounded-rect",
+            r#"<rect x="30" y="5" width="20" height="10" rx="3"/>"#,
+        ),
+        ("line", r#"<line x1="30" y1="30" x2="50" y2="50"/>"#),
+    ] {
+        let (converted, preflattened) = both(name, shape);
+        assert_eq!(converted, preflattened, "{}", shape);
+    }
+}
+
+#[test]
+fn polylines_and_polygons_visit_the_same_points_preflattened() {
+    // The converter cuts to each one's first point as it gets there, which its path doesn't
+    for (name, shape) in [
+        ("polyline", r#"<polyline points="60,30 70,40 80,30"/>"#),
+        ("polygon", r#"<polygon points="10,60 20,70 30,60"/>"#),
+    ] {
+        let (converted, preflattened) = both(name, shape);
+        assert_eq!(points(&converted), points(&preflattened), "{}", shape);
+    }
+}
+
+#[test]
+fn circles_and_ellipses_cover_the_same_area_preflattened() {
+    // Flattened from different arcs, so only the outline they trace matches
+    for (name, shape) in [
+        ("circle", r#"<circle cx="70" cy="10" r="5"/>"#),
+        ("ellipse", r#"<ellipse cx="15" cy="40" rx="10" ry="5"/>"#),
+    ] {
+        let (converted, preflattened) = both(name, shape);
+        let (converted, preflattened) = (points(&converted), points(&preflattened));
+        for (a, b) in bounds(&converted).iter().zip(bounds(&preflattened)) {
+            assert!((a - b).abs() < 0.01, "{}: {} and {}", shape, a, b);
+        }
+        assert_eq!(converted.first(), preflattened.first(), "{}", shape);
+    }
+}
+
+#[test]
+fn shapes_that_draw_nothing_are_warned_about_and_removed() {
+    let rect = r#"<rect x="5" y="5" width="20" height="10"/>"#;
+    let degenerate = format!(
+        r#"{}<rect width="3"/><circle r="0"/><line x1="3" y1="4" x2="3" y2="4"/><polyline points="1,1"/>"#,
+        rect
+    );
+    let (output, gcode) = convert("degenerate", &document(&degenerate), &["--preflatten"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for warning in [
+        "removing <rect> at 1:134, it has no width or height",
+        "removing <circle> at 1:151, its radius is zero or missing and it draws nothing",
+        "removing <line> at 1:166, it has zero length",
+        "removing <polyline> at 1:201, it has 1 points and draws nothing",
+    ] {
+        assert!(stderr.contains(warning), "{}", stderr);
+    }
+    let (_, alone) = convert("degenerate-alone", &document(rect), &["--quiet"]);
+    assert_eq!(gcode, alone);
+}
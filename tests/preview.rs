@@ -0,0 +1,49 @@
+use std::{fs, process::Command};
+
+/// Three squares, cut as three paths
+const SQUARES: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">
+    <rect x="10" y="10" width="10" height="10"/>
+    <rect x="30" y="10" width="10" height="10"/>
+    <rect x="50" y="10" width="10" height="10"/>
+</svg>"#;
+
+/// The SVG preview of the squares, drawn with the arguments
+fn preview(name: &str, args: &[&str]) -> String {
+    let dir = std::env::temp_dir().join(format!("usgcode-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (input, preview) = (dir.join("squares.svg"), dir.join("preview.svg"));
+    fs::write(&input, SQUARES).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_usGcode"))
+        .arg("--quiet")
+        .args(args)
+        .arg("--toolpath-preview-svg")
+        .arg(&preview)
+        .arg(&input)
+        .arg(dir.join("squares.gcode"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let svg = fs::read_to_string(&preview).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    svg
+}
+
+#[test]
+fn each_path_is_labelled_with_its_place_in_the_cutting_order() {
+    let svg = preview("number-paths", &["--number-paths"]);
+    let cuts = svg.matches("stroke=\"blue\"").count();
+    let labels: Vec<&str> = svg
+        .lines()
+        .filter(|line| line.trim_start().starts_with("<text"))
+        .filter_map(|line| line.strip_suffix("</text>")?.rsplit('>').next())
+        .collect();
+    assert_eq!(cuts, 3);
+    assert_eq!(labels, ["1", "2", "3"]);
+}
+
+#[test]
+fn paths_are_not_labelled_by_default() {
+    let svg = preview("no-number-paths", &[]);
+    assert_eq!(svg.matches("stroke=\"blue\"").count(), 3);
+    assert!(!svg.contains("<text"));
+}
@@ -0,0 +1,75 @@
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    fs,
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+const SQUARE: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">
+    <rect x="10" y="10" width="30" height="30"/>
+</svg>"#;
+
+fn gzipped(svg: &str) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(svg.as_bytes()).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Converts the bytes read from stdin with the arguments, returning what the process printed
+/// and the program it wrote
+fn convert(name: &str, input: &[u8], args: &[&str]) -> (Output, String) {
+    let dir = std::env::temp_dir().join(format!("usgcode-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("square.gcode");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_usGcode"))
+        .arg("--quiet")
+        .args(args)
+        .arg("-")
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    let gcode = fs::read_to_string(&path).unwrap_or_default();
+    fs::remove_dir_all(&dir).unwrap();
+    (output, gcode)
+}
+
+#[test]
+fn gzipped_svg_on_stdin_is_decompressed() {
+    let (plain, expected) = convert("stdin-svg", SQUARE.as_bytes(), &[]);
+    assert!(plain.status.success());
+    assert!(expected.contains("G1"));
+
+    let (output, gcode) = convert("stdin-svgz", &gzipped(SQUARE), &["--input-format", "svgz"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(gcode, expected);
+
+    // Recognised by its magic bytes without --input-format
+    let (output, gcode) = convert("stdin-auto", &gzipped(SQUARE), &[]);
+    assert!(output.status.success());
+    assert_eq!(gcode, expected);
+}
+
+#[test]
+fn plain_svg_read_as_svgz_fails() {
+    let (output, _) = convert(
+        "stdin-not-svgz",
+        SQUARE.as_bytes(),
+        &["--input-format", "svgz"],
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("could not decompress it as gzip"),
+        "{}",
+        stderr
+    );
+}
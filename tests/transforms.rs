@@ -0,0 +1,69 @@
+use std::{
+    fs,
+    process::{Command, Output},
+};
+
+/// A square 28.8mm across on a 50mm page, at the converter's scale of 0.96mm to the unit,
+/// doubled by the group around it
+const SCALED: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" width="50mm" height="50mm" viewBox="0 0 50 50">
+    <g transform="translate(5 5)"><g transform="scale(2)">
+        <rect x="0" y="0" width="30" height="30"/>
+    </g></g>
+</svg>"#;
+
+/// Converts the document with the arguments, returning what the process printed
+fn convert(name: &str, svg: &str, args: &[&str]) -> Output {
+    let dir = std::env::temp_dir().join(format!("usgcode-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("scaled.svg");
+    fs::write(&input, svg).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_usGcode"))
+        .args(args)
+        .arg(&input)
+        .arg(dir.join("scaled.gcode"))
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    output
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn a_toolpath_larger_than_the_document_is_warned_about() {
+    let output = convert("outgrown", SCALED, &[]);
+    assert!(output.status.success());
+    assert!(
+        stderr(&output).contains("the toolpath measures 57.600 x 57.600 mm")
+            && stderr(&output).contains("larger than the document's declared 50.000 x 50.000 mm")
+            && stderr(&output).contains("check the 2 transforms listed with --verbose"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn strict_refuses_a_toolpath_larger_than_the_document() {
+    let output = convert("outgrown-strict", SCALED, &["--strict"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("Could not convert svg file")
+            && stderr(&output).contains("the toolpath measures"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn toolpaths_within_the_tolerance_are_not_warned_about() {
+    let unscaled = SCALED.replace("scale(2)", "scale(1)");
+    let output = convert("not-outgrown", &unscaled, &[]);
+    assert!(output.status.success());
+    assert!(
+        !stderr(&output).contains("the toolpath measures"),
+        "{}",
+        stderr(&output)
+    );
+}
@@ -0,0 +1,65 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+/// A square with a label the converter can't cut
+const LABELLED: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">
+    <rect x="10" y="10" width="30" height="30"/>
+    <text x="10" y="60">Part 7</text>
+</svg>"#;
+
+/// Converts the labelled square with the arguments, writing to `out.gcode` in the directory
+fn convert(dir: &Path, args: &[&str]) -> Output {
+    let input = dir.join("labelled.svg");
+    fs::write(&input, LABELLED).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_usGcode"))
+        .args(args)
+        .arg(&input)
+        .arg(dir.join("out.gcode"))
+        .output()
+        .unwrap()
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("usgcode-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn text_is_skipped_with_a_warning() {
+    let dir = temp_dir("unsupported-warning");
+    let output = convert(&dir, &[]);
+    let written = fs::read_to_string(dir.join("out.gcode"));
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Warning: <text> at 3:5: text is not converted"),
+        "{}",
+        stderr
+    );
+    // The square is still cut
+    assert!(written.unwrap().lines().any(|line| line.starts_with("G1")));
+}
+
+#[test]
+fn strict_refuses_text_and_writes_nothing() {
+    let dir = temp_dir("unsupported-strict");
+    let output = convert(&dir, &["--strict"]);
+    let written = dir.join("out.gcode").exists();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("it uses unsupported features"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("<text> at 3:5: text is not converted"));
+    assert!(!written);
+}